@@ -1,22 +1,86 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
-use tower_lsp::{jsonrpc, lsp_types::*, ClientToServer, LanguageClient};
+use serde_json::Value;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{mpsc, oneshot};
+use tower_lsp::{jsonrpc, lsp_types::*, ClientToServer, LanguageClient, LspService};
 use tracing::info;
 
+/// Notifications from the server that need to be forwarded to the TUI
+#[derive(Debug)]
+pub enum ClientNotification {
+    Diagnostics(PublishDiagnosticsParams),
+    /// The server process exited with the given status code
+    ServerExited(Option<i32>),
+    LogMessage(LogMessageParams),
+    /// A message the server wants the user to see right away
+    ShowMessage(ShowMessageParams),
+    Progress(ProgressParams),
+    /// The server is waiting for the user to pick one of the actions
+    ShowMessageRequest(
+        ShowMessageRequestParams,
+        Responder<Option<MessageActionItem>>,
+    ),
+}
+
+/// Sends the user's answer to a server request back to the client.
+/// Dropping every clone without answering dismisses the request.
+#[derive(Debug)]
+pub struct Responder<T>(Arc<Mutex<Option<oneshot::Sender<T>>>>);
+
+impl<T> Responder<T> {
+    pub fn new(tx: oneshot::Sender<T>) -> Self {
+        Self(Arc::new(Mutex::new(Some(tx))))
+    }
+
+    /// Only the first answer is sent
+    pub fn respond(&self, value: T) {
+        if let Some(tx) = self.0.lock().unwrap().take() {
+            let _ = tx.send(value);
+        }
+    }
+}
+
+// Derived `Clone` would require `T: Clone`
+impl<T> Clone for Responder<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
 pub struct Client {
     client: Arc<tower_lsp::Client<ClientToServer>>,
+    notification_tx: mpsc::UnboundedSender<ClientNotification>,
+    /// Sent in response to `workspace/configuration`
+    settings: Value,
 }
 
 impl Client {
-    pub fn new(client: tower_lsp::Client<ClientToServer>) -> Self {
+    pub fn new(
+        client: tower_lsp::Client<ClientToServer>,
+        notification_tx: mpsc::UnboundedSender<ClientNotification>,
+    ) -> Self {
         Self {
             client: Arc::new(client),
+            notification_tx,
+            settings: Value::Null,
         }
     }
 
+    pub fn with_settings(mut self, settings: Value) -> Self {
+        self.settings = settings;
+        self
+    }
+
     pub fn server_client(&self) -> Arc<tower_lsp::Client<ClientToServer>> {
         self.client.clone()
     }
+
+    fn notify(&self, notification: ClientNotification) {
+        if self.notification_tx.send(notification).is_err() {
+            info!("Notification receiver dropped");
+        }
+    }
 }
 
 #[tower_lsp::async_trait]
@@ -26,7 +90,114 @@ impl LanguageClient for Client {
         Ok(())
     }
 
+    async fn configuration(&self, params: ConfigurationParams) -> jsonrpc::Result<Vec<Value>> {
+        info!("Configuration {params:?}");
+        Ok(params
+            .items
+            .iter()
+            .map(|item| settings_section(&self.settings, item.section.as_deref()))
+            .collect())
+    }
+
     async fn log_message(&self, params: LogMessageParams) {
         info!("Log message {params:?}");
+        self.notify(ClientNotification::LogMessage(params));
+    }
+
+    async fn show_message(&self, params: ShowMessageParams) {
+        info!("Show message {params:?}");
+        self.notify(ClientNotification::ShowMessage(params));
+    }
+
+    async fn show_message_request(
+        &self,
+        params: ShowMessageRequestParams,
+    ) -> jsonrpc::Result<Option<MessageActionItem>> {
+        info!("Show message request {params:?}");
+        let (tx, rx) = oneshot::channel();
+        self.notify(ClientNotification::ShowMessageRequest(
+            params,
+            Responder::new(tx),
+        ));
+        Ok(rx.await.unwrap_or(None))
+    }
+
+    async fn work_done_progress_create(
+        &self,
+        params: WorkDoneProgressCreateParams,
+    ) -> jsonrpc::Result<()> {
+        info!("Create progress {:?}", params.token);
+        Ok(())
+    }
+
+    async fn progress(&self, params: ProgressParams) {
+        self.notify(ClientNotification::Progress(params));
+    }
+
+    async fn publish_diagnostics(&self, params: PublishDiagnosticsParams) {
+        info!("Publish diagnostics {:?}", params.uri);
+        self.notify(ClientNotification::Diagnostics(params));
+    }
+}
+
+/// Runs a client that reads the server's output from `reader` and writes to its input through
+/// `writer`. Returns the handle for sending requests to the server. `settings` answer its
+/// `workspace/configuration` requests.
+pub fn serve_client<R, W>(
+    reader: R,
+    writer: W,
+    notification_tx: mpsc::UnboundedSender<ClientNotification>,
+    settings: Value,
+) -> Arc<tower_lsp::Client<ClientToServer>>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let (client_service, client_socket) = LspService::new_client(|client| {
+        Client::new(client, notification_tx).with_settings(settings)
+    });
+    let lsp_client = client_service.inner().server_client();
+    tokio::spawn(tower_lsp::Server::new(reader, writer, client_socket).serve(client_service));
+    lsp_client
+}
+
+/// Finds a section like `typescript.format` in the settings, or all of them when no section is
+/// given. Missing sections are `null`.
+fn settings_section(settings: &Value, section: Option<&str>) -> Value {
+    let Some(section) = section else {
+        return settings.clone();
+    };
+    section
+        .split('.')
+        .try_fold(settings, |value, key| value.get(key))
+        .cloned()
+        .unwrap_or(Value::Null)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::settings_section;
+
+    #[test]
+    fn find_settings_section() {
+        let settings = json!({
+            "typescript": { "format": { "semicolons": "remove" } },
+        });
+        assert_eq!(settings, settings_section(&settings, None));
+        assert_eq!(
+            json!({ "semicolons": "remove" }),
+            settings_section(&settings, Some("typescript.format"))
+        );
+        assert_eq!(
+            json!("remove"),
+            settings_section(&settings, Some("typescript.format.semicolons"))
+        );
+        assert_eq!(Value::Null, settings_section(&settings, Some("javascript")));
+        assert_eq!(
+            Value::Null,
+            settings_section(&settings, Some("typescript.format.semicolons.x"))
+        );
     }
 }
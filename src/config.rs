@@ -0,0 +1,225 @@
+use std::{collections::HashMap, env, ffi::OsStr, fs, path::PathBuf};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::tui::keymap::Keymap;
+
+const CONFIG_ENV_VAR: &str = "LSP_TUI_CONFIG";
+const DEFAULT_CONFIG_PATH: &str = "./lsp-tui.toml";
+/// Languages that `server` doesn't handle by default, on top of the ones the built-in server
+/// can parse
+pub(crate) const LANGUAGE_SERVERS: &[ExtraLanguage] = &[
+    ExtraLanguage {
+        language_id: "rust",
+        extensions: &["rs"],
+        command: "rust-analyzer",
+        args: &[],
+    },
+    ExtraLanguage {
+        language_id: "python",
+        extensions: &["py"],
+        command: "pyright-langserver",
+        args: &["--stdio"],
+    },
+];
+
+/// The server for a language that isn't handled by default
+pub(crate) struct ExtraLanguage {
+    pub language_id: &'static str,
+    /// The first extension is the one given to new documents
+    pub extensions: &'static [&'static str],
+    pub command: &'static str,
+    pub args: &'static [&'static str],
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// The server for languages without a server of their own
+    pub server: ServerConfig,
+    /// Servers for specific languages, by language ID like `rust` or `python`
+    pub servers: HashMap<String, LanguageServerConfig>,
+    pub editor: EditorConfig,
+    pub completion: CompletionConfig,
+    pub log: LogConfig,
+    /// Key bindings added on top of the defaults
+    pub keys: Keymap,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub command: String,
+    pub args: Vec<String>,
+    pub language_id: String,
+    /// Use the built-in tree-sitter server instead of spawning `command`
+    pub local: bool,
+    /// How many bytes can be in flight in each direction between the client and the built-in
+    /// server. Bigger messages are streamed through in pieces.
+    pub local_buffer_size: usize,
+    /// Passed to the server unchanged as `initializationOptions`
+    pub initialization_options: Option<serde_json::Value>,
+    /// Settings for servers that ask for them with `workspace/configuration`.
+    /// Sections are looked up by their dotted names, like `typescript.format`.
+    pub settings: serde_json::Value,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            command: "typescript-language-server".to_owned(),
+            args: vec!["--stdio".to_owned()],
+            language_id: "typescript".to_owned(),
+            local: false,
+            local_buffer_size: 64 * 1024,
+            initialization_options: None,
+            settings: serde_json::Value::Null,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct EditorConfig {
+    pub tab_width: usize,
+    /// Insert spaces instead of a tab character when pressing Tab
+    pub soft_tabs: bool,
+    /// `dark`, `light`, the name of a theme in `./themes`, or a path to a theme file
+    pub theme: String,
+    /// Format the document with the server before writing it to disk
+    pub format_on_save: bool,
+    /// Wrap lines that don't fit on screen instead of scrolling horizontally
+    pub soft_wrap: bool,
+    /// Draw tabs as `→` and trailing spaces as `·`
+    pub render_whitespace: bool,
+    /// Reopen files where the cursor was when they were last closed
+    pub restore_positions: bool,
+}
+
+impl Default for EditorConfig {
+    fn default() -> Self {
+        Self {
+            tab_width: 4,
+            soft_tabs: false,
+            theme: "dark".to_owned(),
+            format_on_save: false,
+            soft_wrap: false,
+            render_whitespace: false,
+            restore_positions: true,
+        }
+    }
+}
+
+/// Overrides for the server of one language. Unset fields are taken from the built-in server for
+/// the language, or from `server` if there isn't one.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct LanguageServerConfig {
+    pub command: Option<String>,
+    /// Use the built-in tree-sitter server for the language. Off unless it's set here, even
+    /// when `server.local` is on.
+    pub local: Option<bool>,
+    pub args: Option<Vec<String>>,
+    pub initialization_options: Option<serde_json::Value>,
+    pub settings: Option<serde_json::Value>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct CompletionConfig {
+    /// Match completions as a subsequence of the typed word instead of requiring a prefix
+    pub fuzzy: bool,
+    /// The most completions to keep from a response after filtering
+    pub max_items: usize,
+    /// Open completions automatically while typing an identifier. Trigger characters like `.`
+    /// and Ctrl+Space still open them when this is off.
+    pub auto_trigger: bool,
+    /// How long the identifier has to be before completions open automatically
+    pub min_word_length: usize,
+}
+
+impl Default for CompletionConfig {
+    fn default() -> Self {
+        Self {
+            fuzzy: true,
+            max_items: 50,
+            auto_trigger: true,
+            min_word_length: 2,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    /// Write logs to a file in `directory`
+    pub enabled: bool,
+    pub directory: PathBuf,
+    /// Which logs to keep, like `info` or `lsp_tui=debug,tower_lsp=warn`. `RUST_LOG` takes
+    /// precedence when it's set.
+    pub filter: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            directory: PathBuf::from("./logs"),
+            filter: "info".to_owned(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config from the path in `LSP_TUI_CONFIG`, or `./lsp-tui.toml` if it isn't set.
+    /// Files ending in `.json` are read as JSON and anything else as TOML. Falls back to the
+    /// defaults when no config file exists.
+    pub fn load() -> anyhow::Result<Self> {
+        let path = env::var(CONFIG_ENV_VAR)
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from(DEFAULT_CONFIG_PATH));
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read config {}", path.display()))?;
+        let invalid = || format!("invalid config {}", path.display());
+        if path.extension() == Some(OsStr::new("json")) {
+            serde_json::from_str(&contents).with_context(invalid)
+        } else {
+            toml::from_str(&contents).with_context(invalid)
+        }
+    }
+
+    /// The server for documents in `language_id`, or `server` if the language isn't known
+    pub fn server_for(&self, language_id: Option<&str>) -> ServerConfig {
+        let mut server = self.server.clone();
+        let Some(language_id) = language_id else {
+            return server;
+        };
+        server.language_id = language_id.to_owned();
+        if let Some(extra) = LANGUAGE_SERVERS
+            .iter()
+            .find(|extra| extra.language_id == language_id)
+        {
+            // Options meant for the fallback server don't apply to a different one
+            server.local = false;
+            server.command = extra.command.to_owned();
+            server.args = extra.args.iter().map(|arg| (*arg).to_owned()).collect();
+            server.initialization_options = None;
+            server.settings = serde_json::Value::Null;
+        }
+        if let Some(overrides) = self.servers.get(language_id) {
+            let overrides = overrides.clone();
+            server.local = overrides.local.unwrap_or(false);
+            server.command = overrides.command.unwrap_or(server.command);
+            server.args = overrides.args.unwrap_or(server.args);
+            server.initialization_options = overrides
+                .initialization_options
+                .or(server.initialization_options);
+            server.settings = overrides.settings.unwrap_or(server.settings);
+        }
+        server
+    }
+}
@@ -1,12 +1,18 @@
 use std::sync::Arc;
 
-use lsp_text::RopeExt;
-use tokio::sync::Mutex;
+use anyhow::Context;
 use tower_lsp::lsp_types::*;
+use tracing::warn;
 
-use super::{session::Session, text::Text};
+use super::{
+    language::LanguageConfig,
+    rope,
+    session::Session,
+    text::{normalize_newlines, Text},
+};
 
 pub struct Document {
+    pub language: LanguageConfig,
     pub content: ropey::Rope,
     pub parser: tree_sitter::Parser,
     pub tree: tree_sitter::Tree,
@@ -16,43 +22,48 @@ impl Document {
     pub async fn open(
         session: Arc<Session>,
         params: DidOpenTextDocumentParams,
-    ) -> anyhow::Result<Option<Self>> {
-        let mut parser = crate::core::parser::javascript(&session.language)?;
-        let content = ropey::Rope::from(params.text_document.text);
-        let result = {
-            let content = content.clone();
-            let byte_idx = 0;
-            let mut callback = content
-                .chunk_walker(byte_idx)
-                .callback_adapter_for_tree_sitter();
-            let old_tree = None;
-            parser.parse_with(&mut callback, old_tree)
-        };
+    ) -> anyhow::Result<Self> {
+        let language = session
+            .languages
+            .for_document(&params.text_document.language_id, &params.text_document.uri);
+        let mut parser = crate::core::parser::new(language.language)?;
+        let content = ropey::Rope::from(normalize_newlines(&params.text_document.text));
+        let tree = parse_or_empty(&mut parser, &content, &params.text_document.uri)?;
 
-        Ok(result.map(|tree| Document {
+        Ok(Document {
+            language,
             content,
             parser,
             tree,
-        }))
+        })
     }
 
-    pub async fn change<'changes>(
+    /// Reparses the document after it changed. When `edits` is provided, the previous tree
+    /// is updated with them and reused for an incremental parse.
+    pub async fn change(
         session: Arc<Session>,
         uri: &Url,
         content: &ropey::Rope,
+        edits: Option<&[tree_sitter::InputEdit]>,
     ) -> anyhow::Result<Option<tree_sitter::Tree>> {
         let result = {
-            let parser = session.get_mut_parser(uri).await?;
+            let parser = session.get_parser(uri).await?;
             let mut parser = parser.lock().await;
-            let text = content.chunks().collect::<String>();
-            parser.parse(text, None)
+            match edits {
+                Some(edits) => {
+                    let tree = session.get_tree(uri).await?;
+                    let mut tree = tree.lock().await;
+                    for edit in edits {
+                        tree.edit(edit);
+                    }
+                    rope::parse(&mut parser, content.slice(..), Some(&*tree))
+                }
+                None => rope::parse(&mut parser, content.slice(..), None),
+            }
         };
         // crate::core::syntax::update_channel(result.as_ref());
         if let Some(tree) = result {
-            {
-                let tree = tree.clone();
-                *session.get_mut_tree(uri).await?.value_mut() = Mutex::new(tree);
-            }
+            *session.get_tree(uri).await?.lock().await = tree.clone();
             Ok(Some(tree))
         } else {
             Ok(None)
@@ -66,8 +77,46 @@ impl Document {
     }
 }
 
+/// Parses the content, or falls back to an empty tree if the parser gives up on it, such as when
+/// it times out. The document is kept around either way so later changes can still be applied.
+fn parse_or_empty(
+    parser: &mut tree_sitter::Parser,
+    content: &ropey::Rope,
+    uri: &Url,
+) -> anyhow::Result<tree_sitter::Tree> {
+    if let Some(tree) = rope::parse(parser, content.slice(..), None) {
+        return Ok(tree);
+    }
+    warn!("failed to parse {uri}");
+    // Otherwise the parser would resume the parse it gave up on
+    parser.reset();
+    parser
+        .parse("", None)
+        .context("failed to create an empty tree")
+}
+
 // #[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
 // pub enum DocumentState {
 //     Closed,
 //     Opened,
 // }
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::Url;
+
+    use super::parse_or_empty;
+    use crate::core::{language::javascript, parser};
+
+    #[test]
+    fn empty_tree_when_parsing_times_out() {
+        let mut parser = parser::new(javascript().language).unwrap();
+        parser.set_timeout_micros(1);
+        let content = ropey::Rope::from("let a = [1, 2, 3];\n".repeat(100_000));
+        let uri: Url = "file:///test.js".parse().unwrap();
+
+        let tree = parse_or_empty(&mut parser, &content, &uri).unwrap();
+        assert_eq!(0, tree.root_node().end_byte());
+        assert!(!tree.root_node().has_error());
+    }
+}
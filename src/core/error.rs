@@ -12,12 +12,22 @@ pub enum Error {
         kind: core::session::SessionResourceKind,
         uri: Url,
     },
+    #[error("core::SessionResourceExists: kind={kind:?}, uri={uri:?}")]
+    SessionResourceExists {
+        kind: core::session::SessionResourceKind,
+        uri: Url,
+    },
+    #[error("{0:?} is not a valid identifier")]
+    InvalidIdentifier(String),
 }
 
 pub struct IntoJsonRpcError(pub anyhow::Error);
 
 impl From<IntoJsonRpcError> for tower_lsp::jsonrpc::Error {
     fn from(error: IntoJsonRpcError) -> Self {
+        if let Some(error @ Error::InvalidIdentifier(_)) = error.0.downcast_ref() {
+            return tower_lsp::jsonrpc::Error::invalid_params(error.to_string());
+        }
         let mut rpc_error = tower_lsp::jsonrpc::Error::internal_error();
         rpc_error.data = Some(serde_json::to_value(format!("{}", error.0)).unwrap());
         rpc_error
@@ -40,4 +50,13 @@ mod tests {
 
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn invalid_identifier_is_invalid_params() {
+        let error = Error::InvalidIdentifier("1x".to_owned());
+        let actual: tower_lsp::jsonrpc::Error = IntoJsonRpcError(error.into()).into();
+
+        assert_eq!(tower_lsp::jsonrpc::ErrorCode::InvalidParams, actual.code);
+        assert_eq!("\"1x\" is not a valid identifier", actual.message);
+    }
 }
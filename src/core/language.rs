@@ -0,0 +1,317 @@
+use tower_lsp::lsp_types::Url;
+
+/// The queries handlers run against a grammar's trees. Supporting another language is a matter
+/// of implementing this for its grammar and registering a [`LanguageConfig`] with it.
+pub trait LanguageSupport: Send + Sync {
+    /// Captures each declaration as `@<declaration kind>` and its name as `@identifier`
+    fn symbol_query(&self) -> &'static str;
+
+    /// Captures identifiers to suggest, named by the kind of completion item
+    fn completion_query(&self) -> &'static str;
+
+    /// Captures the nodes that can be folded as `@fold`, or as `@fold.comment` for comments
+    fn fold_query(&self) -> &'static str;
+}
+
+/// A grammar the server can parse along with the queries written against it
+#[derive(Clone, Copy)]
+pub struct LanguageConfig {
+    /// Used to label code blocks in markdown responses
+    pub name: &'static str,
+    pub language: tree_sitter::Language,
+    /// `languageId`s sent by the client that select this grammar
+    pub language_ids: &'static [&'static str],
+    /// File extensions that select this grammar when the `languageId` isn't recognized
+    pub extensions: &'static [&'static str],
+    pub support: &'static dyn LanguageSupport,
+}
+
+/// The grammars available to the server. The first registered language is used for documents
+/// that don't match any other.
+#[derive(Clone)]
+pub struct LanguageRegistry {
+    languages: Vec<LanguageConfig>,
+}
+
+impl LanguageRegistry {
+    pub fn new(default: LanguageConfig) -> Self {
+        Self {
+            languages: vec![default],
+        }
+    }
+
+    /// The grammars bundled with the server
+    pub fn builtin() -> Self {
+        let mut registry = Self::new(javascript());
+        registry.register(jsx());
+        registry.register(typescript());
+        registry.register(tsx());
+        registry
+    }
+
+    pub fn register(&mut self, language: LanguageConfig) {
+        self.languages.push(language);
+    }
+
+    /// Picks the grammar for a document by its `languageId`, then by its file extension
+    pub fn for_document(&self, language_id: &str, uri: &Url) -> LanguageConfig {
+        let extension = uri
+            .path()
+            .rsplit_once('.')
+            .map(|(_, extension)| extension)
+            .unwrap_or_default();
+        *self
+            .languages
+            .iter()
+            .find(|l| l.language_ids.contains(&language_id))
+            .or_else(|| {
+                self.languages
+                    .iter()
+                    .find(|l| l.extensions.contains(&extension))
+            })
+            .unwrap_or(&self.languages[0])
+    }
+
+    /// The `languageId` of files with the extension
+    pub fn language_id(&self, extension: &str) -> Option<&'static str> {
+        self.languages
+            .iter()
+            .find(|l| l.extensions.contains(&extension))
+            .and_then(|l| l.language_ids.first().copied())
+    }
+
+    /// The extension given to new files in the language
+    pub fn extension(&self, language_id: &str) -> Option<&'static str> {
+        self.languages
+            .iter()
+            .find(|l| l.language_ids.contains(&language_id))
+            .and_then(|l| l.extensions.first().copied())
+    }
+}
+
+pub fn javascript() -> LanguageConfig {
+    LanguageConfig {
+        name: "javascript",
+        language: tree_sitter_javascript::language(),
+        language_ids: &["javascript"],
+        extensions: &["js", "mjs", "cjs"],
+        support: &Javascript,
+    }
+}
+
+/// The JavaScript grammar parses JSX too, so this only gives it a `languageId` of its own
+pub fn jsx() -> LanguageConfig {
+    LanguageConfig {
+        name: "jsx",
+        language_ids: &["javascriptreact"],
+        extensions: &["jsx"],
+        ..javascript()
+    }
+}
+
+pub fn typescript() -> LanguageConfig {
+    LanguageConfig {
+        name: "typescript",
+        language: tree_sitter_typescript::language_typescript(),
+        language_ids: &["typescript"],
+        extensions: &["ts", "mts", "cts"],
+        support: &Typescript,
+    }
+}
+
+pub fn tsx() -> LanguageConfig {
+    LanguageConfig {
+        name: "tsx",
+        language: tree_sitter_typescript::language_tsx(),
+        language_ids: &["typescriptreact"],
+        extensions: &["tsx"],
+        ..typescript()
+    }
+}
+
+struct Javascript;
+
+impl LanguageSupport for Javascript {
+    fn symbol_query(&self) -> &'static str {
+        ECMA_SYMBOLS_QUERY
+    }
+
+    fn completion_query(&self) -> &'static str {
+        ECMA_COMPLETION_QUERY
+    }
+
+    fn fold_query(&self) -> &'static str {
+        ECMA_FOLD_QUERY
+    }
+}
+
+/// Also used for TSX, which only adds JSX to the grammar
+struct Typescript;
+
+impl LanguageSupport for Typescript {
+    fn symbol_query(&self) -> &'static str {
+        TYPESCRIPT_SYMBOLS_QUERY
+    }
+
+    fn completion_query(&self) -> &'static str {
+        TYPESCRIPT_COMPLETION_QUERY
+    }
+
+    fn fold_query(&self) -> &'static str {
+        TYPESCRIPT_FOLD_QUERY
+    }
+}
+
+const ECMA_SYMBOLS_QUERY: &str = indoc::indoc! {r"
+  (function_declaration
+    name: (identifier) @identifier) @function_declaration
+  (lexical_declaration
+    (variable_declarator
+      name: (identifier) @identifier)) @lexical_declaration
+  (variable_declaration
+    (variable_declarator
+      name: (identifier) @identifier)) @variable_declaration
+  (class_declaration
+    name: (identifier) @identifier) @class_declaration
+  (method_definition
+    name: (property_identifier) @identifier) @method_definition
+  (field_definition
+    property: (property_identifier) @identifier) @field_definition
+"};
+
+const ECMA_COMPLETION_QUERY: &str = indoc::indoc! {r"
+  (function_declaration
+    name: (identifier) @function)
+  (class_declaration
+    name: (identifier) @class)
+  (method_definition
+    name: (property_identifier) @method)
+  (variable_declarator
+    name: (identifier) @variable)
+  (formal_parameters
+    (identifier) @variable)
+"};
+
+const ECMA_FOLD_QUERY: &str = indoc::indoc! {r"
+  [
+    (statement_block)
+    (class_body)
+    (object)
+    (switch_body)
+    (array)
+  ] @fold
+  (comment) @fold.comment
+"};
+
+const TYPESCRIPT_SYMBOLS_QUERY: &str = indoc::indoc! {r"
+  (function_declaration
+    name: (identifier) @identifier) @function_declaration
+  (lexical_declaration
+    (variable_declarator
+      name: (identifier) @identifier)) @lexical_declaration
+  (variable_declaration
+    (variable_declarator
+      name: (identifier) @identifier)) @variable_declaration
+  (class_declaration
+    name: (type_identifier) @identifier) @class_declaration
+  (abstract_class_declaration
+    name: (type_identifier) @identifier) @abstract_class_declaration
+  (interface_declaration
+    name: (type_identifier) @identifier) @interface_declaration
+  (enum_declaration
+    name: (identifier) @identifier) @enum_declaration
+  (method_definition
+    name: (property_identifier) @identifier) @method_definition
+  (public_field_definition
+    name: (property_identifier) @identifier) @public_field_definition
+"};
+
+const TYPESCRIPT_COMPLETION_QUERY: &str = indoc::indoc! {r"
+  (function_declaration
+    name: (identifier) @function)
+  (class_declaration
+    name: (type_identifier) @class)
+  (interface_declaration
+    name: (type_identifier) @interface)
+  (enum_declaration
+    name: (identifier) @enum)
+  (method_definition
+    name: (property_identifier) @method)
+  (variable_declarator
+    name: (identifier) @variable)
+  (required_parameter
+    (identifier) @variable)
+  (optional_parameter
+    (identifier) @variable)
+"};
+
+const TYPESCRIPT_FOLD_QUERY: &str = indoc::indoc! {r"
+  [
+    (statement_block)
+    (class_body)
+    (object)
+    (object_type)
+    (enum_body)
+    (switch_body)
+    (array)
+  ] @fold
+  (comment) @fold.comment
+"};
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::Url;
+
+    use super::{javascript, jsx, tsx, typescript, LanguageConfig, LanguageRegistry};
+
+    #[test]
+    fn select_language() {
+        let other = LanguageConfig {
+            name: "other",
+            language_ids: &["other"],
+            extensions: &["oth"],
+            ..javascript()
+        };
+        let mut registry = LanguageRegistry::builtin();
+        registry.register(other);
+
+        let js: Url = "file:///a.js".parse().unwrap();
+        let oth: Url = "file:///a.oth".parse().unwrap();
+        assert_eq!("other", registry.for_document("other", &js).name);
+        assert_eq!("other", registry.for_document("plaintext", &oth).name);
+        assert_eq!("javascript", registry.for_document("plaintext", &js).name);
+        let no_extension: Url = "file:///a".parse().unwrap();
+        assert_eq!(
+            "javascript",
+            registry.for_document("plaintext", &no_extension).name
+        );
+    }
+
+    #[test]
+    fn language_ids_by_extension() {
+        let registry = LanguageRegistry::builtin();
+        assert_eq!(Some("javascript"), registry.language_id("mjs"));
+        assert_eq!(Some("javascriptreact"), registry.language_id("jsx"));
+        assert_eq!(Some("typescriptreact"), registry.language_id("tsx"));
+        assert_eq!(None, registry.language_id("rs"));
+        assert_eq!(Some("ts"), registry.extension("typescript"));
+        assert_eq!(Some("jsx"), registry.extension("javascriptreact"));
+        assert_eq!(None, registry.extension("rust"));
+    }
+
+    #[test]
+    fn builtin_queries_are_valid() {
+        for language in [javascript(), jsx(), typescript(), tsx()] {
+            let support = language.support;
+            for query in [
+                support.symbol_query(),
+                support.completion_query(),
+                support.fold_query(),
+            ] {
+                if let Err(e) = tree_sitter::Query::new(language.language, query) {
+                    panic!("invalid query for {}: {e:?}", language.name);
+                }
+            }
+        }
+    }
+}
@@ -1,6 +1,8 @@
 pub mod document;
 pub mod error;
+pub mod language;
 pub mod parser;
+pub mod rope;
 pub mod session;
 pub mod syntax;
 pub mod text;
@@ -0,0 +1,70 @@
+//! Feeds rope contents to tree-sitter chunk by chunk, so the parser and queries never need the
+//! document copied into a single string
+
+use ropey::RopeSlice;
+use tree_sitter::{Node, Parser, TextProvider, Tree};
+
+/// Parses the text, reusing the parts of `old_tree` that weren't edited
+pub fn parse(parser: &mut Parser, text: RopeSlice, old_tree: Option<&Tree>) -> Option<Tree> {
+    parser.parse_with(
+        &mut |byte, _| {
+            if byte <= text.len_bytes() {
+                let (chunk, start_byte, _, _) = text.chunk_at_byte(byte);
+                &chunk.as_bytes()[byte - start_byte..]
+            } else {
+                // out of range
+                &[]
+            }
+        },
+        old_tree,
+    )
+}
+
+pub struct ChunksBytes<'a> {
+    chunks: ropey::iter::Chunks<'a>,
+}
+impl<'a> Iterator for ChunksBytes<'a> {
+    type Item = &'a [u8];
+    fn next(&mut self) -> Option<Self::Item> {
+        self.chunks.next().map(str::as_bytes)
+    }
+}
+
+/// Gives a `QueryCursor` the text of each node for checking predicates like `#eq?`
+pub struct RopeProvider<'a>(pub RopeSlice<'a>);
+impl<'a> TextProvider<'a> for RopeProvider<'a> {
+    type I = ChunksBytes<'a>;
+
+    fn text(&mut self, node: Node) -> Self::I {
+        let fragment = self.0.byte_slice(node.start_byte()..node.end_byte());
+        ChunksBytes {
+            chunks: fragment.chunks(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ropey::Rope;
+    use tree_sitter::{Query, QueryCursor};
+
+    use super::{parse, RopeProvider};
+
+    #[test]
+    fn parse_and_query_across_chunks() {
+        let source = "let apple = 1;\nlet banana = apple;\n".repeat(500);
+        let rope = Rope::from(source.as_str());
+        assert!(rope.chunks().count() > 1);
+
+        let language = tree_sitter_javascript::language();
+        let mut parser = crate::core::parser::new(language).unwrap();
+        let tree = parse(&mut parser, rope.slice(..), None).unwrap();
+        let expected = parser.parse(&source, None).unwrap();
+        assert_eq!(expected.root_node().to_sexp(), tree.root_node().to_sexp());
+
+        let query = Query::new(language, r#"((identifier) @id (#eq? @id "apple"))"#).unwrap();
+        let mut cursor = QueryCursor::new();
+        let matches = cursor.matches(&query, tree.root_node(), RopeProvider(rope.slice(..)));
+        assert_eq!(1000, matches.count());
+    }
+}
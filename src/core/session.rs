@@ -1,145 +1,248 @@
 use dashmap::{
-    mapref::one::{Ref, RefMut},
+    mapref::{
+        entry::Entry,
+        one::{Ref, RefMut},
+    },
     DashMap,
 };
+use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
 use tokio::sync::{Mutex, RwLock};
 use tower_lsp::{lsp_types::*, ServerToClient};
+use tree_sitter::Query;
 
-use super::{document::Document, error, text::Text};
+use super::{
+    document::Document,
+    error,
+    language::{LanguageConfig, LanguageRegistry},
+    text::{PositionEncoding, Text},
+};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum SessionResourceKind {
     Document,
+    Language,
     Parser,
     Tree,
 }
 
+/// Everything the session stores for an open document. The parser and tree have their own locks
+/// so they can be used while other parts of the document are borrowed.
+pub struct DocumentState {
+    pub language: LanguageConfig,
+    pub text: Text,
+    pub parser: Mutex<tree_sitter::Parser>,
+    pub tree: Mutex<tree_sitter::Tree>,
+}
+
+impl From<Document> for DocumentState {
+    fn from(document: Document) -> Self {
+        Self {
+            language: document.language,
+            text: document.text(),
+            parser: Mutex::new(document.parser),
+            tree: Mutex::new(document.tree),
+        }
+    }
+}
+
+/// Part of a document's state borrowed from the session. Unlike dashmap's mapped refs, this can
+/// be held across an await.
+pub struct DocumentRef<'a, T> {
+    state: Ref<'a, Url, DocumentState>,
+    part: fn(&DocumentState) -> &T,
+}
+
+impl<T> Deref for DocumentRef<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        (self.part)(&self.state)
+    }
+}
+
+/// Part of a document's state borrowed mutably from the session
+pub struct DocumentRefMut<'a, T> {
+    state: RefMut<'a, Url, DocumentState>,
+    part: fn(&DocumentState) -> &T,
+    part_mut: fn(&mut DocumentState) -> &mut T,
+}
+
+impl<T> Deref for DocumentRefMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        (self.part)(&self.state)
+    }
+}
+
+impl<T> DerefMut for DocumentRefMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        (self.part_mut)(&mut self.state)
+    }
+}
+
 pub struct Session {
     pub server_capabilities: RwLock<ServerCapabilities>,
     pub client_capabilities: RwLock<Option<ClientCapabilities>>,
     client: Option<tower_lsp::Client<ServerToClient>>,
-    pub language: tree_sitter::Language,
-    // pub document_states: DashMap<Url, DocumentState>,
-    document_texts: DashMap<Url, Text>,
-    document_parsers: DashMap<Url, Mutex<tree_sitter::Parser>>,
-    document_trees: DashMap<Url, Mutex<tree_sitter::Tree>>,
+    pub languages: LanguageRegistry,
+    document_states: DashMap<Url, DocumentState>,
+    /// Compiled queries by the name of the language and the query's source
+    queries: DashMap<(&'static str, &'static str), Arc<Query>>,
 }
 
 impl Session {
     pub fn new(
         client: Option<tower_lsp::Client<ServerToClient>>,
-        language: tree_sitter::Language,
+        languages: LanguageRegistry,
     ) -> Arc<Self> {
         let server_capabilities = RwLock::new(crate::server::capabilities());
         let client_capabilities = Default::default();
-        // let document_states = Default::default();
-        let document_texts = Default::default();
-        let document_parsers = Default::default();
-        let document_trees = Default::default();
+        let document_states = Default::default();
+        let queries = Default::default();
         Arc::new(Session {
             server_capabilities,
             client_capabilities,
             client,
-            language,
-            // document_states,
-            document_texts,
-            document_parsers,
-            document_trees,
+            languages,
+            document_states,
+            queries,
         })
     }
 
+    /// The encoding for the columns of positions exchanged with the client
+    pub async fn position_encoding(&self) -> PositionEncoding {
+        let capabilities = self.server_capabilities.read().await;
+        capabilities
+            .position_encoding
+            .as_ref()
+            .and_then(PositionEncoding::from_kind)
+            .unwrap_or_default()
+    }
+
     pub fn client(&self) -> anyhow::Result<&tower_lsp::Client<ServerToClient>> {
         self.client
             .as_ref()
             .ok_or_else(|| super::error::Error::ClientNotInitialized.into())
     }
 
+    /// Stores a newly opened document, failing if it's already open
     pub fn insert_document(&self, uri: Url, document: Document) -> anyhow::Result<()> {
-        let result = self.document_texts.insert(uri.clone(), document.text());
-        debug_assert!(result.is_none());
-        let result = self
-            .document_parsers
-            .insert(uri.clone(), Mutex::new(document.parser));
-        debug_assert!(result.is_none());
-        let result = self.document_trees.insert(uri, Mutex::new(document.tree));
-        debug_assert!(result.is_none());
-        Ok(())
+        match self.document_states.entry(uri) {
+            Entry::Occupied(entry) => {
+                let kind = SessionResourceKind::Document;
+                let uri = entry.key().clone();
+                Err(error::Error::SessionResourceExists { kind, uri }.into())
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(document.into());
+                Ok(())
+            }
+        }
     }
 
     pub fn remove_document(&self, uri: &Url) -> anyhow::Result<()> {
-        let result = self.document_texts.remove(uri);
-        debug_assert!(result.is_some());
-        let result = self.document_parsers.remove(uri);
-        debug_assert!(result.is_some());
-        let result = self.document_trees.remove(uri);
-        debug_assert!(result.is_some());
-        Ok(())
-    }
-
-    // pub async fn semantic_tokens_legend(&self) -> Option<SemanticTokensLegend> {
-    //     let capabilities = self.server_capabilities.read().await;
-    //     if let Some(capabilities) = &capabilities.semantic_tokens_provider {
-    //         match capabilities {
-    //             SemanticTokensServerCapabilities::SemanticTokensOptions(options) => {
-    //                 Some(options.legend.clone())
-    //             }
-    //             SemanticTokensServerCapabilities::SemanticTokensRegistrationOptions(options) => {
-    //                 Some(options.semantic_tokens_options.legend.clone())
-    //             }
-    //         }
-    //     } else {
-    //         None
-    //     }
-    // }
+        self.document_states
+            .remove(uri)
+            .map(|_| ())
+            .ok_or_else(|| self.not_found(SessionResourceKind::Document, uri))
+    }
 
-    pub async fn get_text(&self, uri: &Url) -> anyhow::Result<Ref<'_, Url, Text>> {
-        self.document_texts.get(uri).ok_or_else(|| {
-            let kind = SessionResourceKind::Document;
-            let uri = uri.clone();
-            error::Error::SessionResourceNotFound { kind, uri }.into()
-        })
+    /// Gets everything stored for a document with a single lookup
+    pub async fn get_document(&self, uri: &Url) -> anyhow::Result<Ref<'_, Url, DocumentState>> {
+        self.document_states
+            .get(uri)
+            .ok_or_else(|| self.not_found(SessionResourceKind::Document, uri))
     }
 
-    pub async fn get_mut_text(&self, uri: &Url) -> anyhow::Result<RefMut<'_, Url, Text>> {
-        self.document_texts.get_mut(uri).ok_or_else(|| {
-            let kind = SessionResourceKind::Document;
-            let uri = uri.clone();
-            error::Error::SessionResourceNotFound { kind, uri }.into()
-        })
+    /// The legend advertised to the client, which the token types and modifiers sent to it are
+    /// indices into
+    pub async fn semantic_tokens_legend(&self) -> Option<SemanticTokensLegend> {
+        let capabilities = self.server_capabilities.read().await;
+        match capabilities.semantic_tokens_provider.as_ref()? {
+            SemanticTokensServerCapabilities::SemanticTokensOptions(options) => {
+                Some(options.legend.clone())
+            }
+            SemanticTokensServerCapabilities::SemanticTokensRegistrationOptions(options) => {
+                Some(options.semantic_tokens_options.legend.clone())
+            }
+        }
     }
 
-    pub async fn get_mut_parser(
+    /// Compiles the query for the language the first time it's asked for and reuses it after
+    /// that, since compiling takes longer than running it on most documents
+    pub fn get_query(
         &self,
-        uri: &Url,
-    ) -> anyhow::Result<RefMut<'_, Url, Mutex<tree_sitter::Parser>>> {
-        self.document_parsers.get_mut(uri).ok_or_else(|| {
-            let kind = SessionResourceKind::Parser;
-            let uri = uri.clone();
-            error::Error::SessionResourceNotFound { kind, uri }.into()
-        })
+        language: &LanguageConfig,
+        source: &'static str,
+    ) -> anyhow::Result<Arc<Query>> {
+        let key = (language.name, source);
+        if let Some(query) = self.queries.get(&key) {
+            return Ok(query.clone());
+        }
+        let query = Arc::new(Query::new(language.language, source)?);
+        self.queries.insert(key, query.clone());
+        Ok(query)
     }
 
-    pub async fn get_tree(
+    pub async fn get_language(&self, uri: &Url) -> anyhow::Result<LanguageConfig> {
+        self.document_states
+            .get(uri)
+            .map(|state| state.language)
+            .ok_or_else(|| self.not_found(SessionResourceKind::Language, uri))
+    }
+
+    pub async fn get_text(&self, uri: &Url) -> anyhow::Result<DocumentRef<'_, Text>> {
+        self.document_states
+            .get(uri)
+            .map(|state| DocumentRef {
+                state,
+                part: |state| &state.text,
+            })
+            .ok_or_else(|| self.not_found(SessionResourceKind::Document, uri))
+    }
+
+    pub async fn get_mut_text(&self, uri: &Url) -> anyhow::Result<DocumentRefMut<'_, Text>> {
+        self.document_states
+            .get_mut(uri)
+            .map(|state| DocumentRefMut {
+                state,
+                part: |state| &state.text,
+                part_mut: |state| &mut state.text,
+            })
+            .ok_or_else(|| self.not_found(SessionResourceKind::Document, uri))
+    }
+
+    pub async fn get_parser(
         &self,
         uri: &Url,
-    ) -> anyhow::Result<Ref<'_, Url, Mutex<tree_sitter::Tree>>> {
-        self.document_trees.get(uri).ok_or_else(|| {
-            let kind = SessionResourceKind::Tree;
-            let uri = uri.clone();
-            error::Error::SessionResourceNotFound { kind, uri }.into()
-        })
+    ) -> anyhow::Result<DocumentRef<'_, Mutex<tree_sitter::Parser>>> {
+        self.document_states
+            .get(uri)
+            .map(|state| DocumentRef {
+                state,
+                part: |state| &state.parser,
+            })
+            .ok_or_else(|| self.not_found(SessionResourceKind::Parser, uri))
     }
 
-    pub async fn get_mut_tree(
+    pub async fn get_tree(
         &self,
         uri: &Url,
-    ) -> anyhow::Result<RefMut<'_, Url, Mutex<tree_sitter::Tree>>> {
-        self.document_trees.get_mut(uri).ok_or_else(|| {
-            let kind = SessionResourceKind::Tree;
-            let uri = uri.clone();
-            error::Error::SessionResourceNotFound { kind, uri }.into()
-        })
+    ) -> anyhow::Result<DocumentRef<'_, Mutex<tree_sitter::Tree>>> {
+        self.document_states
+            .get(uri)
+            .map(|state| DocumentRef {
+                state,
+                part: |state| &state.tree,
+            })
+            .ok_or_else(|| self.not_found(SessionResourceKind::Tree, uri))
+    }
+
+    fn not_found(&self, kind: SessionResourceKind, uri: &Url) -> anyhow::Error {
+        let uri = uri.clone();
+        error::Error::SessionResourceNotFound { kind, uri }.into()
     }
 
     // pub fn get_channel_syntax() -> anyhow::Result<web_sys::HtmlTextAreaElement> {
@@ -155,3 +258,90 @@ impl Session {
     //     Ok(channel_syntax)
     // }
 }
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::*;
+
+    use super::{Session, SessionResourceKind};
+    use crate::core::document::Document;
+    use crate::core::error::Error;
+    use crate::core::language::{javascript, typescript, LanguageRegistry};
+
+    async fn open(session: &std::sync::Arc<Session>, uri: &Url) -> Document {
+        let params = DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(
+                uri.clone(),
+                "javascript".to_owned(),
+                0,
+                "let a = 1;".to_owned(),
+            ),
+        };
+        Document::open(session.clone(), params).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn remove_missing_document() {
+        let session = Session::new(None, LanguageRegistry::builtin());
+        let uri: Url = "file:///test.js".parse().unwrap();
+
+        let error = session.remove_document(&uri).unwrap_err();
+        assert_eq!(
+            Some(&Error::SessionResourceNotFound {
+                kind: SessionResourceKind::Document,
+                uri
+            }),
+            error.downcast_ref()
+        );
+    }
+
+    #[tokio::test]
+    async fn insert_existing_document() {
+        let session = Session::new(None, LanguageRegistry::builtin());
+        let uri: Url = "file:///test.js".parse().unwrap();
+        session
+            .insert_document(uri.clone(), open(&session, &uri).await)
+            .unwrap();
+
+        let error = session
+            .insert_document(uri.clone(), open(&session, &uri).await)
+            .unwrap_err();
+        assert_eq!(
+            Some(&Error::SessionResourceExists {
+                kind: SessionResourceKind::Document,
+                uri: uri.clone()
+            }),
+            error.downcast_ref()
+        );
+        let document = session.get_document(&uri).await.unwrap();
+        assert_eq!("let a = 1;", document.text.content.to_string());
+        drop(document);
+
+        session.remove_document(&uri).unwrap();
+        assert!(session.get_document(&uri).await.is_err());
+        assert!(session.get_tree(&uri).await.is_err());
+    }
+
+    #[test]
+    fn queries_are_compiled_once() {
+        let session = Session::new(None, LanguageRegistry::builtin());
+        let (javascript, typescript) = (javascript(), typescript());
+
+        let symbols = session
+            .get_query(&javascript, javascript.support.symbol_query())
+            .unwrap();
+        let again = session
+            .get_query(&javascript, javascript.support.symbol_query())
+            .unwrap();
+        assert!(std::sync::Arc::ptr_eq(&symbols, &again));
+
+        let folds = session
+            .get_query(&javascript, javascript.support.fold_query())
+            .unwrap();
+        assert!(!std::sync::Arc::ptr_eq(&symbols, &folds));
+        let typescript_symbols = session
+            .get_query(&typescript, typescript.support.symbol_query())
+            .unwrap();
+        assert!(!std::sync::Arc::ptr_eq(&symbols, &typescript_symbols));
+    }
+}
@@ -1,3 +1,205 @@
+use tower_lsp::lsp_types::*;
+
+use super::text::PositionEncoding;
+
+/// Collects a diagnostic for every error or missing node in the tree
+pub(crate) fn diagnostics(
+    content: &ropey::Rope,
+    tree: &tree_sitter::Tree,
+    encoding: PositionEncoding,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let mut nodes = vec![tree.root_node()];
+    while let Some(node) = nodes.pop() {
+        if node.is_error() || node.is_missing() {
+            let message = if node.is_missing() {
+                format!("Missing {}", node.kind())
+            } else {
+                "Syntax error".to_owned()
+            };
+            diagnostics.push(Diagnostic {
+                range: encoding.range(content, node.range()),
+                severity: Some(DiagnosticSeverity::ERROR),
+                source: Some("lsp-tui".to_owned()),
+                message,
+                ..Default::default()
+            });
+        } else if node.has_error() {
+            let mut cursor = node.walk();
+            nodes.extend(node.children(&mut cursor));
+        }
+    }
+    diagnostics.sort_by_key(|d| (d.range.start.line, d.range.start.character));
+    diagnostics
+}
+
+/// The token types nodes are classified as, in the order they're listed in the legend
+const SEMANTIC_TOKEN_TYPES: &[SemanticTokenType] = &[
+    SemanticTokenType::KEYWORD,
+    SemanticTokenType::COMMENT,
+    SemanticTokenType::STRING,
+    SemanticTokenType::NUMBER,
+    SemanticTokenType::REGEXP,
+    SemanticTokenType::FUNCTION,
+    SemanticTokenType::METHOD,
+    SemanticTokenType::CLASS,
+    SemanticTokenType::TYPE,
+    SemanticTokenType::PARAMETER,
+    SemanticTokenType::VARIABLE,
+    SemanticTokenType::PROPERTY,
+];
+
+const SEMANTIC_TOKEN_MODIFIERS: &[SemanticTokenModifier] = &[SemanticTokenModifier::DECLARATION];
+
+pub fn semantic_tokens_legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: SEMANTIC_TOKEN_TYPES.to_vec(),
+        token_modifiers: SEMANTIC_TOKEN_MODIFIERS.to_vec(),
+    }
+}
+
+/// Classifies the nodes of the tree as the token types in the legend. Tokens spanning several
+/// lines, like block comments, are split into one token per line.
+pub(crate) fn semantic_tokens(
+    content: &ropey::Rope,
+    tree: &tree_sitter::Tree,
+    encoding: PositionEncoding,
+    legend: &SemanticTokensLegend,
+) -> Vec<SemanticToken> {
+    let declaration_bit = legend
+        .token_modifiers
+        .iter()
+        .position(|modifier| *modifier == SemanticTokenModifier::DECLARATION);
+    let mut tokens = vec![];
+    let (mut previous_line, mut previous_start) = (0, 0);
+    // Children are pushed in reverse so nodes are visited in the order they appear
+    let mut nodes = vec![tree.root_node()];
+    while let Some(node) = nodes.pop() {
+        let Some((token_type, declaration)) = token_type(node) else {
+            let mut cursor = node.walk();
+            let children: Vec<_> = node.children(&mut cursor).collect();
+            nodes.extend(children.into_iter().rev());
+            continue;
+        };
+        let Some(token_type) = legend.token_types.iter().position(|t| *t == token_type) else {
+            continue;
+        };
+        let token_modifiers_bitset = match declaration_bit {
+            Some(bit) if declaration => 1 << bit,
+            _ => 0,
+        };
+        let range = encoding.range(content, node.range());
+        for line in range.start.line..=range.end.line {
+            let start = if line == range.start.line {
+                range.start.character
+            } else {
+                0
+            };
+            let end = if line == range.end.line {
+                range.end.character
+            } else {
+                // The position of the newline ending the line
+                let next_line = content.line_to_char(line as usize + 1);
+                encoding.char_to_position(content, next_line - 1).character
+            };
+            if end <= start {
+                continue;
+            }
+            tokens.push(SemanticToken {
+                delta_line: line - previous_line,
+                delta_start: if line == previous_line {
+                    start - previous_start
+                } else {
+                    start
+                },
+                length: end - start,
+                token_type: token_type as u32,
+                token_modifiers_bitset,
+            });
+            (previous_line, previous_start) = (line, start);
+        }
+    }
+    tokens
+}
+
+/// What the node is highlighted as and whether it declares the name, or `None` if it isn't a
+/// token itself and its children should be classified instead
+fn token_type(node: tree_sitter::Node) -> Option<(SemanticTokenType, bool)> {
+    let parent = node.parent();
+    let parent_kind = parent.map(|parent| parent.kind()).unwrap_or_default();
+    let is_field =
+        |field: &str| parent.and_then(|parent| parent.child_by_field_name(field)) == Some(node);
+    let token_type = match node.kind() {
+        "comment" => SemanticTokenType::COMMENT,
+        "string" | "template_string" => SemanticTokenType::STRING,
+        "number" => SemanticTokenType::NUMBER,
+        "regex" => SemanticTokenType::REGEXP,
+        "true" | "false" | "null" | "undefined" | "this" | "super" => SemanticTokenType::KEYWORD,
+        "type_identifier" | "predefined_type" => match parent_kind {
+            "class_declaration" | "abstract_class_declaration" | "class" if is_field("name") => {
+                return Some((SemanticTokenType::CLASS, true));
+            }
+            "interface_declaration" | "type_alias_declaration" if is_field("name") => {
+                return Some((SemanticTokenType::TYPE, true));
+            }
+            _ => SemanticTokenType::TYPE,
+        },
+        "property_identifier" => match parent_kind {
+            "method_definition" => return Some((SemanticTokenType::METHOD, true)),
+            "member_expression"
+                if parent
+                    .and_then(|parent| parent.parent())
+                    .filter(|grandparent| grandparent.kind() == "call_expression")
+                    .and_then(|call| call.child_by_field_name("function"))
+                    == parent =>
+            {
+                SemanticTokenType::METHOD
+            }
+            _ => SemanticTokenType::PROPERTY,
+        },
+        "shorthand_property_identifier" => SemanticTokenType::VARIABLE,
+        "identifier" => match parent_kind {
+            "function_declaration" | "generator_function_declaration" | "function"
+                if is_field("name") =>
+            {
+                return Some((SemanticTokenType::FUNCTION, true));
+            }
+            "class_declaration" | "class" if is_field("name") => {
+                return Some((SemanticTokenType::CLASS, true));
+            }
+            "formal_parameters" | "required_parameter" | "optional_parameter" => {
+                return Some((SemanticTokenType::PARAMETER, true));
+            }
+            "arrow_function" if is_field("parameter") => {
+                return Some((SemanticTokenType::PARAMETER, true));
+            }
+            "variable_declarator" if is_field("name") => {
+                let is_function = parent
+                    .and_then(|parent| parent.child_by_field_name("value"))
+                    .is_some_and(|value| ["function", "arrow_function"].contains(&value.kind()));
+                let token_type = if is_function {
+                    SemanticTokenType::FUNCTION
+                } else {
+                    SemanticTokenType::VARIABLE
+                };
+                return Some((token_type, true));
+            }
+            "call_expression" if is_field("function") => SemanticTokenType::FUNCTION,
+            "new_expression" if is_field("constructor") => SemanticTokenType::CLASS,
+            _ => SemanticTokenType::VARIABLE,
+        },
+        // Anonymous nodes spelled with letters are keywords, like `const` or `return`
+        kind if !node.is_named()
+            && !kind.is_empty()
+            && kind.chars().all(|ch| ch.is_ascii_lowercase()) =>
+        {
+            SemanticTokenType::KEYWORD
+        }
+        _ => return None,
+    };
+    Some((token_type, false))
+}
+
 // use super::session::Session;
 
 // pub(crate) fn update_channel(tree: Option<&tree_sitter::Tree>) {
@@ -1,15 +1,187 @@
+use anyhow::Context;
+use tower_lsp::lsp_types::{Position, PositionEncodingKind, Range};
+
 use super::document::Document;
 
+/// How the columns of LSP positions are counted, as agreed on with the client in `initialize`
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum PositionEncoding {
+    Utf8,
+    /// Every client supports UTF-16, so it's used unless something else was negotiated
+    #[default]
+    Utf16,
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// Picks the first of the client's encodings that's supported, in the client's order
+    /// of preference
+    pub fn negotiate(client_encodings: Option<&[PositionEncodingKind]>) -> Self {
+        client_encodings
+            .into_iter()
+            .flatten()
+            .find_map(Self::from_kind)
+            .unwrap_or_default()
+    }
+
+    pub fn from_kind(kind: &PositionEncodingKind) -> Option<Self> {
+        if *kind == PositionEncodingKind::UTF8 {
+            Some(Self::Utf8)
+        } else if *kind == PositionEncodingKind::UTF16 {
+            Some(Self::Utf16)
+        } else if *kind == PositionEncodingKind::UTF32 {
+            Some(Self::Utf32)
+        } else {
+            None
+        }
+    }
+
+    pub fn kind(self) -> PositionEncodingKind {
+        match self {
+            Self::Utf8 => PositionEncodingKind::UTF8,
+            Self::Utf16 => PositionEncodingKind::UTF16,
+            Self::Utf32 => PositionEncodingKind::UTF32,
+        }
+    }
+
+    /// The number of code units the character takes up
+    fn len(self, ch: char) -> usize {
+        match self {
+            Self::Utf8 => ch.len_utf8(),
+            Self::Utf16 => ch.len_utf16(),
+            Self::Utf32 => 1,
+        }
+    }
+
+    /// Converts a position into a char index, clamping the column to the end of the line
+    pub fn position_to_char(self, content: &ropey::Rope, position: Position) -> Option<usize> {
+        let line_idx = position.line as usize;
+        if line_idx >= content.len_lines() {
+            return None;
+        }
+        let line = content.line(line_idx);
+        let mut units = 0;
+        let mut column = 0;
+        for ch in line.chars() {
+            if units >= position.character as usize || ch == '\n' || ch == '\r' {
+                break;
+            }
+            units += self.len(ch);
+            column += 1;
+        }
+        Some(content.line_to_char(line_idx) + column)
+    }
+
+    pub fn char_to_position(self, content: &ropey::Rope, char_idx: usize) -> Position {
+        let line_idx = content.char_to_line(char_idx);
+        let line_start = content.line_to_char(line_idx);
+        let character: usize = content
+            .slice(line_start..char_idx)
+            .chars()
+            .map(|ch| self.len(ch))
+            .sum();
+        Position::new(line_idx as u32, character as u32)
+    }
+
+    pub fn byte_to_position(self, content: &ropey::Rope, byte_idx: usize) -> Position {
+        self.char_to_position(content, content.byte_to_char(byte_idx))
+    }
+
+    pub fn range(self, content: &ropey::Rope, range: tree_sitter::Range) -> Range {
+        Range::new(
+            self.byte_to_position(content, range.start_byte),
+            self.byte_to_position(content, range.end_byte),
+        )
+    }
+}
+
 pub struct Text {
     pub content: ropey::Rope,
 }
 
+/// Lines are always stored with `\n` endings so they're counted the same way as the client's
+pub(crate) fn normalize_newlines(text: &str) -> String {
+    text.replace("\r\n", "\n")
+}
+
 impl Text {
     pub fn new(text: impl AsRef<str>) -> anyhow::Result<Self> {
-        let text = text.as_ref();
-        let content = ropey::Rope::from_str(text);
+        let text = normalize_newlines(text.as_ref());
+        let content = ropey::Rope::from_str(&text);
         Ok(Text { content })
     }
+
+    /// Converts an LSP position into a char index, clamping the column to the end of the line
+    pub fn position_to_char(
+        &self,
+        position: Position,
+        encoding: PositionEncoding,
+    ) -> Option<usize> {
+        encoding.position_to_char(&self.content, position)
+    }
+
+    /// Replaces the text in `range` and returns the edit that brings a tree for the old text
+    /// up to date
+    pub fn apply_change(
+        &mut self,
+        range: Range,
+        new_text: &str,
+        encoding: PositionEncoding,
+    ) -> anyhow::Result<tree_sitter::InputEdit> {
+        let start_char = self
+            .position_to_char(range.start, encoding)
+            .with_context(|| format!("change starts past the end: {:?}", range.start))?;
+        let end_char = self
+            .position_to_char(range.end, encoding)
+            .with_context(|| format!("change ends past the end: {:?}", range.end))?;
+        anyhow::ensure!(
+            start_char <= end_char,
+            "change range is reversed: {range:?}"
+        );
+
+        let start_byte = self.content.char_to_byte(start_char);
+        let old_end_byte = self.content.char_to_byte(end_char);
+        let start_position = self.point(start_byte);
+        let old_end_position = self.point(old_end_byte);
+
+        let new_text = normalize_newlines(new_text);
+        self.content.remove(start_char..end_char);
+        self.content.insert(start_char, &new_text);
+
+        let new_end_byte = start_byte + new_text.len();
+        Ok(tree_sitter::InputEdit {
+            start_byte,
+            old_end_byte,
+            new_end_byte,
+            start_position,
+            old_end_position,
+            new_end_position: self.point(new_end_byte),
+        })
+    }
+
+    /// The tree-sitter point for a byte index, whose column is counted in bytes
+    fn point(&self, byte_idx: usize) -> tree_sitter::Point {
+        let row = self.content.byte_to_line(byte_idx);
+        let column = byte_idx - self.content.line_to_byte(row);
+        tree_sitter::Point::new(row, column)
+    }
+
+    /// The identifier characters directly before the position
+    pub fn word_before(&self, position: Position, encoding: PositionEncoding) -> String {
+        let Some(end) = self.position_to_char(position, encoding) else {
+            return String::new();
+        };
+        let start = self.content.line_to_char(position.line as usize);
+        let mut word: Vec<_> = self
+            .content
+            .slice(start..end)
+            .chars()
+            .rev()
+            .take_while(|c| c.is_alphanumeric() || *c == '_' || *c == '$')
+            .collect();
+        word.reverse();
+        word.into_iter().collect()
+    }
 }
 
 impl From<Document> for Text {
@@ -17,3 +189,83 @@ impl From<Document> for Text {
         value.text()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::{Position, PositionEncodingKind, Range};
+
+    use super::{PositionEncoding, Text};
+
+    // 'é' is 2 bytes and 1 UTF-16 unit, '😀' is 4 bytes and 2 UTF-16 units
+    const LINE: &str = "aé😀b\n";
+
+    #[test]
+    fn negotiate_follows_client_preference() {
+        let negotiate = |kinds: &[PositionEncodingKind]| PositionEncoding::negotiate(Some(kinds));
+        assert_eq!(
+            negotiate(&[PositionEncodingKind::UTF8, PositionEncodingKind::UTF16]),
+            PositionEncoding::Utf8
+        );
+        assert_eq!(
+            negotiate(&[
+                PositionEncodingKind::new("utf-7"),
+                PositionEncodingKind::UTF32
+            ]),
+            PositionEncoding::Utf32
+        );
+        assert_eq!(negotiate(&[]), PositionEncoding::Utf16);
+        assert_eq!(PositionEncoding::negotiate(None), PositionEncoding::Utf16);
+    }
+
+    #[test]
+    fn multibyte_columns() {
+        let text = Text::new(LINE).unwrap();
+        // The column of 'b' in each encoding
+        for (encoding, column) in [
+            (PositionEncoding::Utf8, 7),
+            (PositionEncoding::Utf16, 4),
+            (PositionEncoding::Utf32, 3),
+        ] {
+            let position = Position::new(0, column);
+            assert_eq!(text.position_to_char(position, encoding), Some(3));
+            assert_eq!(encoding.char_to_position(&text.content, 3), position);
+            assert_eq!(encoding.byte_to_position(&text.content, 7), position);
+        }
+    }
+
+    #[test]
+    fn apply_change_with_multibyte_columns() {
+        // Replace the emoji with 'c' in each encoding
+        for (encoding, start, end) in [
+            (PositionEncoding::Utf8, 3, 7),
+            (PositionEncoding::Utf16, 2, 4),
+            (PositionEncoding::Utf32, 2, 3),
+        ] {
+            let mut text = Text::new(LINE).unwrap();
+            let range = Range::new(Position::new(0, start), Position::new(0, end));
+            let edit = text.apply_change(range, "c", encoding).unwrap();
+            assert_eq!(text.content.to_string(), "aécb\n");
+            assert_eq!(
+                (edit.start_byte, edit.old_end_byte, edit.new_end_byte),
+                (3, 7, 4)
+            );
+            assert_eq!(edit.new_end_position, tree_sitter::Point::new(0, 4));
+        }
+    }
+
+    #[test]
+    fn newlines_are_normalized() {
+        let mut text = Text::new("a\r\nb\r\n").unwrap();
+        assert_eq!("a\nb\n", text.content.to_string());
+
+        let position = Position::new(1, 1);
+        let range = Range::new(position, position);
+        let edit = text
+            .apply_change(range, "\r\nc", PositionEncoding::Utf16)
+            .unwrap();
+        assert_eq!("a\nb\nc\n", text.content.to_string());
+        assert_eq!(4, text.content.len_lines());
+        assert_eq!(edit.new_end_byte, edit.start_byte + 2);
+        assert_eq!(edit.new_end_position, tree_sitter::Point::new(2, 1));
+    }
+}
@@ -1,25 +1,30 @@
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
 use lsp_text::RopeExt;
 use tower_lsp::lsp_types::*;
-use tracing::warn;
-use tree_sitter::{Query, QueryCursor};
+use tree_sitter::QueryCursor;
 
-use crate::core::{document::Document, session::Session, text::Text};
+use crate::core::{
+    document::Document,
+    error::Error,
+    rope::RopeProvider,
+    session::Session,
+    text::{PositionEncoding, Text},
+};
 
 pub async fn did_open(
     session: Arc<Session>,
     params: DidOpenTextDocumentParams,
 ) -> anyhow::Result<()> {
     let uri = params.text_document.uri.clone();
+    let version = params.text_document.version;
 
-    if let Some(document) = Document::open(session.clone(), params).await? {
-        session.insert_document(uri.clone(), document)?;
-    } else {
-        warn!("'textDocument/didOpen' failed :: uri: {:#?}", uri);
-    }
-
-    Ok(())
+    let document = Document::open(session.clone(), params).await?;
+    session.insert_document(uri.clone(), document)?;
+    publish_syntax_diagnostics(session, uri, Some(version)).await
 }
 
 pub async fn did_change(
@@ -27,9 +32,56 @@ pub async fn did_change(
     params: DidChangeTextDocumentParams,
 ) -> anyhow::Result<()> {
     let uri = &params.text_document.uri;
-    let mut text = session.get_mut_text(uri).await?;
-    *text = Text::new(params.content_changes[0].text.clone())?;
-    Document::change(session.clone(), uri, &text.content).await?;
+    let encoding = session.position_encoding().await;
+    // The text is released before reparsing since the parser is stored alongside it
+    let (content, edits) = {
+        let mut text = session.get_mut_text(uri).await?;
+        // Edits to apply to the previous tree, or None if it can't be reused
+        let mut edits = Some(vec![]);
+        for change in &params.content_changes {
+            if let Some(range) = change.range {
+                let edit = text.apply_change(range, &change.text, encoding)?;
+                if let Some(edits) = edits.as_mut() {
+                    edits.push(edit);
+                }
+            } else {
+                // No range means the change contains the full document
+                *text = Text::new(&change.text)?;
+                edits = None;
+            }
+        }
+        (text.content.clone(), edits)
+    };
+    Document::change(session.clone(), uri, &content, edits.as_deref()).await?;
+    let version = Some(params.text_document.version);
+    publish_syntax_diagnostics(session, uri.clone(), version).await
+}
+
+/// The document is kept up to date by `did_change`, so saving only checks it again
+pub async fn did_save(
+    session: Arc<Session>,
+    params: DidSaveTextDocumentParams,
+) -> anyhow::Result<()> {
+    publish_syntax_diagnostics(session, params.text_document.uri, None).await
+}
+
+async fn publish_syntax_diagnostics(
+    session: Arc<Session>,
+    uri: Url,
+    version: Option<i32>,
+) -> anyhow::Result<()> {
+    // There's nobody to publish to when the session is used without a client
+    let Ok(client) = session.client() else {
+        return Ok(());
+    };
+    let encoding = session.position_encoding().await;
+    let diagnostics = {
+        let text = session.get_text(&uri).await?;
+        let tree = session.get_tree(&uri).await?;
+        let tree = tree.lock().await;
+        crate::core::syntax::diagnostics(&text.content, &tree, encoding)
+    };
+    client.publish_diagnostics(uri, diagnostics, version).await;
     Ok(())
 }
 
@@ -52,55 +104,67 @@ pub async fn document_symbol(
     session: Arc<Session>,
     params: DocumentSymbolParams,
 ) -> anyhow::Result<Option<DocumentSymbolResponse>> {
+    struct Symbol {
+        byte_range: std::ops::Range<usize>,
+        symbol: DocumentSymbol,
+    }
+
     fn make_symbol(
-        uri: &Url,
         content: &ropey::Rope,
         declaration: tree_sitter::Node,
         identifier: tree_sitter::Node,
         kind: SymbolKind,
-    ) -> SymbolInformation {
+        encoding: PositionEncoding,
+    ) -> Symbol {
         let name = content.utf8_text_for_tree_sitter_node(&identifier).into();
-        let range = content.tree_sitter_range_to_lsp_range(declaration.range());
+        let range = encoding.range(content, declaration.range());
+        let selection_range = encoding.range(content, identifier.range());
         #[allow(deprecated)]
-        SymbolInformation {
+        let symbol = DocumentSymbol {
             name,
+            detail: Default::default(),
             kind,
             tags: Default::default(),
             deprecated: Default::default(),
-            location: Location::new(uri.clone(), range),
-            container_name: Default::default(),
+            range,
+            selection_range,
+            children: Default::default(),
+        };
+        Symbol {
+            byte_range: declaration.byte_range(),
+            symbol,
+        }
+    }
+
+    // Attaches the symbol to its parent on the top of the stack, or to the roots if it has none
+    fn attach(symbol: Symbol, stack: &mut [Symbol], roots: &mut Vec<DocumentSymbol>) {
+        if let Some(parent) = stack.last_mut() {
+            parent
+                .symbol
+                .children
+                .get_or_insert_with(Vec::new)
+                .push(symbol.symbol);
+        } else {
+            roots.push(symbol.symbol);
         }
     }
 
     let uri = &params.text_document.uri;
+    let encoding = session.position_encoding().await;
 
     let text = session.get_text(uri).await?;
     let content = &text.content;
 
     let tree = session.get_tree(uri).await?;
-    let tree = tree.lock().await.clone();
+    let tree = tree.lock().await;
 
     let node = tree.root_node();
 
-    let language = session.language;
-
-    static QUERY: &str = indoc::indoc! {r"
-      (function_declaration
-        name: (identifier) @identifier) @function_declaration
-      (lexical_declaration
-        (variable_declarator
-          name: (identifier) @identifier)) @class_declaration
-      (variable_declaration
-        (variable_declarator
-          name: (identifier) @identifier)) @variable_declaration
-      (class_declaration
-        name: (identifier) @identifier) @class_declaration
-    "};
-    let query = Query::new(language, QUERY)?;
+    let language = session.get_language(uri).await?;
+    let query = session.get_query(&language, language.support.symbol_query())?;
     let mut cursor = QueryCursor::new();
 
-    let content_str = text.content.to_string();
-    let matches = cursor.matches(&query, node, content_str.as_bytes());
+    let matches = cursor.matches(&query, node, RopeProvider(content.slice(..)));
 
     let mut symbols = vec![];
 
@@ -113,38 +177,82 @@ pub async fn document_symbol(
             match declaration.node.kind() {
                 "function_declaration" => {
                     symbols.push(make_symbol(
-                        uri,
                         content,
                         declaration_node,
                         identifier_node,
                         SymbolKind::FUNCTION,
+                        encoding,
                     ));
                 }
                 "lexical_declaration" => {
+                    // `let` and `const` are both lexical declarations
+                    let kind = match declaration_node
+                        .child_by_field_name("kind")
+                        .map(|kind| kind.kind())
+                    {
+                        Some("const") => SymbolKind::CONSTANT,
+                        _ => SymbolKind::VARIABLE,
+                    };
                     symbols.push(make_symbol(
-                        uri,
                         content,
                         declaration_node,
                         identifier_node,
-                        SymbolKind::VARIABLE,
+                        kind,
+                        encoding,
                     ));
                 }
                 "variable_declaration" => {
                     symbols.push(make_symbol(
-                        uri,
                         content,
                         declaration_node,
                         identifier_node,
                         SymbolKind::VARIABLE,
+                        encoding,
                     ));
                 }
-                "class_declaration" => {
+                "class_declaration" | "abstract_class_declaration" => {
                     symbols.push(make_symbol(
-                        uri,
                         content,
                         declaration_node,
                         identifier_node,
-                        SymbolKind::VARIABLE,
+                        SymbolKind::CLASS,
+                        encoding,
+                    ));
+                }
+                "method_definition" => {
+                    symbols.push(make_symbol(
+                        content,
+                        declaration_node,
+                        identifier_node,
+                        SymbolKind::METHOD,
+                        encoding,
+                    ));
+                }
+                "interface_declaration" => {
+                    symbols.push(make_symbol(
+                        content,
+                        declaration_node,
+                        identifier_node,
+                        SymbolKind::INTERFACE,
+                        encoding,
+                    ));
+                }
+                "enum_declaration" => {
+                    symbols.push(make_symbol(
+                        content,
+                        declaration_node,
+                        identifier_node,
+                        SymbolKind::ENUM,
+                        encoding,
+                    ));
+                }
+                "field_definition" | "public_field_definition" => {
+                    symbols.push(make_symbol(
+                        content,
+                        declaration_node,
+                        identifier_node,
+                        SymbolKind::PROPERTY,
+                        encoding,
                     ));
                 }
                 _ => {}
@@ -152,5 +260,1340 @@ pub async fn document_symbol(
         }
     }
 
-    Ok(Some(DocumentSymbolResponse::Flat(symbols)))
+    // Outer declarations come before the declarations they contain
+    symbols.sort_by_key(|s| (s.byte_range.start, std::cmp::Reverse(s.byte_range.end)));
+
+    let mut roots = vec![];
+    let mut stack: Vec<Symbol> = vec![];
+    for symbol in symbols {
+        while let Some(parent) = stack.last() {
+            // Declarators sharing a declaration have the same range and are siblings
+            let contains = parent.byte_range.end >= symbol.byte_range.end
+                && parent.byte_range != symbol.byte_range;
+            if contains {
+                break;
+            }
+            let parent = stack.pop().unwrap();
+            attach(parent, &mut stack, &mut roots);
+        }
+        stack.push(symbol);
+    }
+    while let Some(symbol) = stack.pop() {
+        attach(symbol, &mut stack, &mut roots);
+    }
+
+    Ok(Some(DocumentSymbolResponse::Nested(roots)))
+}
+
+pub async fn completion(
+    session: Arc<Session>,
+    params: CompletionParams,
+) -> anyhow::Result<Option<CompletionResponse>> {
+    let uri = &params.text_document_position.text_document.uri;
+    let position = params.text_document_position.position;
+    let encoding = session.position_encoding().await;
+
+    let text = session.get_text(uri).await?;
+    let content = &text.content;
+    let prefix = text.word_before(position, encoding);
+
+    let tree = session.get_tree(uri).await?;
+    let tree = tree.lock().await;
+
+    let language = session.get_language(uri).await?;
+    let query = session.get_query(&language, language.support.completion_query())?;
+    let mut cursor = QueryCursor::new();
+
+    let captures = cursor.captures(&query, tree.root_node(), RopeProvider(content.slice(..)));
+
+    let mut seen = HashSet::new();
+    let mut items = vec![];
+    for (r#match, capture_index) in captures {
+        let capture = r#match.captures[capture_index];
+        let label: String = content.utf8_text_for_tree_sitter_node(&capture.node).into();
+        // Don't suggest the word that's currently being typed
+        if !label.starts_with(&prefix) || label == prefix || !seen.insert(label.clone()) {
+            continue;
+        }
+        let kind = match query.capture_names()[capture.index as usize].as_str() {
+            "function" => CompletionItemKind::FUNCTION,
+            "class" => CompletionItemKind::CLASS,
+            "method" => CompletionItemKind::METHOD,
+            "interface" => CompletionItemKind::INTERFACE,
+            "enum" => CompletionItemKind::ENUM,
+            _ => CompletionItemKind::VARIABLE,
+        };
+        items.push(CompletionItem {
+            label,
+            kind: Some(kind),
+            ..Default::default()
+        });
+    }
+
+    Ok(Some(CompletionResponse::Array(items)))
+}
+
+pub async fn hover(session: Arc<Session>, params: HoverParams) -> anyhow::Result<Option<Hover>> {
+    const MAX_DECLARATION_LINES: usize = 10;
+
+    let uri = &params.text_document_position_params.text_document.uri;
+    let position = params.text_document_position_params.position;
+    let encoding = session.position_encoding().await;
+
+    let language = session.get_language(uri).await?;
+    let text = session.get_text(uri).await?;
+    let content = &text.content;
+    let Some(char_idx) = text.position_to_char(position, encoding) else {
+        return Ok(None);
+    };
+    match content.get_char(char_idx) {
+        Some(c) if !c.is_whitespace() => {}
+        _ => return Ok(None),
+    }
+
+    let tree = session.get_tree(uri).await?;
+    let tree = tree.lock().await;
+
+    let byte_idx = content.char_to_byte(char_idx);
+    let node = match tree
+        .root_node()
+        .descendant_for_byte_range(byte_idx, byte_idx)
+    {
+        Some(node) if node.is_named() => node,
+        _ => return Ok(None),
+    };
+
+    let mut declaration = node;
+    while let Some(parent) = declaration.parent() {
+        declaration = parent;
+        let kind = declaration.kind();
+        if kind.ends_with("_declaration") || kind.ends_with("_definition") {
+            break;
+        }
+    }
+    let declaration_text: String = content
+        .utf8_text_for_tree_sitter_node(&declaration)
+        .lines()
+        .take(MAX_DECLARATION_LINES)
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    Ok(Some(Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: format!(
+                "`{}`\n```{}\n{declaration_text}\n```",
+                node.kind(),
+                language.name
+            ),
+        }),
+        range: Some(encoding.range(content, node.range())),
+    }))
+}
+
+pub async fn folding_range(
+    session: Arc<Session>,
+    params: FoldingRangeParams,
+) -> anyhow::Result<Option<Vec<FoldingRange>>> {
+    let uri = &params.text_document.uri;
+    let encoding = session.position_encoding().await;
+    let text = session.get_text(uri).await?;
+    let content = &text.content;
+    let tree = session.get_tree(uri).await?;
+    let tree = tree.lock().await;
+
+    let language = session.get_language(uri).await?;
+    let query = session.get_query(&language, language.support.fold_query())?;
+    let mut cursor = QueryCursor::new();
+    let captures = cursor.captures(&query, tree.root_node(), RopeProvider(content.slice(..)));
+
+    let mut ranges = vec![];
+    for (r#match, capture_index) in captures {
+        let capture = r#match.captures[capture_index];
+        let kind = match query.capture_names()[capture.index as usize].as_str() {
+            "fold.comment" => Some(FoldingRangeKind::Comment),
+            _ => None,
+        };
+        let range = encoding.range(content, capture.node.range());
+        if range.start.line == range.end.line {
+            continue;
+        }
+        ranges.push(FoldingRange {
+            start_line: range.start.line,
+            start_character: Some(range.start.character),
+            end_line: range.end.line,
+            end_character: Some(range.end.character),
+            kind,
+            collapsed_text: None,
+        });
+    }
+    ranges.sort_by_key(|r| (r.start_line, r.start_character));
+
+    Ok(Some(ranges))
+}
+
+pub async fn selection_range(
+    session: Arc<Session>,
+    params: SelectionRangeParams,
+) -> anyhow::Result<Option<Vec<SelectionRange>>> {
+    let uri = &params.text_document.uri;
+    let encoding = session.position_encoding().await;
+    let text = session.get_text(uri).await?;
+    let content = &text.content;
+    let tree = session.get_tree(uri).await?;
+    let tree = tree.lock().await;
+
+    let ranges = params
+        .positions
+        .into_iter()
+        .map(|position| {
+            let empty = SelectionRange {
+                range: Range::new(position, position),
+                parent: None,
+            };
+            let Some(char_idx) = text.position_to_char(position, encoding) else {
+                return empty;
+            };
+            let byte_idx = content.char_to_byte(char_idx);
+            let Some(node) = tree
+                .root_node()
+                .named_descendant_for_byte_range(byte_idx, byte_idx)
+            else {
+                return empty;
+            };
+
+            // Nodes that span the same text as their child would select nothing new
+            let mut chain: Vec<tree_sitter::Node> = vec![];
+            let mut node = Some(node);
+            while let Some(current) = node {
+                match chain.last_mut() {
+                    Some(last) if last.byte_range() == current.byte_range() => *last = current,
+                    _ => chain.push(current),
+                }
+                node = current.parent();
+            }
+            chain
+                .into_iter()
+                .rev()
+                .fold(None, |parent, node| {
+                    Some(Box::new(SelectionRange {
+                        range: encoding.range(content, node.range()),
+                        parent,
+                    }))
+                })
+                .map_or(empty, |range| *range)
+        })
+        .collect();
+
+    Ok(Some(ranges))
+}
+
+pub async fn references(
+    session: Arc<Session>,
+    params: ReferenceParams,
+) -> anyhow::Result<Option<Vec<Location>>> {
+    let uri = &params.text_document_position.text_document.uri;
+    let position = params.text_document_position.position;
+    let encoding = session.position_encoding().await;
+
+    let text = session.get_text(uri).await?;
+    let tree = session.get_tree(uri).await?;
+    let tree = tree.lock().await;
+
+    let locations = find_occurrences(&text, &tree, position, encoding)
+        .into_iter()
+        .filter(|node| params.context.include_declaration || !is_declaration(*node))
+        .map(|node| {
+            let range = encoding.range(&text.content, node.range());
+            Location::new(uri.clone(), range)
+        })
+        .collect();
+
+    Ok(Some(locations))
+}
+
+pub async fn document_highlight(
+    session: Arc<Session>,
+    params: DocumentHighlightParams,
+) -> anyhow::Result<Option<Vec<DocumentHighlight>>> {
+    let uri = &params.text_document_position_params.text_document.uri;
+    let position = params.text_document_position_params.position;
+    let encoding = session.position_encoding().await;
+
+    let text = session.get_text(uri).await?;
+    let tree = session.get_tree(uri).await?;
+    let tree = tree.lock().await;
+
+    let highlights: Vec<_> = find_occurrences(&text, &tree, position, encoding)
+        .into_iter()
+        .map(|node| DocumentHighlight {
+            range: encoding.range(&text.content, node.range()),
+            kind: Some(if is_write(node) {
+                DocumentHighlightKind::WRITE
+            } else {
+                DocumentHighlightKind::READ
+            }),
+        })
+        .collect();
+    if highlights.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(highlights))
+}
+
+/// Labels the arguments of calls to functions declared in the document with the names of the
+/// parameters they're passed as. Arguments that already have the parameter's name are skipped.
+pub async fn inlay_hint(
+    session: Arc<Session>,
+    params: InlayHintParams,
+) -> anyhow::Result<Option<Vec<InlayHint>>> {
+    let uri = &params.text_document.uri;
+    let encoding = session.position_encoding().await;
+
+    let text = session.get_text(uri).await?;
+    let tree = session.get_tree(uri).await?;
+    let tree = tree.lock().await;
+    let content = &text.content;
+
+    let mut functions = HashMap::new();
+    let mut calls = vec![];
+    let mut nodes = vec![tree.root_node()];
+    while let Some(node) = nodes.pop() {
+        match node.kind() {
+            "function_declaration" => {
+                if let (Some(name), Some(parameters)) = (
+                    node.child_by_field_name("name"),
+                    node.child_by_field_name("parameters"),
+                ) {
+                    let name = content.utf8_text_for_tree_sitter_node(&name).to_string();
+                    functions.insert(name, parameter_names(content, parameters));
+                }
+            }
+            "variable_declarator" => {
+                let parameters = node
+                    .child_by_field_name("value")
+                    .filter(|value| ["function", "arrow_function"].contains(&value.kind()))
+                    .and_then(|value| value.child_by_field_name("parameters"));
+                if let (Some(name), Some(parameters)) =
+                    (node.child_by_field_name("name"), parameters)
+                {
+                    let name = content.utf8_text_for_tree_sitter_node(&name).to_string();
+                    functions.insert(name, parameter_names(content, parameters));
+                }
+            }
+            "call_expression" => calls.push(node),
+            _ => {}
+        }
+        let mut cursor = node.walk();
+        nodes.extend(node.named_children(&mut cursor));
+    }
+
+    let mut hints = vec![];
+    for call in calls {
+        let (Some(function), Some(arguments)) = (
+            call.child_by_field_name("function"),
+            call.child_by_field_name("arguments"),
+        ) else {
+            continue;
+        };
+        let name = content
+            .utf8_text_for_tree_sitter_node(&function)
+            .to_string();
+        let Some(parameters) = functions.get(&name) else {
+            continue;
+        };
+        let mut cursor = arguments.walk();
+        let arguments = arguments
+            .named_children(&mut cursor)
+            .filter(|argument| argument.kind() != "comment");
+        for (argument, parameter) in arguments.zip(parameters) {
+            // Nothing lines up after a spread argument
+            if argument.kind() == "spread_element" {
+                break;
+            }
+            let Some(parameter) = parameter else {
+                continue;
+            };
+            if content.utf8_text_for_tree_sitter_node(&argument) == parameter.as_str() {
+                continue;
+            }
+            let position = encoding.range(content, argument.range()).start;
+            if position < params.range.start || position > params.range.end {
+                continue;
+            }
+            hints.push(InlayHint {
+                position,
+                label: InlayHintLabel::String(format!("{parameter}:")),
+                kind: Some(InlayHintKind::PARAMETER),
+                text_edits: None,
+                tooltip: None,
+                padding_left: None,
+                padding_right: Some(true),
+                data: None,
+            });
+        }
+    }
+    hints.sort_by_key(|hint| hint.position);
+    Ok(Some(hints))
+}
+
+/// The name of each parameter, or `None` for ones that are destructured. A rest parameter ends
+/// the list since it takes every argument after it.
+fn parameter_names(content: &ropey::Rope, parameters: tree_sitter::Node) -> Vec<Option<String>> {
+    parameters
+        .named_children(&mut parameters.walk())
+        .filter(|parameter| parameter.kind() != "comment")
+        .take_while(|parameter| parameter.kind() != "rest_pattern")
+        .map(|parameter| {
+            let identifier = match parameter.kind() {
+                "identifier" => Some(parameter),
+                "assignment_pattern" => parameter
+                    .child_by_field_name("left")
+                    .filter(|left| left.kind() == "identifier"),
+                _ => None,
+            };
+            identifier.map(|identifier| {
+                content
+                    .utf8_text_for_tree_sitter_node(&identifier)
+                    .to_string()
+            })
+        })
+        .collect()
+}
+
+pub async fn semantic_tokens_full(
+    session: Arc<Session>,
+    params: SemanticTokensParams,
+) -> anyhow::Result<Option<SemanticTokensResult>> {
+    let uri = &params.text_document.uri;
+    let Some(legend) = session.semantic_tokens_legend().await else {
+        return Ok(None);
+    };
+    let encoding = session.position_encoding().await;
+
+    let text = session.get_text(uri).await?;
+    let tree = session.get_tree(uri).await?;
+    let tree = tree.lock().await;
+    let data = crate::core::syntax::semantic_tokens(&text.content, &tree, encoding, &legend);
+    Ok(Some(SemanticTokensResult::Tokens(SemanticTokens {
+        result_id: None,
+        data,
+    })))
+}
+
+pub async fn rename(
+    session: Arc<Session>,
+    params: RenameParams,
+) -> anyhow::Result<Option<WorkspaceEdit>> {
+    let uri = &params.text_document_position.text_document.uri;
+    let position = params.text_document_position.position;
+    if !is_identifier(&params.new_name) {
+        return Err(Error::InvalidIdentifier(params.new_name).into());
+    }
+    let encoding = session.position_encoding().await;
+
+    let text = session.get_text(uri).await?;
+    let tree = session.get_tree(uri).await?;
+    let tree = tree.lock().await;
+
+    let edits: Vec<_> = find_occurrences(&text, &tree, position, encoding)
+        .into_iter()
+        .map(|node| TextEdit {
+            range: encoding.range(&text.content, node.range()),
+            new_text: params.new_name.clone(),
+        })
+        .collect();
+    if edits.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(WorkspaceEdit {
+        changes: Some(HashMap::from([(uri.clone(), edits)])),
+        ..Default::default()
+    }))
+}
+
+fn is_identifier(name: &str) -> bool {
+    const RESERVED_WORDS: &str = "await break case catch class const continue debugger default \
+        delete do else enum export extends false finally for function if import in instanceof \
+        let new null return static super switch this throw true try typeof var void while with \
+        yield";
+
+    let mut chars = name.chars();
+    let Some(first) = chars.next() else {
+        return false;
+    };
+    (first.is_alphabetic() || first == '_' || first == '$')
+        && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+        && !RESERVED_WORDS.split_whitespace().any(|word| word == name)
+}
+
+pub async fn formatting(
+    session: Arc<Session>,
+    params: DocumentFormattingParams,
+) -> anyhow::Result<Option<Vec<TextEdit>>> {
+    let uri = &params.text_document.uri;
+    let text = session.get_text(uri).await?;
+    let tree = session.get_tree(uri).await?;
+    let tree = tree.lock().await;
+    // Indentation can't be trusted while parts of the document don't parse
+    if tree.root_node().has_error() {
+        return Ok(None);
+    }
+
+    let options = &params.options;
+    let indent_unit = if options.insert_spaces {
+        " ".repeat(options.tab_size as usize)
+    } else {
+        "\t".to_owned()
+    };
+    let content = &text.content;
+    let mut edits = vec![];
+    for (line_idx, line) in content.lines().enumerate() {
+        let indent_len = line.chars().take_while(|c| *c == ' ' || *c == '\t').count();
+        let is_blank = line
+            .chars()
+            .skip(indent_len)
+            .all(|c| c == '\n' || c == '\r');
+        let new_indent = if is_blank {
+            Some(String::new())
+        } else {
+            let byte_idx = content.line_to_byte(line_idx) + indent_len;
+            indent_level(&tree, line_idx, byte_idx).map(|level| indent_unit.repeat(level))
+        };
+        let Some(new_indent) = new_indent else {
+            continue;
+        };
+        if line.slice(..indent_len) != new_indent.as_str() {
+            edits.push(TextEdit {
+                range: Range::new(
+                    Position::new(line_idx as u32, 0),
+                    Position::new(line_idx as u32, indent_len as u32),
+                ),
+                new_text: new_indent,
+            });
+        }
+    }
+
+    Ok(Some(edits))
+}
+
+/// Counts the blocks enclosing the first character of a line, treating blocks that start on the
+/// same line as one level. Returns `None` for lines inside multi-line strings and comments,
+/// whose indentation is part of their content.
+fn indent_level(tree: &tree_sitter::Tree, line_idx: usize, byte_idx: usize) -> Option<usize> {
+    const INDENT_KINDS: &[&str] = &[
+        "arguments",
+        "array",
+        "array_pattern",
+        "class_body",
+        "enum_body",
+        "export_clause",
+        "formal_parameters",
+        "named_imports",
+        "object",
+        "object_pattern",
+        "object_type",
+        "parenthesized_expression",
+        "statement_block",
+        "switch_body",
+        "switch_case",
+        "switch_default",
+    ];
+    const VERBATIM_KINDS: &[&str] = &["comment", "string", "template_string"];
+
+    let mut start_rows = HashSet::new();
+    let mut closed_rows = HashSet::new();
+    let mut node = tree
+        .root_node()
+        .descendant_for_byte_range(byte_idx, byte_idx);
+    while let Some(current) = node {
+        let start_row = current.start_position().row;
+        if start_row < line_idx {
+            if VERBATIM_KINDS.contains(&current.kind()) {
+                return None;
+            }
+            if INDENT_KINDS.contains(&current.kind()) {
+                start_rows.insert(start_row);
+                // A line starting with the closing bracket lines up with the line that opened it
+                let closes_here = current
+                    .child(current.child_count().saturating_sub(1))
+                    .is_some_and(|last| !last.is_named() && last.start_byte() == byte_idx);
+                if closes_here {
+                    closed_rows.insert(start_row);
+                }
+            }
+        }
+        node = current.parent();
+    }
+    Some(start_rows.difference(&closed_rows).count())
+}
+
+pub async fn code_action(
+    session: Arc<Session>,
+    params: CodeActionParams,
+) -> anyhow::Result<Option<CodeActionResponse>> {
+    let uri = &params.text_document.uri;
+    let encoding = session.position_encoding().await;
+    let text = session.get_text(uri).await?;
+    let content = &text.content;
+    let tree = session.get_tree(uri).await?;
+    let tree = tree.lock().await;
+
+    let Some(char_idx) = text.position_to_char(params.range.start, encoding) else {
+        return Ok(None);
+    };
+    let byte_idx = content.char_to_byte(char_idx);
+    let mut node = tree
+        .root_node()
+        .named_descendant_for_byte_range(byte_idx, byte_idx);
+    while let Some(current) = node {
+        if matches!(
+            current.kind(),
+            "lexical_declaration" | "variable_declaration"
+        ) {
+            break;
+        }
+        node = current.parent();
+    }
+    let Some(declaration) = node else {
+        return Ok(Some(vec![]));
+    };
+
+    let mut actions = vec![];
+    let mut cursor = declaration.walk();
+    let declarators: Vec<_> = declaration.named_children(&mut cursor).collect();
+    // Removing one declarator out of several would leave dangling commas behind
+    if let [declarator] = declarators.as_slice() {
+        let name = declarator
+            .child_by_field_name("name")
+            .filter(|name| name.kind() == "identifier");
+        if let Some(name) = name {
+            let position = encoding.byte_to_position(content, name.start_byte());
+            if find_occurrences(&text, &tree, position, encoding).len() == 1 {
+                let edit = TextEdit {
+                    range: line_range(content, declaration, encoding),
+                    new_text: String::new(),
+                };
+                let name = content.utf8_text_for_tree_sitter_node(&name);
+                actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                    title: format!("Remove unused variable `{name}`"),
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    edit: Some(WorkspaceEdit::new(HashMap::from([(
+                        uri.clone(),
+                        vec![edit],
+                    )]))),
+                    ..Default::default()
+                }));
+            }
+        }
+    }
+
+    Ok(Some(actions))
+}
+
+/// The range of a node, widened to whole lines when nothing else is on them
+fn line_range(content: &ropey::Rope, node: tree_sitter::Node, encoding: PositionEncoding) -> Range {
+    let start_row = node.start_position().row;
+    let end_row = node.end_position().row;
+    let is_blank = |bytes: std::ops::Range<usize>| {
+        content.byte_slice(bytes).chars().all(|c| c.is_whitespace())
+    };
+    if end_row + 1 < content.len_lines()
+        && is_blank(content.line_to_byte(start_row)..node.start_byte())
+        && is_blank(node.end_byte()..content.line_to_byte(end_row + 1))
+    {
+        return Range::new(
+            Position::new(start_row as u32, 0),
+            Position::new(end_row as u32 + 1, 0),
+        );
+    }
+    encoding.range(content, node.range())
+}
+
+const IDENTIFIER_KINDS: &[&str] = &[
+    "identifier",
+    "property_identifier",
+    "shorthand_property_identifier",
+    "shorthand_property_identifier_pattern",
+    "type_identifier",
+];
+
+/// Finds every identifier in the document with the same name as the one at `position`.
+/// Occurrences are matched by name only, so shadowed variables and unrelated properties
+/// with the same name are included.
+fn find_occurrences<'tree>(
+    text: &Text,
+    tree: &'tree tree_sitter::Tree,
+    position: Position,
+    encoding: PositionEncoding,
+) -> Vec<tree_sitter::Node<'tree>> {
+    let content = &text.content;
+    let Some(char_idx) = text.position_to_char(position, encoding) else {
+        return vec![];
+    };
+    let byte_idx = content.char_to_byte(char_idx);
+    let target = match tree
+        .root_node()
+        .named_descendant_for_byte_range(byte_idx, byte_idx)
+    {
+        Some(node) if IDENTIFIER_KINDS.contains(&node.kind()) => node,
+        _ => return vec![],
+    };
+    let name = content.utf8_text_for_tree_sitter_node(&target);
+
+    let mut occurrences = vec![];
+    let mut nodes = vec![tree.root_node()];
+    while let Some(node) = nodes.pop() {
+        if IDENTIFIER_KINDS.contains(&node.kind())
+            && content.utf8_text_for_tree_sitter_node(&node) == name
+        {
+            occurrences.push(node);
+        }
+        let mut cursor = node.walk();
+        nodes.extend(node.named_children(&mut cursor));
+    }
+    occurrences.sort_by_key(|node| node.start_byte());
+    occurrences
+}
+
+/// Whether the identifier is the name being declared rather than a use of it
+fn is_declaration(identifier: tree_sitter::Node) -> bool {
+    let Some(parent) = identifier.parent() else {
+        return false;
+    };
+    parent.kind() == "formal_parameters"
+        || parent
+            .child_by_field_name("name")
+            .is_some_and(|name| name == identifier)
+}
+
+/// Whether the identifier is given a value rather than read
+fn is_write(identifier: tree_sitter::Node) -> bool {
+    let Some(parent) = identifier.parent() else {
+        return false;
+    };
+    let is_field = |name| {
+        parent
+            .child_by_field_name(name)
+            .is_some_and(|node| node == identifier)
+    };
+    match parent.kind() {
+        "assignment_expression" | "augmented_assignment_expression" => is_field("left"),
+        "update_expression" => is_field("argument"),
+        "variable_declarator" => is_field("name"),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tower_lsp::lsp_types::*;
+
+    use super::{
+        code_action, did_change, did_open, document_highlight, document_symbol, folding_range,
+        formatting, inlay_hint, references, rename, selection_range, semantic_tokens_full,
+    };
+    use crate::core::error::Error;
+    use crate::core::{language::LanguageRegistry, session::Session, text::PositionEncoding};
+
+    async fn open_document(session: &Arc<Session>, uri: &Url, text: &str) {
+        let params = DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(
+                uri.clone(),
+                "javascript".to_owned(),
+                0,
+                text.to_owned(),
+            ),
+        };
+        did_open(session.clone(), params).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn open_document_with_syntax_errors() {
+        let session = Session::new(None, LanguageRegistry::builtin());
+        let uri: Url = "file:///test.js".parse().unwrap();
+        open_document(&session, &uri, "function (").await;
+
+        assert!(session
+            .get_tree(&uri)
+            .await
+            .unwrap()
+            .lock()
+            .await
+            .root_node()
+            .has_error());
+
+        let params = DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier::new(uri.clone(), 1),
+            content_changes: vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: "function f() {}".to_owned(),
+            }],
+        };
+        did_change(session.clone(), params).await.unwrap();
+
+        let text = session.get_text(&uri).await.unwrap();
+        assert_eq!("function f() {}", text.content.to_string());
+    }
+
+    #[tokio::test]
+    async fn document_symbol_kinds() {
+        let session = Session::new(None, LanguageRegistry::builtin());
+        let uri: Url = "file:///test.js".parse().unwrap();
+        let text = indoc::indoc! {"
+            function f() {}
+            const c = 1;
+            let l = 2;
+            var v = 3;
+            class C {}
+        "};
+        open_document(&session, &uri, text).await;
+
+        let params = DocumentSymbolParams {
+            text_document: TextDocumentIdentifier::new(uri),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let symbols = match document_symbol(session, params).await.unwrap() {
+            Some(DocumentSymbolResponse::Nested(symbols)) => symbols,
+            response => panic!("unexpected response {response:?}"),
+        };
+        let kinds: Vec<_> = symbols.iter().map(|s| (s.name.as_str(), s.kind)).collect();
+
+        assert_eq!(
+            vec![
+                ("f", SymbolKind::FUNCTION),
+                ("c", SymbolKind::CONSTANT),
+                ("l", SymbolKind::VARIABLE),
+                ("v", SymbolKind::VARIABLE),
+                ("C", SymbolKind::CLASS),
+            ],
+            kinds
+        );
+    }
+
+    #[tokio::test]
+    async fn typescript_document_symbols() {
+        let session = Session::new(None, LanguageRegistry::builtin());
+        let uri: Url = "file:///test.ts".parse().unwrap();
+        let text = indoc::indoc! {"
+            interface I {}
+            enum E { A }
+            class C {
+                field: number = 1;
+                method(): void {}
+            }
+        "};
+        let params = DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(
+                uri.clone(),
+                "typescript".to_owned(),
+                0,
+                text.to_owned(),
+            ),
+        };
+        did_open(session.clone(), params).await.unwrap();
+        assert!(!session
+            .get_tree(&uri)
+            .await
+            .unwrap()
+            .lock()
+            .await
+            .root_node()
+            .has_error());
+
+        let params = DocumentSymbolParams {
+            text_document: TextDocumentIdentifier::new(uri),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let symbols = match document_symbol(session, params).await.unwrap() {
+            Some(DocumentSymbolResponse::Nested(symbols)) => symbols,
+            response => panic!("unexpected response {response:?}"),
+        };
+        let kinds: Vec<_> = symbols.iter().map(|s| (s.name.as_str(), s.kind)).collect();
+        assert_eq!(
+            vec![
+                ("I", SymbolKind::INTERFACE),
+                ("E", SymbolKind::ENUM),
+                ("C", SymbolKind::CLASS),
+            ],
+            kinds
+        );
+        let children: Vec<_> = symbols[2]
+            .children
+            .iter()
+            .flatten()
+            .map(|s| (s.name.as_str(), s.kind))
+            .collect();
+        assert_eq!(
+            vec![
+                ("field", SymbolKind::PROPERTY),
+                ("method", SymbolKind::METHOD)
+            ],
+            children
+        );
+    }
+
+    #[tokio::test]
+    async fn folding_ranges() {
+        let session = Session::new(None, LanguageRegistry::builtin());
+        let uri: Url = "file:///test.js".parse().unwrap();
+        let text = indoc::indoc! {"
+            /**
+             * Docs
+             */
+            function f() {
+                if (true) { return; }
+                return {
+                    a: 1,
+                };
+            }
+            class C {
+                m() {}
+            }
+        "};
+        open_document(&session, &uri, text).await;
+
+        let params = FoldingRangeParams {
+            text_document: TextDocumentIdentifier::new(uri),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let ranges: Vec<_> = folding_range(session, params)
+            .await
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.start_line, r.end_line, r.kind))
+            .collect();
+
+        assert_eq!(
+            vec![
+                (0, 2, Some(FoldingRangeKind::Comment)),
+                (3, 8, None),
+                (5, 7, None),
+                (9, 11, None),
+            ],
+            ranges
+        );
+    }
+
+    #[tokio::test]
+    async fn selection_range_parents() {
+        let session = Session::new(None, LanguageRegistry::builtin());
+        let uri: Url = "file:///test.js".parse().unwrap();
+        let text = indoc::indoc! {"
+            function f() {
+                g(a.b, 1);
+            }
+        "};
+        open_document(&session, &uri, text).await;
+
+        let params = SelectionRangeParams {
+            text_document: TextDocumentIdentifier::new(uri),
+            positions: vec![Position::new(1, 8)],
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let ranges = selection_range(session, params).await.unwrap().unwrap();
+        assert_eq!(1, ranges.len());
+
+        let mut chain = vec![];
+        let mut range = Some(&ranges[0]);
+        while let Some(r) = range {
+            chain.push(r.range);
+            range = r.parent.as_deref();
+        }
+        let range = |start: (u32, u32), end: (u32, u32)| {
+            Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1))
+        };
+        assert_eq!(
+            vec![
+                // b
+                range((1, 8), (1, 9)),
+                // a.b
+                range((1, 6), (1, 9)),
+                // (a.b, 1)
+                range((1, 5), (1, 13)),
+                // g(a.b, 1)
+                range((1, 4), (1, 13)),
+                // g(a.b, 1);
+                range((1, 4), (1, 14)),
+                // { ... }
+                range((0, 13), (2, 1)),
+                // function f() { ... }
+                range((0, 0), (2, 1)),
+                // program
+                range((0, 0), (3, 0)),
+            ],
+            chain
+        );
+    }
+
+    #[tokio::test]
+    async fn references_include_declaration() {
+        let session = Session::new(None, LanguageRegistry::builtin());
+        let uri: Url = "file:///test.js".parse().unwrap();
+        let text = indoc::indoc! {"
+            let count = 0;
+            function inc() {
+                count += 1;
+                return count;
+            }
+        "};
+        open_document(&session, &uri, text).await;
+
+        let find = |include_declaration| {
+            let params = ReferenceParams {
+                text_document_position: TextDocumentPositionParams::new(
+                    TextDocumentIdentifier::new(uri.clone()),
+                    Position::new(2, 6),
+                ),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+                context: ReferenceContext {
+                    include_declaration,
+                },
+            };
+            references(session.clone(), params)
+        };
+        let lines = |locations: Option<Vec<Location>>| -> Vec<_> {
+            locations
+                .unwrap()
+                .into_iter()
+                .map(|l| l.range.start.line)
+                .collect()
+        };
+
+        assert_eq!(vec![0, 2, 3], lines(find(true).await.unwrap()));
+        assert_eq!(vec![2, 3], lines(find(false).await.unwrap()));
+    }
+
+    #[tokio::test]
+    async fn highlight_reads_and_writes() {
+        let session = Session::new(None, LanguageRegistry::builtin());
+        let uri: Url = "file:///test.js".parse().unwrap();
+        let text = indoc::indoc! {"
+            let count = 0;
+            count += 1;
+            count++;
+            console.log(count);
+            count = count * 2;
+        "};
+        open_document(&session, &uri, text).await;
+
+        let params = |line, character| DocumentHighlightParams {
+            text_document_position_params: TextDocumentPositionParams::new(
+                TextDocumentIdentifier::new(uri.clone()),
+                Position::new(line, character),
+            ),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let highlights = document_highlight(session.clone(), params(3, 14))
+            .await
+            .unwrap()
+            .unwrap();
+        let highlights: Vec<_> = highlights
+            .into_iter()
+            .map(|h| (h.range.start.line, h.range.start.character, h.kind.unwrap()))
+            .collect();
+        let (read, write) = (DocumentHighlightKind::READ, DocumentHighlightKind::WRITE);
+        assert_eq!(
+            vec![
+                (0, 4, write),
+                (1, 0, write),
+                (2, 0, write),
+                (3, 12, read),
+                (4, 0, write),
+                (4, 8, read),
+            ],
+            highlights
+        );
+
+        // Nothing to highlight on a keyword
+        assert_eq!(
+            None,
+            document_highlight(session.clone(), params(0, 1))
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn parameter_name_hints() {
+        let session = Session::new(None, LanguageRegistry::builtin());
+        let uri: Url = "file:///test.js".parse().unwrap();
+        let text = indoc::indoc! {"
+            function move(x, y = 0, { speed }, ...rest) {}
+            const scale = (factor) => factor;
+            move(1, y, 3, 4, 5);
+            scale(2);
+            unknown(1);
+        "};
+        open_document(&session, &uri, text).await;
+
+        let params = |end_line| InlayHintParams {
+            text_document: TextDocumentIdentifier::new(uri.clone()),
+            range: Range::new(Position::new(0, 0), Position::new(end_line, 0)),
+            work_done_progress_params: Default::default(),
+        };
+        let hints = |hints: Option<Vec<InlayHint>>| -> Vec<_> {
+            hints
+                .unwrap()
+                .into_iter()
+                .map(|hint| {
+                    let InlayHintLabel::String(label) = hint.label else {
+                        panic!("unexpected label {:?}", hint.label);
+                    };
+                    (hint.position, label)
+                })
+                .collect()
+        };
+
+        let all = inlay_hint(session.clone(), params(5)).await.unwrap();
+        assert_eq!(
+            vec![
+                (Position::new(2, 5), "x:".to_owned()),
+                (Position::new(3, 6), "factor:".to_owned()),
+            ],
+            hints(all)
+        );
+        // Only the hints in the requested range are returned
+        let first_call = inlay_hint(session.clone(), params(3)).await.unwrap();
+        assert_eq!(
+            vec![(Position::new(2, 5), "x:".to_owned())],
+            hints(first_call)
+        );
+    }
+
+    #[tokio::test]
+    async fn classify_semantic_tokens() {
+        let session = Session::new(None, LanguageRegistry::builtin());
+        let uri: Url = "file:///test.js".parse().unwrap();
+        let text = indoc::indoc! {"
+            function add(a) {
+              return a + 1; // one
+            }
+            const s = `x
+            yz`;
+        "};
+        open_document(&session, &uri, text).await;
+
+        let params = SemanticTokensParams {
+            text_document: TextDocumentIdentifier::new(uri.clone()),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let Some(SemanticTokensResult::Tokens(tokens)) =
+            semantic_tokens_full(session.clone(), params).await.unwrap()
+        else {
+            panic!("expected tokens");
+        };
+        let legend = session.semantic_tokens_legend().await.unwrap();
+        let (mut line, mut start) = (0, 0);
+        let tokens: Vec<_> = tokens
+            .data
+            .iter()
+            .map(|token| {
+                if token.delta_line > 0 {
+                    (line, start) = (line + token.delta_line, 0);
+                }
+                start += token.delta_start;
+                let token_type = legend.token_types[token.token_type as usize].as_str();
+                let declaration = token.token_modifiers_bitset != 0;
+                (line, start, token.length, token_type, declaration)
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                (0, 0, 8, "keyword", false),
+                (0, 9, 3, "function", true),
+                (0, 13, 1, "parameter", true),
+                (1, 2, 6, "keyword", false),
+                (1, 9, 1, "variable", false),
+                (1, 13, 1, "number", false),
+                (1, 16, 6, "comment", false),
+                (3, 0, 5, "keyword", false),
+                (3, 6, 1, "variable", true),
+                // Split at the end of each line it spans
+                (3, 10, 2, "string", false),
+                (4, 0, 3, "string", false),
+            ],
+            tokens
+        );
+    }
+
+    #[tokio::test]
+    async fn rename_variable() {
+        let session = Session::new(None, LanguageRegistry::builtin());
+        let uri: Url = "file:///test.js".parse().unwrap();
+        let text = indoc::indoc! {"
+            let total = 0;
+            total += 1;
+            console.log(total);
+        "};
+        open_document(&session, &uri, text).await;
+
+        let params = |new_name: &str| RenameParams {
+            text_document_position: TextDocumentPositionParams::new(
+                TextDocumentIdentifier::new(uri.clone()),
+                Position::new(1, 2),
+            ),
+            new_name: new_name.to_owned(),
+            work_done_progress_params: Default::default(),
+        };
+        let edit = rename(session.clone(), params("sum"))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let text_edit = |line, start, end| TextEdit {
+            range: Range::new(Position::new(line, start), Position::new(line, end)),
+            new_text: "sum".to_owned(),
+        };
+        let expected = vec![text_edit(0, 4, 9), text_edit(1, 0, 5), text_edit(2, 12, 17)];
+        assert_eq!(Some(&expected), edit.changes.unwrap().get(&uri));
+
+        let error = rename(session, params("1sum")).await.unwrap_err();
+        assert_eq!(
+            Some(&Error::InvalidIdentifier("1sum".to_owned())),
+            error.downcast_ref()
+        );
+    }
+
+    #[tokio::test]
+    async fn multibyte_columns_follow_position_encoding() {
+        let uri: Url = "file:///test.js".parse().unwrap();
+        // The second `s` comes after 'é' and '😀', which take up a different number of code
+        // units in each encoding
+        for (encoding, column) in [
+            (PositionEncoding::Utf8, 18),
+            (PositionEncoding::Utf16, 15),
+            (PositionEncoding::Utf32, 14),
+        ] {
+            let session = Session::new(None, LanguageRegistry::builtin());
+            session.server_capabilities.write().await.position_encoding = Some(encoding.kind());
+            open_document(&session, &uri, "let s = \"\"; s;\n").await;
+
+            let insert = Position::new(0, 9);
+            let params = DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier::new(uri.clone(), 1),
+                content_changes: vec![TextDocumentContentChangeEvent {
+                    range: Some(Range::new(insert, insert)),
+                    range_length: None,
+                    text: "é😀".to_owned(),
+                }],
+            };
+            did_change(session.clone(), params).await.unwrap();
+
+            let params = RenameParams {
+                text_document_position: TextDocumentPositionParams::new(
+                    TextDocumentIdentifier::new(uri.clone()),
+                    Position::new(0, column),
+                ),
+                new_name: "t".to_owned(),
+                work_done_progress_params: Default::default(),
+            };
+            let edit = rename(session, params).await.unwrap().unwrap();
+            let ranges: Vec<_> = edit.changes.unwrap()[&uri]
+                .iter()
+                .map(|edit| (edit.range.start.character, edit.range.end.character))
+                .collect();
+            assert_eq!(vec![(4, 5), (column, column + 1)], ranges, "{encoding:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn format_indentation() {
+        let session = Session::new(None, LanguageRegistry::builtin());
+        let uri: Url = "file:///test.js".parse().unwrap();
+        // Not written with `indoc` so the inconsistent indentation is kept as is
+        let text = concat!(
+            "function f(a) {\n",
+            "if (a) {\n",
+            "      foo({\n",
+            "  b: 1,\n",
+            "});\n",
+            "    }\n",
+            "  const s = `\n",
+            "keep`;\n",
+            "\t\n",
+            "}\n",
+        );
+        open_document(&session, &uri, text).await;
+
+        let params = DocumentFormattingParams {
+            text_document: TextDocumentIdentifier::new(uri),
+            options: FormattingOptions {
+                tab_size: 2,
+                insert_spaces: true,
+                ..Default::default()
+            },
+            work_done_progress_params: Default::default(),
+        };
+        let edits: Vec<_> = formatting(session, params)
+            .await
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.range.start.line, e.range.end.character, e.new_text))
+            .collect();
+
+        let edit = |line, end, indent: &str| (line, end, indent.to_owned());
+        assert_eq!(
+            vec![
+                edit(1, 0, "  "),
+                edit(2, 6, "    "),
+                edit(3, 2, "      "),
+                edit(4, 0, "    "),
+                edit(5, 4, "  "),
+                edit(8, 1, ""),
+            ],
+            edits
+        );
+    }
+
+    #[tokio::test]
+    async fn remove_unused_variable() {
+        let session = Session::new(None, LanguageRegistry::builtin());
+        let uri: Url = "file:///test.js".parse().unwrap();
+        let text = indoc::indoc! {"
+            const unused = 1;
+            const used = 2;
+            console.log(used);
+        "};
+        open_document(&session, &uri, text).await;
+
+        let actions = |line| {
+            let params = CodeActionParams {
+                text_document: TextDocumentIdentifier::new(uri.clone()),
+                range: Range::new(Position::new(line, 8), Position::new(line, 8)),
+                context: Default::default(),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            };
+            code_action(session.clone(), params)
+        };
+
+        let actions_for_unused = actions(0).await.unwrap().unwrap();
+        let [CodeActionOrCommand::CodeAction(action)] = actions_for_unused.as_slice() else {
+            panic!("unexpected actions {actions_for_unused:?}");
+        };
+        assert_eq!("Remove unused variable `unused`", action.title);
+        let edits = action.edit.as_ref().unwrap().changes.as_ref().unwrap();
+        let expected = TextEdit {
+            range: Range::new(Position::new(0, 0), Position::new(1, 0)),
+            new_text: String::new(),
+        };
+        assert_eq!(Some(&vec![expected]), edits.get(&uri));
+
+        assert!(actions(1).await.unwrap().unwrap().is_empty());
+    }
 }
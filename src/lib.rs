@@ -0,0 +1,16 @@
+//! A language server backed by tree-sitter, along with a terminal editor that talks to it.
+//!
+//! The server can be embedded by constructing a [`Server`] over a [`LanguageRegistry`] and
+//! serving it with `tower_lsp`, or by calling the [`handler`] functions on a [`Session`] directly.
+
+pub mod client;
+pub mod config;
+pub mod core;
+pub mod handler;
+pub mod server;
+pub mod tui;
+
+pub use crate::core::{
+    document::Document, error::Error, language::LanguageRegistry, session::Session, text::Text,
+};
+pub use crate::server::Server;
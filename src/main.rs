@@ -1,15 +1,57 @@
-mod client;
-mod core;
-mod handler;
-mod server;
-mod tui;
+use std::fs::{self, OpenOptions};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use lsp_tui::config::{Config, LogConfig};
+use tracing_subscriber::EnvFilter;
+
+const LOG_FILE_NAME: &str = "log";
 
 #[tokio::main]
 pub async fn main() {
-    let writer = tracing_appender::rolling::never("./logs", "log");
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e:#}");
+            std::process::exit(1);
+        }
+    };
+    init_logging(&config.log);
+    let files = std::env::args().skip(1).map(PathBuf::from).collect();
+    if let Err(e) = lsp_tui::tui::run(config, files).await {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
+
+/// Appends logs to a file in the configured directory. Nothing is logged if logging is turned
+/// off or the file can't be opened.
+fn init_logging(config: &LogConfig) {
+    if !config.enabled {
+        return;
+    }
+    let file = fs::create_dir_all(&config.directory).and_then(|_| {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(config.directory.join(LOG_FILE_NAME))
+    });
+    let file = match file {
+        Ok(file) => file,
+        Err(e) => {
+            // Still there once the app exits and the terminal is restored
+            eprintln!(
+                "Logging is off, couldn't open a log file in {}: {e}",
+                config.directory.display()
+            );
+            return;
+        }
+    };
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&config.filter));
     tracing_subscriber::fmt()
-        .with_writer(writer)
+        .with_env_filter(filter)
+        .with_writer(Mutex::new(file))
         .with_ansi(false)
         .init();
-    crate::tui::run().await;
 }
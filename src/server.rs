@@ -1,9 +1,12 @@
 use std::sync::Arc;
 
-use tower_lsp::{jsonrpc, lsp_types::*, LanguageServer, ServerToClient};
-use tracing::info;
+use tokio::io::DuplexStream;
+use tower_lsp::{jsonrpc, lsp_types::*, LanguageServer, LspService, ServerToClient};
+use tracing::{info, warn};
 
-use crate::core::{error::IntoJsonRpcError, session::Session};
+use crate::core::{
+    error::IntoJsonRpcError, language::LanguageRegistry, session::Session, text::PositionEncoding,
+};
 
 pub struct Server {
     pub client: tower_lsp::Client<ServerToClient>,
@@ -11,8 +14,8 @@ pub struct Server {
 }
 
 impl Server {
-    pub fn new(client: tower_lsp::Client<ServerToClient>, language: tree_sitter::Language) -> Self {
-        let session = Session::new(Some(client.clone()), language);
+    pub fn new(client: tower_lsp::Client<ServerToClient>, languages: LanguageRegistry) -> Self {
+        let session = Session::new(Some(client.clone()), languages);
         Server { client, session }
     }
 }
@@ -21,8 +24,15 @@ impl Server {
 impl LanguageServer for Server {
     async fn initialize(&self, params: InitializeParams) -> jsonrpc::Result<InitializeResult> {
         info!("server::initialize");
+        let encodings = params
+            .capabilities
+            .general
+            .as_ref()
+            .and_then(|general| general.position_encodings.as_deref());
+        let mut capabilities = capabilities();
+        capabilities.position_encoding = Some(PositionEncoding::negotiate(encodings).kind());
+        *self.session.server_capabilities.write().await = capabilities.clone();
         *self.session.client_capabilities.write().await = Some(params.capabilities);
-        let capabilities = capabilities();
         Ok(InitializeResult {
             capabilities,
             ..InitializeResult::default()
@@ -49,19 +59,33 @@ impl LanguageServer for Server {
         self.client.log_message(typ, message).await;
 
         let session = self.session.clone();
-        crate::handler::did_open(session, params).await.unwrap();
+        if let Err(e) = crate::handler::did_open(session, params).await {
+            warn!("did_open failed: {e:?}");
+        }
     }
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
         info!("server::did_change");
         let session = self.session.clone();
-        crate::handler::did_change(session, params).await.unwrap();
+        if let Err(e) = crate::handler::did_change(session, params).await {
+            warn!("did_change failed: {e:?}");
+        }
+    }
+
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
+        info!("server::did_save");
+        let session = self.session.clone();
+        if let Err(e) = crate::handler::did_save(session, params).await {
+            warn!("did_save failed: {e:?}");
+        }
     }
 
     async fn did_close(&self, params: DidCloseTextDocumentParams) {
         info!("server::did_close");
         let session = self.session.clone();
-        crate::handler::did_close(session, params).await.unwrap();
+        if let Err(e) = crate::handler::did_close(session, params).await {
+            warn!("did_close failed: {e:?}");
+        }
     }
 
     async fn document_symbol(
@@ -73,15 +97,148 @@ impl LanguageServer for Server {
         let result = crate::handler::document_symbol(session, params).await;
         Ok(result.map_err(IntoJsonRpcError)?)
     }
+
+    async fn completion(
+        &self,
+        params: CompletionParams,
+    ) -> jsonrpc::Result<Option<CompletionResponse>> {
+        info!("server::completion");
+        let session = self.session.clone();
+        let result = crate::handler::completion(session, params).await;
+        Ok(result.map_err(IntoJsonRpcError)?)
+    }
+
+    async fn hover(&self, params: HoverParams) -> jsonrpc::Result<Option<Hover>> {
+        info!("server::hover");
+        let session = self.session.clone();
+        let result = crate::handler::hover(session, params).await;
+        Ok(result.map_err(IntoJsonRpcError)?)
+    }
+
+    async fn folding_range(
+        &self,
+        params: FoldingRangeParams,
+    ) -> jsonrpc::Result<Option<Vec<FoldingRange>>> {
+        info!("server::folding_range");
+        let session = self.session.clone();
+        let result = crate::handler::folding_range(session, params).await;
+        Ok(result.map_err(IntoJsonRpcError)?)
+    }
+
+    async fn selection_range(
+        &self,
+        params: SelectionRangeParams,
+    ) -> jsonrpc::Result<Option<Vec<SelectionRange>>> {
+        info!("server::selection_range");
+        let session = self.session.clone();
+        let result = crate::handler::selection_range(session, params).await;
+        Ok(result.map_err(IntoJsonRpcError)?)
+    }
+
+    async fn references(&self, params: ReferenceParams) -> jsonrpc::Result<Option<Vec<Location>>> {
+        info!("server::references");
+        let session = self.session.clone();
+        let result = crate::handler::references(session, params).await;
+        Ok(result.map_err(IntoJsonRpcError)?)
+    }
+
+    async fn document_highlight(
+        &self,
+        params: DocumentHighlightParams,
+    ) -> jsonrpc::Result<Option<Vec<DocumentHighlight>>> {
+        info!("server::document_highlight");
+        let session = self.session.clone();
+        let result = crate::handler::document_highlight(session, params).await;
+        Ok(result.map_err(IntoJsonRpcError)?)
+    }
+
+    async fn inlay_hint(&self, params: InlayHintParams) -> jsonrpc::Result<Option<Vec<InlayHint>>> {
+        info!("server::inlay_hint");
+        let session = self.session.clone();
+        let result = crate::handler::inlay_hint(session, params).await;
+        Ok(result.map_err(IntoJsonRpcError)?)
+    }
+
+    async fn semantic_tokens_full(
+        &self,
+        params: SemanticTokensParams,
+    ) -> jsonrpc::Result<Option<SemanticTokensResult>> {
+        info!("server::semantic_tokens_full");
+        let session = self.session.clone();
+        let result = crate::handler::semantic_tokens_full(session, params).await;
+        Ok(result.map_err(IntoJsonRpcError)?)
+    }
+
+    async fn rename(&self, params: RenameParams) -> jsonrpc::Result<Option<WorkspaceEdit>> {
+        info!("server::rename");
+        let session = self.session.clone();
+        let result = crate::handler::rename(session, params).await;
+        Ok(result.map_err(IntoJsonRpcError)?)
+    }
+
+    async fn formatting(
+        &self,
+        params: DocumentFormattingParams,
+    ) -> jsonrpc::Result<Option<Vec<TextEdit>>> {
+        info!("server::formatting");
+        let session = self.session.clone();
+        let result = crate::handler::formatting(session, params).await;
+        Ok(result.map_err(IntoJsonRpcError)?)
+    }
+
+    async fn code_action(
+        &self,
+        params: CodeActionParams,
+    ) -> jsonrpc::Result<Option<CodeActionResponse>> {
+        info!("server::code_action");
+        let session = self.session.clone();
+        let result = crate::handler::code_action(session, params).await;
+        Ok(result.map_err(IntoJsonRpcError)?)
+    }
 }
 
 pub fn capabilities() -> ServerCapabilities {
     let document_symbol_provider = Some(OneOf::Left(true));
 
+    let completion_provider = Some(CompletionOptions {
+        trigger_characters: Some(vec![".".to_owned()]),
+        ..Default::default()
+    });
+
+    let hover_provider = Some(HoverProviderCapability::Simple(true));
+
+    let folding_range_provider = Some(FoldingRangeProviderCapability::Simple(true));
+
+    let selection_range_provider = Some(SelectionRangeProviderCapability::Simple(true));
+
+    let references_provider = Some(OneOf::Left(true));
+
+    let document_highlight_provider = Some(OneOf::Left(true));
+
+    let rename_provider = Some(OneOf::Left(true));
+
+    let inlay_hint_provider = Some(OneOf::Left(true));
+
+    let semantic_tokens_provider = {
+        let options = SemanticTokensOptions {
+            legend: crate::core::syntax::semantic_tokens_legend(),
+            full: Some(SemanticTokensFullOptions::Bool(true)),
+            ..Default::default()
+        };
+        Some(SemanticTokensServerCapabilities::SemanticTokensOptions(
+            options,
+        ))
+    };
+
+    let document_formatting_provider = Some(OneOf::Left(true));
+
+    let code_action_provider = Some(CodeActionProviderCapability::Simple(true));
+
     let text_document_sync = {
         let options = TextDocumentSyncOptions {
             open_close: Some(true),
-            change: Some(TextDocumentSyncKind::FULL),
+            change: Some(TextDocumentSyncKind::INCREMENTAL),
+            save: Some(TextDocumentSyncSaveOptions::Supported(true)),
             ..Default::default()
         };
         Some(TextDocumentSyncCapability::Options(options))
@@ -90,6 +247,45 @@ pub fn capabilities() -> ServerCapabilities {
     ServerCapabilities {
         text_document_sync,
         document_symbol_provider,
+        completion_provider,
+        hover_provider,
+        folding_range_provider,
+        selection_range_provider,
+        references_provider,
+        document_highlight_provider,
+        rename_provider,
+        inlay_hint_provider,
+        semantic_tokens_provider,
+        document_formatting_provider,
+        code_action_provider,
         ..Default::default()
     }
 }
+
+/// Serves the built-in languages in-process. Returns the streams a client reads from and writes
+/// to, along with the server's session. `buffer_size` is the most bytes each stream holds
+/// before the writer waits for them to be read.
+pub fn start_local_server(buffer_size: usize) -> (DuplexStream, DuplexStream, Arc<Session>) {
+    let mut session = None;
+    let (in_stream, out_stream) = serve_in_process(buffer_size, |client| {
+        let server = Server::new(client, LanguageRegistry::builtin());
+        session = Some(server.session.clone());
+        server
+    });
+    (in_stream, out_stream, session.unwrap())
+}
+
+/// Serves the server built by `init` in-process, like [`start_local_server`] does for the
+/// built-in one. Returns the streams a client reads from and writes to.
+pub fn serve_in_process<S: LanguageServer>(
+    buffer_size: usize,
+    init: impl FnOnce(tower_lsp::Client<ServerToClient>) -> S,
+) -> (DuplexStream, DuplexStream) {
+    let (req_client, req_server) = tokio::io::duplex(buffer_size);
+    let (resp_server, resp_client) = tokio::io::duplex(buffer_size);
+    let (server_service, server_socket) = LspService::new_server(init);
+    tokio::spawn(
+        tower_lsp::Server::new(req_server, resp_server, server_socket).serve(server_service),
+    );
+    (req_client, resp_client)
+}
@@ -1,38 +1,153 @@
-use super::completion_menu::CompletionMenuState;
-use super::lsp_capabilities::{Encoding, LspCapabilities};
-use crate::client::Client;
-use crate::server::Server;
-use crate::tui::completion_menu::CompletionMenu;
-use crate::tui::text_area::TextArea;
-use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+use super::buffer::Buffer;
+use super::command_palette::command_palette;
+use super::editor::{save_failed, Editor, EditorMessage};
+use super::keymap::Action;
+use super::log_panel::ServerLog;
+use super::lsp_capabilities::LspCapabilities;
+use super::message_request::MessageRequest;
+use super::picker::{Picker, PickerEvent};
+use super::progress::ServerProgress;
+use super::status_bar::StatusBar;
+use super::view_state::ViewStates;
+use crate::client::{serve_client, ClientNotification};
+use crate::config::{Config, ServerConfig, LANGUAGE_SERVERS};
+use crate::core::language::LanguageRegistry;
+use crate::server::start_local_server;
+use crate::tui::theme::Theme;
+use anyhow::Context;
+use crossterm::event::{Event, KeyCode, KeyEvent};
 use crossterm::terminal;
 use elm_ui::{Message, Model, OptionalCommand};
 use kaolinite::{Document, Loc, Size};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
 use ratatui::backend::CrosstermBackend;
-use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Spans;
+use ratatui::widgets::Tabs;
 use ratatui::{Frame, Terminal};
-use std::io::Stdout;
+use std::collections::VecDeque;
+use std::env;
+use std::fs;
+use std::io::{self, Stdout};
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
-use std::sync::atomic::{AtomicI32, Ordering};
 use std::sync::Arc;
-use std::{cmp, io};
-use tokio::io::{BufReader, BufWriter, DuplexStream};
-use tower_lsp::{lsp_types::*, ClientToServer, LspService};
+use std::time::{Duration, Instant};
+use tempfile::NamedTempFile;
+use tokio::io::{BufReader, BufWriter};
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tower_lsp::{lsp_types::*, ClientToServer};
+use tracing::warn;
 
+const STATUS_BAR_HEIGHT: usize = 1;
+const TAB_BAR_HEIGHT: usize = 1;
+/// Includes the border with the panel's title
+const LOG_PANEL_HEIGHT: usize = 8;
+/// The delay before the first restart of a crashed server, doubled for each consecutive crash
+const RESTART_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(30);
+/// A server that stays up this long after a restart is considered healthy again
+const RESTART_RESET_AFTER: Duration = Duration::from_secs(60);
+/// How long quitting waits for each step of the server's shutdown before moving on
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+/// Where the cursor position of each file is kept between runs
+const VIEW_STATES_PATH: &str = "./.lsp-tui-view-states.json";
 #[derive(Debug)]
 enum LspResponse {
-    Completions(Vec<String>),
+    Restarted(ServerConnection, ServerCapabilities),
+    /// A request that failed in the background, reported in the status bar
+    RequestFailed(String),
 }
 
+enum Prompt {
+    SaveBeforeClose,
+    Rename(String),
+    /// The line number typed so far
+    GotoLine(String),
+    RestartServer(Option<i32>),
+    /// A document with unsaved changes was changed on disk
+    Reload(Url),
+}
+
+impl Prompt {
+    fn message(&self) -> String {
+        match self {
+            Prompt::SaveBeforeClose => "Save changes before closing? (y/n/esc)".to_owned(),
+            Prompt::Rename(name) => format!("Rename to: {name}"),
+            Prompt::GotoLine(line) => format!("Go to line: {line}"),
+            Prompt::RestartServer(Some(code)) => {
+                format!("Language server exited with code {code}. Restart? (y/n)")
+            }
+            Prompt::RestartServer(None) => "Language server exited. Restart? (y/n)".to_owned(),
+            Prompt::Reload(uri) => format!(
+                "{} changed on disk. Reload and discard your changes? (y/n)",
+                uri.path_segments()
+                    .and_then(|segments| segments.last())
+                    .unwrap_or_default()
+            ),
+        }
+    }
+}
+
+/// A watched file was changed, possibly by the editor itself
+#[derive(Debug)]
+struct FileChanged(PathBuf);
+
+/// A client connected to a running server
+struct ServerConnection {
+    lsp_client: Arc<tower_lsp::Client<ClientToServer>>,
+    notification_rx: Arc<Mutex<mpsc::UnboundedReceiver<ClientNotification>>>,
+    /// `None` for the built-in server
+    process: Option<ServerProcess>,
+}
+
+impl std::fmt::Debug for ServerConnection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServerConnection")
+            .field("process", &self.process.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Stops a spawned server, killing it if it doesn't exit on its own
+#[derive(Clone)]
+struct ServerProcess {
+    stop_tx: mpsc::UnboundedSender<oneshot::Sender<()>>,
+}
+
+/// Hosts an [`Editor`] for each open document, shown as tabs, along with the server they share
 pub struct App {
-    capabilities: LspCapabilities,
-    docs: Vec<Document>,
-    doc_index: usize,
+    config: Arc<Config>,
+    theme: Arc<Theme>,
+    capabilities: Arc<LspCapabilities>,
+    editors: Vec<Editor>,
+    editor_index: usize,
     lsp_client: Arc<tower_lsp::Client<ClientToServer>>,
-    document_uri: Url,
-    document_version: AtomicI32,
-    completion_menu_state: CompletionMenuState,
-    show_completions: bool,
+    notification_rx: Arc<Mutex<mpsc::UnboundedReceiver<ClientNotification>>>,
+    server_process: Option<ServerProcess>,
+    /// Shown in the status bar until the next key press
+    status_error: Option<String>,
+    /// Shown in the status bar until the next key press
+    status_message: Option<String>,
+    prompt: Option<Prompt>,
+    /// Requests from the server waiting for the user to pick an action, shown one at a time
+    message_requests: VecDeque<MessageRequest>,
+    palette: Option<Picker<Action>>,
+    view_states: ViewStates,
+    /// Sent to the server as the workspace folder
+    root: PathBuf,
+    /// Backs the document opened when no file is given. Deleted when the app is dropped.
+    _scratch_file: Option<NamedTempFile>,
+    server_log: ServerLog,
+    progress: ServerProgress,
+    /// Whether the server log is shown below the document
+    show_log: bool,
+    file_change_tx: mpsc::UnboundedSender<PathBuf>,
+    file_change_rx: Arc<Mutex<mpsc::UnboundedReceiver<PathBuf>>>,
+    /// Consecutive restarts of a server that keeps crashing, used to back off
+    restart_attempts: u32,
+    last_restart: Option<Instant>,
     width: usize,
     height: usize,
 }
@@ -42,21 +157,10 @@ impl Model for App {
     type Error = io::Error;
 
     fn init(&mut self) -> Result<OptionalCommand, Self::Error> {
-        let lsp_client = self.lsp_client.clone();
-        let document_uri = self.document_uri.clone();
-        let document_version = self.document_version.fetch_add(1, Ordering::SeqCst);
-        Ok(Some(elm_ui::Command::new_async(move |_, _| async move {
-            lsp_client.initialized().await;
-            lsp_client
-                .did_open(TextDocumentItem {
-                    uri: document_uri.clone(),
-                    language_id: "typescript".to_owned(),
-                    version: document_version,
-                    text: "".to_owned(),
-                })
-                .await;
-            None
-        })))
+        Ok(Some(elm_ui::Command::simple(Message::Batch(vec![
+            self.get_initialized_command(),
+            self.get_file_change_command(),
+        ]))))
     }
 
     fn update(&mut self, msg: Arc<Message>) -> Result<OptionalCommand, Self::Error> {
@@ -65,20 +169,30 @@ impl Model for App {
                 Event::Resize(width, height) => {
                     self.width = *width as usize;
                     self.height = *height as usize;
-                    for doc in self.docs.iter_mut() {
-                        doc.size.w = self.width;
-                        doc.size.h = self.height;
-                    }
+                    self.resize_editors();
                 }
                 Event::Key(key_event) => {
                     return Ok(self.handle_key_event(key_event));
                 }
+                Event::Mouse(mouse_event) => {
+                    let area = self.text_area();
+                    let editor = self.current_editor_mut();
+                    return Ok(editor.handle_mouse_event(mouse_event, area));
+                }
                 _ => {}
             },
             Message::Custom(msg) => {
-                if let Some(LspResponse::Completions(completions)) = msg.downcast_ref() {
-                    self.completion_menu_state
-                        .set_completions(completions.clone());
+                if let Some(message) = msg.downcast_ref() {
+                    return Ok(self.handle_editor_message(message));
+                } else if let Some(response) = msg.downcast_ref() {
+                    return Ok(self.handle_response(response));
+                } else if let Some(notification) = msg.downcast_ref() {
+                    self.handle_notification(notification);
+                    return Ok(Some(self.get_notification_command()));
+                } else if let Some(FileChanged(path)) = msg.downcast_ref() {
+                    let mut commands = vec![self.get_file_change_command()];
+                    commands.extend(self.handle_file_change(path));
+                    return Ok(Some(elm_ui::Command::simple(Message::Batch(commands))));
                 }
             }
             _ => {}
@@ -93,431 +207,1009 @@ impl Model for App {
 }
 
 impl App {
-    pub async fn initialize() -> App {
-        let (client_service, client_socket) = LspService::new_client(Client::new);
-        let lsp_client = client_service.inner().server_client();
-        let local = false;
-        if local {
-            let (in_stream, out_stream) = start_local_server();
-            tokio::spawn(
-                tower_lsp::Server::new(out_stream, in_stream, client_socket).serve(client_service),
-            );
+    /// Opens each of the files in its own tab, starting on the first one. Files that can't be
+    /// opened are skipped with a warning, and a new document is opened if none of them can be.
+    /// The server is chosen by the language of the first file that exists, and files in languages
+    /// it doesn't handle are skipped too. Fails if that server can't be started, such as when it
+    /// isn't installed.
+    pub async fn initialize(config: Config, files: Vec<PathBuf>) -> anyhow::Result<App> {
+        let server = server_for_files(&config, &files);
+        let connection = connect(&server).map_err(|e| spawn_error(&server, e))?;
+        Self::connected(config, server, files, connection).await
+    }
+
+    /// Like [`App::initialize`], but uses a client that's already connected to a server instead
+    /// of starting one, such as a mock server in tests. The server's notifications are read from
+    /// `notification_rx`.
+    pub(crate) async fn with_client(
+        config: Config,
+        files: Vec<PathBuf>,
+        lsp_client: Arc<tower_lsp::Client<ClientToServer>>,
+        notification_rx: mpsc::UnboundedReceiver<ClientNotification>,
+    ) -> anyhow::Result<App> {
+        let connection = ServerConnection {
+            lsp_client,
+            notification_rx: Arc::new(Mutex::new(notification_rx)),
+            process: None,
+        };
+        let server = server_for_files(&config, &files);
+        Self::connected(config, server, files, connection).await
+    }
+
+    /// Initializes `server` on the other end of `connection` and opens the files it handles.
+    /// Fails if none of them can be opened and there's nowhere to put a new document.
+    async fn connected(
+        mut config: Config,
+        server: ServerConfig,
+        files: Vec<PathBuf>,
+        connection: ServerConnection,
+    ) -> anyhow::Result<App> {
+        // There's no terminal when the app is driven by tests
+        let (width, height) = terminal::size().unwrap_or((80, 24));
+        let size = Size {
+            w: width as usize,
+            h: (height as usize).saturating_sub(STATUS_BAR_HEIGHT + TAB_BAR_HEIGHT),
+        };
+        // Only one server runs at a time, so files for other servers are left closed
+        let (files, other_languages): (Vec<_>, Vec<_>) = files
+            .into_iter()
+            .partition(|path| serves(&config, &server, language_id(path)));
+        config.server = server;
+        let (docs, skipped) = open_files(files, size);
+        let scratch_file = if docs.is_empty() {
+            let file = new_scratch_file(&config.server.language_id)
+                .context("failed to create a file for the new document")?;
+            Some(file)
         } else {
-            let process = tokio::process::Command::new("typescript-language-server")
-                .arg("--stdio")
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .spawn()
-                .unwrap();
-            let stdin = BufWriter::new(process.stdin.unwrap());
-            let stdout = BufReader::new(process.stdout.unwrap());
-            tokio::spawn(
-                tower_lsp::Server::new(stdout, stdin, client_socket).serve(client_service),
-            );
-        }
+            None
+        };
+        let root = workspace_root(docs.first().map(|(path, _)| path.as_path()));
 
-        let InitializeResult { capabilities, .. } =
-            lsp_client.initialize(initialize_params()).await.unwrap();
+        // Keep the editor usable without language features if the server can't start up
+        let (capabilities, server_error) =
+            match initialize_server(&connection.lsp_client, &config.server, &root).await {
+                Ok(capabilities) => (capabilities, None),
+                Err(e) => (ServerCapabilities::default(), Some(e)),
+            };
+        let skipped_warning = skipped_files_warning("couldn't be opened", &skipped);
+        let other_language_warning =
+            skipped_files_warning("are for a different language server", &other_languages);
+        let warnings: Vec<_> = [server_error, skipped_warning, other_language_warning]
+            .into_iter()
+            .flatten()
+            .collect();
 
-        let document_version = AtomicI32::new(0);
-        let document_uri: Url = "file://temp".parse().unwrap();
+        let view_states = if config.editor.restore_positions {
+            ViewStates::load(Path::new(VIEW_STATES_PATH))
+        } else {
+            ViewStates::default()
+        };
+        let mut buffers: Vec<_> = docs
+            .into_iter()
+            .map(|(path, doc)| {
+                let uri = Url::from_file_path(&path).unwrap();
+                let language_id = language_id(&path).unwrap_or(&config.server.language_id);
+                let buffer = Buffer::new(doc, uri, language_id.to_owned());
+                (buffer, view_states.get(&path))
+            })
+            .collect();
+        if let Some(file) = &scratch_file {
+            let uri = Url::from_file_path(file.path()).unwrap();
+            let doc = Document::open_empty(size);
+            let buffer = Buffer::scratch(doc, uri, config.server.language_id.clone());
+            buffers.push((buffer, None));
+        }
 
-        let (width, height) = terminal::size().unwrap();
+        let theme = Theme::load(&config.editor.theme).unwrap_or_else(|e| {
+            warn!("Failed to load theme {}: {e:?}", config.editor.theme);
+            Theme::dark()
+        });
 
-        Self {
-            lsp_client,
-            document_uri,
-            document_version,
-            capabilities: capabilities.into(),
-            docs: vec![Document::open_empty(Size {
-                w: width as usize,
-                h: height as usize,
-            })],
-            doc_index: 0,
-            completion_menu_state: CompletionMenuState::default(),
-            show_completions: false,
+        let (file_change_tx, file_change_rx) = mpsc::unbounded_channel();
+        let mut app = Self {
+            config: Arc::new(config),
+            theme: Arc::new(theme),
+            lsp_client: connection.lsp_client,
+            notification_rx: connection.notification_rx,
+            server_process: connection.process,
+            capabilities: Arc::new(capabilities.into()),
+            editors: vec![],
+            editor_index: 0,
+            status_error: (!warnings.is_empty()).then(|| warnings.join("; ")),
+            status_message: None,
+            prompt: None,
+            message_requests: VecDeque::new(),
+            palette: None,
+            view_states,
+            root,
+            _scratch_file: scratch_file,
+            server_log: ServerLog::default(),
+            progress: ServerProgress::default(),
+            show_log: false,
+            file_change_tx,
+            file_change_rx: Arc::new(Mutex::new(file_change_rx)),
+            restart_attempts: 0,
+            last_restart: None,
             width: width as usize,
             height: height as usize,
+        };
+        for (mut buffer, view_state) in buffers {
+            buffer.doc.tab_width = app.config.editor.tab_width;
+            let mut editor = app.new_editor(buffer);
+            if let Some(view_state) = view_state {
+                editor.restore_view_state(view_state);
+            }
+            app.editors.push(editor);
+        }
+        Ok(app)
+    }
+
+    /// Handles a key as if it was pressed, for driving the app without a terminal. Returns the
+    /// command that would run next, such as the one that sends the changes to the server.
+    pub(crate) fn apply_key(&mut self, event: KeyEvent) -> OptionalCommand {
+        self.handle_key_event(&event)
+    }
+
+    /// The text of the current document
+    pub(crate) fn text(&self) -> String {
+        self.current_editor().doc().rope().to_string()
+    }
+
+    /// The items listed in the current editor's completion menu
+    pub(crate) fn completions(&self) -> Vec<&CompletionItem> {
+        self.current_editor().completions()
+    }
+
+    /// Each line of the current editor's hover popup, if it's showing
+    pub(crate) fn hover(&self) -> Option<Vec<String>> {
+        let hover = self.current_editor().hover()?;
+        let lines = hover
+            .lines
+            .iter()
+            .map(|line| line.0.iter().map(|span| span.content.as_ref()).collect())
+            .collect();
+        Some(lines)
+    }
+
+    /// Where the cursor is in the current document
+    pub(crate) fn cursor(&self) -> Loc {
+        self.current_editor().doc().char_loc()
+    }
+
+    fn new_editor(&self, buffer: Buffer) -> Editor {
+        let mut editor = Editor::new(
+            buffer,
+            self.lsp_client.clone(),
+            self.capabilities.clone(),
+            self.config.clone(),
+            self.theme.clone(),
+        );
+        if editor.buffer().scratch {
+            return editor;
+        }
+        if let Ok(path) = editor.uri().to_file_path() {
+            match self.watch_file(path) {
+                Ok(watcher) => editor.set_watcher(watcher),
+                Err(e) => warn!("Failed to watch {}: {e:?}", editor.uri()),
+            }
+        }
+        editor
+    }
+
+    /// Reports changes to the file through `file_change_tx`. The directory is watched rather than
+    /// the file so that saving by replacing the file is picked up too.
+    fn watch_file(&self, path: PathBuf) -> notify::Result<RecommendedWatcher> {
+        let file_change_tx = self.file_change_tx.clone();
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+                Ok(event) if event.paths.contains(&path) => {
+                    if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                        let _ = file_change_tx.send(path.clone());
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Error watching {path:?}: {e:?}"),
+            })?;
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+        Ok(watcher)
+    }
+
+    fn get_file_change_command(&self) -> elm_ui::Command {
+        let file_change_rx = self.file_change_rx.clone();
+        elm_ui::Command::new_async(move |_, _| async move {
+            let path = file_change_rx.lock().await.recv().await?;
+            Some(Message::custom(FileChanged(path)))
+        })
+    }
+
+    /// Reloads a document that changed on disk, asking first if that would discard changes
+    fn handle_file_change(&mut self, path: &Path) -> Option<elm_ui::Command> {
+        let uri = Url::from_file_path(path).ok()?;
+        let index = self.editors.iter().position(|e| e.uri() == &uri)?;
+        let doc = self.editors[index].doc();
+        // Saving from the editor changes the file too
+        let contents = fs::read_to_string(path).ok()?;
+        if contents == doc.rope().to_string() {
+            return None;
+        }
+        if doc.modified {
+            // Don't interrupt another question, the next change will ask again
+            if self.prompt.is_none() {
+                self.prompt = Some(Prompt::Reload(uri));
+            }
+            return None;
+        }
+        self.reload(index)
+    }
+
+    fn reload(&mut self, index: usize) -> Option<elm_ui::Command> {
+        let editor = &mut self.editors[index];
+        match editor.reload() {
+            Ok(command) => command,
+            Err(e) => {
+                warn!("Failed to reload {}: {e:?}", editor.uri());
+                self.status_error =
+                    Some(format!("Failed to reload {}: {e}", editor.buffer().name()));
+                None
+            }
         }
     }
 
     fn ui(&self, f: &mut Frame<CrosstermBackend<Stdout>>) {
-        const MIN_HEIGHT: usize = 1;
-        let height = cmp::max(self.current_doc().len_lines(), MIN_HEIGHT) as u16;
         let chunks = Layout::default()
             .direction(Direction::Vertical)
-            .constraints([Constraint::Length(height), Constraint::Min(0)].as_slice())
+            .constraints(
+                [
+                    Constraint::Length(TAB_BAR_HEIGHT as u16),
+                    Constraint::Min(0),
+                    Constraint::Length(self.log_height() as u16),
+                    Constraint::Length(STATUS_BAR_HEIGHT as u16),
+                ]
+                .as_slice(),
+            )
             .split(f.size());
+        let (tab_area, text_area, log_area, status_area) =
+            (chunks[0], chunks[1], chunks[2], chunks[3]);
+
+        let titles = self
+            .editors
+            .iter()
+            .map(|editor| {
+                let buffer = editor.buffer();
+                if buffer.doc.modified {
+                    Spans::from(format!("{}*", buffer.name()))
+                } else {
+                    Spans::from(buffer.name())
+                }
+            })
+            .collect();
         f.render_widget(
-            TextArea {
-                doc: self.current_doc(),
+            Tabs::new(titles)
+                .select(self.editor_index)
+                .style(Style::default().fg(Color::Gray))
+                .highlight_style(
+                    Style::default()
+                        .fg(Color::White)
+                        .add_modifier(Modifier::BOLD),
+                ),
+            tab_area,
+        );
+
+        let editor = self.current_editor();
+        f.render_widget(editor, text_area);
+        if let Some(palette) = &self.palette {
+            f.render_widget(palette, text_area);
+        }
+        if self.show_log {
+            f.render_widget(&self.server_log, log_area);
+        }
+        if let Some(request) = self.message_requests.front() {
+            f.render_widget(request, text_area);
+        }
+
+        let diagnostics = editor.diagnostics();
+        let prompt_message = self
+            .prompt
+            .as_ref()
+            .map(Prompt::message)
+            .or_else(|| self.status_message.clone());
+        let count_severity = |severity| {
+            diagnostics
+                .iter()
+                .filter(|d| d.severity.unwrap_or(DiagnosticSeverity::ERROR) == severity)
+                .count()
+        };
+        let progress = self.progress.status();
+        f.render_widget(
+            StatusBar {
+                errors: count_severity(DiagnosticSeverity::ERROR),
+                warnings: count_severity(DiagnosticSeverity::WARNING),
+                progress: progress.as_deref(),
+                message: prompt_message.as_deref(),
+                error: self.status_error.as_deref(),
             },
-            chunks[0],
+            status_area,
         );
 
-        if self.show_completions && !self.completion_menu_state.is_empty() {
-            f.render_stateful_widget(
-                CompletionMenu::new(
-                    self.completion_menu_state.completions(),
-                    self.current_doc().cursor,
-                ),
-                f.size(),
-                &mut self.completion_menu_state.clone(),
-            )
+        let (x, y) = match &self.palette {
+            Some(palette) => palette.cursor_position(text_area),
+            None => editor.cursor_position(text_area),
+        };
+        f.set_cursor(x, y);
+    }
+
+    fn handle_editor_message(&mut self, message: &EditorMessage) -> Option<elm_ui::Command> {
+        match message {
+            EditorMessage::Response(uri, response) => {
+                // The user may have switched or closed buffers while waiting for the server
+                self.editors
+                    .iter_mut()
+                    .find(|e| e.uri() == uri)?
+                    .handle_response(response)
+            }
+            EditorMessage::ApplyEdit(edit) => self.apply_workspace_edit(edit),
+            EditorMessage::Goto(location) => self.goto_location(location),
+            EditorMessage::RequestFailed(message) => {
+                self.status_error = Some(message.clone());
+                None
+            }
+            EditorMessage::Status(message) => {
+                self.status_message = Some(message.clone());
+                None
+            }
+        }
+    }
+
+    fn handle_response(&mut self, response: &LspResponse) -> Option<elm_ui::Command> {
+        match response {
+            LspResponse::Restarted(connection, capabilities) => {
+                self.lsp_client = connection.lsp_client.clone();
+                self.notification_rx = connection.notification_rx.clone();
+                self.server_process = connection.process.clone();
+                self.capabilities = Arc::new(capabilities.clone().into());
+                // Tasks of the old server will never end
+                self.progress.clear();
+                for editor in self.editors.iter_mut() {
+                    editor.set_server(self.lsp_client.clone(), self.capabilities.clone());
+                }
+                return Some(self.get_initialized_command());
+            }
+            LspResponse::RequestFailed(message) => {
+                self.status_error = Some(message.clone());
+            }
         }
-        let Loc { x, y } = self.current_doc().cursor;
-        f.set_cursor(x as u16, y as u16);
+        None
     }
 
-    fn current_doc(&self) -> &Document {
-        &self.docs[self.doc_index]
+    fn apply_workspace_edit(&mut self, edit: &WorkspaceEdit) -> Option<elm_ui::Command> {
+        let mut document_edits: Vec<_> = edit
+            .changes
+            .clone()
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        let text_document_edits = match &edit.document_changes {
+            Some(DocumentChanges::Edits(edits)) => edits.clone(),
+            Some(DocumentChanges::Operations(operations)) => operations
+                .iter()
+                .filter_map(|operation| match operation {
+                    DocumentChangeOperation::Edit(edit) => Some(edit.clone()),
+                    DocumentChangeOperation::Op(op) => {
+                        warn!("Unsupported resource operation {op:?}");
+                        None
+                    }
+                })
+                .collect(),
+            None => vec![],
+        };
+        document_edits.extend(text_document_edits.into_iter().map(|edit| {
+            let edits = edit
+                .edits
+                .into_iter()
+                .map(|edit| match edit {
+                    OneOf::Left(edit) => edit,
+                    OneOf::Right(annotated) => annotated.text_edit,
+                })
+                .collect();
+            (edit.text_document.uri, edits)
+        }));
+
+        let mut commands = vec![];
+        for (uri, edits) in document_edits {
+            let Some(editor) = self.editors.iter_mut().find(|e| e.uri() == &uri) else {
+                warn!("Skipping edits to {uri}, which isn't open");
+                continue;
+            };
+            commands.extend(editor.apply_edits(edits));
+        }
+        Some(elm_ui::Command::simple(Message::Sequence(commands)))
     }
 
-    fn current_doc_mut(&mut self) -> &mut Document {
-        &mut self.docs[self.doc_index]
+    fn goto_location(&mut self, location: &Location) -> Option<elm_ui::Command> {
+        let mut command = None;
+        if let Some(index) = self.editors.iter().position(|e| e.uri() == &location.uri) {
+            self.switch_editor(index);
+        } else {
+            let path = match location.uri.to_file_path() {
+                Ok(path) => path,
+                Err(_) => {
+                    warn!("Unable to open non-file uri {}", location.uri);
+                    return None;
+                }
+            };
+            let mut doc = match Document::open(self.doc_size(), path.to_string_lossy()) {
+                Ok(doc) => doc,
+                Err(e) => {
+                    warn!("Failed to open {path:?}: {e:?}");
+                    return None;
+                }
+            };
+            doc.tab_width = self.config.editor.tab_width;
+            let language_id = language_id(&path).unwrap_or(&self.config.server.language_id);
+            let buffer = Buffer::new(doc, location.uri.clone(), language_id.to_owned());
+            let editor = self.new_editor(buffer);
+            command = Some(editor.get_open_command());
+            self.editors.push(editor);
+            self.switch_editor(self.editors.len() - 1);
+        }
+        self.current_editor_mut()
+            .goto_position(&location.range.start);
+        command
     }
 
-    fn handle_key_event(&mut self, event: &KeyEvent) -> Option<elm_ui::Command> {
-        let mut changes = vec![];
-        let cursor = self.current_doc().cursor;
-        let is_showing_completions = self.show_completions;
-        self.show_completions = false;
-        match (event.modifiers, event.code) {
-            (KeyModifiers::NONE, KeyCode::Up) => {
-                if is_showing_completions && !self.completion_menu_state.is_empty() {
-                    self.completion_menu_state.previous();
-                    self.show_completions = true;
-                } else {
-                    self.current_doc_mut().move_up();
+    fn doc_size(&self) -> Size {
+        Size {
+            w: self.width,
+            h: self
+                .height
+                .saturating_sub(STATUS_BAR_HEIGHT + TAB_BAR_HEIGHT + self.log_height()),
+        }
+    }
+
+    fn log_height(&self) -> usize {
+        if self.show_log {
+            LOG_PANEL_HEIGHT
+        } else {
+            0
+        }
+    }
+
+    fn resize_editors(&mut self) {
+        let size = self.doc_size();
+        for editor in self.editors.iter_mut() {
+            editor.resize(size);
+        }
+    }
+
+    /// Where the document is drawn, between the tab bar and the status bar or server log
+    fn text_area(&self) -> Rect {
+        let size = self.doc_size();
+        Rect {
+            x: 0,
+            y: TAB_BAR_HEIGHT as u16,
+            width: size.w as u16,
+            height: size.h as u16,
+        }
+    }
+
+    fn handle_prompt_key_event(
+        &mut self,
+        prompt: Prompt,
+        event: &KeyEvent,
+    ) -> Option<elm_ui::Command> {
+        match (prompt, event.code) {
+            (Prompt::SaveBeforeClose, KeyCode::Char('y')) => {
+                let editor = self.current_editor_mut();
+                // Closing would throw away the changes that couldn't be written
+                if let Err(e) = editor.save() {
+                    let message = save_failed(editor.buffer(), &e);
+                    self.status_error = Some(message);
+                    return None;
                 }
+                let mut commands: Vec<_> = editor.get_did_save_command().into_iter().collect();
+                commands.push(self.close_editor());
+                return Some(elm_ui::Command::simple(Message::Sequence(commands)));
             }
-            (KeyModifiers::NONE, KeyCode::Down) => {
-                if is_showing_completions && !self.completion_menu_state.is_empty() {
-                    self.completion_menu_state.next();
-                    self.show_completions = true;
-                } else {
-                    self.current_doc_mut().move_down();
-                }
+            (Prompt::SaveBeforeClose, KeyCode::Char('n')) => {
+                return Some(self.close_editor());
+            }
+            (Prompt::Rename(mut name), KeyCode::Char(c)) => {
+                name.push(c);
+                self.prompt = Some(Prompt::Rename(name));
             }
-            (KeyModifiers::NONE, KeyCode::Left) => {
-                self.current_doc_mut().move_left();
+            (Prompt::Rename(mut name), KeyCode::Backspace) => {
+                name.pop();
+                self.prompt = Some(Prompt::Rename(name));
             }
-            (KeyModifiers::NONE, KeyCode::Right) => {
-                self.current_doc_mut().move_right();
+            (Prompt::Rename(name), KeyCode::Enter) => {
+                if !name.is_empty() {
+                    return Some(self.current_editor().get_rename_command(name));
+                }
             }
-            (KeyModifiers::CONTROL, KeyCode::Char('q')) => return Some(elm_ui::Command::quit()),
-            (KeyModifiers::SHIFT | KeyModifiers::NONE, KeyCode::Char(c)) => {
-                changes.extend(self.character(c));
+            (Prompt::GotoLine(mut line), KeyCode::Char(c)) if c.is_ascii_digit() => {
+                line.push(c);
+                self.prompt = Some(Prompt::GotoLine(line));
             }
-            (KeyModifiers::NONE, KeyCode::Tab) => {
-                changes.extend(self.character('\t'));
+            (Prompt::GotoLine(mut line), KeyCode::Backspace) => {
+                line.pop();
+                self.prompt = Some(Prompt::GotoLine(line));
             }
-            (KeyModifiers::NONE, KeyCode::Backspace) => {
-                if let Some(change) = self.backspace() {
-                    changes.push(change);
+            (Prompt::GotoLine(line), KeyCode::Enter) => {
+                // Too many digits to be a line is past the end, like any other large number
+                if !line.is_empty() {
+                    let line = line.parse().unwrap_or(usize::MAX);
+                    self.current_editor_mut().goto_line(line);
                 }
             }
-            (KeyModifiers::NONE, KeyCode::Enter) => {
-                if let Some(change) = self.enter() {
-                    changes.push(change);
-                }
+            (Prompt::RestartServer(_), KeyCode::Char('y')) => {
+                return Some(self.get_restart_command());
+            }
+            (Prompt::RestartServer(_), KeyCode::Char('n')) => {}
+            (Prompt::Reload(uri), KeyCode::Char('y')) => {
+                let index = self.editors.iter().position(|e| e.uri() == &uri)?;
+                return self.reload(index);
+            }
+            (Prompt::Reload(_), KeyCode::Char('n')) => {}
+            (_, KeyCode::Esc) => {}
+            (prompt, _) => {
+                // Keep waiting for a valid answer
+                self.prompt = Some(prompt);
             }
-            _ => {}
         }
+        None
+    }
 
-        let new_cursor = self.current_doc().cursor;
-        let mut commands = vec![];
-        let mut is_trigger = false;
-        if self.current_doc().cursor != cursor || !changes.is_empty() {
-            if new_cursor.x > 0 {
-                let previous_char = &self
-                    .current_doc()
-                    .line(new_cursor.y)
-                    .unwrap()
-                    .chars()
-                    .nth(new_cursor.x - 1)
-                    .unwrap();
-
-                is_trigger = self
-                    .capabilities
-                    .trigger_characters
-                    .iter()
-                    .any(|t| t == &previous_char.to_string());
-                if previous_char.is_alphanumeric() || *previous_char == '_' || is_trigger {
-                    self.show_completions = true;
-                }
+    fn handle_key_event(&mut self, event: &KeyEvent) -> Option<elm_ui::Command> {
+        // The server is blocked until it gets an answer, so these come before everything else
+        if let Some(request) = self.message_requests.front_mut() {
+            if !request.handle_key_event(event) {
+                self.message_requests.pop_front();
             }
-
-            if !changes.is_empty() {
-                commands.push(self.get_change_command(changes));
+            return None;
+        }
+        if let Some(prompt) = self.prompt.take() {
+            return self.handle_prompt_key_event(prompt, event);
+        }
+        self.status_error = None;
+        self.status_message = None;
+        // Typing into a picker shouldn't run key bindings
+        if self.current_editor().is_picking() {
+            return self.current_editor_mut().handle_key_event(event);
+        }
+        if let Some(mut palette) = self.palette.take() {
+            match palette.handle_key_event(event) {
+                PickerEvent::Continue => self.palette = Some(palette),
+                PickerEvent::Close => {}
+                PickerEvent::Pick(action) => return self.handle_action(action),
             }
+            return None;
+        }
+        match self.config.keys.get(event) {
+            Some(action) => self.handle_action(action),
+            None => self.current_editor_mut().handle_key_event(event),
+        }
+    }
 
-            if self.show_completions {
-                let lsp_pos = self.get_lsp_position(&new_cursor);
-                let word_under_cursor: String = self.current_doc().line(new_cursor.y).unwrap()
-                    [..new_cursor.x]
-                    .chars()
-                    .rev()
-                    .take_while(|c| c.is_alphanumeric() || *c == '_')
-                    .collect::<Vec<_>>()
-                    .iter()
-                    .rev()
-                    .collect();
-
-                let min_completion_length = 2;
-                if !is_trigger && word_under_cursor.len() < min_completion_length {
-                    self.show_completions = false;
+    /// Runs actions that involve more than the current editor and passes the rest on to it
+    fn handle_action(&mut self, action: Action) -> Option<elm_ui::Command> {
+        match action {
+            Action::Quit => Some(self.quit()),
+            Action::CommandPalette => {
+                self.palette = Some(command_palette());
+                None
+            }
+            Action::ToggleLog => {
+                self.show_log = !self.show_log;
+                self.resize_editors();
+                None
+            }
+            Action::Rename => {
+                if self.capabilities.rename {
+                    self.prompt = Some(Prompt::Rename(String::new()));
                 } else {
-                    let lsp_client = self.lsp_client.clone();
-                    let document_uri = self.document_uri.clone();
-
-                    commands.push(elm_ui::Command::new_async(move |_, _| async move {
-                        let completions = lsp_client
-                            .completion(CompletionParams {
-                                text_document_position: TextDocumentPositionParams {
-                                    text_document: TextDocumentIdentifier {
-                                        uri: document_uri.clone(),
-                                    },
-                                    position: lsp_pos,
-                                },
-                                work_done_progress_params: Default::default(),
-                                partial_result_params: Default::default(),
-                                context: Default::default(),
-                            })
-                            .await
-                            .unwrap();
-                        if let Some(completions) = completions {
-                            return Some(Message::custom(LspResponse::Completions(
-                                handle_completion_response(completions, &word_under_cursor),
-                            )));
-                        }
-
-                        None
-                    }));
+                    self.status_error = Some("Rename isn't supported by the server".to_owned());
+                }
+                None
+            }
+            Action::GotoLine => {
+                self.prompt = Some(Prompt::GotoLine(String::new()));
+                None
+            }
+            Action::NextDiagnostic | Action::PreviousDiagnostic => {
+                let forward = action == Action::NextDiagnostic;
+                self.status_message = self
+                    .current_editor_mut()
+                    .goto_diagnostic(forward)
+                    .map(|diagnostic| diagnostic.message.lines().collect::<Vec<_>>().join(" "));
+                None
+            }
+            Action::NextEditor => {
+                self.switch_editor((self.editor_index + 1) % self.editors.len());
+                None
+            }
+            Action::PreviousEditor => {
+                let len = self.editors.len();
+                self.switch_editor((self.editor_index + len - 1) % len);
+                None
+            }
+            Action::Close => {
+                if self.current_editor().doc().modified {
+                    self.prompt = Some(Prompt::SaveBeforeClose);
+                    return None;
                 }
+                Some(self.close_editor())
             }
+            action => self.current_editor_mut().handle_action(action),
         }
-        if !self.show_completions {
-            self.completion_menu_state.set_completions(vec![]);
+    }
+
+    fn switch_editor(&mut self, index: usize) {
+        if index != self.editor_index {
+            self.current_editor_mut().blur();
+            self.editor_index = index;
         }
-        Some(elm_ui::Command::simple(Message::Sequence(commands)))
     }
 
-    fn get_change_command(&self, changes: Vec<(Range, String)>) -> elm_ui::Command {
+    fn close_editor(&mut self) -> elm_ui::Command {
+        if self.editors.len() == 1 {
+            return self.quit();
+        }
+        let editor = self.editors.remove(self.editor_index);
+        self.editor_index = self.editor_index.min(self.editors.len() - 1);
+        remember_view_state(&mut self.view_states, &editor);
+        self.save_view_states();
+        editor.get_close_command()
+    }
+
+    /// Saves where each editor was before shutting down
+    fn quit(&mut self) -> elm_ui::Command {
+        for editor in &self.editors {
+            remember_view_state(&mut self.view_states, editor);
+        }
+        self.save_view_states();
+        self.get_quit_command()
+    }
+
+    fn save_view_states(&self) {
+        if !self.config.editor.restore_positions {
+            return;
+        }
+        if let Err(e) = self.view_states.save(Path::new(VIEW_STATES_PATH)) {
+            warn!("Failed to save view states: {e:?}");
+        }
+    }
+
+    /// Finishes the handshake with a newly initialized server and opens every buffer on it
+    fn get_initialized_command(&self) -> elm_ui::Command {
         let lsp_client = self.lsp_client.clone();
-        let document_uri = self.document_uri.clone();
-        let document_version = self.document_version.fetch_add(1, Ordering::SeqCst);
+        let initialized_command = elm_ui::Command::new_async(move |_, _| async move {
+            lsp_client.initialized().await;
+            None
+        });
+        let mut commands = vec![initialized_command];
+        commands.extend(self.editors.iter().map(Editor::get_open_command));
+        elm_ui::Command::simple(Message::Batch(vec![
+            elm_ui::Command::simple(Message::Sequence(commands)),
+            self.get_notification_command(),
+        ]))
+    }
+
+    /// Starts a new server after a delay that grows while the server keeps crashing
+    fn get_restart_command(&mut self) -> elm_ui::Command {
+        let recently_restarted = self
+            .last_restart
+            .is_some_and(|last| last.elapsed() < RESTART_RESET_AFTER);
+        if !recently_restarted {
+            self.restart_attempts = 0;
+        }
+        let delay = RESTART_BACKOFF
+            .saturating_mul(2u32.saturating_pow(self.restart_attempts))
+            .min(MAX_RESTART_BACKOFF);
+        self.restart_attempts += 1;
+        self.last_restart = Some(Instant::now() + delay);
+        self.status_error = Some(format!(
+            "Restarting language server in {}s",
+            delay.as_secs()
+        ));
+
+        let config = self.config.server.clone();
+        let root = self.root.clone();
         elm_ui::Command::new_async(move |_, _| async move {
-            lsp_client
-                .did_change(DidChangeTextDocumentParams {
-                    text_document: VersionedTextDocumentIdentifier {
-                        uri: document_uri,
-                        version: document_version,
-                    },
-                    content_changes: changes
-                        .into_iter()
-                        .map(|(range, text)| TextDocumentContentChangeEvent {
-                            range: Some(range),
-                            text,
-                            range_length: None,
-                        })
-                        .collect(),
-                })
-                .await;
+            tokio::time::sleep(delay).await;
+            let connection = match connect(&config) {
+                Ok(connection) => connection,
+                Err(e) => {
+                    warn!("Failed to restart the language server: {e:?}");
+                    return Some(Message::custom(LspResponse::RequestFailed(format!(
+                        "Failed to restart the language server: {e}"
+                    ))));
+                }
+            };
+            let initialized = initialize_server(&connection.lsp_client, &config, &root).await;
+            let capabilities = match initialized {
+                Ok(capabilities) => capabilities,
+                Err(e) => return Some(Message::custom(LspResponse::RequestFailed(e))),
+            };
+            Some(Message::custom(LspResponse::Restarted(
+                connection,
+                capabilities,
+            )))
+        })
+    }
 
+    /// Closes the open documents and shuts the server down before quitting so a spawned
+    /// server isn't left running
+    fn get_quit_command(&self) -> elm_ui::Command {
+        let lsp_client = self.lsp_client.clone();
+        let server_process = self.server_process.clone();
+        let uris: Vec<_> = self.editors.iter().map(|e| e.uri().clone()).collect();
+        let shutdown_command = elm_ui::Command::new_async(move |_, _| async move {
+            for uri in uris {
+                lsp_client
+                    .did_close(DidCloseTextDocumentParams {
+                        text_document: TextDocumentIdentifier { uri },
+                    })
+                    .await;
+            }
+            match tokio::time::timeout(SHUTDOWN_TIMEOUT, lsp_client.shutdown()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => warn!("Shutdown request failed: {e:?}"),
+                Err(_) => warn!("Shutdown request timed out"),
+            }
+            match server_process {
+                Some(process) => {
+                    let (done_tx, done_rx) = oneshot::channel();
+                    // Stop watching for a crash before the server exits on purpose
+                    let stopping = process.stop_tx.send(done_tx).is_ok();
+                    lsp_client.exit().await;
+                    if stopping {
+                        let _ = done_rx.await;
+                    }
+                }
+                None => lsp_client.exit().await,
+            }
             None
+        });
+        elm_ui::Command::simple(Message::Sequence(vec![
+            shutdown_command,
+            elm_ui::Command::quit(),
+        ]))
+    }
+
+    fn get_notification_command(&self) -> elm_ui::Command {
+        let notification_rx = self.notification_rx.clone();
+        elm_ui::Command::new_async(move |_, _| async move {
+            let notification = notification_rx.lock().await.recv().await?;
+            Some(Message::custom(notification))
         })
     }
 
-    fn enter(&mut self) -> Option<(Range, String)> {
-        if self.current_doc().loc().y != self.current_doc().len_lines() {
-            // Enter pressed in the middle or end of the line
-            let loc = self.current_doc().char_loc();
-            let lsp_pos = self.get_lsp_position(&loc);
-            self.current_doc_mut()
-                .exe(kaolinite::event::Event::SplitDown(loc))
-                .unwrap();
-            Some((
-                Range {
-                    start: lsp_pos,
-                    end: Position {
-                        line: lsp_pos.line + 1,
-                        character: 0,
-                    },
-                },
-                "\r\n".to_owned(),
-            ))
-        } else {
-            // Enter pressed on the empty line at the bottom of the document
-            self.new_row()
-        }
-    }
-
-    fn backspace(&mut self) -> Option<(Range, String)> {
-        let mut c = self.current_doc().char_ptr;
-        let on_first_line = self.current_doc().loc().y == 0;
-        let out_of_range = self
-            .current_doc()
-            .out_of_range(0, self.current_doc().loc().y)
-            .is_err();
-        if c == 0 && !on_first_line && !out_of_range {
-            // Backspace was pressed on the start of the line, move line to the top
-            self.new_row();
-            let mut loc = self.current_doc().char_loc();
-            loc.y -= 1;
-            loc.x = self.current_doc().line(loc.y).unwrap().chars().count();
-            let lsp_pos = self.get_lsp_position(&loc);
-            self.current_doc_mut()
-                .exe(kaolinite::event::Event::SpliceUp(loc))
-                .unwrap();
-            return Some((
-                Range {
-                    start: lsp_pos,
-                    end: Position {
-                        line: lsp_pos.line + 1,
-                        character: 0,
-                    },
-                },
-                "".to_owned(),
-            ));
-        } else if c > 0 {
-            // Backspace was pressed in the middle of the line, delete the character
-            c -= 1;
-            if let Some(line) = self.current_doc().line(self.current_doc().loc().y) {
-                if let Some(ch) = line.chars().nth(c) {
-                    let loc = Loc {
-                        x: c,
-                        y: self.current_doc().loc().y,
-                    };
-                    let lsp_pos = self.get_lsp_position(&loc);
-                    self.current_doc_mut()
-                        .exe(kaolinite::event::Event::Delete(loc, ch.to_string()))
-                        .unwrap();
-                    return Some((
-                        Range {
-                            start: lsp_pos,
-                            end: Position {
-                                line: lsp_pos.line,
-                                character: lsp_pos.character + 1,
-                            },
-                        },
-                        "".to_owned(),
-                    ));
+    fn handle_notification(&mut self, notification: &ClientNotification) {
+        match notification {
+            ClientNotification::Diagnostics(params) => {
+                if let Some(editor) = self.editors.iter_mut().find(|e| e.uri() == &params.uri) {
+                    editor.set_diagnostics(params.diagnostics.clone());
+                }
+            }
+            ClientNotification::ServerExited(code) => {
+                self.prompt = Some(Prompt::RestartServer(*code));
+            }
+            ClientNotification::LogMessage(params) => {
+                self.server_log.push(params.typ, params.message.clone());
+            }
+            ClientNotification::ShowMessage(params) => {
+                self.server_log.push(params.typ, params.message.clone());
+                let message = params.message.lines().collect::<Vec<_>>().join(" ");
+                match params.typ {
+                    MessageType::ERROR | MessageType::WARNING => self.status_error = Some(message),
+                    _ => self.status_message = Some(message),
                 }
             }
+            ClientNotification::Progress(params) => self.progress.update(params),
+            ClientNotification::ShowMessageRequest(params, responder) => {
+                self.server_log.push(params.typ, params.message.clone());
+                self.message_requests
+                    .push_back(MessageRequest::new(params.clone(), responder.clone()));
+            }
         }
-        None
     }
 
-    fn character(&mut self, ch: char) -> Vec<(Range, String)> {
-        let mut changes = vec![];
-        if let Some(change) = self.new_row() {
-            changes.push(change);
-        }
+    fn current_editor(&self) -> &Editor {
+        &self.editors[self.editor_index]
+    }
 
-        let loc = self.current_doc().char_loc();
-        let lsp_pos = self.get_lsp_position(&loc);
-        self.current_doc_mut()
-            .exe(kaolinite::event::Event::Insert(loc, ch.to_string()))
-            .unwrap();
-        changes.push((
-            Range {
-                start: lsp_pos,
-                end: lsp_pos,
-            },
-            ch.to_string(),
-        ));
-        changes
-    }
-
-    fn new_row(&mut self) -> Option<(Range, String)> {
-        if self.current_doc().loc().y == self.current_doc().len_lines() {
-            let loc = self.current_doc().loc();
-            self.current_doc_mut()
-                .exe(kaolinite::event::Event::InsertLine(loc.y, "".to_string()))
-                .unwrap();
-            let lsp_pos = self.get_lsp_position(&Loc {
-                x: self.current_doc().line(loc.y).unwrap().len(),
-                y: loc.y,
-            });
-            Some((
-                Range {
-                    start: lsp_pos,
-                    end: Position {
-                        line: lsp_pos.line + 1,
-                        character: 0,
-                    },
-                },
-                "\r\n".to_string(),
-            ))
-        } else {
-            None
-        }
+    fn current_editor_mut(&mut self) -> &mut Editor {
+        &mut self.editors[self.editor_index]
     }
+}
 
-    fn get_lsp_position(&self, loc: &Loc) -> Position {
-        let new_loc = match self.capabilities.encoding {
-            Encoding::Utf8 => self.current_doc().to_utf8_loc(loc),
-            Encoding::Utf16 => self.current_doc().to_utf16_loc(loc),
-            Encoding::Utf32 => *loc,
-        };
-        Position {
-            line: new_loc.y as u32,
-            character: new_loc.x as u32,
+fn remember_view_state(view_states: &mut ViewStates, editor: &Editor) {
+    // Unsaved documents don't have a path to remember them by
+    if editor.buffer().scratch {
+        return;
+    }
+    if let Ok(path) = editor.uri().to_file_path() {
+        view_states.insert(path, editor.view_state());
+    }
+}
+
+/// Explains why the configured server couldn't be started and how to fix it
+fn spawn_error(config: &ServerConfig, error: io::Error) -> anyhow::Error {
+    let command = &config.command;
+    if error.kind() == io::ErrorKind::NotFound {
+        anyhow::anyhow!(
+            "Couldn't find the language server `{command}`. Install it (for \
+             `typescript-language-server`, run `npm install -g typescript-language-server \
+             typescript`), set `server.command` in the config to a server that's installed, or \
+             set `server.local = true` to use the built-in one."
+        )
+    } else {
+        anyhow::anyhow!("Couldn't start the language server `{command}`: {error}")
+    }
+}
+
+/// Starts the configured server, or the built-in one, and connects a client to it.
+/// A spawned server's exit is reported through the notification channel unless it was stopped.
+fn connect(config: &ServerConfig) -> io::Result<ServerConnection> {
+    let (notification_tx, notification_rx) = mpsc::unbounded_channel();
+    let exit_tx = notification_tx.clone();
+    let settings = config.settings.clone();
+    let lsp_client;
+    let mut process = None;
+    if config.local {
+        let (in_stream, out_stream, _) = start_local_server(config.local_buffer_size);
+        lsp_client = serve_client(out_stream, in_stream, notification_tx, settings);
+    } else {
+        let mut child = tokio::process::Command::new(&config.command)
+            .args(&config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = BufWriter::new(child.stdin.take().unwrap());
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        lsp_client = serve_client(stdout, stdin, notification_tx, settings);
+        let (stop_tx, mut stop_rx) = mpsc::unbounded_channel::<oneshot::Sender<()>>();
+        tokio::spawn(async move {
+            tokio::select! {
+                status = child.wait() => {
+                    warn!("Language server exited: {status:?}");
+                    let code = status.ok().and_then(|status| status.code());
+                    // The receiver is gone if the server was already replaced
+                    let _ = exit_tx.send(ClientNotification::ServerExited(code));
+                }
+                Some(done_tx) = stop_rx.recv() => {
+                    if tokio::time::timeout(SHUTDOWN_TIMEOUT, child.wait()).await.is_err() {
+                        warn!("Language server didn't exit, killing it");
+                        if let Err(e) = child.kill().await {
+                            warn!("Failed to kill the language server: {e:?}");
+                        }
+                    }
+                    let _ = done_tx.send(());
+                }
+            }
+        });
+        process = Some(ServerProcess { stop_tx });
+    }
+    Ok(ServerConnection {
+        lsp_client,
+        notification_rx: Arc::new(Mutex::new(notification_rx)),
+        process,
+    })
+}
+
+/// Sends `initialize` and returns the server's capabilities or a message for the status bar
+async fn initialize_server(
+    lsp_client: &tower_lsp::Client<ClientToServer>,
+    config: &ServerConfig,
+    root: &Path,
+) -> Result<ServerCapabilities, String> {
+    match lsp_client.initialize(initialize_params(config, root)).await {
+        Ok(InitializeResult { capabilities, .. }) => Ok(capabilities),
+        Err(e) => {
+            warn!("Initialize request failed: {e:?}");
+            Err(format!("Language server failed to start: {}", e.message))
         }
     }
 }
 
-fn handle_completion_response(
-    completions: CompletionResponse,
-    word_under_cursor: &str,
-) -> Vec<String> {
-    match completions {
-        CompletionResponse::Array(items) => {
-            let mut filtered: Vec<_> = items
+/// An empty file for a new document, named so the server can tell what language it's in
+fn new_scratch_file(language_id: &str) -> io::Result<NamedTempFile> {
+    let extension = LanguageRegistry::builtin()
+        .extension(language_id)
+        .or_else(|| {
+            LANGUAGE_SERVERS
                 .iter()
-                .filter(|i| i.label.starts_with(word_under_cursor))
-                .collect();
-            filtered.sort_by(|a, b| a.sort_text.cmp(&b.sort_text));
-            filtered.into_iter().map(|i| i.label.clone()).collect()
-        }
-        CompletionResponse::List(list) => {
-            let mut filtered: Vec<_> = list
-                .items
+                .find(|server| server.language_id == language_id)
+                .and_then(|server| server.extensions.first().copied())
+        })
+        .map(|extension| format!(".{extension}"))
+        .unwrap_or_default();
+    tempfile::Builder::new()
+        .prefix("untitled-")
+        .suffix(&extension)
+        .tempfile()
+}
+
+/// The language of the file, going by its extension
+fn language_id(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?;
+    LanguageRegistry::builtin()
+        .language_id(extension)
+        .or_else(|| {
+            LANGUAGE_SERVERS
                 .iter()
-                .filter(|i| {
-                    if let Some(filter_text) = &i.filter_text {
-                        filter_text.starts_with(word_under_cursor)
-                    } else {
-                        i.label.starts_with(word_under_cursor)
-                    }
-                })
-                .collect();
-            filtered.sort_by(|a, b| a.sort_text.cmp(&b.sort_text));
-            filtered.into_iter().map(|i| i.label.clone()).collect()
+                .find(|server| server.extensions.contains(&extension))
+                .map(|server| server.language_id)
+        })
+}
+
+/// The server for the first of the files that exists, like the one [`open_files`] starts on
+fn server_for_files(config: &Config, files: &[PathBuf]) -> ServerConfig {
+    let first = files.iter().find(|path| path.is_file());
+    config.server_for(first.and_then(|path| language_id(path)))
+}
+
+/// Whether `server` is the one for documents in the language. Languages that only differ by ID,
+/// like JavaScript and TypeScript, share a server.
+fn serves(config: &Config, server: &ServerConfig, language_id: Option<&str>) -> bool {
+    let other = config.server_for(language_id);
+    (other.local, &other.command, &other.args) == (server.local, &server.command, &server.args)
+}
+
+/// A warning listing the files that were skipped, if there are any
+fn skipped_files_warning(reason: &str, files: &[PathBuf]) -> Option<String> {
+    let names: Vec<_> = files.iter().map(|path| path.to_string_lossy()).collect();
+    (!names.is_empty()).then(|| format!("Skipped files that {reason}: {}", names.join(", ")))
+}
+
+/// Opens each file that exists, skipping any that are listed more than once. Returns the
+/// canonical path of each file along with its document, and the files that couldn't be opened.
+fn open_files(files: Vec<PathBuf>, size: Size) -> (Vec<(PathBuf, Document)>, Vec<PathBuf>) {
+    let mut docs: Vec<(PathBuf, Document)> = vec![];
+    let mut skipped = vec![];
+    for file in files {
+        let Ok(path) = file.canonicalize() else {
+            warn!("Skipping missing file {file:?}");
+            skipped.push(file);
+            continue;
+        };
+        if docs.iter().any(|(opened, _)| *opened == path) {
+            continue;
+        }
+        match Document::open(size, path.to_string_lossy()) {
+            Ok(doc) => docs.push((path, doc)),
+            Err(e) => {
+                warn!("Failed to open {path:?}: {e:?}");
+                skipped.push(file);
+            }
         }
     }
+    (docs, skipped)
 }
 
-pub fn start_local_server() -> (DuplexStream, DuplexStream) {
-    let language = tree_sitter_javascript::language();
-    let (req_client, req_server) = tokio::io::duplex(1024);
-    let (resp_server, resp_client) = tokio::io::duplex(1024);
-    let (server_service, server_socket) =
-        LspService::new_server(|client| Server::new(client, language));
-    tokio::spawn(
-        tower_lsp::Server::new(req_server, resp_server, server_socket).serve(server_service),
-    );
-    (req_client, resp_client)
+/// The directory of the opened file, or the working directory for a new document
+fn workspace_root(path: Option<&Path>) -> PathBuf {
+    path.and_then(Path::parent)
+        .map(Path::to_path_buf)
+        .or_else(|| env::current_dir().ok())
+        .unwrap_or_else(|| PathBuf::from("/"))
 }
 
-pub fn initialize_params() -> InitializeParams {
+pub fn initialize_params(config: &ServerConfig, root: &Path) -> InitializeParams {
+    let root_uri = Url::from_directory_path(root).ok();
+    let workspace_folders = root_uri.clone().map(|uri| {
+        let name = root
+            .file_name()
+            .unwrap_or(root.as_os_str())
+            .to_string_lossy()
+            .into_owned();
+        vec![WorkspaceFolder { uri, name }]
+    });
     InitializeParams {
-        // initialization_options: Some(json!({
-        //     "tsserver": {
-        //         "path": "/home/aschey/.nvm/versions/node/v18.12.1/lib/tsserver.js"
-        //     }
-        // })),
+        root_uri,
+        workspace_folders,
+        initialization_options: config.initialization_options.clone(),
         capabilities: ClientCapabilities {
             general: Some(GeneralClientCapabilities {
                 position_encodings: Some(vec![
@@ -527,12 +1219,68 @@ pub fn initialize_params() -> InitializeParams {
                 ]),
                 ..Default::default()
             }),
+            workspace: Some(WorkspaceClientCapabilities {
+                configuration: Some(true),
+                workspace_folders: Some(true),
+                ..Default::default()
+            }),
+            window: Some(WindowClientCapabilities {
+                work_done_progress: Some(true),
+                ..Default::default()
+            }),
             text_document: Some(TextDocumentClientCapabilities {
+                completion: Some(CompletionClientCapabilities {
+                    completion_item: Some(CompletionItemCapability {
+                        snippet_support: Some(true),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
                 synchronization: Some(TextDocumentSyncClientCapabilities {
                     dynamic_registration: Some(true),
                     will_save: Some(false),
-                    will_save_wait_until: Some(false),
-                    did_save: Some(false),
+                    will_save_wait_until: Some(true),
+                    did_save: Some(true),
+                }),
+                document_highlight: Some(DocumentHighlightClientCapabilities {
+                    dynamic_registration: Some(false),
+                }),
+                semantic_tokens: Some(SemanticTokensClientCapabilities {
+                    dynamic_registration: Some(false),
+                    requests: SemanticTokensClientCapabilitiesRequests {
+                        range: Some(false),
+                        full: Some(SemanticTokensFullOptions::Bool(true)),
+                    },
+                    token_types: vec![
+                        SemanticTokenType::NAMESPACE,
+                        SemanticTokenType::TYPE,
+                        SemanticTokenType::CLASS,
+                        SemanticTokenType::ENUM,
+                        SemanticTokenType::INTERFACE,
+                        SemanticTokenType::STRUCT,
+                        SemanticTokenType::TYPE_PARAMETER,
+                        SemanticTokenType::PARAMETER,
+                        SemanticTokenType::VARIABLE,
+                        SemanticTokenType::PROPERTY,
+                        SemanticTokenType::ENUM_MEMBER,
+                        SemanticTokenType::FUNCTION,
+                        SemanticTokenType::METHOD,
+                        SemanticTokenType::KEYWORD,
+                        SemanticTokenType::COMMENT,
+                        SemanticTokenType::STRING,
+                        SemanticTokenType::NUMBER,
+                        SemanticTokenType::REGEXP,
+                        SemanticTokenType::OPERATOR,
+                        SemanticTokenType::DECORATOR,
+                    ],
+                    token_modifiers: vec![],
+                    formats: vec![TokenFormat::RELATIVE],
+                    multiline_token_support: Some(false),
+                    ..Default::default()
+                }),
+                inlay_hint: Some(InlayHintClientCapabilities {
+                    dynamic_registration: Some(false),
+                    resolve_support: None,
                 }),
                 document_symbol: Some(DocumentSymbolClientCapabilities {
                     dynamic_registration: Some(true),
@@ -578,3 +1326,527 @@ pub fn initialize_params() -> InitializeParams {
         ..Default::default()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+    use elm_ui::{Message, Model, OptionalCommand};
+    use kaolinite::{event::Event, Document, Loc, Size};
+    use serde_json::Value;
+    use tokio::sync::mpsc;
+    use tokio_util::sync::CancellationToken;
+    use tower_lsp::{jsonrpc, lsp_types::*, ClientToServer, LanguageServer};
+
+    use super::{
+        initialize_params, language_id, new_scratch_file, open_files, server_for_files,
+        workspace_root, App,
+    };
+    use crate::client::{serve_client, ClientNotification};
+    use crate::config::{Config, ServerConfig};
+    use crate::server::{serve_in_process, start_local_server};
+    use crate::tui::editor::encode_change;
+    use crate::tui::lsp_capabilities::LspCapabilities;
+
+    #[tokio::test]
+    async fn incremental_changes_round_trip() {
+        let buffer_size = ServerConfig::default().local_buffer_size;
+        let (in_stream, out_stream, session) = start_local_server(buffer_size);
+        let (notification_tx, _notification_rx) = mpsc::unbounded_channel();
+        let lsp_client = serve_client(out_stream, in_stream, notification_tx, Value::Null);
+
+        let InitializeResult { capabilities, .. } = lsp_client
+            .initialize(initialize_params(
+                &ServerConfig::default(),
+                &env::current_dir().unwrap(),
+            ))
+            .await
+            .unwrap();
+        let capabilities: LspCapabilities = capabilities.into();
+        assert_eq!(
+            TextDocumentSyncKind::INCREMENTAL,
+            capabilities.text_document_sync
+        );
+        lsp_client.initialized().await;
+
+        let mut doc = Document::open_empty(Size { w: 80, h: 24 });
+        doc.exe(Event::InsertLine(0, "let a = 'é';".to_owned()))
+            .unwrap();
+        doc.exe(Event::InsertLine(1, "let b = '😀';".to_owned()))
+            .unwrap();
+        let uri: Url = "file:///test.js".parse().unwrap();
+        lsp_client
+            .did_open(TextDocumentItem::new(
+                uri.clone(),
+                "javascript".to_owned(),
+                0,
+                doc.rope().to_string(),
+            ))
+            .await;
+
+        // Each change is encoded against the document as it was before its event
+        let events = [
+            Event::Insert(Loc { x: 10, y: 0 }, "ü".to_owned()),
+            Event::Delete(Loc { x: 9, y: 1 }, "😀".to_owned()),
+            Event::Insert(Loc { x: 9, y: 1 }, "ß".to_owned()),
+            Event::SplitDown(Loc { x: 7, y: 0 }),
+            Event::SpliceUp(Loc { x: 7, y: 0 }),
+            Event::SplitDown(Loc { x: 7, y: 1 }),
+            Event::InsertLine(1, "// ö".to_owned()),
+            Event::DeleteLine(0, "let a = 'éü';".to_owned()),
+        ];
+        let mut content_changes = vec![];
+        for event in events {
+            let (range, text) = encode_change(&doc, &event, capabilities.encoding);
+            content_changes.push(TextDocumentContentChangeEvent {
+                range: Some(range),
+                range_length: None,
+                text,
+            });
+            doc.exe(event).unwrap();
+        }
+        lsp_client
+            .did_change(DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier::new(uri.clone(), 1),
+                content_changes,
+            })
+            .await;
+
+        let lines: Vec<_> = (0..3).filter_map(|y| doc.line(y)).collect();
+        assert_eq!(vec!["// ö", "let b =", " 'ß';"], lines);
+        let expected = doc.rope().to_string();
+        // Notifications are handled asynchronously, so wait for the server to catch up
+        for _ in 0..100 {
+            let actual = session
+                .get_text(&uri)
+                .await
+                .map(|text| text.content.to_string());
+            if actual.ok().as_ref() == Some(&expected) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("server text never matched the client");
+    }
+
+    #[test]
+    fn initialization_options_from_config() {
+        let config: Config = toml::from_str(
+            r#"
+            [server.initialization_options.tsserver]
+            path = "/usr/lib/tsserver.js"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            Some(serde_json::json!({ "tsserver": { "path": "/usr/lib/tsserver.js" } })),
+            initialize_params(&config.server, Path::new("/")).initialization_options
+        );
+        assert_eq!(
+            None,
+            initialize_params(&ServerConfig::default(), Path::new("/")).initialization_options
+        );
+    }
+
+    #[test]
+    fn workspace_is_the_directory_of_the_file() {
+        let root = workspace_root(Some(Path::new("/projects/app/src/main.ts")));
+        assert_eq!(Path::new("/projects/app/src"), root);
+        assert_eq!(env::current_dir().unwrap(), workspace_root(None));
+
+        let params = initialize_params(&ServerConfig::default(), &root);
+        let uri: Url = "file:///projects/app/src/".parse().unwrap();
+        assert_eq!(Some(&uri), params.root_uri.as_ref());
+        assert_eq!(
+            Some(vec![WorkspaceFolder {
+                uri,
+                name: "src".to_owned()
+            }]),
+            params.workspace_folders
+        );
+    }
+
+    #[test]
+    fn server_for_the_language_of_the_file() {
+        let config: Config = toml::from_str(
+            r#"
+            [server]
+            local = true
+
+            [servers.python]
+            command = "pylsp"
+            args = []
+
+            [servers.rust.initialization_options]
+            checkOnSave = false
+            "#,
+        )
+        .unwrap();
+        assert_eq!(Some("typescriptreact"), language_id(Path::new("view.tsx")));
+        assert_eq!(None, language_id(Path::new("Makefile")));
+
+        let rust = config.server_for(language_id(Path::new("src/main.rs")));
+        assert_eq!("rust-analyzer", rust.command);
+        assert_eq!("rust", rust.language_id);
+        assert!(!rust.local);
+        assert!(rust.args.is_empty());
+        assert_eq!(
+            Some(serde_json::json!({ "checkOnSave": false })),
+            rust.initialization_options
+        );
+        let python = config.server_for(Some("python"));
+        assert_eq!(("pylsp", 0), (&*python.command, python.args.len()));
+        assert!(!python.local);
+
+        // Everything else falls back to `server`, named by its language when it's known
+        let javascript = config.server_for(Some("javascript"));
+        assert_eq!("typescript-language-server", javascript.command);
+        assert_eq!("javascript", javascript.language_id);
+        assert!(javascript.local);
+        assert_eq!("typescript", config.server_for(None).language_id);
+
+        // Files that don't exist are skipped
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("main.py");
+        fs::write(&file, "").unwrap();
+        let files = [dir.path().join("missing.rs"), file];
+        assert_eq!("pylsp", server_for_files(&config, &files).command);
+    }
+
+    #[test]
+    fn scratch_file_is_removed_when_dropped() {
+        let file = new_scratch_file("typescript").unwrap();
+        let path = file.path().to_path_buf();
+        assert!(path.exists());
+        assert_eq!(Some("ts".as_ref()), path.extension());
+        drop(file);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn open_existing_files_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a.ts"), dir.path().join("b.ts"));
+        fs::write(&a, "let a = 1;").unwrap();
+        fs::write(&b, "let b = 2;").unwrap();
+        let missing = dir.path().join("missing.ts");
+
+        let files = vec![b.clone(), missing.clone(), a.clone(), b.clone()];
+        let (docs, skipped) = open_files(files, Size { w: 80, h: 24 });
+        let paths: Vec<PathBuf> = docs.iter().map(|(path, _)| path.clone()).collect();
+        assert_eq!(
+            vec![b.canonicalize().unwrap(), a.canonicalize().unwrap()],
+            paths
+        );
+        assert_eq!(Some("let b = 2;".to_owned()), docs[0].1.line(0));
+        assert_eq!(vec![missing], skipped);
+    }
+
+    #[tokio::test]
+    async fn drive_keys_without_a_terminal() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("main.js");
+        fs::write(&path, "let a = 1;").unwrap();
+        let mut config = Config::default();
+        config.server.local = true;
+        config.editor.restore_positions = false;
+        let mut app = App::initialize(config, vec![path]).await.unwrap();
+
+        let press = |app: &mut App, code| app.apply_key(KeyEvent::new(code, KeyModifiers::NONE));
+        for c in "ab".chars() {
+            assert!(press(&mut app, KeyCode::Char(c)).is_some());
+        }
+        press(&mut app, KeyCode::Backspace);
+        assert_eq!(Some("alet a = 1;"), app.text().lines().next());
+
+        // Nothing is listed until the server responds
+        assert!(app.completions().is_empty());
+    }
+
+    /// Answers requests with the same canned responses no matter where they're made
+    struct MockServer;
+
+    #[tower_lsp::async_trait]
+    impl LanguageServer for MockServer {
+        async fn initialize(&self, _: InitializeParams) -> jsonrpc::Result<InitializeResult> {
+            Ok(InitializeResult {
+                capabilities: ServerCapabilities {
+                    text_document_sync: Some(TextDocumentSyncCapability::Kind(
+                        TextDocumentSyncKind::INCREMENTAL,
+                    )),
+                    completion_provider: Some(CompletionOptions::default()),
+                    hover_provider: Some(HoverProviderCapability::Simple(true)),
+                    definition_provider: Some(OneOf::Left(true)),
+                    // Advertised without being implemented, so formatting requests fail
+                    document_formatting_provider: Some(OneOf::Left(true)),
+                    ..Default::default()
+                },
+                server_info: None,
+            })
+        }
+
+        async fn shutdown(&self) -> jsonrpc::Result<()> {
+            Ok(())
+        }
+
+        async fn completion(
+            &self,
+            _: CompletionParams,
+        ) -> jsonrpc::Result<Option<CompletionResponse>> {
+            Ok(Some(CompletionResponse::Array(vec![
+                CompletionItem::new_simple("console".to_owned(), "var console: Console".to_owned()),
+            ])))
+        }
+
+        async fn hover(&self, _: HoverParams) -> jsonrpc::Result<Option<Hover>> {
+            Ok(Some(Hover {
+                contents: HoverContents::Markup(MarkupContent {
+                    kind: MarkupKind::PlainText,
+                    value: "let a: number".to_owned(),
+                }),
+                range: None,
+            }))
+        }
+
+        async fn goto_definition(
+            &self,
+            params: GotoDefinitionParams,
+        ) -> jsonrpc::Result<Option<GotoDefinitionResponse>> {
+            let uri = params.text_document_position_params.text_document.uri;
+            let location = Location::new(uri, Range::new(Position::new(0, 4), Position::new(0, 5)));
+            Ok(Some(GotoDefinitionResponse::Scalar(location)))
+        }
+    }
+
+    /// Edits the document both before it's saved and when it's formatted
+    struct SavingServer;
+
+    #[tower_lsp::async_trait]
+    impl LanguageServer for SavingServer {
+        async fn initialize(&self, _: InitializeParams) -> jsonrpc::Result<InitializeResult> {
+            Ok(InitializeResult {
+                capabilities: ServerCapabilities {
+                    text_document_sync: Some(TextDocumentSyncCapability::Options(
+                        TextDocumentSyncOptions {
+                            change: Some(TextDocumentSyncKind::INCREMENTAL),
+                            will_save_wait_until: Some(true),
+                            ..Default::default()
+                        },
+                    )),
+                    document_formatting_provider: Some(OneOf::Left(true)),
+                    ..Default::default()
+                },
+                server_info: None,
+            })
+        }
+
+        async fn shutdown(&self) -> jsonrpc::Result<()> {
+            Ok(())
+        }
+
+        async fn will_save_wait_until(
+            &self,
+            _: WillSaveTextDocumentParams,
+        ) -> jsonrpc::Result<Option<Vec<TextEdit>>> {
+            let start = Position::new(0, 0);
+            let edit = TextEdit::new(Range::new(start, start), "'use strict';\n".to_owned());
+            Ok(Some(vec![edit]))
+        }
+
+        async fn formatting(
+            &self,
+            _: DocumentFormattingParams,
+        ) -> jsonrpc::Result<Option<Vec<TextEdit>>> {
+            // Only lines up once the pre-save edit has been applied
+            let range = Range::new(Position::new(1, 5), Position::new(1, 6));
+            Ok(Some(vec![TextEdit::new(range, " = ".to_owned())]))
+        }
+    }
+
+    /// A client connected to `server` running in-process
+    fn mock_client<S: LanguageServer>(
+        server: S,
+    ) -> (
+        Arc<tower_lsp::Client<ClientToServer>>,
+        mpsc::UnboundedReceiver<ClientNotification>,
+    ) {
+        let buffer_size = ServerConfig::default().local_buffer_size;
+        let (in_stream, out_stream) = serve_in_process(buffer_size, |_| server);
+        let (notification_tx, notification_rx) = mpsc::unbounded_channel();
+        let lsp_client = serve_client(out_stream, in_stream, notification_tx, Value::Null);
+        (lsp_client, notification_rx)
+    }
+
+    /// Runs a command the way the program would, handing each message it produces back to the
+    /// app along with the commands the app returns for them
+    async fn run(app: &mut App, command: OptionalCommand) {
+        let (tx, _rx) = mpsc::channel(16);
+        let mut pending: VecDeque<_> = command.into_iter().collect();
+        while let Some(command) = pending.pop_front() {
+            let Some(message) = command.execute(tx.clone(), CancellationToken::new()).await else {
+                continue;
+            };
+            match message {
+                Message::Batch(commands) | Message::Sequence(commands) => pending.extend(commands),
+                message => pending.extend(app.update(Arc::new(message)).unwrap()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn use_a_mock_server() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("main.ts");
+        fs::write(&path, "let a = 1;\na;").unwrap();
+        let mut config = Config::default();
+        config.editor.restore_positions = false;
+        let (lsp_client, notification_rx) = mock_client(MockServer);
+        let mut app = App::with_client(config, vec![path], lsp_client, notification_rx)
+            .await
+            .unwrap();
+        assert_eq!(None, app.status_error);
+        assert!(app.capabilities.completion);
+        assert!(app.capabilities.hover);
+        assert!(app.capabilities.definition);
+        assert!(!app.capabilities.rename);
+
+        let key = |code, modifiers| KeyEvent::new(code, modifiers);
+        let command = app.apply_key(key(KeyCode::Char(' '), KeyModifiers::CONTROL));
+        run(&mut app, command).await;
+        let labels: Vec<_> = app.completions().iter().map(|item| &item.label).collect();
+        assert_eq!(vec!["console"], labels);
+        app.apply_key(key(KeyCode::Esc, KeyModifiers::NONE));
+
+        let command = app.apply_key(key(KeyCode::Char('k'), KeyModifiers::CONTROL));
+        run(&mut app, command).await;
+        assert_eq!(Some(vec!["let a: number".to_owned()]), app.hover());
+
+        app.apply_key(key(KeyCode::Down, KeyModifiers::NONE));
+        let command = app.apply_key(key(KeyCode::F(12), KeyModifiers::NONE));
+        run(&mut app, command).await;
+        assert_eq!(Loc { x: 4, y: 0 }, app.cursor());
+
+        // Features the mock doesn't have are reported rather than requested
+        let rename = key(KeyCode::F(2), KeyModifiers::NONE);
+        assert!(app.apply_key(rename).is_none());
+        assert_eq!(
+            Some("Rename isn't supported by the server"),
+            app.status_error.as_deref()
+        );
+    }
+
+    #[tokio::test]
+    async fn files_are_opened_in_their_own_language() {
+        let dir = tempfile::tempdir().unwrap();
+        let files =
+            ["main.ts", "view.jsx", "script.py", "missing.ts"].map(|name| dir.path().join(name));
+        for file in &files[..3] {
+            fs::write(file, "a;").unwrap();
+        }
+        let mut config = Config::default();
+        config.editor.restore_positions = false;
+        let (lsp_client, notification_rx) = mock_client(MockServer);
+        let app = App::with_client(config, files.to_vec(), lsp_client, notification_rx)
+            .await
+            .unwrap();
+
+        let language_ids: Vec<_> = app.editors.iter().map(|e| e.language_id()).collect();
+        assert_eq!(vec!["typescript", "javascriptreact"], language_ids);
+        // Python has a server of its own, and both reasons for skipping files are reported
+        let message = app.status_error.unwrap();
+        assert!(message.contains("couldn't be opened"), "{message}");
+        assert!(message.contains("missing.ts"), "{message}");
+        assert!(message.contains("different language server"), "{message}");
+        assert!(message.contains("script.py"), "{message}");
+    }
+
+    #[tokio::test]
+    async fn failed_saves_keep_the_document_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = ["main.ts", "other.ts"].map(|name| dir.path().join(name));
+        for file in &files {
+            fs::write(file, "a;").unwrap();
+        }
+        let mut config = Config::default();
+        config.editor.restore_positions = false;
+        let (lsp_client, notification_rx) = mock_client(MockServer);
+        let mut app = App::with_client(config, files.to_vec(), lsp_client, notification_rx)
+            .await
+            .unwrap();
+        // The files can't be written once their directory is gone
+        fs::remove_dir_all(dir.path()).unwrap();
+
+        let key = |code, modifiers| KeyEvent::new(code, modifiers);
+        app.apply_key(key(KeyCode::Char('b'), KeyModifiers::NONE));
+        let command = app.apply_key(key(KeyCode::Char('s'), KeyModifiers::CONTROL));
+        run(&mut app, command).await;
+        let message = app.status_error.take().unwrap();
+        assert!(message.starts_with("Failed to save main.ts"), "{message}");
+
+        app.apply_key(key(KeyCode::Char('w'), KeyModifiers::CONTROL));
+        let answer = app.apply_key(key(KeyCode::Char('y'), KeyModifiers::NONE));
+        assert!(answer.is_none());
+        assert_eq!(2, app.editors.len());
+        let message = app.status_error.unwrap();
+        assert!(message.starts_with("Failed to save main.ts"), "{message}");
+    }
+
+    #[tokio::test]
+    async fn failed_requests_are_shown_in_the_status_bar() {
+        let (lsp_client, notification_rx) = mock_client(MockServer);
+        let mut app = App::with_client(Config::default(), vec![], lsp_client, notification_rx)
+            .await
+            .unwrap();
+        let format = KeyEvent::new(
+            KeyCode::Char('f'),
+            KeyModifiers::CONTROL | KeyModifiers::SHIFT,
+        );
+        let command = app.apply_key(format);
+        run(&mut app, command).await;
+        assert_eq!(
+            Some("Formatting failed: Method not found"),
+            app.status_error.as_deref()
+        );
+    }
+
+    #[tokio::test]
+    async fn pre_save_edits_are_formatted_before_saving() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("main.ts");
+        fs::write(&path, "let a=1;").unwrap();
+        let mut config = Config::default();
+        config.editor.restore_positions = false;
+        config.editor.format_on_save = true;
+        let (lsp_client, notification_rx) = mock_client(SavingServer);
+        let mut app = App::with_client(config, vec![path.clone()], lsp_client, notification_rx)
+            .await
+            .unwrap();
+        assert!(app.capabilities.will_save_wait_until);
+        assert!(app.capabilities.formatting);
+
+        let command = app.apply_key(KeyEvent::new(KeyCode::Char('s'), KeyModifiers::CONTROL));
+        run(&mut app, command).await;
+        assert_eq!(None, app.status_error);
+        let expected = "'use strict';\nlet a = 1;";
+        assert_eq!(expected, app.text().trim_end());
+        assert_eq!(expected, fs::read_to_string(&path).unwrap().trim_end());
+    }
+
+    #[tokio::test]
+    async fn missing_server_is_explained() {
+        let mut config = Config::default();
+        config.server.command = "lsp-tui-missing-server".to_owned();
+        let Err(e) = App::initialize(config, vec![]).await else {
+            panic!("started a server that doesn't exist");
+        };
+        let message = e.to_string();
+        assert!(message.contains("`lsp-tui-missing-server`"), "{message}");
+        assert!(message.contains("server.local = true"), "{message}");
+    }
+}
@@ -0,0 +1,85 @@
+use ropey::Rope;
+
+const PAIRS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+/// Stop looking for a partner after this many chars so huge files stay responsive
+const MAX_SCAN: usize = 20_000;
+
+pub fn is_bracket(ch: char) -> bool {
+    PAIRS
+        .iter()
+        .any(|(open, close)| ch == *open || ch == *close)
+}
+
+/// Finds the char index of the bracket paired with the one at `char_idx`.
+/// Brackets inside strings and comments aren't skipped.
+pub fn find_match(rope: &Rope, char_idx: usize) -> Option<usize> {
+    let ch = rope.get_char(char_idx)?;
+    for (open, close) in PAIRS {
+        if ch == open {
+            let mut depth = 0;
+            for (i, c) in rope.chars_at(char_idx).enumerate().take(MAX_SCAN) {
+                if c == open {
+                    depth += 1;
+                } else if c == close {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(char_idx + i);
+                    }
+                }
+            }
+            return None;
+        }
+        if ch == close {
+            let mut depth = 0;
+            let mut chars = rope.chars_at(char_idx + 1);
+            for i in 0..=char_idx.min(MAX_SCAN) {
+                match chars.prev() {
+                    Some(c) if c == close => depth += 1,
+                    Some(c) if c == open => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(char_idx - i);
+                        }
+                    }
+                    Some(_) => {}
+                    None => return None,
+                }
+            }
+            return None;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use ropey::Rope;
+
+    use super::find_match;
+
+    #[test]
+    fn nested_brackets() {
+        let rope = Rope::from_str("fn(a[0], { b: (c) })");
+        assert_eq!(Some(19), find_match(&rope, 2));
+        assert_eq!(Some(2), find_match(&rope, 19));
+        assert_eq!(Some(6), find_match(&rope, 4));
+        assert_eq!(Some(18), find_match(&rope, 9));
+        assert_eq!(Some(14), find_match(&rope, 16));
+        assert_eq!(None, find_match(&rope, 0));
+    }
+
+    #[test]
+    fn across_lines() {
+        let rope = Rope::from_str("if (a) {\n  b();\n}");
+        assert_eq!(Some(16), find_match(&rope, 7));
+        assert_eq!(Some(7), find_match(&rope, 16));
+    }
+
+    #[test]
+    fn unbalanced() {
+        let rope = Rope::from_str("foo(bar");
+        assert_eq!(None, find_match(&rope, 3));
+        let rope = Rope::from_str("bar)");
+        assert_eq!(None, find_match(&rope, 3));
+    }
+}
@@ -0,0 +1,55 @@
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use kaolinite::Document;
+use tower_lsp::lsp_types::Url;
+
+/// An open document along with the state needed to keep it in sync with the server
+pub struct Buffer {
+    pub(crate) doc: Document,
+    pub(crate) uri: Url,
+    /// The `languageId` the document is opened with, which also picks its syntax highlighting
+    pub(crate) language_id: String,
+    /// A new document backed by a temporary file that only exists so the server has a real path
+    pub(crate) scratch: bool,
+    version: AtomicI32,
+}
+
+impl Buffer {
+    pub fn new(doc: Document, uri: Url, language_id: String) -> Self {
+        Self {
+            doc,
+            uri,
+            language_id,
+            scratch: false,
+            version: AtomicI32::new(0),
+        }
+    }
+
+    pub fn scratch(doc: Document, uri: Url, language_id: String) -> Self {
+        Self {
+            scratch: true,
+            ..Self::new(doc, uri, language_id)
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        if self.scratch {
+            return "untitled";
+        }
+        self.uri
+            .path_segments()
+            .and_then(|segments| segments.last())
+            .filter(|name| !name.is_empty())
+            .unwrap_or("untitled")
+    }
+
+    pub fn next_version(&self) -> i32 {
+        self.version.fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// The version the next change will be sent with. Responses requested at an older version
+    /// are for contents that have changed since.
+    pub fn version(&self) -> i32 {
+        self.version.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,83 @@
+use tower_lsp::lsp_types::CompletionItem;
+
+use super::keymap::Action;
+use super::picker::Picker;
+
+/// The commands listed in the palette, in the order they're shown before anything is typed
+const COMMANDS: &[(&str, Action)] = &[
+    ("Save", Action::Save),
+    ("Close buffer", Action::Close),
+    ("Next buffer", Action::NextEditor),
+    ("Previous buffer", Action::PreviousEditor),
+    ("Format document", Action::Format),
+    ("Go to definition", Action::GotoDefinition),
+    ("Go to symbol", Action::GotoSymbol),
+    ("Go to line", Action::GotoLine),
+    ("Search in document", Action::Search),
+    ("Replace in document", Action::Replace),
+    ("Rename symbol", Action::Rename),
+    ("Show hover", Action::Hover),
+    ("Show code actions", Action::CodeAction),
+    ("Trigger completion", Action::Complete),
+    ("Show diagnostics under cursor", Action::ShowDiagnostics),
+    ("Next diagnostic", Action::NextDiagnostic),
+    ("Previous diagnostic", Action::PreviousDiagnostic),
+    ("Toggle soft wrap", Action::ToggleWrap),
+    ("Toggle server log", Action::ToggleLog),
+    ("Add cursor at next occurrence", Action::AddCursor),
+    ("Copy", Action::Copy),
+    ("Paste", Action::Paste),
+    ("Quit", Action::Quit),
+];
+
+/// A fuzzy-filtered list of commands that run the same actions as their key bindings
+pub fn command_palette() -> Picker<Action> {
+    Picker::new(
+        COMMANDS
+            .iter()
+            .map(|(label, action)| {
+                let item = CompletionItem {
+                    label: (*label).to_owned(),
+                    ..Default::default()
+                };
+                (item, *action)
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    use super::command_palette;
+    use crate::tui::keymap::Action;
+    use crate::tui::picker::{Picker, PickerEvent};
+
+    fn press(palette: &mut Picker<Action>, code: KeyCode) -> PickerEvent<Action> {
+        palette.handle_key_event(&KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    #[test]
+    fn filter_and_pick_command() {
+        let mut palette = command_palette();
+        assert_eq!(
+            PickerEvent::Pick(Action::Save),
+            press(&mut palette, KeyCode::Enter)
+        );
+
+        for c in "wrap".chars() {
+            assert_eq!(PickerEvent::Continue, press(&mut palette, KeyCode::Char(c)));
+        }
+        assert_eq!(
+            PickerEvent::Pick(Action::ToggleWrap),
+            press(&mut palette, KeyCode::Enter)
+        );
+
+        for c in "zzz".chars() {
+            press(&mut palette, KeyCode::Char(c));
+        }
+        assert_eq!(PickerEvent::Close, press(&mut palette, KeyCode::Enter));
+        assert_eq!(PickerEvent::Close, press(&mut palette, KeyCode::Esc));
+    }
+}
@@ -1,99 +1,419 @@
+use std::cell::Cell;
+
 use kaolinite::Loc;
 use ratatui::{
     buffer::Buffer,
-    layout::{Constraint, Direction, Layout, Rect},
-    style::{Color, Style},
-    text::Span,
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
     widgets::{Clear, List, ListItem, ListState, StatefulWidget, Widget},
 };
+use tower_lsp::lsp_types::{CompletionItem, CompletionItemKind, Documentation};
+
+use super::popup::{near_cursor, wrap_text, Popup};
+
+const MENU_MAX_WIDTH: usize = 40;
+const MENU_MAX_HEIGHT: usize = 6;
+const DOCUMENTATION_WIDTH: usize = 40;
+const DOCUMENTATION_MAX_HEIGHT: usize = 10;
+/// Columns taken up by the kind icon and the space after it
+const KIND_WIDTH: usize = 4;
 
+/// The icon shown before a completion's label and its color for each kind of item
+const KIND_ICONS: &[(CompletionItemKind, &str, Color)] = &[
+    (CompletionItemKind::TEXT, "abc", Color::Gray),
+    (CompletionItemKind::METHOD, "ƒ", Color::Magenta),
+    (CompletionItemKind::FUNCTION, "ƒ", Color::Blue),
+    (CompletionItemKind::CONSTRUCTOR, "ƒ", Color::Yellow),
+    (CompletionItemKind::FIELD, "□", Color::LightCyan),
+    (CompletionItemKind::VARIABLE, "x", Color::LightBlue),
+    (CompletionItemKind::CLASS, "C", Color::Yellow),
+    (CompletionItemKind::INTERFACE, "I", Color::Yellow),
+    (CompletionItemKind::MODULE, "{}", Color::Yellow),
+    (CompletionItemKind::PROPERTY, "□", Color::LightCyan),
+    (CompletionItemKind::UNIT, "u", Color::LightYellow),
+    (CompletionItemKind::VALUE, "=", Color::LightYellow),
+    (CompletionItemKind::ENUM, "E", Color::Yellow),
+    (CompletionItemKind::KEYWORD, "kw", Color::Magenta),
+    (CompletionItemKind::SNIPPET, "<>", Color::Green),
+    (CompletionItemKind::COLOR, "#", Color::LightRed),
+    (CompletionItemKind::FILE, "f", Color::Gray),
+    (CompletionItemKind::REFERENCE, "&", Color::LightBlue),
+    (CompletionItemKind::FOLDER, "/", Color::Gray),
+    (CompletionItemKind::ENUM_MEMBER, "e", Color::LightYellow),
+    (CompletionItemKind::CONSTANT, "π", Color::LightYellow),
+    (CompletionItemKind::STRUCT, "S", Color::Yellow),
+    (CompletionItemKind::EVENT, "!", Color::LightRed),
+    (CompletionItemKind::OPERATOR, "+", Color::Gray),
+    (CompletionItemKind::TYPE_PARAMETER, "T", Color::Yellow),
+];
+
+/// A completion item along with how well it matched the word under the cursor
+#[derive(Clone, Debug)]
+pub struct CompletionMatch {
+    pub(crate) item: CompletionItem,
+    pub(crate) score: i64,
+    /// Char indices of the label that matched the typed word
+    pub(crate) indices: Vec<usize>,
+}
+
+/// Draws the items of a menu state next to the cursor. The state keeps the scroll position
+/// from one draw to the next.
 pub struct CompletionMenu<'a> {
-    list: List<'a>,
-    num_items: usize,
+    state: &'a CompletionMenuState,
     cursor: Loc,
+    show_kinds: bool,
 }
 
 impl<'a> CompletionMenu<'a> {
-    pub fn new(items: &'a [String], cursor: Loc) -> Self {
-        let num_items = items.len();
-        let list_items: Vec<_> = items.iter().map(|c| ListItem::new(Span::raw(c))).collect();
+    pub fn new(state: &'a CompletionMenuState, cursor: Loc) -> Self {
+        // Lists without any kinds, like code actions, don't need the icon column
+        let show_kinds = state.completions.iter().any(|c| c.item.kind.is_some());
         Self {
-            num_items,
+            state,
             cursor,
-            list: List::new(list_items)
-                .style(Style::default().fg(Color::DarkGray).bg(Color::Cyan))
-                .highlight_style(Style::default().fg(Color::Cyan).bg(Color::DarkGray)),
+            show_kinds,
         }
     }
+
+    /// Fits the longest label, up to a maximum.
+    /// Positioning handles clamping this to the available space.
+    fn width(&self) -> u16 {
+        let label_width = self
+            .state
+            .completions
+            .iter()
+            .map(|c| c.item.label.chars().count())
+            .max()
+            .unwrap_or_default()
+            .min(MENU_MAX_WIDTH);
+        let kind_width = if self.show_kinds { KIND_WIDTH } else { 0 };
+        (label_width + kind_width) as u16
+    }
+
+    /// Where the menu is drawn within the text area
+    pub fn area(&self, area: Rect) -> Rect {
+        near_cursor(
+            self.cursor.x as u16,
+            self.cursor.y as u16,
+            self.width(),
+            self.state.completions.len().min(MENU_MAX_HEIGHT) as u16,
+            area,
+        )
+    }
 }
 
-impl<'a> StatefulWidget for CompletionMenu<'a> {
-    type State = CompletionMenuState;
-
-    fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
-        let overlay_vertical = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints([
-                Constraint::Length(self.cursor.y as u16 + 1),
-                Constraint::Length(self.num_items.min(6) as u16),
-                Constraint::Min(0),
-            ])
-            .split(area)[1];
-        let overlay = Layout::default()
-            .direction(Direction::Horizontal)
-            .constraints([
-                Constraint::Length(self.cursor.x as u16),
-                Constraint::Length(20),
-                Constraint::Min(0),
-            ])
-            .split(overlay_vertical)[1];
+impl<'a> Widget for CompletionMenu<'a> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let overlay = self.area(area);
+        // There's no room for it next to the cursor, such as in a one-row area
+        if overlay.height == 0 {
+            return;
+        }
+        let state = self.state;
+        let height = overlay.height as usize;
+        let offset = state.scroll_to_selection(height);
+        // Only the items in view are listed, so the list itself never needs to scroll
+        let items: Vec<_> = state.completions[offset..]
+            .iter()
+            .take(height)
+            .map(|c| ListItem::new(label_spans(c, self.show_kinds)))
+            .collect();
+        let list = List::new(items)
+            .style(Style::default().fg(Color::DarkGray).bg(Color::Cyan))
+            .highlight_style(Style::default().fg(Color::Cyan).bg(Color::DarkGray));
+        let mut list_state = ListState::default();
+        list_state.select(state.selected.map(|selected| selected - offset));
 
         Clear.render(overlay, buf);
-        StatefulWidget::render(self.list, overlay, buf, &mut state.list_state);
+        StatefulWidget::render(list, overlay, buf, &mut list_state);
+
+        if let Some(documentation) = state.selected().and_then(documentation_lines) {
+            let popup = Popup::new(
+                documentation
+                    .into_iter()
+                    .take(DOCUMENTATION_MAX_HEIGHT)
+                    .map(Spans::from)
+                    .collect::<Vec<_>>(),
+            );
+            // Show the documentation on whichever side of the menu has room for it
+            let x = if overlay.right() + popup.width() <= area.right() {
+                overlay.right()
+            } else {
+                overlay.x.saturating_sub(popup.width()).max(area.x)
+            };
+            let popup_area = Rect {
+                x,
+                y: overlay.y,
+                width: popup.width(),
+                height: popup.height(),
+            }
+            .intersection(area);
+            popup.render(popup_area, buf);
+        }
     }
 }
 
+fn label_spans(completion: &CompletionMatch, show_kind: bool) -> Spans<'static> {
+    let matched_style = Style::default().add_modifier(Modifier::BOLD);
+    let kind = show_kind.then(|| {
+        let (icon, color) = completion
+            .item
+            .kind
+            .and_then(kind_icon)
+            .unwrap_or(("", Color::Reset));
+        Span::styled(
+            format!("{icon:<width$}", width = KIND_WIDTH),
+            Style::default().fg(color),
+        )
+    });
+    let label = completion.item.label.chars().enumerate().map(|(i, ch)| {
+        if completion.indices.contains(&i) {
+            Span::styled(ch.to_string(), matched_style)
+        } else {
+            Span::raw(ch.to_string())
+        }
+    });
+    kind.into_iter().chain(label).collect::<Vec<_>>().into()
+}
+
+fn kind_icon(kind: CompletionItemKind) -> Option<(&'static str, Color)> {
+    KIND_ICONS
+        .iter()
+        .find(|(k, _, _)| *k == kind)
+        .map(|(_, icon, color)| (*icon, *color))
+}
+
+fn documentation_lines(item: &CompletionItem) -> Option<Vec<String>> {
+    let documentation = item.documentation.as_ref().map(|d| match d {
+        Documentation::String(value) => value,
+        Documentation::MarkupContent(content) => &content.value,
+    });
+    let text = match (&item.detail, documentation) {
+        (Some(detail), Some(documentation)) => format!("{detail}\n\n{documentation}"),
+        (Some(detail), None) => detail.clone(),
+        (None, Some(documentation)) => documentation.clone(),
+        (None, None) => return None,
+    };
+    Some(wrap_text(&text, DOCUMENTATION_WIDTH))
+}
+
 #[derive(Default, Clone)]
 pub struct CompletionMenuState {
-    list_state: ListState,
-    completions: Vec<String>,
+    selected: Option<usize>,
+    /// The first item in view when the menu was last drawn. Drawing scrolls it just far enough
+    /// to show the selection.
+    offset: Cell<usize>,
+    completions: Vec<CompletionMatch>,
+    /// Incremented for every request so responses to superseded requests can be discarded
+    generation: u64,
 }
 
 impl CompletionMenuState {
     pub fn next(&mut self) {
-        if let Some(selected) = self.list_state.selected() {
+        if let Some(selected) = self.selected {
             if selected < self.completions.len() - 1 {
-                self.list_state.select(Some(selected + 1));
+                self.selected = Some(selected + 1);
             } else {
-                self.list_state.select(Some(0));
+                self.selected = Some(0);
             }
         }
     }
 
     pub fn previous(&mut self) {
-        if let Some(selected) = self.list_state.selected() {
+        if let Some(selected) = self.selected {
             if selected > 0 {
-                self.list_state.select(Some(selected - 1));
+                self.selected = Some(selected - 1);
             } else {
-                self.list_state.select(Some(self.completions.len() - 1));
+                self.selected = Some(self.completions.len() - 1);
+            }
+        }
+    }
+
+    /// Moves the selection down without wrapping around to the top
+    pub fn scroll_down(&mut self) {
+        if let Some(selected) = self.selected {
+            if selected + 1 < self.completions.len() {
+                self.selected = Some(selected + 1);
+            }
+        }
+    }
+
+    /// Moves the selection up without wrapping around to the bottom
+    pub fn scroll_up(&mut self) {
+        if let Some(selected) = self.selected {
+            self.selected = Some(selected.saturating_sub(1));
+        }
+    }
+
+    /// Moves the offset for a menu that shows `height` items just far enough for the selection
+    /// to be in view, and returns it
+    fn scroll_to_selection(&self, height: usize) -> usize {
+        let mut offset = self.offset.get();
+        if let Some(selected) = self.selected {
+            if selected < offset {
+                offset = selected;
+            } else if selected >= offset + height {
+                offset = selected + 1 - height;
             }
         }
+        // The list may have gotten shorter since it was last drawn
+        offset = offset.min(self.completions.len().saturating_sub(height));
+        self.offset.set(offset);
+        offset
     }
 
-    pub fn completions(&self) -> &Vec<String> {
+    /// The index of the item on the given row of a menu that shows `height` items, as it was
+    /// last drawn
+    pub fn item_at(&self, row: usize, height: usize) -> Option<usize> {
+        let index = self.offset.get() + row;
+        (row < height && index < self.completions.len()).then_some(index)
+    }
+
+    pub fn select(&mut self, index: usize) {
+        if index < self.completions.len() {
+            self.selected = Some(index);
+        }
+    }
+
+    pub fn completions(&self) -> &Vec<CompletionMatch> {
         &self.completions
     }
 
+    pub fn selected(&self) -> Option<&CompletionItem> {
+        self.selected
+            .and_then(|selected| self.completions.get(selected))
+            .map(|completion| &completion.item)
+    }
+
+    pub fn selected_index(&self) -> Option<usize> {
+        self.selected
+    }
+
     pub fn is_empty(&self) -> bool {
         self.completions.is_empty()
     }
 
-    pub fn set_completions(&mut self, completions: Vec<String>) {
+    /// Starts a new completion request, invalidating any that are still in flight.
+    /// The returned generation must be passed back to `receive` with the response.
+    pub fn start_request(&mut self) -> u64 {
+        self.generation += 1;
+        self.generation
+    }
+
+    /// Hides the menu and discards any outstanding requests
+    pub fn cancel(&mut self) {
+        self.generation += 1;
+        self.set_completions(vec![]);
+    }
+
+    /// Shows the completions if they belong to the latest request.
+    /// Returns whether they were accepted.
+    pub fn receive(&mut self, generation: u64, completions: Vec<CompletionMatch>) -> bool {
+        let is_latest = generation == self.generation;
+        if is_latest {
+            self.set_completions(completions);
+        }
+        is_latest
+    }
+
+    pub fn set_completions(&mut self, completions: Vec<CompletionMatch>) {
         self.completions = completions;
+        self.offset.set(0);
         if self.completions.is_empty() {
-            self.list_state.select(None);
+            self.selected = None;
         } else {
-            self.list_state.select(Some(0));
+            self.selected = Some(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kaolinite::Loc;
+    use ratatui::{buffer::Buffer, layout::Rect, widgets::Widget};
+    use tower_lsp::lsp_types::CompletionItem;
+
+    use super::{CompletionMatch, CompletionMenu, CompletionMenuState};
+
+    fn completion(label: &str) -> CompletionMatch {
+        CompletionMatch {
+            item: CompletionItem::new_simple(label.to_owned(), "".to_owned()),
+            score: 0,
+            indices: vec![],
         }
     }
+
+    #[test]
+    fn stale_responses_are_discarded() {
+        let mut state = CompletionMenuState::default();
+        let first = state.start_request();
+        let second = state.start_request();
+
+        // The second response can arrive before the first
+        state.receive(second, vec![completion("second")]);
+        state.receive(first, vec![completion("first")]);
+        let labels: Vec<_> = state
+            .completions()
+            .iter()
+            .map(|c| c.item.label.as_str())
+            .collect();
+        assert_eq!(vec!["second"], labels);
+
+        let third = state.start_request();
+        state.cancel();
+        state.receive(third, vec![completion("third")]);
+        assert!(state.is_empty());
+    }
+
+    #[test]
+    fn menu_without_room_is_not_drawn() {
+        let mut state = CompletionMenuState::default();
+        state.set_completions(["a", "b", "c"].map(completion).to_vec());
+        state.scroll_down();
+        let area = Rect::new(0, 0, 20, 1);
+        let mut buf = Buffer::empty(area);
+        CompletionMenu::new(&state, Loc { x: 0, y: 0 }).render(area, &mut buf);
+        assert_eq!(Buffer::empty(area), buf);
+    }
+
+    #[test]
+    fn scroll_and_hit_test() {
+        let mut state = CompletionMenuState::default();
+        let labels = ["a", "b", "c", "d", "e", "f", "g", "h"];
+        state.set_completions(labels.map(completion).to_vec());
+        let area = Rect::new(0, 0, 20, 10);
+        let mut buf = Buffer::empty(area);
+        let mut draw = |state: &CompletionMenuState| {
+            let menu = CompletionMenu::new(state, Loc { x: 0, y: 0 });
+            let menu_area = menu.area(area);
+            menu.render(area, &mut buf);
+            let first_row: String = (menu_area.x..menu_area.right())
+                .map(|x| buf.get(x, menu_area.y).symbol.as_str())
+                .collect();
+            (menu_area.height as usize, first_row.trim_end().to_owned())
+        };
+
+        state.scroll_up();
+        assert_eq!(Some(0), state.selected_index());
+        let (height, _) = draw(&state);
+        assert_eq!(6, height);
+        assert_eq!(Some(1), state.item_at(1, height));
+        for _ in 0..10 {
+            state.scroll_down();
+        }
+        assert_eq!(Some(7), state.selected_index());
+
+        // The last items are in view with the selection on the bottom row
+        assert_eq!((6, "c".to_owned()), draw(&state));
+        assert_eq!(Some(2), state.item_at(0, height));
+        assert_eq!(Some(7), state.item_at(5, height));
+        assert_eq!(None, state.item_at(6, height));
+
+        // Moving the selection within view doesn't scroll
+        state.scroll_up();
+        state.scroll_up();
+        assert_eq!((6, "c".to_owned()), draw(&state));
+        assert_eq!(Some(5), state.item_at(3, height));
+    }
 }
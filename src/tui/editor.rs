@@ -0,0 +1,3908 @@
+use super::brackets::{find_match, is_bracket};
+use super::buffer::Buffer;
+use super::completion_menu::{CompletionMatch, CompletionMenu, CompletionMenuState};
+use super::highlight::{semantic_token_scope, Overlay, VirtualText};
+use super::keymap::Action;
+use super::lsp_capabilities::{Encoding, LspCapabilities};
+use super::picker::{Picker, PickerEvent};
+use super::popup::{above_cursor, below_cursor, markdown_to_text, wrap_text, Popup};
+use super::search::{self, Search, SearchEvent};
+use super::snippet::{self, Snippet};
+use super::text_area::{display_width, wrapped_position, wrapped_rows, TextArea};
+use super::theme::Theme;
+use super::view_state::ViewState;
+use crate::config::{CompletionConfig, Config};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+use elm_ui::Message;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+use kaolinite::{Document, Loc, Size};
+use notify::RecommendedWatcher;
+use ratatui::layout::Rect;
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Span, Spans, Text};
+use ratatui::widgets::Widget;
+use regex::Regex;
+use ropey::Rope;
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::iter;
+use std::mem;
+use std::sync::Arc;
+use std::time::Duration;
+use tower_lsp::{lsp_types::*, ClientToServer};
+use tracing::{info, warn};
+
+const HOVER_WIDTH: usize = 60;
+const HOVER_MAX_HEIGHT: u16 = 15;
+/// How long saving waits for the server to format the document before writing it unformatted.
+/// Also used for the server's `willSaveWaitUntil` edits.
+const FORMAT_ON_SAVE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Messages produced by an editor's async commands. Responses go back to the editor showing
+/// the document, everything else is handled by whatever hosts the editor.
+#[derive(Debug)]
+pub enum EditorMessage {
+    Response(Url, EditorResponse),
+    /// Edits that can span several documents
+    ApplyEdit(WorkspaceEdit),
+    /// A location to show, which may be in another document
+    Goto(Location),
+    /// A request or save that failed, to report in the status bar
+    RequestFailed(String),
+    /// Something to report in the status bar
+    Status(String),
+}
+
+#[derive(Debug)]
+pub enum EditorResponse {
+    Completions(u64, CompletionCache, Vec<CompletionMatch>),
+    Hover(Hover),
+    SignatureHelp(SignatureHelp),
+    /// Formatting edits and whether to save the document once they're applied
+    Format(Vec<TextEdit>, bool),
+    /// `willSaveWaitUntil` edits, which are applied before the document is formatted and saved
+    WillSave(Vec<TextEdit>),
+    DocumentSymbols(DocumentSymbolResponse),
+    /// The occurrences of the identifier at the position they were requested for
+    DocumentHighlights(Position, Vec<DocumentHighlight>),
+    /// The hints for the document at the version they were requested for
+    InlayHints(i32, Vec<InlayHint>),
+    /// The tokens for the whole document at the version they were requested for
+    SemanticTokens(i32, Vec<SemanticToken>),
+    CodeActions(Vec<CodeActionOrCommand>),
+    /// Shown next to the cursor when a request had nothing to act on. Requests that fail are
+    /// reported with [`EditorMessage::RequestFailed`] instead.
+    Error(String),
+}
+
+/// The unfiltered items from a completion response
+#[derive(Clone, Debug)]
+pub struct CompletionCache {
+    word_start: Loc,
+    /// The word that was typed when the completions were requested
+    word: String,
+    items: Vec<CompletionItem>,
+    /// The server wants to be asked again as the word grows instead of having the items filtered
+    is_incomplete: bool,
+}
+
+/// Code actions offered at the cursor, shown with the completion menu widget
+struct CodeActionMenu {
+    state: CompletionMenuState,
+    actions: Vec<CodeActionOrCommand>,
+}
+
+impl CodeActionMenu {
+    fn new(actions: Vec<CodeActionOrCommand>) -> Self {
+        let mut state = CompletionMenuState::default();
+        state.set_completions(
+            actions
+                .iter()
+                .map(|action| {
+                    let label = match action {
+                        CodeActionOrCommand::Command(command) => command.title.clone(),
+                        CodeActionOrCommand::CodeAction(action) => action.title.clone(),
+                    };
+                    CompletionMatch {
+                        item: CompletionItem {
+                            label,
+                            ..Default::default()
+                        },
+                        score: 0,
+                        indices: vec![],
+                    }
+                })
+                .collect(),
+        );
+        Self { state, actions }
+    }
+
+    fn selected(&self) -> Option<&CodeActionOrCommand> {
+        self.actions.get(self.state.selected_index()?)
+    }
+}
+
+/// Tab stops of an expanded snippet that the user can jump between with Tab
+struct SnippetSession {
+    stops: Vec<(Loc, Loc)>,
+    current: usize,
+}
+
+/// A single document along with its connection to the language server. Key events edit the
+/// document and return the commands that keep the server in sync. Render `&Editor` as a widget
+/// to draw the text with its popups.
+pub struct Editor {
+    config: Arc<Config>,
+    theme: Arc<Theme>,
+    capabilities: Arc<LspCapabilities>,
+    lsp_client: Arc<tower_lsp::Client<ClientToServer>>,
+    buffer: Buffer,
+    diagnostics: Vec<Diagnostic>,
+    completion_menu_state: CompletionMenuState,
+    completion_cache: Option<CompletionCache>,
+    show_completions: bool,
+    code_action_menu: Option<CodeActionMenu>,
+    /// Jumps to the position of the picked symbol
+    symbol_picker: Option<Picker<Position>>,
+    /// Stays open after its query is confirmed to go between the matches
+    search: Option<Search>,
+    hover: Option<Text<'static>>,
+    error: Option<String>,
+    signature_help: Option<SignatureHelp>,
+    selection_anchor: Option<Loc>,
+    /// Cursors that repeat the edits made at the main one, in the order they were added. They
+    /// never coexist with a selection.
+    extra_cursors: Vec<Loc>,
+    snippet: Option<SnippetSession>,
+    /// Every occurrence of the identifier under the cursor
+    document_highlights: Vec<DocumentHighlight>,
+    /// Labels drawn before a location without being part of the document, sorted by location
+    inlay_hints: Vec<(Loc, String)>,
+    /// The server's semantic tokens, drawn over the local syntax highlighting
+    semantic_overlays: Vec<Overlay>,
+    /// The first line and display column in view. It follows the cursor whenever the editor is
+    /// drawn.
+    scroll: Cell<Loc>,
+    /// Starts out as configured and can be toggled while editing
+    soft_wrap: bool,
+    /// Reports changes made to the file outside of the editor until it's dropped
+    watcher: Option<RecommendedWatcher>,
+}
+
+impl Editor {
+    pub fn new(
+        buffer: Buffer,
+        lsp_client: Arc<tower_lsp::Client<ClientToServer>>,
+        capabilities: Arc<LspCapabilities>,
+        config: Arc<Config>,
+        theme: Arc<Theme>,
+    ) -> Self {
+        Self {
+            theme,
+            capabilities,
+            lsp_client,
+            buffer,
+            diagnostics: vec![],
+            completion_menu_state: CompletionMenuState::default(),
+            completion_cache: None,
+            show_completions: false,
+            code_action_menu: None,
+            symbol_picker: None,
+            search: None,
+            hover: None,
+            error: None,
+            signature_help: None,
+            selection_anchor: None,
+            extra_cursors: vec![],
+            snippet: None,
+            document_highlights: vec![],
+            inlay_hints: vec![],
+            semantic_overlays: vec![],
+            scroll: Cell::new(Loc { x: 0, y: 0 }),
+            soft_wrap: config.editor.soft_wrap,
+            watcher: None,
+            config,
+        }
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    pub fn uri(&self) -> &Url {
+        &self.buffer.uri
+    }
+
+    pub fn language_id(&self) -> &str {
+        &self.buffer.language_id
+    }
+
+    pub fn doc(&self) -> &Document {
+        &self.buffer.doc
+    }
+
+    fn doc_mut(&mut self) -> &mut Document {
+        &mut self.buffer.doc
+    }
+
+    pub fn resize(&mut self, size: Size) {
+        self.doc_mut().size = size;
+    }
+
+    /// The items in the completion menu, best match first. Empty while the menu is hidden.
+    pub fn completions(&self) -> Vec<&CompletionItem> {
+        if !self.show_completions {
+            return vec![];
+        }
+        self.completion_menu_state
+            .completions()
+            .iter()
+            .map(|completion| &completion.item)
+            .collect()
+    }
+
+    /// The hover popup, if it's showing
+    pub fn hover(&self) -> Option<&Text<'static>> {
+        self.hover.as_ref()
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Stores the diagnostics in the order they appear in the document
+    pub fn set_diagnostics(&mut self, mut diagnostics: Vec<Diagnostic>) {
+        diagnostics.sort_by_key(|d| d.range.start);
+        self.diagnostics = diagnostics;
+    }
+
+    /// Moves the cursor to the next diagnostic after it, or the previous one before it,
+    /// wrapping around at the ends of the document. Returns the diagnostic it moved to.
+    pub fn goto_diagnostic(&mut self, forward: bool) -> Option<&Diagnostic> {
+        let cursor = self.get_lsp_position(&self.doc().char_loc());
+        let index = if forward {
+            self.diagnostics
+                .iter()
+                .position(|d| d.range.start > cursor)
+                .unwrap_or(0)
+        } else {
+            self.diagnostics
+                .iter()
+                .rposition(|d| d.range.start < cursor)
+                .or_else(|| self.diagnostics.len().checked_sub(1))?
+        };
+        let start = self.diagnostics.get(index)?.range.start;
+        self.selection_anchor = None;
+        self.goto_position(&start);
+        self.diagnostics.get(index)
+    }
+
+    /// Switches to a new server, such as after the old one was restarted.
+    /// The document needs to be opened on it again.
+    pub fn set_server(
+        &mut self,
+        lsp_client: Arc<tower_lsp::Client<ClientToServer>>,
+        capabilities: Arc<LspCapabilities>,
+    ) {
+        self.lsp_client = lsp_client;
+        self.capabilities = capabilities;
+        self.diagnostics.clear();
+        self.completion_cache = None;
+    }
+
+    /// Closes any popups and drops the selection and extra cursors, such as when switching to
+    /// another editor
+    pub fn blur(&mut self) {
+        self.show_completions = false;
+        self.completion_menu_state.cancel();
+        self.completion_cache = None;
+        self.code_action_menu = None;
+        self.symbol_picker = None;
+        self.search = None;
+        self.hover = None;
+        self.error = None;
+        self.signature_help = None;
+        self.selection_anchor = None;
+        self.extra_cursors.clear();
+        self.snippet = None;
+        self.document_highlights.clear();
+    }
+
+    /// Whether a picker is open or a search query is being typed, in which case keys type into it
+    /// rather than running their bindings
+    pub fn is_picking(&self) -> bool {
+        self.symbol_picker.is_some() || self.search.as_ref().is_some_and(Search::is_typing)
+    }
+
+    /// Where the terminal cursor goes when the editor is drawn in `area`
+    pub fn cursor_position(&self, area: Rect) -> (u16, u16) {
+        if let Some(picker) = &self.symbol_picker {
+            return picker.cursor_position(area);
+        }
+        if let Some(search) = self.search.as_ref().filter(|search| search.is_typing()) {
+            return search.cursor_position(area);
+        }
+        let Loc { x, y } = self.screen_cursor(area);
+        (area.x + x as u16, area.y + y as u16)
+    }
+
+    /// Scrolls just far enough for the cursor to be inside `area`
+    fn scroll_to_cursor(&self, area: Rect) {
+        let (width, height) = (area.width as usize, area.height.max(1) as usize);
+        let cursor = self.doc().char_loc();
+        let mut scroll = self.scroll.get();
+        scroll.y = scroll.y.min(cursor.y);
+        if self.soft_wrap {
+            scroll.x = 0;
+            let tab_width = self.doc().tab_width;
+            let (row, _) = wrapped_position(
+                &self.display_line(cursor.y),
+                self.display_x(cursor),
+                tab_width,
+                width,
+            );
+            // Drop lines off the top until there's room for every row down to the cursor's
+            let mut rows = row + 1;
+            let mut y = cursor.y;
+            while y > scroll.y {
+                let line_rows = wrapped_rows(&self.display_line(y - 1), tab_width, width);
+                if rows + line_rows > height {
+                    scroll.y = y;
+                    break;
+                }
+                rows += line_rows;
+                y -= 1;
+            }
+        } else {
+            if cursor.y >= scroll.y + height {
+                scroll.y = cursor.y + 1 - height;
+            }
+            let x = self.display_column(cursor);
+            if x < scroll.x {
+                scroll.x = x;
+            } else if x >= scroll.x + width {
+                scroll.x = x + 1 - width.max(1);
+            }
+        }
+        self.scroll.set(scroll);
+    }
+
+    /// The display column of a location within its line
+    fn display_column(&self, loc: Loc) -> usize {
+        let line = self.display_line(loc.y);
+        let x = self.display_x(loc);
+        display_width(&line[..x.min(line.len())], self.doc().tab_width)
+    }
+
+    /// Where the cursor is drawn relative to the top left of `area`
+    fn screen_cursor(&self, area: Rect) -> Loc {
+        self.screen_loc(self.doc().char_loc(), area)
+    }
+
+    /// Where a location is drawn relative to the top left of `area`
+    fn screen_loc(&self, loc: Loc, area: Rect) -> Loc {
+        let scroll = self.scroll.get();
+        if self.soft_wrap {
+            let (width, tab_width) = (area.width as usize, self.doc().tab_width);
+            let rows: usize = (scroll.y..loc.y)
+                .map(|y| wrapped_rows(&self.display_line(y), tab_width, width))
+                .sum();
+            let (row, column) = wrapped_position(
+                &self.display_line(loc.y),
+                self.display_x(loc),
+                tab_width,
+                width,
+            );
+            Loc {
+                x: column,
+                y: rows + row,
+            }
+        } else {
+            Loc {
+                x: self.display_column(loc).saturating_sub(scroll.x),
+                y: loc.y.saturating_sub(scroll.y),
+            }
+        }
+    }
+
+    /// The location drawn at a screen position in `area`. Positions past the end of a line are
+    /// at its end, and positions below the document are on its last line.
+    fn loc_at(&self, area: Rect, column: u16, row: u16) -> Option<Loc> {
+        let target = ((row - area.y) as usize, (column - area.x) as usize);
+        let y = (self.scroll.get().y..self.doc().len_lines())
+            .take_while(|y| self.screen_loc(Loc { x: 0, y: *y }, area).y <= target.0)
+            .last()?;
+        (0..=self.line_chars(y).len())
+            .map(|x| Loc { x, y })
+            .take_while(|loc| {
+                let screen = self.screen_loc(*loc, area);
+                (screen.y, screen.x) <= target
+            })
+            .last()
+    }
+
+    pub fn save(&mut self) -> anyhow::Result<()> {
+        if let Err(e) = self.doc_mut().save() {
+            warn!("Failed to save {}: {e:?}", self.buffer.uri);
+            anyhow::bail!("{e:?}");
+        }
+        Ok(())
+    }
+
+    /// Saves the document and tells the server, or reports why it couldn't be written
+    fn get_save_command(&mut self) -> Option<elm_ui::Command> {
+        match self.save() {
+            Ok(()) => self.get_did_save_command(),
+            Err(e) => Some(elm_ui::Command::simple(Message::custom(
+                EditorMessage::RequestFailed(save_failed(&self.buffer, &e)),
+            ))),
+        }
+    }
+
+    pub fn set_watcher(&mut self, watcher: RecommendedWatcher) {
+        self.watcher = Some(watcher);
+    }
+
+    /// Replaces the document with the file's contents on disk, keeping the cursor as close to
+    /// where it was as the new contents allow. Returns the command that sends the new contents
+    /// to the server.
+    pub fn reload(&mut self) -> anyhow::Result<Option<elm_ui::Command>> {
+        let path = self
+            .buffer
+            .uri
+            .to_file_path()
+            .map_err(|_| anyhow::anyhow!("{} isn't a file", self.buffer.uri))?;
+        let mut doc = Document::open(self.doc().size, path.to_string_lossy())
+            .map_err(|e| anyhow::anyhow!("{e:?}"))?;
+        doc.tab_width = self.doc().tab_width;
+        let view_state = self.view_state();
+        self.blur();
+        self.inlay_hints.clear();
+        self.semantic_overlays.clear();
+        self.buffer.doc = doc;
+        self.restore_view_state(view_state);
+        Ok(self.get_full_change_command())
+    }
+
+    pub fn view_state(&self) -> ViewState {
+        let cursor = self.doc().char_loc();
+        let scroll = self.scroll.get();
+        ViewState {
+            line: cursor.y,
+            column: cursor.x,
+            scroll_line: scroll.y,
+            scroll_column: scroll.x,
+        }
+    }
+
+    /// Puts the cursor and viewport back where they were. The file may have changed since, so
+    /// positions past the end are clamped.
+    pub fn restore_view_state(&mut self, state: ViewState) {
+        let y = state.line.min(self.doc().len_lines().saturating_sub(1));
+        let x = state.column.min(self.line_chars(y).len());
+        self.doc_mut().goto(&Loc { x, y });
+        self.scroll.set(Loc {
+            x: state.scroll_column,
+            y: state.scroll_line.min(y),
+        });
+    }
+
+    /// Moves the cursor to a position sent by the server
+    pub fn goto_position(&mut self, position: &Position) {
+        let loc = self.get_loc(position);
+        self.doc_mut().goto(&loc);
+    }
+
+    /// Moves the cursor to the start of a line, counting from 1, and scrolls the line to the
+    /// middle of the view. Lines past the end go to the last one.
+    pub fn goto_line(&mut self, line: usize) {
+        let y = line
+            .saturating_sub(1)
+            .min(self.doc().len_lines().saturating_sub(1));
+        self.selection_anchor = None;
+        self.doc_mut().goto(&Loc { x: 0, y });
+        let height = self.doc().size.h;
+        self.scroll.set(Loc {
+            x: 0,
+            y: y.saturating_sub(height / 2),
+        });
+    }
+
+    pub fn handle_response(&mut self, response: &EditorResponse) -> Option<elm_ui::Command> {
+        match response {
+            EditorResponse::Completions(generation, cache, completions) => {
+                if self
+                    .completion_menu_state
+                    .receive(*generation, completions.clone())
+                {
+                    self.completion_cache = Some(cache.clone());
+                }
+            }
+            EditorResponse::Hover(hover) => {
+                let text = hover_to_text(&hover.contents);
+                if text.height() > 0 {
+                    self.hover = Some(text);
+                }
+            }
+            EditorResponse::SignatureHelp(signature_help) => {
+                if signature_help.signatures.is_empty() {
+                    self.signature_help = None;
+                } else {
+                    self.signature_help = Some(signature_help.clone());
+                }
+            }
+            EditorResponse::Format(edits, save) => {
+                let mut commands: Vec<_> = self.apply_edits(edits.clone()).into_iter().collect();
+                if *save {
+                    commands.extend(self.get_save_command());
+                }
+                return Some(elm_ui::Command::simple(Message::Sequence(commands)));
+            }
+            EditorResponse::WillSave(edits) => {
+                let mut commands: Vec<_> = self.apply_edits(edits.clone()).into_iter().collect();
+                if self.config.editor.format_on_save && self.capabilities.formatting {
+                    commands.push(self.get_format_command(true));
+                } else {
+                    commands.extend(self.get_save_command());
+                }
+                return Some(elm_ui::Command::simple(Message::Sequence(commands)));
+            }
+            EditorResponse::DocumentHighlights(position, highlights) => {
+                // The cursor has moved on since they were requested
+                if self.get_lsp_position(&self.doc().char_loc()) == *position {
+                    self.document_highlights = highlights.clone();
+                }
+            }
+            EditorResponse::InlayHints(version, hints) => {
+                // The document has changed since they were requested
+                if *version == self.buffer.version() {
+                    let mut inlay_hints: Vec<_> = hints
+                        .iter()
+                        .map(|hint| (self.get_loc(&hint.position), inlay_hint_label(hint)))
+                        .collect();
+                    inlay_hints.sort_by_key(|(loc, _)| (loc.y, loc.x));
+                    self.inlay_hints = inlay_hints;
+                }
+            }
+            EditorResponse::SemanticTokens(version, tokens) => {
+                if *version == self.buffer.version() {
+                    self.semantic_overlays = self.semantic_token_overlays(tokens);
+                }
+            }
+            EditorResponse::DocumentSymbols(symbols) => {
+                let symbols = picker_symbols(symbols, self.uri());
+                if symbols.is_empty() {
+                    self.error = Some("No symbols in the document".to_owned());
+                } else {
+                    self.symbol_picker = Some(Picker::new(symbols));
+                }
+            }
+            EditorResponse::CodeActions(actions) => {
+                if actions.is_empty() {
+                    self.error = Some("No code actions available".to_owned());
+                } else {
+                    self.code_action_menu = Some(CodeActionMenu::new(actions.clone()));
+                }
+            }
+            EditorResponse::Error(message) => {
+                self.error = Some(message.clone());
+            }
+        }
+        None
+    }
+
+    /// Applies edits from the server and returns the command that reports the resulting
+    /// changes back to it
+    pub fn apply_edits(&mut self, edits: Vec<TextEdit>) -> Option<elm_ui::Command> {
+        let changes = self.apply_text_edits(edits);
+        self.selection_anchor = None;
+        self.snippet = None;
+        if changes.is_empty() {
+            return None;
+        }
+        self.get_change_command(changes)
+    }
+
+    fn apply_code_action(&mut self, action: &CodeActionOrCommand) -> Option<elm_ui::Command> {
+        let (edit, command) = match action {
+            CodeActionOrCommand::Command(command) => (None, Some(command)),
+            CodeActionOrCommand::CodeAction(action) => {
+                (action.edit.as_ref(), action.command.as_ref())
+            }
+        };
+        let mut commands = vec![];
+        if let Some(edit) = edit {
+            // The edit can touch other documents, so it's up to the host to apply it
+            commands.push(elm_ui::Command::simple(Message::custom(
+                EditorMessage::ApplyEdit(edit.clone()),
+            )));
+        }
+        if let Some(command) = command {
+            commands.push(self.get_execute_command(command.clone()));
+        }
+        Some(elm_ui::Command::simple(Message::Sequence(commands)))
+    }
+
+    pub fn get_open_command(&self) -> elm_ui::Command {
+        let lsp_client = self.lsp_client.clone();
+        let document_uri = self.buffer.uri.clone();
+        let document_version = self.buffer.next_version();
+        let language_id = self.buffer.language_id.clone();
+        let text = self.doc().rope().to_string();
+        let did_open = elm_ui::Command::new_async(move |_, _| async move {
+            lsp_client
+                .did_open(TextDocumentItem {
+                    uri: document_uri,
+                    language_id,
+                    version: document_version,
+                    text,
+                })
+                .await;
+            None
+        });
+        self.then_request_decorations(did_open)
+    }
+
+    /// Tells the server the document was written to disk, if it wants to know
+    pub fn get_did_save_command(&self) -> Option<elm_ui::Command> {
+        if !self.capabilities.did_save {
+            return None;
+        }
+        let lsp_client = self.lsp_client.clone();
+        let uri = self.buffer.uri.clone();
+        let text = self
+            .capabilities
+            .save_include_text
+            .then(|| self.doc().rope().to_string());
+        Some(elm_ui::Command::new_async(move |_, _| async move {
+            lsp_client
+                .did_save(DidSaveTextDocumentParams {
+                    text_document: TextDocumentIdentifier { uri },
+                    text,
+                })
+                .await;
+            None
+        }))
+    }
+
+    pub fn get_close_command(&self) -> elm_ui::Command {
+        let lsp_client = self.lsp_client.clone();
+        let uri = self.buffer.uri.clone();
+        elm_ui::Command::new_async(move |_, _| async move {
+            lsp_client
+                .did_close(DidCloseTextDocumentParams {
+                    text_document: TextDocumentIdentifier { uri },
+                })
+                .await;
+            None
+        })
+    }
+
+    fn completion_menu_area(&self, area: Rect) -> Option<Rect> {
+        if !self.show_completions || self.completion_menu_state.is_empty() {
+            return None;
+        }
+        let menu = CompletionMenu::new(&self.completion_menu_state, self.screen_cursor(area));
+        Some(menu.area(area))
+    }
+
+    /// Scrolling over the completion menu moves the selection and clicking an item accepts it.
+    /// `area` is where the editor was drawn.
+    pub fn handle_mouse_event(
+        &mut self,
+        event: &MouseEvent,
+        area: Rect,
+    ) -> Option<elm_ui::Command> {
+        let (column, row) = (event.column, event.row);
+        let inside = |area: Rect| {
+            column >= area.x && column < area.right() && row >= area.y && row < area.bottom()
+        };
+        if event.kind == MouseEventKind::Down(MouseButton::Left)
+            && event.modifiers == KeyModifiers::ALT
+            && inside(area)
+        {
+            if let Some(loc) = self.loc_at(area, column, row) {
+                self.add_cursor(loc);
+            }
+            return None;
+        }
+        let area = self.completion_menu_area(area)?;
+        if !inside(area) {
+            return None;
+        }
+        match event.kind {
+            MouseEventKind::ScrollDown => self.completion_menu_state.scroll_down(),
+            MouseEventKind::ScrollUp => self.completion_menu_state.scroll_up(),
+            MouseEventKind::Down(MouseButton::Left) => {
+                let index = self
+                    .completion_menu_state
+                    .item_at((row - area.y) as usize, area.height as usize)?;
+                self.completion_menu_state.select(index);
+                return self.handle_action(Action::Newline);
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Applies edits to the current document and returns the changes to send to the server.
+    /// The cursor is moved along with the text before it.
+    fn apply_text_edits(&mut self, mut edits: Vec<TextEdit>) -> Vec<(Range, String)> {
+        self.extra_cursors.clear();
+        self.document_highlights.clear();
+        self.inlay_hints.clear();
+        self.semantic_overlays.clear();
+        let mut cursor = self.doc().char_loc();
+        // Apply from the bottom up so each edit leaves the ranges of the ones before it intact
+        edits.sort_by_key(|edit| (edit.range.start.line, edit.range.start.character));
+        let mut changes = vec![];
+        for edit in edits.into_iter().rev() {
+            let start = self.get_loc(&edit.range.start);
+            let end = self.get_loc(&edit.range.end);
+            if start != end {
+                changes.push(self.delete_range(start, end));
+            }
+            let new_text = edit.new_text.replace("\r\n", "\n");
+            self.doc_mut().goto(&start);
+            changes.extend(self.insert_lines(&new_text));
+
+            if (end.y, end.x) <= (cursor.y, cursor.x) {
+                let new_lines: Vec<_> = new_text.split('\n').collect();
+                if end.y == cursor.y {
+                    let line_start = if new_lines.len() == 1 { start.x } else { 0 };
+                    let last_line_len = new_lines[new_lines.len() - 1].chars().count();
+                    cursor.x = cursor.x - end.x + line_start + last_line_len;
+                }
+                cursor.y = cursor.y + new_lines.len() - 1 - (end.y - start.y);
+            }
+        }
+        let len_lines = self.doc().len_lines();
+        let y = cursor.y.min(len_lines.saturating_sub(1));
+        let x = cursor.x.min(self.line_chars(y).len());
+        self.doc_mut().goto(&Loc { x, y });
+        changes
+    }
+
+    fn diagnostic_overlays(&self) -> Vec<Overlay> {
+        // Later overlays are drawn over earlier ones, so overlapping ranges take the color of
+        // the most severe diagnostic
+        let mut diagnostics: Vec<_> = self.diagnostics.iter().collect();
+        diagnostics.sort_by_key(|diagnostic| severity_rank(diagnostic.severity));
+        let mut overlays = vec![];
+        for diagnostic in diagnostics {
+            let style = Style::default()
+                .fg(severity_color(diagnostic.severity))
+                .add_modifier(Modifier::UNDERLINED);
+            let start = self.get_loc(&diagnostic.range.start);
+            let end = self.get_loc(&diagnostic.range.end);
+            for line in start.y..=end.y {
+                overlays.push(Overlay {
+                    line,
+                    start: if line == start.y { start.x } else { 0 },
+                    end: if line == end.y { end.x } else { usize::MAX },
+                    style,
+                });
+            }
+        }
+        overlays
+    }
+
+    /// Decodes the tokens' relative positions and styles each one by its type. Types the theme
+    /// has no style for are left to the local highlighting.
+    fn semantic_token_overlays(&self, tokens: &[SemanticToken]) -> Vec<Overlay> {
+        let Some(legend) = &self.capabilities.semantic_tokens_legend else {
+            return vec![];
+        };
+        let mut overlays = vec![];
+        let (mut line, mut start) = (0, 0);
+        for token in tokens {
+            if token.delta_line > 0 {
+                line += token.delta_line;
+                start = 0;
+            }
+            start += token.delta_start;
+            let Some(style) = legend
+                .token_types
+                .get(token.token_type as usize)
+                .and_then(|token_type| self.theme.style(semantic_token_scope(token_type.as_str())))
+            else {
+                continue;
+            };
+            let start_loc = self.get_loc(&Position::new(line, start));
+            let end_loc = self.get_loc(&Position::new(line, start + token.length));
+            overlays.push(Overlay {
+                line: start_loc.y,
+                start: start_loc.x,
+                end: end_loc.x,
+                style,
+            });
+        }
+        overlays
+    }
+
+    fn selection_overlays(&self) -> Vec<Overlay> {
+        let Some((start, end)) = self.selection() else {
+            return vec![];
+        };
+        let style = Style::default().add_modifier(Modifier::REVERSED);
+        (start.y..=end.y)
+            .map(|line| Overlay {
+                line,
+                start: if line == start.y { start.x } else { 0 },
+                end: if line == end.y { end.x } else { usize::MAX },
+                style,
+            })
+            .collect()
+    }
+
+    fn document_highlight_overlays(&self) -> Vec<Overlay> {
+        let read = self
+            .theme
+            .style("ui.highlight.read")
+            .unwrap_or_else(|| Style::default().bg(Color::DarkGray));
+        let write = self
+            .theme
+            .style("ui.highlight.write")
+            .unwrap_or_else(|| read.add_modifier(Modifier::BOLD));
+        self.document_highlights
+            .iter()
+            .filter(|highlight| highlight.range.start.line == highlight.range.end.line)
+            .map(|highlight| {
+                let start = self.get_loc(&highlight.range.start);
+                let end = self.get_loc(&highlight.range.end);
+                Overlay {
+                    line: start.y,
+                    start: start.x,
+                    end: end.x,
+                    style: if highlight.kind == Some(DocumentHighlightKind::WRITE) {
+                        write
+                    } else {
+                        read
+                    },
+                }
+            })
+            .collect()
+    }
+
+    /// Keeps the highlights while the cursor stays on one of them. Otherwise they're cleared
+    /// and, if the cursor is on an identifier, its occurrences are requested.
+    fn update_document_highlights(&mut self) -> Option<elm_ui::Command> {
+        let cursor = self.doc().char_loc();
+        let on_highlight = self.document_highlights.iter().any(|highlight| {
+            let start = self.get_loc(&highlight.range.start);
+            let end = self.get_loc(&highlight.range.end);
+            start.y == cursor.y && (start.x..=end.x).contains(&cursor.x)
+        });
+        if on_highlight {
+            return None;
+        }
+        self.document_highlights.clear();
+
+        let line = self.line_chars(cursor.y);
+        let is_word_char = |x: usize| {
+            line.get(x)
+                .is_some_and(|c| c.is_alphanumeric() || *c == '_')
+        };
+        let on_identifier = is_word_char(cursor.x) || (cursor.x > 0 && is_word_char(cursor.x - 1));
+        if !on_identifier || !self.capabilities.document_highlight {
+            return None;
+        }
+        Some(self.get_document_highlight_command())
+    }
+
+    /// Emphasizes the bracket on or just before the cursor along with its partner
+    fn bracket_overlays(&self) -> Vec<Overlay> {
+        let rope = self.doc().rope();
+        let cursor = self.doc().char_loc();
+        if cursor.y >= rope.len_lines() {
+            return vec![];
+        }
+        let cursor_idx = rope.line_to_char(cursor.y) + cursor.x;
+        let on_bracket = |idx: usize| rope.get_char(idx).is_some_and(is_bracket);
+        let bracket = if on_bracket(cursor_idx) {
+            cursor_idx
+        } else if cursor.x > 0 && on_bracket(cursor_idx - 1) {
+            cursor_idx - 1
+        } else {
+            return vec![];
+        };
+
+        let style = Style::default()
+            .fg(Color::Yellow)
+            .add_modifier(Modifier::BOLD);
+        // Unbalanced brackets only highlight the one at the cursor
+        iter::once(bracket)
+            .chain(find_match(rope, bracket))
+            .map(|idx| {
+                let line = rope.char_to_line(idx);
+                let start = idx - rope.line_to_char(line);
+                Overlay {
+                    line,
+                    start,
+                    end: start + 1,
+                    style,
+                }
+            })
+            .collect()
+    }
+
+    pub fn handle_key_event(&mut self, event: &KeyEvent) -> Option<elm_ui::Command> {
+        if let Some(mut picker) = self.symbol_picker.take() {
+            match picker.handle_key_event(event) {
+                PickerEvent::Continue => self.symbol_picker = Some(picker),
+                PickerEvent::Close => {}
+                PickerEvent::Pick(position) => {
+                    self.selection_anchor = None;
+                    self.goto_position(&position);
+                }
+            }
+            return None;
+        }
+        if self.search.as_ref().is_some_and(Search::is_typing) {
+            self.handle_search_key_event(event);
+            return None;
+        }
+        let action = self.config.keys.get(event);
+        self.handle_input(action, event)
+    }
+
+    /// Moves the cursor to the first match from where the search started as the query is typed
+    fn handle_search_key_event(&mut self, event: &KeyEvent) {
+        let Some(search) = &mut self.search else {
+            return;
+        };
+        let origin = search.origin();
+        match search.handle_key_event(event) {
+            SearchEvent::Continue | SearchEvent::Confirm => {}
+            SearchEvent::Changed => {
+                self.selection_anchor = None;
+                self.doc_mut().goto(&origin);
+                self.goto_search_match(origin, true, true);
+            }
+            SearchEvent::Cancel => {
+                self.search = None;
+                self.doc_mut().goto(&origin);
+            }
+        }
+    }
+
+    /// Moves the cursor to the next match after `from`, or at it if `inclusive`, or to the
+    /// previous match before it. Both wrap around at the ends of the document.
+    fn goto_search_match(&mut self, from: Loc, forward: bool, inclusive: bool) {
+        let Some(search) = &self.search else {
+            return;
+        };
+        let matches = search.matches(self.doc().rope());
+        let found = if forward {
+            search::next_match(&matches, from, inclusive)
+        } else {
+            search::previous_match(&matches, from)
+        };
+        if let Some(start) = found {
+            self.selection_anchor = None;
+            self.doc_mut().goto(&start);
+        }
+    }
+
+    /// Replaces the match at the cursor, if there is one, and moves on to the next match
+    fn replace_match(&mut self) -> Option<elm_ui::Command> {
+        let search = self.search.as_ref()?;
+        let replacement = search.replacement()?.to_owned();
+        let cursor = self.doc().char_loc();
+        let Some((start, end)) = search
+            .matches(self.doc().rope())
+            .into_iter()
+            .find(|(start, _)| *start == cursor)
+        else {
+            self.goto_search_match(cursor, true, false);
+            return None;
+        };
+        let command = self.replace_ranges(&[(start, end)], &replacement);
+        // Skip over the replacement in case it matches too
+        let after = Loc {
+            x: start.x + replacement.chars().count(),
+            y: start.y,
+        };
+        self.goto_search_match(after, true, true);
+        command
+    }
+
+    /// Replaces every match and closes the search
+    fn replace_all_matches(&mut self) -> Option<elm_ui::Command> {
+        let search = self.search.take()?;
+        let replacement = search.replacement()?;
+        let matches = search.matches(self.doc().rope());
+        self.replace_ranges(&matches, replacement)
+    }
+
+    /// Sends the replacements to the server as a single change and reports how many were made
+    fn replace_ranges(
+        &mut self,
+        ranges: &[(Loc, Loc)],
+        replacement: &str,
+    ) -> Option<elm_ui::Command> {
+        let edits = ranges
+            .iter()
+            .map(|(start, end)| TextEdit {
+                range: Range::new(self.get_lsp_position(start), self.get_lsp_position(end)),
+                new_text: replacement.to_owned(),
+            })
+            .collect();
+        let count = ranges.len();
+        let status = format!(
+            "Replaced {count} {}",
+            if count == 1 { "match" } else { "matches" }
+        );
+        let mut commands: Vec<_> = self.apply_edits(edits).into_iter().collect();
+        commands.push(elm_ui::Command::simple(Message::custom(
+            EditorMessage::Status(status),
+        )));
+        Some(elm_ui::Command::simple(Message::Sequence(commands)))
+    }
+
+    /// Adds an extra cursor unless there's already one there
+    fn add_cursor(&mut self, loc: Loc) {
+        self.selection_anchor = None;
+        if loc != self.doc().char_loc() && !self.extra_cursors.contains(&loc) {
+            self.extra_cursors.push(loc);
+        }
+    }
+
+    /// Adds a cursor at the same offset into the next occurrence of the word under the cursor,
+    /// after the cursor that was added last. Occurrences that already have a cursor are skipped.
+    fn add_cursor_at_next_occurrence(&mut self) {
+        let cursor = self.doc().char_loc();
+        let line = self.line_chars(cursor.y);
+        let x = cursor.x.min(line.len());
+        let is_word_char = |c: &&char| c.is_alphanumeric() || **c == '_';
+        let start = x - line[..x].iter().rev().take_while(is_word_char).count();
+        let end = x + line[x..].iter().take_while(is_word_char).count();
+        if start == end {
+            return;
+        }
+        let word: String = line[start..end].iter().collect();
+        let taken: Vec<_> = iter::once(cursor)
+            .chain(self.extra_cursors.iter().copied())
+            .collect();
+        let candidates: Vec<_> = word_occurrences(self.doc().rope(), &word)
+            .into_iter()
+            .map(|occurrence| Loc {
+                x: occurrence.x + x - start,
+                y: occurrence.y,
+            })
+            .filter(|loc| !taken.contains(loc))
+            .collect();
+        let last = self.extra_cursors.last().copied().unwrap_or(cursor);
+        let next = candidates
+            .iter()
+            .find(|loc| (loc.y, loc.x) > (last.y, last.x))
+            .or_else(|| candidates.first());
+        if let Some(loc) = next.copied() {
+            self.add_cursor(loc);
+        }
+    }
+
+    /// Drops the extra cursors that ended up in the same place as another cursor
+    fn dedup_cursors(&mut self) {
+        let mut seen = vec![self.doc().char_loc()];
+        self.extra_cursors.retain(|loc| {
+            if seen.contains(loc) {
+                return false;
+            }
+            seen.push(*loc);
+            true
+        });
+    }
+
+    /// Moves the main cursor along with every extra cursor
+    fn move_cursors(&mut self, movement: impl Fn(&mut Document)) {
+        if !self.extra_cursors.is_empty() {
+            let cursor = self.doc().char_loc();
+            for i in 0..self.extra_cursors.len() {
+                let loc = self.extra_cursors[i];
+                self.doc_mut().goto(&loc);
+                movement(self.doc_mut());
+                self.extra_cursors[i] = self.doc().char_loc();
+            }
+            self.doc_mut().goto(&cursor);
+        }
+        movement(self.doc_mut());
+        self.dedup_cursors();
+    }
+
+    /// Makes the same edit at every cursor, from the bottom of the document up so that each edit
+    /// leaves the locations above it intact. `edit` changes the text at the cursor and leaves the
+    /// cursor after whatever it inserted. The changes are in the order they were made.
+    fn edit_at_cursors(
+        &mut self,
+        mut edit: impl FnMut(&mut Self) -> Vec<(Range, String)>,
+    ) -> Vec<(Range, String)> {
+        if self.extra_cursors.is_empty() {
+            return edit(self);
+        }
+        // The main cursor goes last
+        let mut cursors = mem::take(&mut self.extra_cursors);
+        cursors.push(self.doc().char_loc());
+        let mut order: Vec<_> = (0..cursors.len()).collect();
+        order.sort_by_key(|i| (cursors[*i].y, cursors[*i].x));
+
+        let mut changes = vec![];
+        for (n, i) in order.iter().enumerate().rev() {
+            let before = cursors[*i];
+            self.doc_mut().goto(&before);
+            changes.extend(edit(self));
+            let after = self.doc().char_loc();
+            cursors[*i] = after;
+            // The cursors below move along with the text that followed this one
+            for j in &order[n + 1..] {
+                let loc = &mut cursors[*j];
+                if loc.y == before.y {
+                    *loc = Loc {
+                        x: after.x + loc.x.saturating_sub(before.x),
+                        y: after.y,
+                    };
+                } else {
+                    loc.y = loc.y + after.y - before.y;
+                }
+            }
+        }
+        if let Some(cursor) = cursors.pop() {
+            self.doc_mut().goto(&cursor);
+        }
+        self.extra_cursors = cursors;
+        self.dedup_cursors();
+        changes
+    }
+
+    /// The terminal only draws the main cursor, so the others are drawn as blocks. Cursors at
+    /// the end of a line are drawn over a space after it.
+    fn extra_cursor_decorations(&self) -> (Vec<Overlay>, Vec<VirtualText>) {
+        let style = self
+            .theme
+            .style("ui.cursor")
+            .unwrap_or_else(|| Style::default().add_modifier(Modifier::REVERSED));
+        let mut overlays = vec![];
+        let mut virtual_text = vec![];
+        for loc in &self.extra_cursors {
+            if loc.x < self.line_chars(loc.y).len() {
+                overlays.push(Overlay {
+                    line: loc.y,
+                    start: loc.x,
+                    end: loc.x + 1,
+                    style,
+                });
+            } else {
+                virtual_text.push(VirtualText {
+                    line: loc.y,
+                    column: loc.x,
+                    text: " ".to_owned(),
+                    style,
+                });
+            }
+        }
+        (overlays, virtual_text)
+    }
+
+    fn search_overlays(&self) -> Vec<Overlay> {
+        let Some(search) = &self.search else {
+            return vec![];
+        };
+        let style = self
+            .theme
+            .style("ui.search")
+            .unwrap_or_else(|| Style::default().fg(Color::Black).bg(Color::Yellow));
+        let current = self
+            .theme
+            .style("ui.search.current")
+            .unwrap_or_else(|| style.add_modifier(Modifier::BOLD | Modifier::UNDERLINED));
+        let cursor = self.doc().char_loc();
+        search
+            .matches(self.doc().rope())
+            .into_iter()
+            .map(|(start, end)| Overlay {
+                line: start.y,
+                start: start.x,
+                end: end.x,
+                style: if start == cursor { current } else { style },
+            })
+            .collect()
+    }
+
+    /// Runs an action that wasn't triggered by its key binding, such as one picked from the
+    /// command palette
+    pub fn handle_action(&mut self, action: Action) -> Option<elm_ui::Command> {
+        self.handle_input(
+            Some(action),
+            &KeyEvent::new(KeyCode::Null, KeyModifiers::NONE),
+        )
+    }
+
+    /// Runs the action bound to `event`. Printable keys without one insert their character.
+    fn handle_input(
+        &mut self,
+        action: Option<Action>,
+        event: &KeyEvent,
+    ) -> Option<elm_ui::Command> {
+        if let Some(mut menu) = self.code_action_menu.take() {
+            match action {
+                Some(Action::MoveUp | Action::MoveDown) => {
+                    if action == Some(Action::MoveUp) {
+                        menu.state.previous();
+                    } else {
+                        menu.state.next();
+                    }
+                    self.code_action_menu = Some(menu);
+                    return None;
+                }
+                Some(Action::Newline) => {
+                    return menu
+                        .selected()
+                        .and_then(|action| self.apply_code_action(action));
+                }
+                Some(Action::Cancel) => return None,
+                // Any other key closes the menu and is handled as usual
+                _ => {}
+            }
+        }
+        if let Some(search) = &self.search {
+            let cursor = self.doc().char_loc();
+            let replacing = search.replacement().is_some();
+            match (action, event.modifiers, event.code) {
+                (Some(Action::Newline), ..) if replacing => return self.replace_match(),
+                (_, KeyModifiers::NONE, KeyCode::Char('a')) if replacing => {
+                    return self.replace_all_matches();
+                }
+                (Some(Action::Newline), ..) | (_, KeyModifiers::NONE, KeyCode::Char('n')) => {
+                    self.goto_search_match(cursor, true, false);
+                    return None;
+                }
+                (_, KeyModifiers::NONE | KeyModifiers::SHIFT, KeyCode::Char('N')) => {
+                    self.goto_search_match(cursor, false, false);
+                    return None;
+                }
+                // Any other key closes the search and is handled as usual
+                _ => self.search = None,
+            }
+        }
+
+        let mut changes = vec![];
+        let cursor = self.doc().cursor;
+        let is_showing_completions = self.show_completions;
+        let mut completion_accepted = false;
+        // Completion was asked for explicitly rather than triggered by typing
+        let mut completion_invoked = false;
+        let snippet_line = self.snippet.as_ref().map(|_| {
+            let y = self.doc().char_loc().y;
+            (y, self.line_chars(y).len(), self.doc().len_lines())
+        });
+        self.show_completions = false;
+        self.hover = None;
+        self.error = None;
+        match action {
+            Some(
+                select @ (Action::SelectUp
+                | Action::SelectDown
+                | Action::SelectLeft
+                | Action::SelectRight),
+            ) => {
+                self.extra_cursors.clear();
+                if self.selection_anchor.is_none() {
+                    self.selection_anchor = Some(self.doc().char_loc());
+                }
+                match select {
+                    Action::SelectUp => self.doc_mut().move_up(),
+                    Action::SelectDown => self.doc_mut().move_down(),
+                    Action::SelectLeft => self.doc_mut().move_left(),
+                    _ => self.doc_mut().move_right(),
+                };
+            }
+            Some(Action::MoveUp | Action::MoveDown | Action::MoveLeft | Action::MoveRight)
+                if self.selection().is_some() =>
+            {
+                self.selection_anchor = None;
+            }
+            Some(Action::MoveUp) => {
+                if is_showing_completions && !self.completion_menu_state.is_empty() {
+                    self.completion_menu_state.previous();
+                    self.show_completions = true;
+                } else {
+                    self.move_cursors(|doc| {
+                        doc.move_up();
+                    });
+                }
+            }
+            Some(Action::MoveDown) => {
+                if is_showing_completions && !self.completion_menu_state.is_empty() {
+                    self.completion_menu_state.next();
+                    self.show_completions = true;
+                } else {
+                    self.move_cursors(|doc| {
+                        doc.move_down();
+                    });
+                }
+            }
+            Some(Action::MoveLeft) => {
+                self.move_cursors(|doc| {
+                    doc.move_left();
+                });
+            }
+            Some(Action::MoveRight) => {
+                self.move_cursors(|doc| {
+                    doc.move_right();
+                });
+            }
+            Some(Action::AddCursor) => {
+                self.add_cursor_at_next_occurrence();
+            }
+            Some(Action::Cancel) => {
+                self.signature_help = None;
+                self.selection_anchor = None;
+                self.extra_cursors.clear();
+                self.snippet = None;
+            }
+            Some(Action::Complete) => {
+                if !self.capabilities.completion {
+                    return self.unsupported("Completion");
+                }
+                completion_invoked = true;
+                self.show_completions = true;
+            }
+            Some(Action::ToggleWrap) => {
+                self.soft_wrap = !self.soft_wrap;
+            }
+            Some(Action::Search) => {
+                self.search = Some(Search::new(self.doc().char_loc()));
+            }
+            Some(Action::Replace) => {
+                self.search = Some(Search::replace(self.doc().char_loc()));
+            }
+            Some(Action::ShowDiagnostics) => {
+                self.show_diagnostics_at_cursor();
+            }
+            Some(Action::Hover) => {
+                if !self.capabilities.hover {
+                    return self.unsupported("Hover");
+                }
+                return Some(self.get_hover_command());
+            }
+            Some(Action::GotoDefinition) => {
+                if !self.capabilities.definition {
+                    return self.unsupported("Go to definition");
+                }
+                return Some(self.get_definition_command());
+            }
+            Some(Action::Format) => {
+                if !self.capabilities.formatting {
+                    return self.unsupported("Formatting");
+                }
+                return Some(self.get_format_command(false));
+            }
+            Some(Action::GotoSymbol) => {
+                if !self.capabilities.document_symbol {
+                    return self.unsupported("Document symbols");
+                }
+                return Some(self.get_document_symbol_command());
+            }
+            Some(Action::CodeAction) => {
+                if !self.capabilities.code_action {
+                    return self.unsupported("Code actions");
+                }
+                return Some(self.get_code_action_command());
+            }
+            Some(Action::Copy) => {
+                self.copy();
+            }
+            Some(Action::Paste) => {
+                changes.extend(self.delete_selection());
+                changes.extend(self.edit_at_cursors(Self::paste));
+            }
+            Some(Action::Save) => {
+                if self.capabilities.will_save_wait_until {
+                    return Some(self.get_will_save_command());
+                }
+                if self.config.editor.format_on_save && self.capabilities.formatting {
+                    return Some(self.get_format_command(true));
+                }
+                return self.get_save_command();
+            }
+            Some(Action::Newline | Action::Indent)
+                if is_showing_completions && !self.completion_menu_state.is_empty() =>
+            {
+                if let Some(item) = self.completion_menu_state.selected().cloned() {
+                    // Only the main cursor was completed
+                    self.extra_cursors.clear();
+                    changes.extend(self.accept_completion(&item));
+                    completion_accepted = true;
+                }
+            }
+            Some(Action::Indent) if self.snippet.is_some() => {
+                self.next_snippet_stop();
+            }
+            Some(Action::Indent) => {
+                changes.extend(self.delete_selection());
+                let indent = if self.config.editor.soft_tabs {
+                    " ".repeat(self.config.editor.tab_width)
+                } else {
+                    "\t".to_owned()
+                };
+                changes.extend(self.edit_at_cursors(|editor| editor.insert_text(&indent)));
+            }
+            Some(Action::Dedent) => {
+                self.extra_cursors.clear();
+                changes.extend(self.dedent());
+            }
+            Some(Action::Backspace | Action::Delete) if self.selection().is_some() => {
+                changes.extend(self.delete_selection());
+            }
+            Some(Action::Backspace) => {
+                changes.extend(
+                    self.edit_at_cursors(|editor| editor.backspace().into_iter().collect()),
+                );
+            }
+            Some(Action::Newline) => {
+                changes.extend(self.delete_selection());
+                changes.extend(self.edit_at_cursors(|editor| editor.enter().into_iter().collect()));
+            }
+            None => {
+                if let (KeyModifiers::SHIFT | KeyModifiers::NONE, KeyCode::Char(c)) =
+                    (event.modifiers, event.code)
+                {
+                    changes.extend(self.delete_selection());
+                    changes.extend(self.edit_at_cursors(|editor| editor.character(c)));
+                }
+            }
+            // Handled by the app
+            _ => {}
+        }
+        if let Some((y, len, len_lines)) = snippet_line {
+            if !changes.is_empty() && !completion_accepted {
+                self.shift_snippet_stops(y, len, len_lines);
+            }
+        }
+        if self.selection().is_none() {
+            // Don't let an empty selection anchor the next one
+            self.selection_anchor = None;
+        }
+
+        let new_cursor = self.doc().cursor;
+        let edited = !changes.is_empty();
+        let mut commands = vec![];
+        let mut trigger_character = None;
+        let mut signature_help_trigger = None;
+        if new_cursor.y != cursor.y {
+            self.signature_help = None;
+        }
+        if self.doc().cursor != cursor || !changes.is_empty() || completion_invoked {
+            let loc = self.doc().char_loc();
+            if let Some(previous_char) = char_before(self.doc(), loc) {
+                let previous = previous_char.to_string();
+                // Asking for completions after a trigger character still counts as invoking it
+                if !completion_invoked && self.capabilities.trigger_characters.contains(&previous) {
+                    trigger_character = Some(previous);
+                }
+                let is_trigger = trigger_character.is_some();
+                let is_word_char = previous_char.is_alphanumeric() || previous_char == '_';
+                // Typing more of the word keeps an open menu open
+                let auto_trigger =
+                    is_word_char && (self.config.completion.auto_trigger || is_showing_completions);
+                if (auto_trigger || is_trigger)
+                    && !completion_accepted
+                    && self.capabilities.completion
+                {
+                    self.show_completions = true;
+                }
+
+                if !changes.is_empty() {
+                    if previous_char == ')' {
+                        self.signature_help = None;
+                    } else if self.is_signature_help_trigger(previous_char) {
+                        signature_help_trigger = Some(previous_char);
+                    }
+                }
+            }
+
+            if !changes.is_empty() {
+                commands.extend(self.get_change_command(changes));
+            }
+
+            if let Some(trigger) = signature_help_trigger {
+                commands.push(self.get_signature_help_command(trigger));
+            }
+
+            if self.show_completions {
+                let lsp_pos = self.get_lsp_position(&self.doc().char_loc());
+                let word_under_cursor = self.word_before_cursor();
+                let is_trigger = trigger_character.is_some();
+
+                // The length only matters for opening the menu, not for keeping it open
+                if !is_trigger
+                    && !completion_invoked
+                    && !is_showing_completions
+                    && word_under_cursor.chars().count() < self.config.completion.min_word_length
+                {
+                    self.show_completions = false;
+                } else {
+                    let cursor = self.doc().char_loc();
+                    let word_start = Loc {
+                        x: cursor.x - word_under_cursor.chars().count(),
+                        y: cursor.y,
+                    };
+                    // Narrow down the last response instead of asking the server again
+                    let continues_cache = !is_trigger
+                        && !completion_invoked
+                        && self.completion_cache.as_ref().is_some_and(|cache| {
+                            cache.word_start == word_start
+                                && word_under_cursor.starts_with(&cache.word)
+                        });
+                    let cached_matches = match &self.completion_cache {
+                        Some(cache) if continues_cache && !cache.is_incomplete => {
+                            Some(filter_completions(
+                                &cache.items,
+                                &word_under_cursor,
+                                &self.config.completion,
+                            ))
+                        }
+                        _ => None,
+                    };
+                    if let Some(matches) = cached_matches {
+                        let generation = self.completion_menu_state.start_request();
+                        self.completion_menu_state.receive(generation, matches);
+                    } else {
+                        // Only an incomplete list gets here while the word continues
+                        let context = completion_context(trigger_character, continues_cache);
+                        commands.push(self.get_completion_command(
+                            lsp_pos,
+                            context,
+                            word_under_cursor,
+                            word_start,
+                        ));
+                    }
+                }
+            }
+        }
+        if !self.show_completions {
+            self.completion_menu_state.cancel();
+            self.completion_cache = None;
+        }
+        if edited {
+            // The ranges are out of date, and asking again on every key typed would flood the
+            // server
+            self.document_highlights.clear();
+            // Cleared until the server sends the ones for the new contents
+            self.inlay_hints.clear();
+            self.semantic_overlays.clear();
+        } else if self.doc().cursor != cursor {
+            commands.extend(self.update_document_highlights());
+        }
+        Some(elm_ui::Command::simple(Message::Sequence(commands)))
+    }
+
+    /// Shows the full messages of the diagnostics under the cursor
+    fn show_diagnostics_at_cursor(&mut self) {
+        let cursor = self.doc().char_loc();
+        let diagnostics: Vec<_> = self
+            .diagnostics
+            .iter()
+            .filter(|diagnostic| {
+                let start = self.get_loc(&diagnostic.range.start);
+                let end = self.get_loc(&diagnostic.range.end);
+                (start.y, start.x) <= (cursor.y, cursor.x) && (cursor.y, cursor.x) <= (end.y, end.x)
+            })
+            .collect();
+        if diagnostics.is_empty() {
+            self.error = Some("No diagnostics under the cursor".to_owned());
+        } else {
+            self.hover = Some(diagnostics_to_text(&diagnostics));
+        }
+    }
+
+    /// Reports a feature the server doesn't provide instead of sending a request for it
+    fn unsupported(&mut self, feature: &str) -> Option<elm_ui::Command> {
+        self.error = Some(format!("{feature} isn't supported by the server"));
+        None
+    }
+
+    fn get_completion_command(
+        &mut self,
+        lsp_pos: Position,
+        context: CompletionContext,
+        word_under_cursor: String,
+        word_start: Loc,
+    ) -> elm_ui::Command {
+        let lsp_client = self.lsp_client.clone();
+        let document_uri = self.buffer.uri.clone();
+        let completion_config = self.config.completion.clone();
+        let generation = self.completion_menu_state.start_request();
+
+        elm_ui::Command::new_async(move |_, _| async move {
+            let completions = lsp_client
+                .completion(CompletionParams {
+                    text_document_position: TextDocumentPositionParams {
+                        text_document: TextDocumentIdentifier {
+                            uri: document_uri.clone(),
+                        },
+                        position: lsp_pos,
+                    },
+                    work_done_progress_params: Default::default(),
+                    partial_result_params: Default::default(),
+                    context: Some(context),
+                })
+                .await;
+            let completions = match completions {
+                Ok(completions) => completions?,
+                Err(e) => return Some(request_failed("Completion", &e.message)),
+            };
+            let (items, is_incomplete) = match completions {
+                CompletionResponse::Array(items) => (items, false),
+                CompletionResponse::List(list) => (list.items, list.is_incomplete),
+            };
+            let matches = filter_completions(&items, &word_under_cursor, &completion_config);
+            let cache = CompletionCache {
+                word_start,
+                word: word_under_cursor,
+                items,
+                is_incomplete,
+            };
+            Some(Message::custom(EditorMessage::Response(
+                document_uri,
+                EditorResponse::Completions(generation, cache, matches),
+            )))
+        })
+    }
+
+    fn is_signature_help_trigger(&self, ch: char) -> bool {
+        let ch = ch.to_string();
+        self.capabilities
+            .signature_help_trigger_characters
+            .contains(&ch)
+            || (self.signature_help.is_some()
+                && self
+                    .capabilities
+                    .signature_help_retrigger_characters
+                    .contains(&ch))
+    }
+
+    fn get_signature_help_command(&self, trigger: char) -> elm_ui::Command {
+        let lsp_client = self.lsp_client.clone();
+        let document_uri = self.buffer.uri.clone();
+        let lsp_pos = self.get_lsp_position(&self.doc().char_loc());
+        let context = SignatureHelpContext {
+            trigger_kind: SignatureHelpTriggerKind::TRIGGER_CHARACTER,
+            trigger_character: Some(trigger.to_string()),
+            is_retrigger: self.signature_help.is_some(),
+            active_signature_help: self.signature_help.clone(),
+        };
+        elm_ui::Command::new_async(move |_, _| async move {
+            let signature_help = lsp_client
+                .signature_help(SignatureHelpParams {
+                    context: Some(context),
+                    text_document_position_params: TextDocumentPositionParams {
+                        text_document: TextDocumentIdentifier {
+                            uri: document_uri.clone(),
+                        },
+                        position: lsp_pos,
+                    },
+                    work_done_progress_params: Default::default(),
+                })
+                .await;
+            match signature_help {
+                Ok(Some(signature_help)) => Some(Message::custom(EditorMessage::Response(
+                    document_uri,
+                    EditorResponse::SignatureHelp(signature_help),
+                ))),
+                Ok(None) => None,
+                Err(e) => Some(request_failed("Signature help", &e.message)),
+            }
+        })
+    }
+
+    fn get_definition_command(&self) -> elm_ui::Command {
+        let lsp_client = self.lsp_client.clone();
+        let document_uri = self.buffer.uri.clone();
+        let lsp_pos = self.get_lsp_position(&self.doc().char_loc());
+        elm_ui::Command::new_async(move |_, _| async move {
+            let definition = lsp_client
+                .goto_definition(GotoDefinitionParams {
+                    text_document_position_params: TextDocumentPositionParams {
+                        text_document: TextDocumentIdentifier { uri: document_uri },
+                        position: lsp_pos,
+                    },
+                    work_done_progress_params: Default::default(),
+                    partial_result_params: Default::default(),
+                })
+                .await;
+            match definition {
+                Ok(Some(definition)) => {
+                    let location = match definition {
+                        GotoDefinitionResponse::Scalar(location) => Some(location),
+                        GotoDefinitionResponse::Array(locations) => locations.into_iter().next(),
+                        GotoDefinitionResponse::Link(links) => {
+                            links.into_iter().next().map(|link| Location {
+                                uri: link.target_uri,
+                                range: link.target_selection_range,
+                            })
+                        }
+                    };
+                    match location {
+                        Some(location) => Some(Message::custom(EditorMessage::Goto(location))),
+                        None => {
+                            info!("No definition found");
+                            None
+                        }
+                    }
+                }
+                Ok(None) => {
+                    info!("No definition found");
+                    None
+                }
+                Err(e) => Some(request_failed("Definition", &e.message)),
+            }
+        })
+    }
+
+    /// Renames the symbol under the cursor. The edits are handed to the host since they can
+    /// span several documents.
+    pub fn get_rename_command(&self, new_name: String) -> elm_ui::Command {
+        let lsp_client = self.lsp_client.clone();
+        let document_uri = self.buffer.uri.clone();
+        let lsp_pos = self.get_lsp_position(&self.doc().char_loc());
+        elm_ui::Command::new_async(move |_, _| async move {
+            let edit = lsp_client
+                .rename(RenameParams {
+                    text_document_position: TextDocumentPositionParams {
+                        text_document: TextDocumentIdentifier {
+                            uri: document_uri.clone(),
+                        },
+                        position: lsp_pos,
+                    },
+                    new_name,
+                    work_done_progress_params: Default::default(),
+                })
+                .await;
+            match edit {
+                Ok(Some(edit)) => Some(Message::custom(EditorMessage::ApplyEdit(edit))),
+                Ok(None) => Some(Message::custom(EditorMessage::Response(
+                    document_uri,
+                    EditorResponse::Error("Nothing to rename here".to_owned()),
+                ))),
+                Err(e) => Some(request_failed("Rename", &e.message)),
+            }
+        })
+    }
+
+    /// Requests formatting for the current document, optionally saving it once the edits
+    /// are applied. Saving goes ahead without formatting if the request fails or times out.
+    fn get_format_command(&self, save: bool) -> elm_ui::Command {
+        let lsp_client = self.lsp_client.clone();
+        let document_uri = self.buffer.uri.clone();
+        let options = FormattingOptions {
+            tab_size: self.config.editor.tab_width as u32,
+            insert_spaces: self.config.editor.soft_tabs,
+            ..Default::default()
+        };
+        elm_ui::Command::new_async(move |_, _| async move {
+            let request = lsp_client.formatting(DocumentFormattingParams {
+                text_document: TextDocumentIdentifier {
+                    uri: document_uri.clone(),
+                },
+                options,
+                work_done_progress_params: Default::default(),
+            });
+            let failure = match tokio::time::timeout(FORMAT_ON_SAVE_TIMEOUT, request).await {
+                Ok(Ok(edits)) => {
+                    let edits = edits.unwrap_or_default();
+                    return Some(Message::custom(EditorMessage::Response(
+                        document_uri,
+                        EditorResponse::Format(edits, save),
+                    )));
+                }
+                Ok(Err(e)) => request_failed("Formatting", &e.message),
+                Err(_) => request_failed("Formatting", "timed out"),
+            };
+            if !save {
+                return Some(failure);
+            }
+            Some(save_anyway(failure, document_uri))
+        })
+    }
+
+    /// Applies the server's last-minute edits and then formats or saves. The document goes on
+    /// without them if the server fails or takes too long.
+    fn get_will_save_command(&self) -> elm_ui::Command {
+        let lsp_client = self.lsp_client.clone();
+        let document_uri = self.buffer.uri.clone();
+        elm_ui::Command::new_async(move |_, _| async move {
+            let request = lsp_client.will_save_wait_until(WillSaveTextDocumentParams {
+                text_document: TextDocumentIdentifier {
+                    uri: document_uri.clone(),
+                },
+                reason: TextDocumentSaveReason::MANUAL,
+            });
+            let failure = match tokio::time::timeout(FORMAT_ON_SAVE_TIMEOUT, request).await {
+                Ok(Ok(edits)) => {
+                    return Some(Message::custom(EditorMessage::Response(
+                        document_uri,
+                        EditorResponse::WillSave(edits.unwrap_or_default()),
+                    )));
+                }
+                Ok(Err(e)) => request_failed("Pre-save edits", &e.message),
+                Err(_) => request_failed("Pre-save edits", "timed out"),
+            };
+            Some(Message::Sequence(vec![
+                elm_ui::Command::simple(failure),
+                elm_ui::Command::simple(Message::custom(EditorMessage::Response(
+                    document_uri,
+                    EditorResponse::WillSave(vec![]),
+                ))),
+            ]))
+        })
+    }
+
+    fn get_code_action_command(&self) -> elm_ui::Command {
+        let lsp_client = self.lsp_client.clone();
+        let document_uri = self.buffer.uri.clone();
+        let cursor = self.doc().char_loc();
+        let (start, end) = self.selection().unwrap_or((cursor, cursor));
+        let range = Range {
+            start: self.get_lsp_position(&start),
+            end: self.get_lsp_position(&end),
+        };
+        let diagnostics = self
+            .diagnostics
+            .iter()
+            .filter(|d| {
+                d.range.start.line <= range.end.line && d.range.end.line >= range.start.line
+            })
+            .cloned()
+            .collect();
+        elm_ui::Command::new_async(move |_, _| async move {
+            let actions = lsp_client
+                .code_action(CodeActionParams {
+                    text_document: TextDocumentIdentifier {
+                        uri: document_uri.clone(),
+                    },
+                    range,
+                    context: CodeActionContext {
+                        diagnostics,
+                        only: None,
+                        trigger_kind: Some(CodeActionTriggerKind::INVOKED),
+                    },
+                    work_done_progress_params: Default::default(),
+                    partial_result_params: Default::default(),
+                })
+                .await;
+            match actions {
+                Ok(actions) => Some(Message::custom(EditorMessage::Response(
+                    document_uri,
+                    EditorResponse::CodeActions(actions.unwrap_or_default()),
+                ))),
+                Err(e) => Some(request_failed("Code actions", &e.message)),
+            }
+        })
+    }
+
+    fn get_document_symbol_command(&self) -> elm_ui::Command {
+        let lsp_client = self.lsp_client.clone();
+        let document_uri = self.buffer.uri.clone();
+        elm_ui::Command::new_async(move |_, _| async move {
+            let symbols = lsp_client
+                .document_symbol(DocumentSymbolParams {
+                    text_document: TextDocumentIdentifier {
+                        uri: document_uri.clone(),
+                    },
+                    work_done_progress_params: Default::default(),
+                    partial_result_params: Default::default(),
+                })
+                .await;
+            match symbols {
+                Ok(symbols) => Some(Message::custom(EditorMessage::Response(
+                    document_uri,
+                    EditorResponse::DocumentSymbols(
+                        symbols.unwrap_or(DocumentSymbolResponse::Flat(vec![])),
+                    ),
+                ))),
+                Err(e) => Some(request_failed("Document symbols", &e.message)),
+            }
+        })
+    }
+
+    fn get_execute_command(&self, command: Command) -> elm_ui::Command {
+        let lsp_client = self.lsp_client.clone();
+        elm_ui::Command::new_async(move |_, _| async move {
+            let result = lsp_client
+                .execute_command(ExecuteCommandParams {
+                    command: command.command,
+                    arguments: command.arguments.unwrap_or_default(),
+                    work_done_progress_params: Default::default(),
+                })
+                .await;
+            match result {
+                Ok(_) => None,
+                Err(e) => Some(request_failed(&command.title, &e.message)),
+            }
+        })
+    }
+
+    fn get_hover_command(&self) -> elm_ui::Command {
+        let lsp_client = self.lsp_client.clone();
+        let document_uri = self.buffer.uri.clone();
+        let lsp_pos = self.get_lsp_position(&self.doc().char_loc());
+        elm_ui::Command::new_async(move |_, _| async move {
+            let hover = lsp_client
+                .hover(HoverParams {
+                    text_document_position_params: TextDocumentPositionParams {
+                        text_document: TextDocumentIdentifier {
+                            uri: document_uri.clone(),
+                        },
+                        position: lsp_pos,
+                    },
+                    work_done_progress_params: Default::default(),
+                })
+                .await;
+            match hover {
+                Ok(Some(hover)) => Some(Message::custom(EditorMessage::Response(
+                    document_uri,
+                    EditorResponse::Hover(hover),
+                ))),
+                Ok(None) => None,
+                Err(e) => Some(request_failed("Hover", &e.message)),
+            }
+        })
+    }
+
+    fn get_document_highlight_command(&self) -> elm_ui::Command {
+        let lsp_client = self.lsp_client.clone();
+        let document_uri = self.buffer.uri.clone();
+        let lsp_pos = self.get_lsp_position(&self.doc().char_loc());
+        elm_ui::Command::new_async(move |_, _| async move {
+            let highlights = lsp_client
+                .document_highlight(DocumentHighlightParams {
+                    text_document_position_params: TextDocumentPositionParams {
+                        text_document: TextDocumentIdentifier {
+                            uri: document_uri.clone(),
+                        },
+                        position: lsp_pos,
+                    },
+                    work_done_progress_params: Default::default(),
+                    partial_result_params: Default::default(),
+                })
+                .await;
+            match highlights {
+                Ok(highlights) => Some(Message::custom(EditorMessage::Response(
+                    document_uri,
+                    EditorResponse::DocumentHighlights(lsp_pos, highlights.unwrap_or_default()),
+                ))),
+                Err(e) => Some(request_failed("Document highlight", &e.message)),
+            }
+        })
+    }
+
+    fn get_change_command(&self, changes: Vec<(Range, String)>) -> Option<elm_ui::Command> {
+        let content_changes = match self.capabilities.text_document_sync {
+            TextDocumentSyncKind::INCREMENTAL => changes
+                .into_iter()
+                .map(|(range, text)| TextDocumentContentChangeEvent {
+                    range: Some(range),
+                    text,
+                    range_length: None,
+                })
+                .collect(),
+            TextDocumentSyncKind::FULL => vec![TextDocumentContentChangeEvent {
+                range: None,
+                text: self.doc().rope().to_string(),
+                range_length: None,
+            }],
+            // The server doesn't want changes
+            _ => return None,
+        };
+        Some(self.get_did_change_command(content_changes))
+    }
+
+    /// Sends the whole document, such as after it was reloaded from disk
+    fn get_full_change_command(&self) -> Option<elm_ui::Command> {
+        if self.capabilities.text_document_sync == TextDocumentSyncKind::NONE {
+            return None;
+        }
+        let change = TextDocumentContentChangeEvent {
+            range: None,
+            text: self.doc().rope().to_string(),
+            range_length: None,
+        };
+        Some(self.get_did_change_command(vec![change]))
+    }
+
+    fn get_did_change_command(
+        &self,
+        content_changes: Vec<TextDocumentContentChangeEvent>,
+    ) -> elm_ui::Command {
+        let lsp_client = self.lsp_client.clone();
+        let document_uri = self.buffer.uri.clone();
+        let document_version = self.buffer.next_version();
+        let did_change = elm_ui::Command::new_async(move |_, _| async move {
+            lsp_client
+                .did_change(DidChangeTextDocumentParams {
+                    text_document: VersionedTextDocumentIdentifier {
+                        uri: document_uri,
+                        version: document_version,
+                    },
+                    content_changes,
+                })
+                .await;
+
+            None
+        });
+        self.then_request_decorations(did_change)
+    }
+
+    /// Follows a command that sends the document's contents with requests for what's drawn
+    /// along with them, like inlay hints and semantic tokens, so they're for the new contents
+    fn then_request_decorations(&self, command: elm_ui::Command) -> elm_ui::Command {
+        let requests: Vec<_> = self
+            .get_inlay_hint_command()
+            .into_iter()
+            .chain(self.get_semantic_tokens_command())
+            .collect();
+        if requests.is_empty() {
+            return command;
+        }
+        let commands = iter::once(command).chain(requests).collect();
+        elm_ui::Command::simple(Message::Sequence(commands))
+    }
+
+    fn get_semantic_tokens_command(&self) -> Option<elm_ui::Command> {
+        if self.capabilities.semantic_tokens_legend.is_none() {
+            return None;
+        }
+        let lsp_client = self.lsp_client.clone();
+        let document_uri = self.buffer.uri.clone();
+        let version = self.buffer.version();
+        Some(elm_ui::Command::new_async(move |_, _| async move {
+            let tokens = lsp_client
+                .semantic_tokens_full(SemanticTokensParams {
+                    text_document: TextDocumentIdentifier {
+                        uri: document_uri.clone(),
+                    },
+                    work_done_progress_params: Default::default(),
+                    partial_result_params: Default::default(),
+                })
+                .await;
+            let tokens = match tokens {
+                Ok(Some(SemanticTokensResult::Tokens(tokens))) => tokens.data,
+                Ok(Some(SemanticTokensResult::Partial(partial))) => partial.data,
+                Ok(None) => vec![],
+                Err(e) => {
+                    warn!("Semantic tokens request failed: {e:?}");
+                    return None;
+                }
+            };
+            Some(Message::custom(EditorMessage::Response(
+                document_uri,
+                EditorResponse::SemanticTokens(version, tokens),
+            )))
+        }))
+    }
+
+    fn get_inlay_hint_command(&self) -> Option<elm_ui::Command> {
+        if !self.capabilities.inlay_hint {
+            return None;
+        }
+        let lsp_client = self.lsp_client.clone();
+        let document_uri = self.buffer.uri.clone();
+        let version = self.buffer.version();
+        let last_line = self.doc().len_lines().saturating_sub(1);
+        let end = self.get_lsp_position(&Loc {
+            x: self.line_chars(last_line).len(),
+            y: last_line,
+        });
+        Some(elm_ui::Command::new_async(move |_, _| async move {
+            let hints = lsp_client
+                .inlay_hint(InlayHintParams {
+                    text_document: TextDocumentIdentifier {
+                        uri: document_uri.clone(),
+                    },
+                    range: Range {
+                        start: Position::new(0, 0),
+                        end,
+                    },
+                    work_done_progress_params: Default::default(),
+                })
+                .await;
+            match hints {
+                Ok(hints) => Some(Message::custom(EditorMessage::Response(
+                    document_uri,
+                    EditorResponse::InlayHints(version, hints.unwrap_or_default()),
+                ))),
+                Err(e) => {
+                    warn!("Inlay hint request failed: {e:?}");
+                    None
+                }
+            }
+        }))
+    }
+
+    fn enter(&mut self) -> Option<(Range, String)> {
+        if self.doc().loc().y != self.doc().len_lines() {
+            // Enter pressed in the middle or end of the line
+            let loc = self.doc().char_loc();
+            Some(self.exe(kaolinite::event::Event::SplitDown(loc)))
+        } else {
+            // Enter pressed on the empty line at the bottom of the document
+            self.new_row()
+        }
+    }
+
+    fn backspace(&mut self) -> Option<(Range, String)> {
+        let mut c = self.doc().char_ptr;
+        let on_first_line = self.doc().loc().y == 0;
+        let out_of_range = self.doc().out_of_range(0, self.doc().loc().y).is_err();
+        if c == 0 && !on_first_line && !out_of_range {
+            // Backspace was pressed on the start of the line, move line to the top
+            self.new_row();
+            let mut loc = self.doc().char_loc();
+            loc.y -= 1;
+            loc.x = self.line_chars(loc.y).len();
+            return Some(self.exe(kaolinite::event::Event::SpliceUp(loc)));
+        } else if c > 0 {
+            // Backspace was pressed in the middle of the line, delete the character.
+            // The cursor can be past the end of a shorter line after moving vertically.
+            let y = self.doc().loc().y;
+            let line = self.line_chars(y);
+            c = c.min(line.len()).checked_sub(1)?;
+            let ch = line[c];
+            let loc = Loc { x: c, y };
+            return Some(self.exe(kaolinite::event::Event::Delete(loc, ch.to_string())));
+        }
+        None
+    }
+
+    fn character(&mut self, ch: char) -> Vec<(Range, String)> {
+        self.insert_text(&ch.to_string())
+    }
+
+    /// Inserts text that doesn't contain any newlines at the cursor
+    fn insert_text(&mut self, text: &str) -> Vec<(Range, String)> {
+        let mut changes = vec![];
+        if let Some(change) = self.new_row() {
+            changes.push(change);
+        }
+
+        let loc = self.doc().char_loc();
+        changes.push(self.exe(kaolinite::event::Event::Insert(loc, text.to_string())));
+        self.doc_mut().goto(&Loc {
+            x: loc.x + text.chars().count(),
+            y: loc.y,
+        });
+        changes
+    }
+
+    /// Returns the ordered start and end of the selection, if there is a non-empty one
+    fn selection(&self) -> Option<(Loc, Loc)> {
+        let anchor = self.selection_anchor?;
+        let cursor = self.doc().char_loc();
+        let (start, end) = if (anchor.y, anchor.x) <= (cursor.y, cursor.x) {
+            (anchor, cursor)
+        } else {
+            (cursor, anchor)
+        };
+        if start == end {
+            return None;
+        }
+        // The cursor can sit on the empty line past the end of the document
+        let len_lines = self.doc().len_lines();
+        let clamp = |loc: Loc| {
+            if loc.y >= len_lines {
+                let y = len_lines.saturating_sub(1);
+                let x = self.line_chars(y).len();
+                Loc { x, y }
+            } else {
+                loc
+            }
+        };
+        Some((clamp(start), clamp(end)))
+    }
+
+    fn selected_text(&self) -> Option<String> {
+        let (start, end) = self.selection()?;
+        let lines: Vec<_> = (start.y..=end.y)
+            .map(|y| {
+                let line = self.line_chars(y);
+                let len = line.len();
+                let from = if y == start.y { start.x.min(len) } else { 0 };
+                let to = if y == end.y { end.x.min(len) } else { len };
+                line[from..to.max(from)].iter().collect::<String>()
+            })
+            .collect();
+        Some(lines.join("\n"))
+    }
+
+    /// Removes the selected text from the document and returns the change to send to the server
+    fn delete_selection(&mut self) -> Option<(Range, String)> {
+        let selection = self.selection();
+        self.selection_anchor = None;
+        let (start, end) = selection?;
+        Some(self.delete_range(start, end))
+    }
+
+    /// Removes the text between two ordered locations and leaves the cursor at `start`
+    fn delete_range(&mut self, start: Loc, end: Loc) -> (Range, String) {
+        let range = Range {
+            start: self.get_lsp_position(&start),
+            end: self.get_lsp_position(&end),
+        };
+
+        if start.y == end.y {
+            let line = self.line_chars(start.y);
+            let text: String = line[start.x.min(line.len())..end.x.min(line.len())]
+                .iter()
+                .collect();
+            self.doc_mut()
+                .exe(kaolinite::event::Event::Delete(start, text))
+                .unwrap();
+        } else {
+            let end_line = self.line_chars(end.y);
+            let end_text: String = end_line[..end.x.min(end_line.len())].iter().collect();
+            let end_loc = Loc { x: 0, y: end.y };
+            self.doc_mut()
+                .exe(kaolinite::event::Event::Delete(end_loc, end_text))
+                .unwrap();
+            for y in (start.y + 1..end.y).rev() {
+                let line = self.doc().line(y).unwrap_or_default();
+                self.doc_mut()
+                    .exe(kaolinite::event::Event::DeleteLine(y, line))
+                    .unwrap();
+            }
+            let start_line = self.line_chars(start.y);
+            let start_text: String = start_line[start.x.min(start_line.len())..].iter().collect();
+            self.doc_mut()
+                .exe(kaolinite::event::Event::Delete(start, start_text))
+                .unwrap();
+            self.doc_mut()
+                .exe(kaolinite::event::Event::SpliceUp(start))
+                .unwrap();
+        }
+        self.doc_mut().goto(&start);
+        (range, "".to_owned())
+    }
+
+    /// Removes one level of indentation from the current line, or every selected line
+    fn dedent(&mut self) -> Vec<(Range, String)> {
+        let cursor = self.doc().char_loc();
+        let (first, last) = match self.selection() {
+            Some((start, end)) => (start.y, end.y),
+            None => (cursor.y, cursor.y),
+        };
+        let tab_width = self.doc().tab_width;
+        let mut changes = vec![];
+        let mut cursor_removed = 0;
+        let mut anchor_removed = 0;
+        for y in first..=last {
+            let line = self.line_chars(y);
+            // Remove a tab, or as many spaces as there are up to the tab width
+            let removed = if line.first() == Some(&'\t') {
+                1
+            } else {
+                line.iter()
+                    .take(tab_width)
+                    .take_while(|ch| **ch == ' ')
+                    .count()
+            };
+            if removed == 0 {
+                continue;
+            }
+            changes.push(self.delete_range(Loc { x: 0, y }, Loc { x: removed, y }));
+            if y == cursor.y {
+                cursor_removed = removed;
+            }
+            if self.selection_anchor.is_some_and(|anchor| anchor.y == y) {
+                anchor_removed = removed;
+            }
+        }
+
+        if let Some(anchor) = &mut self.selection_anchor {
+            anchor.x = anchor.x.saturating_sub(anchor_removed);
+        }
+        self.doc_mut().goto(&Loc {
+            x: cursor.x.saturating_sub(cursor_removed),
+            y: cursor.y,
+        });
+        changes
+    }
+
+    fn line_chars(&self, y: usize) -> Vec<char> {
+        self.doc().line(y).unwrap_or_default().chars().collect()
+    }
+
+    /// The characters of the line as it's drawn, with its inlay hints
+    fn display_line(&self, y: usize) -> Vec<char> {
+        let mut line = self.line_chars(y);
+        // From the end of the line so the columns of the earlier hints stay put
+        for (loc, label) in self.inlay_hints.iter().rev().filter(|(loc, _)| loc.y == y) {
+            let x = loc.x.min(line.len());
+            line.splice(x..x, label.chars());
+        }
+        line
+    }
+
+    /// The index of the character at `loc` in its line as it's drawn, with the inlay hints
+    /// before it
+    fn display_x(&self, loc: Loc) -> usize {
+        let hints_before: usize = self
+            .inlay_hints
+            .iter()
+            .filter(|(hint, _)| hint.y == loc.y && hint.x <= loc.x)
+            .map(|(_, label)| label.chars().count())
+            .sum();
+        loc.x + hints_before
+    }
+
+    fn inlay_hint_virtual_text(&self) -> Vec<VirtualText> {
+        let style = self
+            .theme
+            .style("ui.virtual.inlay-hint")
+            .unwrap_or_else(|| Style::default().fg(Color::DarkGray));
+        self.inlay_hints
+            .iter()
+            .map(|(loc, label)| VirtualText {
+                line: loc.y,
+                column: loc.x,
+                text: label.clone(),
+                style,
+            })
+            .collect()
+    }
+
+    fn copy(&self) {
+        let text = match self.selected_text() {
+            Some(text) => text,
+            None => {
+                let loc = self.doc().char_loc();
+                let Some(line) = self.doc().line(loc.y) else {
+                    return;
+                };
+                format!("{line}\n")
+            }
+        };
+        let result = arboard::Clipboard::new().and_then(|mut c| c.set_text(text));
+        if let Err(e) = result {
+            warn!("Failed to copy to clipboard: {e:?}");
+        }
+    }
+
+    fn paste(&mut self) -> Vec<(Range, String)> {
+        let text = match arboard::Clipboard::new().and_then(|mut c| c.get_text()) {
+            Ok(text) => text.replace("\r\n", "\n"),
+            Err(e) => {
+                warn!("Failed to paste from clipboard: {e:?}");
+                return vec![];
+            }
+        };
+        self.insert_lines(&text)
+    }
+
+    /// Inserts text that may span multiple lines at the cursor
+    fn insert_lines(&mut self, text: &str) -> Vec<(Range, String)> {
+        let mut changes = vec![];
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                changes.extend(self.enter());
+            }
+            if !line.is_empty() {
+                changes.extend(self.insert_text(line));
+            }
+        }
+        changes
+    }
+
+    fn accept_completion(&mut self, item: &CompletionItem) -> Vec<(Range, String)> {
+        let cursor = self.doc().char_loc();
+        let (range, new_text) = match &item.text_edit {
+            Some(CompletionTextEdit::Edit(edit)) => (edit.range, edit.new_text.clone()),
+            Some(CompletionTextEdit::InsertAndReplace(edit)) => {
+                (edit.replace, edit.new_text.clone())
+            }
+            None => {
+                let word_start = Loc {
+                    x: cursor.x - self.word_before_cursor().chars().count(),
+                    y: cursor.y,
+                };
+                let range = Range {
+                    start: self.get_lsp_position(&word_start),
+                    end: self.get_lsp_position(&cursor),
+                };
+                let text = item.insert_text.as_ref().unwrap_or(&item.label);
+                (range, text.clone())
+            }
+        };
+
+        let snippet = (item.insert_text_format == Some(InsertTextFormat::SNIPPET))
+            .then(|| snippet::parse(&new_text.replace("\r\n", "\n")));
+        let new_text = match &snippet {
+            Some(snippet) => snippet.text.clone(),
+            None => new_text.replace("\r\n", "\n"),
+        };
+
+        let mut start = self.get_loc(&range.start);
+        let mut end = self.get_loc(&range.end);
+        // The edit was computed when the request was sent, so the user may have typed past it
+        if end.y == cursor.y && end.x < cursor.x {
+            end = cursor;
+        }
+        // The selected text is replaced along with the word being completed
+        if let Some((selection_start, selection_end)) = self.selection() {
+            if (selection_start.y, selection_start.x) < (start.y, start.x) {
+                start = selection_start;
+            }
+            if (selection_end.y, selection_end.x) > (end.y, end.x) {
+                end = selection_end;
+            }
+        }
+        self.selection_anchor = None;
+        let mut changes = vec![];
+        if start != end {
+            changes.push(self.delete_range(start, end));
+        }
+        self.doc_mut().goto(&start);
+        changes.extend(self.insert_lines(&new_text));
+        if let Some(snippet) = snippet {
+            self.start_snippet(start, &snippet);
+        }
+        changes
+    }
+
+    fn start_snippet(&mut self, start: Loc, snippet: &Snippet) {
+        // Convert char offsets in the snippet text to document locations
+        let to_loc = |offset: usize| {
+            let before: Vec<_> = snippet.text.chars().take(offset).collect();
+            let lines = before.iter().filter(|ch| **ch == '\n').count();
+            let x = before.iter().rev().take_while(|ch| **ch != '\n').count();
+            if lines == 0 {
+                Loc {
+                    x: start.x + x,
+                    y: start.y,
+                }
+            } else {
+                Loc {
+                    x,
+                    y: start.y + lines,
+                }
+            }
+        };
+        let stops = snippet
+            .tabstops
+            .iter()
+            .map(|range| (to_loc(range.start), to_loc(range.end)))
+            .collect();
+        self.snippet = Some(SnippetSession { stops, current: 0 });
+        self.select_snippet_stop();
+    }
+
+    fn next_snippet_stop(&mut self) {
+        if let Some(session) = &mut self.snippet {
+            session.current += 1;
+        }
+        self.select_snippet_stop();
+    }
+
+    /// Selects the placeholder of the current tab stop, ending the session at the final stop
+    fn select_snippet_stop(&mut self) {
+        let Some(session) = &self.snippet else {
+            return;
+        };
+        let Some(&(start, end)) = session.stops.get(session.current) else {
+            self.snippet = None;
+            return;
+        };
+        if session.current == session.stops.len() - 1 {
+            self.snippet = None;
+        }
+        self.doc_mut().goto(&end);
+        self.selection_anchor = (start != end).then_some(start);
+    }
+
+    /// Keeps the remaining tab stops in place after an edit inside the current placeholder
+    fn shift_snippet_stops(&mut self, y: usize, old_len: usize, old_len_lines: usize) {
+        if self.doc().len_lines() != old_len_lines {
+            // Edits that add or remove lines aren't tracked
+            self.snippet = None;
+            return;
+        }
+        let new_len = self.line_chars(y).len();
+        let Some(session) = &mut self.snippet else {
+            return;
+        };
+        let (_, current_end) = session.stops[session.current];
+        for (start, end) in session.stops.iter_mut() {
+            if start.y == y && start.x >= current_end.x {
+                start.x = (start.x + new_len).saturating_sub(old_len);
+            }
+            if end.y == y && end.x >= current_end.x {
+                end.x = (end.x + new_len).saturating_sub(old_len);
+            }
+        }
+    }
+
+    /// The identifier characters directly before the cursor
+    fn word_before_cursor(&self) -> String {
+        let cursor = self.doc().char_loc();
+        word_before(&self.line_chars(cursor.y), cursor.x)
+    }
+
+    fn new_row(&mut self) -> Option<(Range, String)> {
+        if self.doc().loc().y == self.doc().len_lines() {
+            let y = self.doc().loc().y;
+            Some(self.exe(kaolinite::event::Event::InsertLine(y, "".to_string())))
+        } else {
+            None
+        }
+    }
+
+    /// Applies an edit to the document and returns the change that brings the server's copy
+    /// up to date
+    fn exe(&mut self, event: kaolinite::event::Event) -> (Range, String) {
+        let change = encode_change(self.doc(), &event, self.capabilities.encoding);
+        self.doc_mut().exe(event).unwrap();
+        change
+    }
+
+    /// Converts a location with a char index for its column into a position for the server
+    fn get_lsp_position(&self, loc: &Loc) -> Position {
+        lsp_position(&self.line_chars(loc.y), *loc, self.capabilities.encoding)
+    }
+
+    fn get_loc(&self, position: &Position) -> Loc {
+        loc_for_position(
+            &self.line_chars(position.line as usize),
+            position,
+            self.capabilities.encoding,
+        )
+    }
+}
+
+impl Widget for &Editor {
+    fn render(self, area: Rect, buf: &mut ratatui::buffer::Buffer) {
+        let mut overlays = self.semantic_overlays.clone();
+        overlays.extend(self.document_highlight_overlays());
+        overlays.extend(self.diagnostic_overlays());
+        overlays.extend(self.search_overlays());
+        overlays.extend(self.bracket_overlays());
+        overlays.extend(self.selection_overlays());
+        let (cursor_overlays, cursor_text) = self.extra_cursor_decorations();
+        overlays.extend(cursor_overlays);
+        let mut virtual_text = self.inlay_hint_virtual_text();
+        virtual_text.extend(cursor_text);
+        self.scroll_to_cursor(area);
+        TextArea {
+            doc: self.doc(),
+            overlays: &overlays,
+            virtual_text: &virtual_text,
+            language_id: &self.buffer.language_id,
+            theme: &self.theme,
+            scroll: self.scroll.get(),
+            wrap: self.soft_wrap,
+            whitespace: self.config.editor.render_whitespace,
+        }
+        .render(area, buf);
+
+        let cursor = self.screen_cursor(area);
+        if self.show_completions && !self.completion_menu_state.is_empty() {
+            CompletionMenu::new(&self.completion_menu_state, cursor).render(area, buf);
+        }
+
+        if let Some(menu) = &self.code_action_menu {
+            CompletionMenu::new(&menu.state, cursor).render(area, buf);
+        }
+
+        let Loc { x, y } = cursor;
+        if let Some(signature_help) = self
+            .signature_help
+            .as_ref()
+            .and_then(signature_help_to_text)
+        {
+            let popup = Popup::new(signature_help);
+            let popup_area = above_cursor(x as u16, y as u16, popup.width(), popup.height(), area);
+            popup.render(popup_area, buf);
+        }
+
+        if let Some(hover) = &self.hover {
+            let popup = Popup::new(hover.clone());
+            let popup_area = below_cursor(
+                x as u16,
+                y as u16,
+                popup.width(),
+                popup.height().min(HOVER_MAX_HEIGHT),
+                area,
+            );
+            popup.render(popup_area, buf);
+        }
+
+        if let Some(error) = &self.error {
+            let popup =
+                Popup::new(error.as_str()).style(Style::default().fg(Color::White).bg(Color::Red));
+            let popup_area = below_cursor(x as u16, y as u16, popup.width(), popup.height(), area);
+            popup.render(popup_area, buf);
+        }
+
+        if let Some(search) = &self.search {
+            search.render(area, buf);
+        }
+
+        if let Some(picker) = &self.symbol_picker {
+            picker.render(area, buf);
+        }
+    }
+}
+
+/// Separates a nested symbol from its parents in the picker
+const SYMBOL_PATH_SEPARATOR: &str = " › ";
+
+/// The symbols to list in the picker, along with where each one's name starts. Nested symbols
+/// follow their parent and are labeled with the path to them, like `Class › method`.
+fn picker_symbols(symbols: &DocumentSymbolResponse, uri: &Url) -> Vec<(CompletionItem, Position)> {
+    fn item(label: String, kind: SymbolKind) -> CompletionItem {
+        CompletionItem {
+            label,
+            kind: symbol_completion_kind(kind),
+            ..Default::default()
+        }
+    }
+
+    fn flatten(
+        symbols: &[DocumentSymbol],
+        parent: Option<&str>,
+        items: &mut Vec<(CompletionItem, Position)>,
+    ) {
+        for symbol in symbols {
+            let path = match parent {
+                Some(parent) => format!("{parent}{SYMBOL_PATH_SEPARATOR}{}", symbol.name),
+                None => symbol.name.clone(),
+            };
+            let children = symbol.children.as_deref().unwrap_or_default();
+            items.push((
+                item(path.clone(), symbol.kind),
+                symbol.selection_range.start,
+            ));
+            flatten(children, Some(&path), items);
+        }
+    }
+
+    match symbols {
+        DocumentSymbolResponse::Flat(symbols) => symbols
+            .iter()
+            .filter(|symbol| symbol.location.uri == *uri)
+            .map(|symbol| {
+                let label = match &symbol.container_name {
+                    Some(container) if !container.is_empty() => {
+                        format!("{container}{SYMBOL_PATH_SEPARATOR}{}", symbol.name)
+                    }
+                    _ => symbol.name.clone(),
+                };
+                (item(label, symbol.kind), symbol.location.range.start)
+            })
+            .collect(),
+        DocumentSymbolResponse::Nested(symbols) => {
+            let mut items = vec![];
+            flatten(symbols, None, &mut items);
+            items
+        }
+    }
+}
+
+/// Symbols are shown with the icon of the closest kind of completion
+fn symbol_completion_kind(kind: SymbolKind) -> Option<CompletionItemKind> {
+    let kind = match kind {
+        SymbolKind::FILE => CompletionItemKind::FILE,
+        SymbolKind::MODULE | SymbolKind::NAMESPACE | SymbolKind::PACKAGE => {
+            CompletionItemKind::MODULE
+        }
+        SymbolKind::CLASS => CompletionItemKind::CLASS,
+        SymbolKind::METHOD => CompletionItemKind::METHOD,
+        SymbolKind::PROPERTY => CompletionItemKind::PROPERTY,
+        SymbolKind::FIELD => CompletionItemKind::FIELD,
+        SymbolKind::CONSTRUCTOR => CompletionItemKind::CONSTRUCTOR,
+        SymbolKind::ENUM => CompletionItemKind::ENUM,
+        SymbolKind::INTERFACE => CompletionItemKind::INTERFACE,
+        SymbolKind::FUNCTION => CompletionItemKind::FUNCTION,
+        SymbolKind::VARIABLE => CompletionItemKind::VARIABLE,
+        SymbolKind::CONSTANT => CompletionItemKind::CONSTANT,
+        SymbolKind::STRING
+        | SymbolKind::NUMBER
+        | SymbolKind::BOOLEAN
+        | SymbolKind::ARRAY
+        | SymbolKind::OBJECT
+        | SymbolKind::NULL => CompletionItemKind::VALUE,
+        SymbolKind::KEY => CompletionItemKind::PROPERTY,
+        SymbolKind::ENUM_MEMBER => CompletionItemKind::ENUM_MEMBER,
+        SymbolKind::STRUCT => CompletionItemKind::STRUCT,
+        SymbolKind::EVENT => CompletionItemKind::EVENT,
+        SymbolKind::OPERATOR => CompletionItemKind::OPERATOR,
+        SymbolKind::TYPE_PARAMETER => CompletionItemKind::TYPE_PARAMETER,
+        _ => return None,
+    };
+    Some(kind)
+}
+
+fn signature_help_to_text(signature_help: &SignatureHelp) -> Option<Text<'static>> {
+    let active_signature = signature_help.active_signature.unwrap_or(0) as usize;
+    let signature = signature_help.signatures.get(active_signature)?;
+    let label: Vec<_> = signature.label.chars().collect();
+
+    let active_parameter = signature
+        .active_parameter
+        .or(signature_help.active_parameter)
+        .unwrap_or(0) as usize;
+    let parameter_range = signature
+        .parameters
+        .as_ref()
+        .and_then(|parameters| parameters.get(active_parameter))
+        .and_then(|parameter| match &parameter.label {
+            ParameterLabel::Simple(name) => {
+                let start = signature.label.find(name.as_str())?;
+                let start = signature.label[..start].chars().count();
+                Some((start, start + name.chars().count()))
+            }
+            ParameterLabel::LabelOffsets([start, end]) => {
+                // Offsets are in UTF-16 code units
+                let mut units = 0;
+                let mut char_range = (label.len(), label.len());
+                for (i, ch) in label.iter().enumerate() {
+                    if units == *start as usize {
+                        char_range.0 = i;
+                    }
+                    if units == *end as usize {
+                        char_range.1 = i;
+                    }
+                    units += ch.len_utf16();
+                }
+                Some(char_range)
+            }
+        });
+
+    let mut spans = vec![];
+    if let Some((start, end)) = parameter_range {
+        spans.push(Span::raw(label[..start].iter().collect::<String>()));
+        spans.push(Span::styled(
+            label[start..end].iter().collect::<String>(),
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        ));
+        spans.push(Span::raw(label[end..].iter().collect::<String>()));
+    } else {
+        spans.push(Span::raw(signature.label.clone()));
+    }
+    if signature_help.signatures.len() > 1 {
+        spans.push(Span::styled(
+            format!(
+                " ({}/{})",
+                active_signature + 1,
+                signature_help.signatures.len()
+            ),
+            Style::default().fg(Color::Gray),
+        ));
+    }
+    Some(Spans::from(spans).into())
+}
+
+fn hover_to_text(contents: &HoverContents) -> Text<'static> {
+    fn marked_string_to_markdown(marked: &MarkedString) -> String {
+        match marked {
+            MarkedString::String(value) => value.clone(),
+            MarkedString::LanguageString(LanguageString { language, value }) => {
+                format!("```{language}\n{value}\n```")
+            }
+        }
+    }
+
+    let markdown = match contents {
+        HoverContents::Scalar(marked) => marked_string_to_markdown(marked),
+        HoverContents::Array(marked) => marked
+            .iter()
+            .map(marked_string_to_markdown)
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        HoverContents::Markup(content) => content.value.clone(),
+    };
+    markdown_to_text(&markdown, HOVER_WIDTH)
+}
+
+/// The hint's text along with the padding the server asked for around it
+fn inlay_hint_label(hint: &InlayHint) -> String {
+    let label = match &hint.label {
+        InlayHintLabel::String(label) => label.clone(),
+        InlayHintLabel::LabelParts(parts) => parts.iter().map(|part| part.value.as_str()).collect(),
+    };
+    let padding = |pad: Option<bool>| if pad == Some(true) { " " } else { "" };
+    format!(
+        "{}{label}{}",
+        padding(hint.padding_left),
+        padding(hint.padding_right)
+    )
+}
+
+/// Orders severities from least to most severe. Diagnostics without one are treated as errors.
+fn severity_rank(severity: Option<DiagnosticSeverity>) -> u8 {
+    match severity {
+        Some(DiagnosticSeverity::HINT) => 0,
+        Some(DiagnosticSeverity::INFORMATION) => 1,
+        Some(DiagnosticSeverity::WARNING) => 2,
+        _ => 3,
+    }
+}
+
+fn severity_color(severity: Option<DiagnosticSeverity>) -> Color {
+    match severity {
+        Some(DiagnosticSeverity::WARNING) => Color::Yellow,
+        Some(DiagnosticSeverity::INFORMATION) => Color::Blue,
+        Some(DiagnosticSeverity::HINT) => Color::Gray,
+        _ => Color::Red,
+    }
+}
+
+fn diagnostics_to_text(diagnostics: &[&Diagnostic]) -> Text<'static> {
+    let mut lines = vec![];
+    for diagnostic in diagnostics {
+        if !lines.is_empty() {
+            lines.push(Spans::default());
+        }
+        let severity = match diagnostic.severity {
+            Some(DiagnosticSeverity::WARNING) => "warning",
+            Some(DiagnosticSeverity::INFORMATION) => "info",
+            Some(DiagnosticSeverity::HINT) => "hint",
+            _ => "error",
+        };
+        let header = match &diagnostic.source {
+            Some(source) => format!("{severity} ({source})"),
+            None => severity.to_owned(),
+        };
+        let style = Style::default()
+            .fg(severity_color(diagnostic.severity))
+            .add_modifier(Modifier::BOLD);
+        lines.push(Spans::from(Span::styled(header, style)));
+        lines.extend(
+            wrap_text(&diagnostic.message, HOVER_WIDTH)
+                .into_iter()
+                .map(Spans::from),
+        );
+    }
+    lines.into()
+}
+
+/// The char before `loc`, treating a cursor past the end of the line as being at its end
+fn char_before(doc: &Document, loc: Loc) -> Option<char> {
+    let line: Vec<_> = doc.line(loc.y)?.chars().collect();
+    let x = loc.x.min(line.len()).checked_sub(1)?;
+    Some(line[x])
+}
+
+/// The position of char column `loc.x` in `line`, with the column counted in code units of
+/// `encoding`
+fn lsp_position(line: &[char], loc: Loc, encoding: Encoding) -> Position {
+    let character: usize = line.iter().take(loc.x).map(|ch| encoding.len(*ch)).sum();
+    Position::new(loc.y as u32, character as u32)
+}
+
+/// Logs a request that failed and reports it in the status bar. Every failed request the user
+/// asked for goes through here, so they're all shown the same way.
+fn request_failed(request: &str, message: &str) -> Message {
+    warn!("{request} request failed: {message}");
+    Message::custom(EditorMessage::RequestFailed(format!(
+        "{request} failed: {message}"
+    )))
+}
+
+/// The status bar message for a document that couldn't be written to disk
+pub(crate) fn save_failed(buffer: &Buffer, error: &anyhow::Error) -> String {
+    format!("Failed to save {}: {error}", buffer.name())
+}
+
+/// Reports the failure and saves the document without the edits that were requested before
+/// saving
+fn save_anyway(failure: Message, document_uri: Url) -> Message {
+    Message::Sequence(vec![
+        elm_ui::Command::simple(failure),
+        elm_ui::Command::simple(Message::custom(EditorMessage::Response(
+            document_uri,
+            EditorResponse::Format(vec![], true),
+        ))),
+    ])
+}
+
+/// Derives the minimal LSP change for an event from the document it's about to be applied to
+pub(crate) fn encode_change(
+    old: &Document,
+    event: &kaolinite::event::Event,
+    encoding: Encoding,
+) -> (Range, String) {
+    use kaolinite::event::Event;
+
+    let line_chars = |y| -> Vec<char> { old.line(y).unwrap_or_default().chars().collect() };
+    let position = |loc: Loc| lsp_position(&line_chars(loc.y), loc, encoding);
+    let line_start = |y: usize| Position::new(y as u32, 0);
+    match event {
+        Event::Insert(loc, text) => {
+            let start = position(*loc);
+            (Range::new(start, start), text.clone())
+        }
+        Event::Delete(loc, text) => {
+            let end = Loc {
+                x: loc.x + text.chars().count(),
+                y: loc.y,
+            };
+            (Range::new(position(*loc), position(end)), String::new())
+        }
+        Event::InsertLine(y, text) => {
+            let start = line_start(*y);
+            (Range::new(start, start), format!("{text}\n"))
+        }
+        Event::DeleteLine(y, _) => (Range::new(line_start(*y), line_start(y + 1)), String::new()),
+        Event::SplitDown(loc) => {
+            let start = position(*loc);
+            (Range::new(start, start), "\n".to_owned())
+        }
+        Event::SpliceUp(loc) => {
+            // Joins the line with the one below it by removing the newline at its end
+            let end_of_line = Loc {
+                x: line_chars(loc.y).len(),
+                y: loc.y,
+            };
+            (
+                Range::new(position(end_of_line), line_start(loc.y + 1)),
+                String::new(),
+            )
+        }
+    }
+}
+
+/// The char location of a position in `line`. Columns that fall inside a character round up
+/// to the next one.
+fn loc_for_position(line: &[char], position: &Position, encoding: Encoding) -> Loc {
+    let mut units = 0;
+    let mut x = 0;
+    for ch in line {
+        if units >= position.character as usize {
+            break;
+        }
+        units += encoding.len(*ch);
+        x += 1;
+    }
+    Loc {
+        x,
+        y: position.line as usize,
+    }
+}
+
+/// The start of every whole-word occurrence of `word` in the document
+fn word_occurrences(rope: &Rope, word: &str) -> Vec<Loc> {
+    let Ok(pattern) = Regex::new(&format!(r"\b{}\b", regex::escape(word))) else {
+        return vec![];
+    };
+    rope.lines()
+        .enumerate()
+        .flat_map(|(y, line)| {
+            let line = line.to_string();
+            pattern
+                .find_iter(&line)
+                .map(|found| Loc {
+                    x: line[..found.start()].chars().count(),
+                    y,
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// The identifier characters directly before column `x`, clamped to the end of the line
+fn word_before(line: &[char], x: usize) -> String {
+    let word: Vec<_> = line[..x.min(line.len())]
+        .iter()
+        .rev()
+        .take_while(|c| c.is_alphanumeric() || **c == '_')
+        .collect();
+    word.into_iter().rev().collect()
+}
+
+/// Tells the server why completion was requested. `retrigger` is for typing more of a word
+/// whose completions were incomplete.
+fn completion_context(trigger_character: Option<String>, retrigger: bool) -> CompletionContext {
+    let trigger_kind = if trigger_character.is_some() {
+        CompletionTriggerKind::TRIGGER_CHARACTER
+    } else if retrigger {
+        CompletionTriggerKind::TRIGGER_FOR_INCOMPLETE_COMPLETIONS
+    } else {
+        CompletionTriggerKind::INVOKED
+    };
+    CompletionContext {
+        trigger_kind,
+        trigger_character,
+    }
+}
+
+/// Filters and ranks the completions against the typed word,
+/// keeping the best match for each label up to the configured maximum
+fn filter_completions(
+    items: &[CompletionItem],
+    word_under_cursor: &str,
+    config: &CompletionConfig,
+) -> Vec<CompletionMatch> {
+    let fuzzy = config.fuzzy;
+    let matcher = SkimMatcherV2::default().smart_case();
+    let mut matches: Vec<_> = items
+        .iter()
+        .filter_map(|item| {
+            let filter_text = item.filter_text.as_ref().unwrap_or(&item.label);
+            let score = if word_under_cursor.is_empty() {
+                0
+            } else if fuzzy {
+                matcher.fuzzy_match(filter_text, word_under_cursor)?
+            } else if filter_text.starts_with(word_under_cursor) {
+                0
+            } else {
+                return None;
+            };
+            // Highlights are drawn on the label, which may differ from the filter text
+            let indices = if fuzzy {
+                matcher
+                    .fuzzy_indices(&item.label, word_under_cursor)
+                    .map(|(_, indices)| indices)
+                    .unwrap_or_default()
+            } else if item.label.starts_with(word_under_cursor) {
+                (0..word_under_cursor.chars().count()).collect()
+            } else {
+                vec![]
+            };
+            Some(CompletionMatch {
+                item: item.clone(),
+                score,
+                indices,
+            })
+        })
+        .collect();
+    matches.sort_by(|a, b| {
+        b.score
+            .cmp(&a.score)
+            .then_with(|| a.item.sort_text.cmp(&b.item.sort_text))
+    });
+    let mut labels = HashSet::new();
+    matches.retain(|m| labels.insert(m.item.label.clone()));
+    matches.truncate(config.max_items);
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::{env, fs, process};
+
+    use crossterm::event::{
+        KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+    };
+    use kaolinite::{event::Event, Document, Loc, Size};
+    use ratatui::layout::Rect;
+    use ratatui::style::{Color, Modifier};
+    use ratatui::widgets::Widget;
+    use tokio::sync::mpsc;
+    use tower_lsp::{lsp_types::*, LspService};
+
+    use super::{
+        char_before, completion_context, encode_change, filter_completions, loc_for_position,
+        lsp_position, picker_symbols, word_before, Editor, EditorResponse,
+    };
+    use crate::client::Client;
+    use crate::config::{CompletionConfig, Config};
+    use crate::core::text::{PositionEncoding, Text};
+    use crate::tui::keymap::Action;
+    use crate::tui::view_state::ViewState;
+    use crate::tui::{Buffer, Encoding, LspCapabilities, Theme};
+
+    /// An editor for `text` whose client isn't connected to a server
+    fn editor(text: &str) -> Editor {
+        editor_with_config(text, Config::default())
+    }
+
+    fn editor_with_config(text: &str, config: Config) -> Editor {
+        let mut doc = Document::open_empty(Size { w: 80, h: 24 });
+        for (y, line) in text.lines().enumerate() {
+            doc.exe(Event::InsertLine(y, line.to_owned())).unwrap();
+        }
+        let uri = "file:///test.js".parse().unwrap();
+        editor_for(Buffer::new(doc, uri, "javascript".to_owned()), config)
+    }
+
+    fn editor_for(buffer: Buffer, config: Config) -> Editor {
+        let (notification_tx, _) = mpsc::unbounded_channel();
+        let (client_service, _) =
+            LspService::new_client(|client| Client::new(client, notification_tx));
+        let capabilities = LspCapabilities::from(crate::server::capabilities());
+        Editor::new(
+            buffer,
+            client_service.inner().server_client(),
+            Arc::new(capabilities),
+            Arc::new(config),
+            Arc::new(Theme::dark()),
+        )
+    }
+
+    #[test]
+    fn fuzzy_completion_filter() {
+        let items = ["getElementById", "getAttribute", "addEventListener"]
+            .into_iter()
+            .map(|label| CompletionItem::new_simple(label.to_owned(), "".to_owned()))
+            .collect::<Vec<_>>();
+
+        let config = CompletionConfig::default();
+        let fuzzy = filter_completions(&items, "gEBI", &config);
+        let labels: Vec<_> = fuzzy.iter().map(|c| c.item.label.as_str()).collect();
+        assert_eq!(vec!["getElementById"], labels);
+        assert_eq!(vec![0, 3, 10, 12], fuzzy[0].indices);
+
+        let config = CompletionConfig {
+            fuzzy: false,
+            ..Default::default()
+        };
+        let prefix = filter_completions(&items, "gEBI", &config);
+        assert!(prefix.is_empty());
+    }
+
+    #[test]
+    fn completion_trigger_kinds() {
+        let context = completion_context(Some(".".to_owned()), false);
+        assert_eq!(
+            CompletionTriggerKind::TRIGGER_CHARACTER,
+            context.trigger_kind
+        );
+        assert_eq!(Some(".".to_owned()), context.trigger_character);
+
+        let context = completion_context(None, true);
+        assert_eq!(
+            CompletionTriggerKind::TRIGGER_FOR_INCOMPLETE_COMPLETIONS,
+            context.trigger_kind
+        );
+
+        let context = completion_context(None, false);
+        assert_eq!(CompletionTriggerKind::INVOKED, context.trigger_kind);
+        assert_eq!(None, context.trigger_character);
+    }
+
+    #[test]
+    fn completions_are_deduplicated_and_capped() {
+        let items = ["log", "log", "logs", "login", "logout"]
+            .into_iter()
+            .map(|label| CompletionItem::new_simple(label.to_owned(), "".to_owned()))
+            .collect::<Vec<_>>();
+        let config = CompletionConfig {
+            fuzzy: false,
+            max_items: 3,
+            ..Default::default()
+        };
+
+        let completions = filter_completions(&items, "lo", &config);
+        let labels: Vec<_> = completions.iter().map(|c| c.item.label.as_str()).collect();
+        assert_eq!(vec!["log", "logs", "login"], labels);
+    }
+
+    #[test]
+    fn cursor_past_end_of_line() {
+        let mut doc = Document::open_empty(Size { w: 80, h: 24 });
+        doc.exe(Event::InsertLine(0, "let ab".to_owned())).unwrap();
+        doc.exe(Event::InsertLine(1, "".to_owned())).unwrap();
+
+        // The cursor keeps its column when moving onto a shorter line
+        assert_eq!(Some('b'), char_before(&doc, Loc { x: 20, y: 0 }));
+        assert_eq!(None, char_before(&doc, Loc { x: 20, y: 1 }));
+        assert_eq!(None, char_before(&doc, Loc { x: 0, y: 0 }));
+        assert_eq!(None, char_before(&doc, Loc { x: 1, y: 5 }));
+
+        let line: Vec<_> = "let ab".chars().collect();
+        assert_eq!("ab", word_before(&line, 20));
+        assert_eq!("le", word_before(&line, 2));
+        assert_eq!("", word_before(&[], 3));
+    }
+
+    #[test]
+    fn encoded_changes_match_document_events() {
+        let mut doc = Document::open_empty(Size { w: 80, h: 24 });
+        doc.exe(Event::InsertLine(0, "let a = 1;".to_owned()))
+            .unwrap();
+        doc.exe(Event::InsertLine(1, "é😀b".to_owned())).unwrap();
+        let mut server_text = Text::new(doc.rope().to_string()).unwrap();
+
+        let events = [
+            Event::Insert(Loc { x: 3, y: 1 }, "c".to_owned()),
+            Event::Delete(Loc { x: 1, y: 1 }, "😀".to_owned()),
+            Event::SplitDown(Loc { x: 4, y: 0 }),
+            Event::SpliceUp(Loc { x: 4, y: 0 }),
+            Event::InsertLine(1, "// x".to_owned()),
+            Event::DeleteLine(1, "// x".to_owned()),
+        ];
+        let mut ranges = vec![];
+        for event in events {
+            let (range, text) = encode_change(&doc, &event, Encoding::Utf16);
+            ranges.push(range);
+            doc.exe(event).unwrap();
+            server_text
+                .apply_change(range, &text, PositionEncoding::Utf16)
+                .unwrap();
+            assert_eq!(doc.rope().to_string(), server_text.content.to_string());
+        }
+
+        let range = |start: (u32, u32), end: (u32, u32)| {
+            Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1))
+        };
+        let expected = vec![
+            range((1, 4), (1, 4)),
+            range((1, 1), (1, 3)),
+            range((0, 4), (0, 4)),
+            range((0, 4), (1, 0)),
+            range((1, 0), (1, 0)),
+            range((1, 0), (2, 0)),
+        ];
+        assert_eq!(expected, ranges);
+    }
+
+    #[test]
+    fn multi_line_insert_keeps_server_lines_in_sync() {
+        let mut editor = editor("let a = 1;\nlet b = 2;");
+        let mut server_text = Text::new(editor.doc().rope().to_string()).unwrap();
+
+        editor.doc_mut().goto(&Loc { x: 8, y: 0 });
+        let mut changes = editor.insert_lines("[\n  3,\n]");
+        changes.extend(editor.enter());
+        for (range, text) in changes {
+            server_text
+                .apply_change(range, &text, PositionEncoding::Utf16)
+                .unwrap();
+        }
+
+        let lines: Vec<_> = (0..5).map(|y| editor.doc().line(y).unwrap()).collect();
+        assert_eq!(vec!["let a = [", "  3,", "]", "1;", "let b = 2;"], lines);
+        assert_eq!(
+            editor.doc().rope().to_string(),
+            server_text.content.to_string()
+        );
+        assert_eq!(
+            editor.doc().rope().len_lines(),
+            server_text.content.len_lines()
+        );
+    }
+
+    #[test]
+    fn completion_replaces_the_selection() {
+        let mut editor = editor("let ab = 1;");
+        editor.doc_mut().goto(&Loc { x: 4, y: 0 });
+        editor.selection_anchor = Some(Loc { x: 10, y: 0 });
+        let item = CompletionItem::new_simple("value".to_owned(), "".to_owned());
+        let changes = editor.accept_completion(&item);
+        assert_eq!(Some("let value;".to_owned()), editor.doc().line(0));
+        assert_eq!(None, editor.selection());
+
+        let range = |start, end| Range::new(Position::new(0, start), Position::new(0, end));
+        assert_eq!(
+            vec![
+                (range(4, 10), "".to_owned()),
+                (range(4, 4), "value".to_owned())
+            ],
+            changes
+        );
+    }
+
+    #[test]
+    fn manual_completion_ignores_word_length() {
+        let ctrl_space = KeyEvent::new(KeyCode::Char(' '), KeyModifiers::CONTROL);
+        let a = KeyEvent::new(KeyCode::Char('a'), KeyModifiers::NONE);
+        let space = KeyEvent::new(KeyCode::Char(' '), KeyModifiers::NONE);
+
+        // A single character is too short to open the menu on its own
+        let mut editor = editor("");
+        editor.handle_key_event(&a);
+        assert!(!editor.show_completions);
+
+        // Once summoned, the menu opens on an empty word and stays open while typing it
+        let mut editor = editor("");
+        editor.handle_key_event(&ctrl_space);
+        assert!(editor.show_completions);
+        editor.handle_key_event(&a);
+        assert!(editor.show_completions);
+
+        // Ending the word closes it again
+        editor.handle_key_event(&space);
+        assert!(!editor.show_completions);
+        editor.handle_key_event(&a);
+        assert!(!editor.show_completions);
+    }
+
+    #[test]
+    fn completion_without_auto_trigger() {
+        let key = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+        let config = Config {
+            completion: CompletionConfig {
+                auto_trigger: false,
+                min_word_length: 3,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let mut editor = editor_with_config("", config);
+        for c in "abcd".chars() {
+            editor.handle_key_event(&key(c));
+            assert!(!editor.show_completions);
+        }
+
+        // Trigger characters still open the menu, which stays open while typing the word
+        editor.handle_key_event(&key('.'));
+        assert!(editor.show_completions);
+        editor.handle_key_event(&key('x'));
+        assert!(editor.show_completions);
+    }
+
+    #[test]
+    fn diagnostics_under_cursor() {
+        let diagnostic = |severity, start, end, message: &str| Diagnostic {
+            range: Range::new(Position::new(0, start), Position::new(0, end)),
+            severity: Some(severity),
+            source: Some("test".to_owned()),
+            message: message.to_owned(),
+            ..Default::default()
+        };
+        let mut editor = editor("let value = 1;");
+        editor.set_diagnostics(vec![
+            diagnostic(DiagnosticSeverity::ERROR, 4, 9, "unknown name"),
+            diagnostic(DiagnosticSeverity::WARNING, 0, 14, "unused\nremove it"),
+        ]);
+
+        // The error is drawn over the warning where they overlap
+        let colors: Vec<_> = editor
+            .diagnostic_overlays()
+            .iter()
+            .map(|overlay| overlay.style.fg)
+            .collect();
+        assert_eq!(vec![Some(Color::Yellow), Some(Color::Red)], colors);
+
+        editor.doc_mut().goto(&Loc { x: 2, y: 0 });
+        editor.show_diagnostics_at_cursor();
+        let lines: Vec<_> = editor
+            .hover
+            .as_ref()
+            .unwrap()
+            .lines
+            .iter()
+            .map(|line| {
+                line.0
+                    .iter()
+                    .map(|span| span.content.as_ref())
+                    .collect::<String>()
+            })
+            .collect();
+        assert_eq!(vec!["warning (test)", "unused", "remove it"], lines);
+
+        editor.doc_mut().goto(&Loc { x: 5, y: 0 });
+        editor.show_diagnostics_at_cursor();
+        assert_eq!(6, editor.hover.as_ref().unwrap().lines.len());
+    }
+
+    #[test]
+    fn goto_diagnostic_wraps_around() {
+        let diagnostic = |line, message: &str| Diagnostic {
+            range: Range::new(Position::new(line, 2), Position::new(line, 3)),
+            message: message.to_owned(),
+            ..Default::default()
+        };
+        let mut editor = editor("a = 1;\nb = 2;\nc = 3;");
+        editor.set_diagnostics(vec![diagnostic(2, "second"), diagnostic(1, "first")]);
+
+        let mut goto = |forward| {
+            let message = editor.goto_diagnostic(forward).unwrap().message.clone();
+            (message, editor.doc().char_loc())
+        };
+        assert_eq!(("first".to_owned(), Loc { x: 2, y: 1 }), goto(true));
+        assert_eq!(("second".to_owned(), Loc { x: 2, y: 2 }), goto(true));
+        assert_eq!(("first".to_owned(), Loc { x: 2, y: 1 }), goto(true));
+        assert_eq!(("second".to_owned(), Loc { x: 2, y: 2 }), goto(false));
+        assert_eq!(("first".to_owned(), Loc { x: 2, y: 1 }), goto(false));
+        assert_eq!(("second".to_owned(), Loc { x: 2, y: 2 }), goto(false));
+
+        editor.set_diagnostics(vec![]);
+        assert!(editor.goto_diagnostic(true).is_none());
+    }
+
+    #[test]
+    fn multibyte_lsp_positions() {
+        let mut doc = Document::open_empty(Size { w: 80, h: 24 });
+        doc.exe(Event::InsertLine(0, "a".to_owned())).unwrap();
+        doc.exe(Event::Insert(Loc { x: 1, y: 0 }, "é😀".to_owned()))
+            .unwrap();
+        let line: Vec<_> = doc.line(0).unwrap().chars().collect();
+
+        // Typing after the emoji inserts at the fourth char
+        let loc = Loc { x: 3, y: 0 };
+        for (encoding, character) in [
+            (Encoding::Utf8, 7),
+            (Encoding::Utf16, 4),
+            (Encoding::Utf32, 3),
+        ] {
+            let position = lsp_position(&line, loc, encoding);
+            assert_eq!(Position::new(0, character), position, "{encoding:?}");
+            assert_eq!(loc, loc_for_position(&line, &position, encoding));
+        }
+
+        // A column in the middle of the emoji's surrogate pair rounds up to the next char
+        let position = Position::new(0, 3);
+        assert_eq!(loc, loc_for_position(&line, &position, Encoding::Utf16));
+    }
+
+    /// Draws the editor in `area` and returns the text on each row
+    fn render(editor: &Editor, area: Rect) -> Vec<String> {
+        let mut buf = ratatui::buffer::Buffer::empty(area);
+        editor.render(area, &mut buf);
+        (0..area.height)
+            .map(|y| {
+                (0..area.width)
+                    .map(|x| buf.get(x, y).symbol.as_str())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn horizontal_scroll_follows_cursor() {
+        let mut editor = editor("0123456789abcdef\nxy");
+        let area = Rect::new(0, 0, 8, 2);
+
+        editor.goto_position(&Position::new(0, 12));
+        assert_eq!(render(&editor, area), ["56789abc", "        "]);
+        assert_eq!((7, 0), editor.cursor_position(area));
+
+        // Moving left within the view doesn't scroll
+        editor.goto_position(&Position::new(0, 6));
+        assert_eq!(render(&editor, area)[0], "56789abc");
+        assert_eq!((1, 0), editor.cursor_position(area));
+
+        editor.goto_position(&Position::new(1, 0));
+        assert_eq!(render(&editor, area), ["01234567", "xy      "]);
+        assert_eq!((0, 1), editor.cursor_position(area));
+    }
+
+    #[test]
+    fn goto_line_is_centered() {
+        let text: Vec<_> = (1..=100).map(|line| line.to_string()).collect();
+        let mut editor = editor(&text.join("\n"));
+        let area = Rect::new(0, 0, 4, 24);
+
+        editor.goto_position(&Position::new(0, 1));
+        editor.goto_line(50);
+        assert_eq!(Loc { x: 0, y: 49 }, editor.doc().char_loc());
+        let rows = render(&editor, area);
+        assert_eq!("38  ", rows[0]);
+        assert_eq!("50  ", rows[12]);
+
+        editor.goto_line(500);
+        assert_eq!(Loc { x: 0, y: 99 }, editor.doc().char_loc());
+        assert_eq!("88  ", render(&editor, area)[0]);
+
+        editor.goto_line(0);
+        assert_eq!(Loc { x: 0, y: 0 }, editor.doc().char_loc());
+        assert_eq!("1   ", render(&editor, area)[0]);
+    }
+
+    #[test]
+    fn soft_wrap_follows_cursor() {
+        let mut config = Config::default();
+        config.editor.soft_wrap = true;
+        let mut editor = editor_with_config("0123456789\nabcdefghij\nklmnopqrst", config);
+        let area = Rect::new(0, 0, 4, 3);
+
+        editor.goto_position(&Position::new(0, 9));
+        assert_eq!(render(&editor, area), ["0123", "4567", "89  "]);
+        assert_eq!((1, 2), editor.cursor_position(area));
+
+        // Each line takes three rows, so only the cursor's line fits
+        editor.goto_position(&Position::new(2, 5));
+        assert_eq!(render(&editor, area), ["klmn", "opqr", "st  "]);
+        assert_eq!((1, 1), editor.cursor_position(area));
+
+        editor.goto_position(&Position::new(1, 0));
+        assert_eq!(render(&editor, area), ["abcd", "efgh", "ij  "]);
+        assert_eq!((0, 0), editor.cursor_position(area));
+    }
+
+    #[test]
+    fn pick_nested_symbol() {
+        let mut editor = editor("class A {\n  render() {}\n}");
+        #[allow(deprecated)]
+        let symbol = |name: &str, kind, line, character, children| DocumentSymbol {
+            name: name.to_owned(),
+            detail: None,
+            kind,
+            tags: None,
+            deprecated: None,
+            range: Range::new(Position::new(line, 0), Position::new(line, 10)),
+            selection_range: Range::new(
+                Position::new(line, character),
+                Position::new(line, character + 1),
+            ),
+            children,
+        };
+        let render = symbol("render", SymbolKind::METHOD, 1, 2, None);
+        let class = symbol("A", SymbolKind::CLASS, 0, 6, Some(vec![render]));
+        let symbols = DocumentSymbolResponse::Nested(vec![class]);
+
+        let items = picker_symbols(&symbols, editor.uri());
+        let labels: Vec<_> = items
+            .iter()
+            .map(|(item, position)| (item.label.as_str(), item.kind, *position))
+            .collect();
+        assert_eq!(
+            labels,
+            [
+                ("A", Some(CompletionItemKind::CLASS), Position::new(0, 6)),
+                (
+                    "A › render",
+                    Some(CompletionItemKind::METHOD),
+                    Position::new(1, 2)
+                ),
+            ]
+        );
+
+        editor.handle_response(&EditorResponse::DocumentSymbols(symbols));
+        assert!(editor.is_picking());
+        for code in [KeyCode::Char('r'), KeyCode::Char('e'), KeyCode::Enter] {
+            editor.handle_key_event(&KeyEvent::new(code, KeyModifiers::NONE));
+        }
+        assert!(!editor.is_picking());
+        assert_eq!(Loc { x: 2, y: 1 }, editor.doc().char_loc());
+        // Typing into the picker doesn't edit the document
+        assert_eq!("class A {", editor.doc().line(0).unwrap());
+    }
+
+    #[test]
+    fn flat_symbols_are_labeled_with_their_container() {
+        let uri: Url = "file:///test.js".parse().unwrap();
+        #[allow(deprecated)]
+        let symbol = |name: &str, container_name: Option<&str>, uri: &Url| SymbolInformation {
+            name: name.to_owned(),
+            kind: SymbolKind::FUNCTION,
+            tags: None,
+            deprecated: None,
+            location: Location::new(
+                uri.clone(),
+                Range::new(Position::new(3, 4), Position::new(3, 8)),
+            ),
+            container_name: container_name.map(str::to_owned),
+        };
+        let other: Url = "file:///other.js".parse().unwrap();
+        let symbols = DocumentSymbolResponse::Flat(vec![
+            symbol("a", None, &uri),
+            symbol("b", Some("A"), &uri),
+            symbol("c", None, &other),
+        ]);
+
+        let labels: Vec<_> = picker_symbols(&symbols, &uri)
+            .into_iter()
+            .map(|(item, position)| (item.label, position))
+            .collect();
+        assert_eq!(
+            labels,
+            [
+                ("a".to_owned(), Position::new(3, 4)),
+                ("A › b".to_owned(), Position::new(3, 4)),
+            ]
+        );
+    }
+
+    #[test]
+    fn restored_view_state_is_clamped() {
+        let mut editor = editor("first\nsecond line\nthird");
+        let state = ViewState {
+            line: 1,
+            column: 7,
+            scroll_line: 1,
+            scroll_column: 3,
+        };
+        editor.restore_view_state(state);
+        assert_eq!(Loc { x: 7, y: 1 }, editor.doc().char_loc());
+        assert_eq!(state, editor.view_state());
+
+        // The file got shorter since the state was saved
+        editor.restore_view_state(ViewState {
+            line: 10,
+            column: 20,
+            scroll_line: 8,
+            scroll_column: 0,
+        });
+        assert_eq!(Loc { x: 5, y: 2 }, editor.doc().char_loc());
+        assert_eq!(2, editor.view_state().scroll_line);
+    }
+
+    #[test]
+    fn reload_keeps_cursor_in_range() {
+        let path = env::temp_dir().join(format!("lsp-tui-reload-{}.js", process::id()));
+        fs::write(&path, "one\ntwo\nthree").unwrap();
+        let doc = Document::open(Size { w: 80, h: 24 }, path.to_string_lossy()).unwrap();
+        let uri = Url::from_file_path(&path).unwrap();
+        let buffer = Buffer::new(doc, uri, "javascript".to_owned());
+        let mut editor = editor_for(buffer, Config::default());
+        editor.goto_position(&Position::new(2, 4));
+
+        fs::write(&path, "one\nTWO").unwrap();
+        let reloaded = editor.reload();
+        fs::remove_file(&path).unwrap();
+        assert!(reloaded.unwrap().is_some());
+        assert_eq!("TWO", editor.doc().line(1).unwrap());
+        assert_eq!(Loc { x: 3, y: 1 }, editor.doc().char_loc());
+        assert!(!editor.doc().modified);
+    }
+
+    #[test]
+    fn document_highlights_follow_the_cursor() {
+        let mut editor = editor("let count = 1;\ncount += 1;");
+        editor.doc_mut().goto(&Loc { x: 5, y: 0 });
+        let highlight = |line, start, end| DocumentHighlight {
+            range: Range::new(Position::new(line, start), Position::new(line, end)),
+            kind: Some(DocumentHighlightKind::WRITE),
+        };
+        let highlights = vec![highlight(0, 4, 9), highlight(1, 0, 5)];
+
+        // The cursor isn't where these were requested for anymore
+        editor.handle_response(&EditorResponse::DocumentHighlights(
+            Position::new(1, 0),
+            highlights.clone(),
+        ));
+        assert!(editor.document_highlight_overlays().is_empty());
+
+        editor.handle_response(&EditorResponse::DocumentHighlights(
+            Position::new(0, 5),
+            highlights,
+        ));
+        let overlays: Vec<_> = editor
+            .document_highlight_overlays()
+            .iter()
+            .map(|overlay| (overlay.line, overlay.start, overlay.end))
+            .collect();
+        assert_eq!(vec![(0, 4, 9), (1, 0, 5)], overlays);
+
+        // Moving within the identifier keeps them, moving off of it clears them
+        editor.handle_action(Action::MoveRight);
+        assert_eq!(2, editor.document_highlight_overlays().len());
+        for _ in 0..4 {
+            editor.handle_action(Action::MoveRight);
+        }
+        assert!(editor.document_highlight_overlays().is_empty());
+    }
+
+    #[test]
+    fn inlay_hints_are_drawn_before_their_position() {
+        let mut editor = editor("scale(2, 3);");
+        let hint = |character, label: &str| InlayHint {
+            position: Position::new(0, character),
+            label: InlayHintLabel::String(label.to_owned()),
+            kind: Some(InlayHintKind::PARAMETER),
+            text_edits: None,
+            tooltip: None,
+            padding_left: None,
+            padding_right: Some(true),
+            data: None,
+        };
+        let hints = vec![hint(9, "factor:"), hint(6, "x:")];
+
+        // Requested before the document last changed
+        let version = editor.buffer.version();
+        editor.handle_response(&EditorResponse::InlayHints(version - 1, hints.clone()));
+        assert!(editor.inlay_hints.is_empty());
+
+        editor.handle_response(&EditorResponse::InlayHints(version, hints));
+        let area = Rect::new(0, 0, 30, 1);
+        assert_eq!(
+            vec!["scale(x: 2, factor: 3);       "],
+            render(&editor, area)
+        );
+        assert_eq!(
+            "scale(2, 3);",
+            editor.line_chars(0).iter().collect::<String>()
+        );
+        // The cursor is drawn after the hints before it
+        editor.doc_mut().goto(&Loc { x: 9, y: 0 });
+        assert_eq!((20, 0), editor.cursor_position(area));
+
+        // Editing drops them until the server sends the ones for the new contents
+        editor.handle_key_event(&KeyEvent::new(KeyCode::Char('0'), KeyModifiers::NONE));
+        assert!(editor.inlay_hints.is_empty());
+    }
+
+    #[test]
+    fn semantic_tokens_are_drawn_over_the_syntax_highlighting() {
+        let mut editor = editor("let n = 1;\nfoo(n);");
+        let legend = editor.capabilities.semantic_tokens_legend.clone().unwrap();
+        let token = |delta_line, delta_start, length, token_type| SemanticToken {
+            delta_line,
+            delta_start,
+            length,
+            token_type: legend
+                .token_types
+                .iter()
+                .position(|t| *t == token_type)
+                .unwrap() as u32,
+            token_modifiers_bitset: 0,
+        };
+        let tokens = vec![
+            token(0, 0, 3, SemanticTokenType::KEYWORD),
+            token(0, 4, 1, SemanticTokenType::PARAMETER),
+            // The theme has no style for plain variables
+            token(1, 0, 3, SemanticTokenType::VARIABLE),
+            token(0, 4, 1, SemanticTokenType::PARAMETER),
+        ];
+
+        let version = editor.buffer.version();
+        editor.handle_response(&EditorResponse::SemanticTokens(version - 1, tokens.clone()));
+        assert!(editor.semantic_overlays.is_empty());
+
+        editor.handle_response(&EditorResponse::SemanticTokens(version, tokens));
+        let overlays: Vec<_> = editor
+            .semantic_overlays
+            .iter()
+            .map(|overlay| (overlay.line, overlay.start, overlay.end, overlay.style.fg))
+            .collect();
+        assert_eq!(
+            vec![
+                (0, 0, 3, Some(Color::Magenta)),
+                (0, 4, 5, Some(Color::LightRed)),
+                (1, 4, 5, Some(Color::LightRed)),
+            ],
+            overlays
+        );
+
+        let area = Rect::new(0, 0, 10, 2);
+        let mut buf = ratatui::buffer::Buffer::empty(area);
+        editor.render(area, &mut buf);
+        assert_eq!(Color::LightRed, buf.get(4, 0).fg);
+        assert_eq!(Color::LightRed, buf.get(4, 1).fg);
+    }
+
+    #[test]
+    fn search_moves_between_matches() {
+        let mut editor = editor("let value = 1;\nvalue += Value;\nreturn");
+        let press = |editor: &mut Editor, code| {
+            editor.handle_key_event(&KeyEvent::new(code, KeyModifiers::NONE));
+        };
+        editor.goto_position(&Position::new(0, 6));
+        editor.handle_action(Action::Search);
+        assert!(editor.is_picking());
+
+        // The first match from where the search started, found as each key is typed
+        press(&mut editor, KeyCode::Char('v'));
+        assert_eq!(Loc { x: 0, y: 1 }, editor.doc().char_loc());
+        for c in "alue".chars() {
+            press(&mut editor, KeyCode::Char(c));
+        }
+        assert_eq!(Loc { x: 0, y: 1 }, editor.doc().char_loc());
+        assert_eq!(3, editor.search_overlays().len());
+
+        press(&mut editor, KeyCode::Enter);
+        assert!(!editor.is_picking());
+        press(&mut editor, KeyCode::Char('n'));
+        assert_eq!(Loc { x: 9, y: 1 }, editor.doc().char_loc());
+        press(&mut editor, KeyCode::Enter);
+        assert_eq!(Loc { x: 4, y: 0 }, editor.doc().char_loc());
+        editor.handle_key_event(&KeyEvent::new(KeyCode::Char('N'), KeyModifiers::SHIFT));
+        assert_eq!(Loc { x: 9, y: 1 }, editor.doc().char_loc());
+
+        // Other keys close the search and are handled as usual
+        press(&mut editor, KeyCode::Char('x'));
+        assert!(editor.search_overlays().is_empty());
+        assert_eq!("value += xValue;", editor.doc().line(1).unwrap());
+
+        // Escape while typing goes back to where the search started
+        editor.handle_action(Action::Search);
+        press(&mut editor, KeyCode::Char('r'));
+        assert_eq!(Loc { x: 0, y: 2 }, editor.doc().char_loc());
+        press(&mut editor, KeyCode::Esc);
+        assert!(!editor.is_picking());
+        assert_eq!(Loc { x: 10, y: 1 }, editor.doc().char_loc());
+    }
+
+    #[test]
+    fn replace_matches() {
+        let mut editor = editor("a = a + b;\nreturn a;");
+        let press = |editor: &mut Editor, code| {
+            editor.handle_key_event(&KeyEvent::new(code, KeyModifiers::NONE));
+        };
+        editor.handle_action(Action::Replace);
+        press(&mut editor, KeyCode::Char('a'));
+        press(&mut editor, KeyCode::Enter);
+        for c in "aa".chars() {
+            press(&mut editor, KeyCode::Char(c));
+        }
+        press(&mut editor, KeyCode::Enter);
+        assert!(!editor.is_picking());
+
+        // The replacement matches too, so it's skipped over
+        press(&mut editor, KeyCode::Enter);
+        assert_eq!("aa = a + b;", editor.doc().line(0).unwrap());
+        assert_eq!(Loc { x: 5, y: 0 }, editor.doc().char_loc());
+        press(&mut editor, KeyCode::Char('n'));
+        assert_eq!(Loc { x: 7, y: 1 }, editor.doc().char_loc());
+
+        // The other matches are replaced from the bottom up so their positions stay valid
+        press(&mut editor, KeyCode::Char('a'));
+        assert_eq!("aaaa = aa + b;", editor.doc().line(0).unwrap());
+        assert_eq!("return aa;", editor.doc().line(1).unwrap());
+        assert!(editor.search_overlays().is_empty());
+    }
+
+    #[test]
+    fn edit_at_every_cursor() {
+        let mut editor = editor("ab = ab;\nabc\nlet ab");
+        let press = |editor: &mut Editor, code| {
+            editor.handle_key_event(&KeyEvent::new(code, KeyModifiers::NONE));
+        };
+        editor.goto_position(&Position::new(0, 1));
+        // Whole words only, wrapping around to the start of the document
+        for _ in 0..4 {
+            editor.handle_action(Action::AddCursor);
+        }
+        assert_eq!(
+            vec![Loc { x: 6, y: 0 }, Loc { x: 5, y: 2 }],
+            editor.extra_cursors
+        );
+
+        press(&mut editor, KeyCode::Char('x'));
+        assert_eq!("axb = axb;", editor.doc().line(0).unwrap());
+        assert_eq!("let axb", editor.doc().line(2).unwrap());
+        assert_eq!(Loc { x: 2, y: 0 }, editor.doc().char_loc());
+
+        // Edits made further up move the cursors after them
+        press(&mut editor, KeyCode::Enter);
+        let lines: Vec<_> = (0..6).map(|y| editor.doc().line(y).unwrap()).collect();
+        assert_eq!(lines, ["ax", "b = ax", "b;", "abc", "let ax", "b"]);
+        assert_eq!(Loc { x: 0, y: 1 }, editor.doc().char_loc());
+        assert_eq!(
+            vec![Loc { x: 0, y: 2 }, Loc { x: 0, y: 5 }],
+            editor.extra_cursors
+        );
+
+        // Changes are made from the bottom up so their ranges are valid in order
+        let changes = editor.edit_at_cursors(|editor| editor.backspace().into_iter().collect());
+        let starts: Vec<_> = changes.iter().map(|(range, _)| range.start).collect();
+        assert_eq!(
+            starts,
+            [
+                Position::new(4, 6),
+                Position::new(1, 6),
+                Position::new(0, 2)
+            ]
+        );
+        assert_eq!("axb = axb;", editor.doc().line(0).unwrap());
+        assert_eq!("let axb", editor.doc().line(2).unwrap());
+
+        press(&mut editor, KeyCode::Esc);
+        assert!(editor.extra_cursors.is_empty());
+    }
+
+    #[test]
+    fn alt_click_adds_a_cursor() {
+        let mut editor = editor("one\ntwo");
+        let area = Rect::new(0, 1, 10, 4);
+        let click = |column, row| MouseEvent {
+            kind: MouseEventKind::Down(MouseButton::Left),
+            column,
+            row,
+            modifiers: KeyModifiers::ALT,
+        };
+        editor.handle_mouse_event(&click(1, 2), area);
+        editor.handle_mouse_event(&click(8, 2), area);
+        // Clicking the main cursor doesn't add another one there
+        editor.handle_mouse_event(&click(0, 1), area);
+        assert_eq!(
+            vec![Loc { x: 1, y: 1 }, Loc { x: 3, y: 1 }],
+            editor.extra_cursors
+        );
+
+        let rows = render(&editor, area);
+        assert_eq!("two       ", rows[1]);
+        let mut buf = ratatui::buffer::Buffer::empty(area);
+        editor.render(area, &mut buf);
+        assert!(buf.get(1, 2).modifier.contains(Modifier::REVERSED));
+        assert!(buf.get(3, 2).modifier.contains(Modifier::REVERSED));
+        assert!(!buf.get(2, 2).modifier.contains(Modifier::REVERSED));
+    }
+}
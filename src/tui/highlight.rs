@@ -1,5 +1,6 @@
 use std::{
     borrow::Cow,
+    cmp,
     fs::{self},
     iter, mem,
     ops::{self},
@@ -8,140 +9,256 @@ use std::{
 
 use once_cell::sync::Lazy;
 use ratatui::{
-    style::{Color, Style},
+    style::Style,
     text::{Span, Spans, Text},
 };
 use regex::Regex;
 use ropey::{Rope, RopeSlice};
 use thiserror::Error;
-use toml::Table;
 use tree_sitter::{
     Language, Node, Parser, Point, Query, QueryCaptures, QueryCursor, QueryError, QueryMatch,
-    Range, TextProvider, Tree,
+    Range, Tree,
 };
 
-pub struct ChunksBytes<'a> {
-    chunks: ropey::iter::Chunks<'a>,
-}
-impl<'a> Iterator for ChunksBytes<'a> {
-    type Item = &'a [u8];
-    fn next(&mut self) -> Option<Self::Item> {
-        self.chunks.next().map(str::as_bytes)
+use super::theme::Theme;
+use crate::core::rope::{self, RopeProvider};
+
+/// Capture names the highlighter recognizes. More specific captures from the queries
+/// (e.g. `keyword.control.return`) resolve to the closest name in this list.
+const HIGHLIGHT_NAMES: &[&str] = &[
+    "attribute",
+    "comment",
+    "constant",
+    "constant.builtin",
+    "constant.numeric",
+    "constructor",
+    "function",
+    "function.builtin",
+    "function.method",
+    "keyword",
+    "keyword.control",
+    "keyword.control.conditional",
+    "keyword.function",
+    "keyword.operator",
+    "keyword.storage",
+    "label",
+    "namespace",
+    "operator",
+    "property",
+    "punctuation",
+    "punctuation.bracket",
+    "punctuation.delimiter",
+    "punctuation.special",
+    "string",
+    "string.regexp",
+    "string.special",
+    "tag",
+    "type",
+    "type.builtin",
+    "variable",
+    "variable.builtin",
+    "variable.other.member",
+    "variable.parameter",
+];
+
+/// The highlight name whose style a semantic token type from the server is drawn with. Types
+/// without a closer match are looked up by their own name.
+pub fn semantic_token_scope(token_type: &str) -> &str {
+    match token_type {
+        "class" | "enum" | "interface" | "struct" | "typeParameter" => "type",
+        "enumMember" => "constant",
+        "decorator" => "attribute",
+        "method" => "function.method",
+        "number" => "constant.numeric",
+        "regexp" => "string.regexp",
+        "parameter" => "variable.parameter",
+        "property" => "variable.other.member",
+        token_type => token_type,
     }
 }
 
-struct RopeProvider<'a>(RopeSlice<'a>);
-impl<'a> TextProvider<'a> for RopeProvider<'a> {
-    type I = ChunksBytes<'a>;
+static JAVASCRIPT_CONFIG: Lazy<HighlightConfiguration> =
+    Lazy::new(|| highlight_configuration(tree_sitter_javascript::language(), "javascript"));
+static TYPESCRIPT_CONFIG: Lazy<HighlightConfiguration> = Lazy::new(|| {
+    highlight_configuration(tree_sitter_typescript::language_typescript(), "typescript")
+});
+static TSX_CONFIG: Lazy<HighlightConfiguration> =
+    Lazy::new(|| highlight_configuration(tree_sitter_typescript::language_tsx(), "typescript"));
+
+fn highlight_configuration(language: Language, query_language: &str) -> HighlightConfiguration {
+    let mut config = HighlightConfiguration::new(
+        language,
+        &read_query(query_language, "highlights.scm"),
+        &read_query(query_language, "injections.scm"),
+        &read_query(query_language, "locals.scm"),
+    )
+    .unwrap();
+    config.configure(HIGHLIGHT_NAMES);
+    config
+}
 
-    fn text(&mut self, node: Node) -> Self::I {
-        let fragment = self.0.byte_slice(node.start_byte()..node.end_byte());
-        ChunksBytes {
-            chunks: fragment.chunks(),
-        }
+fn highlight_config(language_id: &str) -> Option<&'static HighlightConfiguration> {
+    match language_id {
+        "javascript" | "javascriptreact" => Some(&JAVASCRIPT_CONFIG),
+        "typescript" => Some(&TYPESCRIPT_CONFIG),
+        "typescriptreact" => Some(&TSX_CONFIG),
+        _ => None,
     }
 }
 
-pub fn highlight(rope: &Rope, start: usize, end: usize) -> Text {
-    let mut javascript_config = HighlightConfiguration::new(
-        tree_sitter_javascript::language(),
-        &read_query("javascript", "highlights.scm"), // tree_sitter_javascript::HIGHLIGHT_QUERY,
-        &read_query("javascript", "injections.scm"), //tree_sitter_javascript::INJECTION_QUERY,
-        &read_query("javascript", "locals.scm"),     //tree_sitter_javascript::LOCALS_QUERY,
-    )
-    .unwrap();
-
-    let highlight_names = &[
-        "attribute",
-        "constant",
-        "function.builtin",
-        "function",
-        "keyword",
-        "operator",
-        "property",
-        "punctuation",
-        "punctuation.bracket",
-        "punctuation.delimiter",
-        "string",
-        "string.special",
-        "tag",
-        "type",
-        "type.builtin",
-        "variable",
-        "variable.builtin",
-        "variable.parameter",
-    ];
-
-    javascript_config.configure(highlight_names);
+/// Highlights the document with the grammar for the given language.
+/// Languages without a grammar are rendered as plain text.
+pub fn highlight(rope: &Rope, language_id: &str, theme: &Theme) -> Text<'static> {
+    let plain_text = || {
+        rope.lines()
+            .map(|line| Spans::from(line.to_string().trim_end_matches(['\r', '\n']).to_owned()))
+            .collect::<Vec<_>>()
+            .into()
+    };
+    let Some(config) = highlight_config(language_id) else {
+        return plain_text();
+    };
     let mut highlighter = Highlighter::new();
-    let language = tree_sitter_javascript::language();
-    highlighter.parser().set_language(language).unwrap();
+    let Ok(highlights) = highlighter.highlight(config, rope.slice(..), None, None, |_| None) else {
+        return plain_text();
+    };
 
-    let highlights = highlighter
-        .highlight(&javascript_config, rope.slice(..), None, None, |_| None)
-        .unwrap();
-
-    let theme = fs::read_to_string("./themes/catppuccin_mocha.toml").unwrap();
-    let theme_table = theme.parse::<Table>().unwrap();
     let mut lines = vec![Spans::default()];
-    let mut current_line = 0;
-    let palette = &theme_table["palette"];
-    let mut current_color: Option<&str> = None;
-
-    for h in highlights {
-        let h = h.unwrap();
-
-        match h {
+    // Highlights can be nested, inner captures without a style inherit the outer one
+    let mut styles: Vec<Style> = vec![];
+    for event in highlights {
+        let Ok(event) = event else {
+            return plain_text();
+        };
+        match event {
             HighlightEvent::Source { start, end } => {
-                let slice = rope.slice(start..end).to_string();
-                if let Some(newline_index) = slice.find('\n') {
-                    if let Some(color) = current_color {
-                        lines[current_line].0.push(Span::styled(
-                            slice[..newline_index].to_string(),
-                            Style::default().fg(hex_to_rgb(color)),
-                        ));
-                    } else {
-                        lines[current_line]
-                            .0
-                            .push(Span::raw(slice[..newline_index].to_string()));
+                let style = styles.last().copied().unwrap_or_default();
+                let source = rope.byte_slice(start..end).to_string();
+                for (i, segment) in source.split('\n').enumerate() {
+                    if i > 0 {
+                        lines.push(Spans::default());
                     }
-                    lines.push(Spans::default());
-                    current_line += 1;
-                    if newline_index < slice.len() - 1 {
+                    let segment = segment.trim_end_matches('\r');
+                    if !segment.is_empty() {
+                        let current_line = lines.len() - 1;
                         lines[current_line]
                             .0
-                            .push(Span::raw(slice[newline_index + 1..].to_string()));
+                            .push(Span::styled(segment.to_owned(), style));
                     }
-                } else if let Some(color) = current_color {
-                    lines[current_line]
-                        .0
-                        .push(Span::styled(slice, Style::default().fg(hex_to_rgb(color))));
-                } else {
-                    lines[current_line].0.push(Span::raw(slice));
                 }
-
-                // dbg!(rope.slice(start..end).to_string());
             }
             HighlightEvent::HighlightStart(h) => {
-                if let Some(highlight_style) = theme_table.get(highlight_names[h.0]) {
-                    current_color = palette[highlight_style.as_str().unwrap()].as_str();
-                } else {
-                    current_color = None;
-                }
+                let parent = styles.last().copied().unwrap_or_default();
+                let style = theme.style(HIGHLIGHT_NAMES[h.0]).unwrap_or_default();
+                styles.push(parent.patch(style));
             }
             HighlightEvent::HighlightEnd => {
-                current_color = None;
+                styles.pop();
             }
         }
     }
     lines.into()
 }
 
-fn hex_to_rgb(color: &str) -> Color {
-    let r = u8::from_str_radix(&color[1..3], 16).unwrap();
-    let g = u8::from_str_radix(&color[3..5], 16).unwrap();
-    let b = u8::from_str_radix(&color[5..7], 16).unwrap();
-    Color::Rgb(r, g, b)
+/// A style applied on top of the syntax highlighting for a range of characters on a single line.
+#[derive(Clone, Debug)]
+pub struct Overlay {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+    pub style: Style,
+}
+
+pub fn apply_overlays(text: &mut Text, overlays: &[Overlay]) {
+    for overlay in overlays {
+        let Some(line) = text.lines.get_mut(overlay.line) else {
+            continue;
+        };
+        // Zero-width ranges still need to be visible
+        let overlay_end = overlay.end.max(overlay.start + 1);
+        let mut spans = vec![];
+        let mut col = 0;
+        for span in line.0.drain(..) {
+            let len = span.content.chars().count();
+            let (span_start, span_end) = (col, col + len);
+            col = span_end;
+            if span_end <= overlay.start || span_start >= overlay_end {
+                spans.push(span);
+                continue;
+            }
+
+            let chars: Vec<_> = span.content.chars().collect();
+            let split_start = overlay.start.max(span_start) - span_start;
+            let split_end = overlay_end.min(span_end) - span_start;
+            let before: String = chars[..split_start].iter().collect();
+            let middle: String = chars[split_start..split_end].iter().collect();
+            let after: String = chars[split_end..].iter().collect();
+            if !before.is_empty() {
+                spans.push(Span::styled(before, span.style));
+            }
+            spans.push(Span::styled(middle, span.style.patch(overlay.style)));
+            if !after.is_empty() {
+                spans.push(Span::styled(after, span.style));
+            }
+        }
+        if overlay.start >= col {
+            // The range starts past the end of the line, mark the end of the line instead
+            spans.push(Span::styled(" ", overlay.style));
+        }
+        line.0 = spans;
+    }
+}
+
+/// Text drawn on a line without being part of the document, like an inlay hint.
+#[derive(Clone, Debug)]
+pub struct VirtualText {
+    pub line: usize,
+    /// The char index it's drawn before. Past the end of the line, it's drawn at the end.
+    pub column: usize,
+    pub text: String,
+    pub style: Style,
+}
+
+/// Must be called after `apply_overlays`, since the overlay columns don't account for the
+/// inserted text.
+pub fn insert_virtual_text(text: &mut Text, virtual_text: &[VirtualText]) {
+    let mut sorted: Vec<_> = virtual_text.iter().collect();
+    // Inserting from the end of the line keeps the columns before it where they were.
+    // The sort is stable so text at the same column keeps its order.
+    sorted.sort_by_key(|v| cmp::Reverse((v.line, v.column)));
+    for virtual_text in sorted {
+        let Some(line) = text.lines.get_mut(virtual_text.line) else {
+            continue;
+        };
+        // The span the column falls in and the offset of the column within it
+        let mut position = None;
+        let mut col = 0;
+        for (i, span) in line.0.iter().enumerate() {
+            let len = span.content.chars().count();
+            if virtual_text.column < col + len {
+                position = Some((i, virtual_text.column - col));
+                break;
+            }
+            col += len;
+        }
+        let index = match position {
+            None => line.0.len(),
+            Some((i, 0)) => i,
+            Some((i, split)) => {
+                let chars: Vec<_> = line.0[i].content.chars().collect();
+                let before: String = chars[..split].iter().collect();
+                let after: String = chars[split..].iter().collect();
+                let style = line.0[i].style;
+                line.0[i] = Span::styled(before, style);
+                line.0.insert(i + 1, Span::styled(after, style));
+                i + 1
+            }
+        };
+        line.0.insert(
+            index,
+            Span::styled(virtual_text.text.clone(), virtual_text.style),
+        );
+    }
 }
 
 pub fn read_query(language: &str, filename: &str) -> String {
@@ -488,21 +605,8 @@ impl<'a> HighlightIterLayer<'a> {
                     .map_err(|_| Error::InvalidLanguage)?;
 
                 unsafe { highlighter.parser.set_cancellation_flag(cancellation_flag) };
-                let tree = highlighter
-                    .parser
-                    .parse_with(
-                        &mut |byte, _| {
-                            if byte <= source.len_bytes() {
-                                let (chunk, start_byte, _, _) = source.chunk_at_byte(byte);
-                                chunk[byte - start_byte..].as_bytes()
-                            } else {
-                                // out of range
-                                &[]
-                            }
-                        },
-                        tree,
-                    )
-                    .ok_or(Error::Cancelled)?;
+                let tree =
+                    rope::parse(&mut highlighter.parser, source, tree).ok_or(Error::Cancelled)?;
                 unsafe { highlighter.parser.set_cancellation_flag(None) };
                 let mut cursor = highlighter.cursors.pop().unwrap_or(QueryCursor::new());
 
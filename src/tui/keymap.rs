@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::{de, Deserialize, Deserializer};
+
+/// Something a key can be bound to
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Quit,
+    Save,
+    /// Close the current editor, asking to save it first if it's modified
+    Close,
+    NextEditor,
+    PreviousEditor,
+    Rename,
+    NextDiagnostic,
+    PreviousDiagnostic,
+    /// Show the diagnostics under the cursor
+    ShowDiagnostics,
+    /// Ask the server for completions at the cursor
+    Complete,
+    CommandPalette,
+    ToggleWrap,
+    /// Show or hide the messages from the server
+    ToggleLog,
+    Hover,
+    GotoDefinition,
+    /// Pick a symbol in the document to jump to
+    GotoSymbol,
+    /// Jump to a line number typed into a prompt
+    GotoLine,
+    /// Search the document, then go between the matches with enter/n and N
+    Search,
+    /// Search the document and replace the match at the cursor with enter, or every match with a
+    Replace,
+    Format,
+    CodeAction,
+    Copy,
+    Paste,
+    /// Move the cursor, or the selection in an open menu
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    /// Move the cursor while extending the selection
+    SelectUp,
+    SelectDown,
+    SelectLeft,
+    SelectRight,
+    /// Add a cursor at the next occurrence of the word under the cursor
+    AddCursor,
+    /// Close popups and drop the selection and extra cursors
+    Cancel,
+    /// Insert a line break, or accept the selected item in an open menu
+    Newline,
+    /// Insert a tab, accept the selected completion, or jump to the next snippet stop
+    Indent,
+    Dedent,
+    Backspace,
+    /// Delete the selection
+    Delete,
+}
+
+/// Maps keys to the actions they run. Printable keys without a binding insert their character.
+#[derive(Clone, Debug)]
+pub struct Keymap(HashMap<(KeyModifiers, KeyCode), Action>);
+
+impl Keymap {
+    pub fn get(&self, event: &KeyEvent) -> Option<Action> {
+        self.0.get(&normalize(event.modifiers, event.code)).copied()
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let none = KeyModifiers::NONE;
+        let ctrl = KeyModifiers::CONTROL;
+        let shift = KeyModifiers::SHIFT;
+        let alt = KeyModifiers::ALT;
+        Self(HashMap::from([
+            ((ctrl, KeyCode::Char('q')), Action::Quit),
+            ((ctrl, KeyCode::Char('s')), Action::Save),
+            ((ctrl, KeyCode::Char('w')), Action::Close),
+            ((ctrl, KeyCode::PageDown), Action::NextEditor),
+            ((ctrl, KeyCode::PageUp), Action::PreviousEditor),
+            ((none, KeyCode::F(2)), Action::Rename),
+            ((none, KeyCode::F(8)), Action::NextDiagnostic),
+            ((shift, KeyCode::F(8)), Action::PreviousDiagnostic),
+            ((ctrl, KeyCode::Char('e')), Action::ShowDiagnostics),
+            ((ctrl, KeyCode::Char(' ')), Action::Complete),
+            ((ctrl, KeyCode::Char('p')), Action::CommandPalette),
+            ((alt, KeyCode::Char('z')), Action::ToggleWrap),
+            ((ctrl, KeyCode::Char('l')), Action::ToggleLog),
+            ((ctrl, KeyCode::Char('k')), Action::Hover),
+            ((none, KeyCode::F(12)), Action::GotoDefinition),
+            ((ctrl | shift, KeyCode::Char('o')), Action::GotoSymbol),
+            ((ctrl, KeyCode::Char('g')), Action::GotoLine),
+            ((ctrl, KeyCode::Char('f')), Action::Search),
+            ((ctrl, KeyCode::Char('r')), Action::Replace),
+            ((ctrl | shift, KeyCode::Char('f')), Action::Format),
+            ((alt, KeyCode::Enter), Action::CodeAction),
+            ((ctrl, KeyCode::Char('c')), Action::Copy),
+            ((ctrl, KeyCode::Char('v')), Action::Paste),
+            ((none, KeyCode::Up), Action::MoveUp),
+            ((none, KeyCode::Down), Action::MoveDown),
+            ((none, KeyCode::Left), Action::MoveLeft),
+            ((none, KeyCode::Right), Action::MoveRight),
+            ((shift, KeyCode::Up), Action::SelectUp),
+            ((shift, KeyCode::Down), Action::SelectDown),
+            ((shift, KeyCode::Left), Action::SelectLeft),
+            ((shift, KeyCode::Right), Action::SelectRight),
+            ((ctrl, KeyCode::Char('d')), Action::AddCursor),
+            ((none, KeyCode::Esc), Action::Cancel),
+            ((none, KeyCode::Enter), Action::Newline),
+            ((none, KeyCode::Tab), Action::Indent),
+            ((none, KeyCode::BackTab), Action::Dedent),
+            ((none, KeyCode::Backspace), Action::Backspace),
+            ((none, KeyCode::Delete), Action::Delete),
+        ]))
+    }
+}
+
+/// Bindings from the config are added to the defaults, replacing any default for the same key.
+/// Keys are written like `ctrl-s`, `shift-f8`, `alt-enter`, or `ctrl-shift-f`.
+impl<'de> Deserialize<'de> for Keymap {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bindings = HashMap::<String, Action>::deserialize(deserializer)?;
+        let mut keymap = Self::default();
+        for (key, action) in bindings {
+            let key = parse_key(&key)
+                .ok_or_else(|| de::Error::custom(format!("invalid key binding `{key}`")))?;
+            keymap.0.insert(key, action);
+        }
+        Ok(keymap)
+    }
+}
+
+/// Terminals differ in whether Shift changes the reported character or shows up as a modifier on
+/// Shift+Tab, so those are stored one way only
+fn normalize(modifiers: KeyModifiers, code: KeyCode) -> (KeyModifiers, KeyCode) {
+    match code {
+        KeyCode::Char(c) if modifiers.contains(KeyModifiers::SHIFT) => {
+            (modifiers, KeyCode::Char(c.to_ascii_lowercase()))
+        }
+        KeyCode::BackTab => (modifiers - KeyModifiers::SHIFT, code),
+        _ => (modifiers, code),
+    }
+}
+
+fn parse_key(key: &str) -> Option<(KeyModifiers, KeyCode)> {
+    let key = key.to_lowercase();
+    let (modifiers, code) = match key.rsplit_once('-') {
+        // `ctrl--` binds the minus key
+        Some((modifiers, "")) => (modifiers.strip_suffix('-')?, "-"),
+        Some((modifiers, code)) => (modifiers, code),
+        None => ("", key.as_str()),
+    };
+    let modifiers = modifiers
+        .split('-')
+        .filter(|modifier| !modifier.is_empty())
+        .try_fold(KeyModifiers::NONE, |modifiers, modifier| {
+            let modifier = match modifier {
+                "ctrl" => KeyModifiers::CONTROL,
+                "shift" => KeyModifiers::SHIFT,
+                "alt" => KeyModifiers::ALT,
+                _ => return None,
+            };
+            Some(modifiers | modifier)
+        })?;
+    let code = match code {
+        "space" => KeyCode::Char(' '),
+        "enter" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "backtab" => KeyCode::BackTab,
+        "backspace" => KeyCode::Backspace,
+        "delete" => KeyCode::Delete,
+        "esc" => KeyCode::Esc,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        code => {
+            let mut chars = code.chars();
+            match (chars.next()?, chars.next()) {
+                (c, None) => KeyCode::Char(c),
+                ('f', Some(_)) => KeyCode::F(code[1..].parse().ok()?),
+                _ => return None,
+            }
+        }
+    };
+    Some(normalize(modifiers, code))
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    use super::{parse_key, Action, Keymap};
+    use crate::config::Config;
+
+    #[test]
+    fn parse_keys() {
+        let ctrl = KeyModifiers::CONTROL;
+        assert_eq!(Some((ctrl, KeyCode::Char('s'))), parse_key("ctrl-s"));
+        assert_eq!(Some((ctrl, KeyCode::Char(' '))), parse_key("Ctrl-Space"));
+        assert_eq!(Some((ctrl, KeyCode::Char('-'))), parse_key("ctrl--"));
+        assert_eq!(
+            Some((ctrl | KeyModifiers::SHIFT, KeyCode::Char('f'))),
+            parse_key("ctrl-shift-F")
+        );
+        assert_eq!(Some((KeyModifiers::NONE, KeyCode::F(12))), parse_key("f12"));
+        assert_eq!(
+            Some((KeyModifiers::ALT, KeyCode::Enter)),
+            parse_key("alt-enter")
+        );
+        assert_eq!(None, parse_key("hyper-a"));
+        assert_eq!(None, parse_key("ctrl-foo"));
+    }
+
+    #[test]
+    fn shifted_keys_match_their_binding() {
+        let keymap = Keymap::default();
+        let event = KeyEvent::new(
+            KeyCode::Char('F'),
+            KeyModifiers::CONTROL | KeyModifiers::SHIFT,
+        );
+        assert_eq!(Some(Action::Format), keymap.get(&event));
+        let event = KeyEvent::new(KeyCode::BackTab, KeyModifiers::SHIFT);
+        assert_eq!(Some(Action::Dedent), keymap.get(&event));
+        let event = KeyEvent::new(KeyCode::Char('A'), KeyModifiers::SHIFT);
+        assert_eq!(None, keymap.get(&event));
+    }
+
+    #[test]
+    fn config_bindings_extend_defaults() {
+        let config: Config = toml::from_str(
+            r#"
+            [keys]
+            "ctrl-x" = "save"
+            "ctrl-s" = "quit"
+            "#,
+        )
+        .unwrap();
+        let key = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL);
+        assert_eq!(Some(Action::Save), config.keys.get(&key('x')));
+        assert_eq!(Some(Action::Quit), config.keys.get(&key('s')));
+        assert_eq!(Some(Action::Quit), config.keys.get(&key('q')));
+
+        let error = toml::from_str::<Config>("[keys]\n\"ctrl-nope\" = \"save\"").unwrap_err();
+        assert!(error.to_string().contains("invalid key binding"), "{error}");
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::VecDeque;
+
+use ratatui::{
+    buffer::Buffer,
+    layout::Rect,
+    style::{Color, Style},
+    text::{Span, Spans},
+    widgets::{Block, Borders, Paragraph, Widget},
+};
+use tower_lsp::lsp_types::MessageType;
+
+/// How many messages are kept before the oldest ones are dropped
+const MAX_MESSAGES: usize = 200;
+
+/// The most recent `window/logMessage` and `window/showMessage` notifications from the server
+#[derive(Debug, Default)]
+pub struct ServerLog {
+    messages: VecDeque<(MessageType, String)>,
+}
+
+impl ServerLog {
+    pub fn push(&mut self, typ: MessageType, message: String) {
+        if self.messages.len() == MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+        self.messages.push_back((typ, message));
+    }
+
+    /// Each line of the logged messages, oldest first
+    fn lines(&self) -> impl DoubleEndedIterator<Item = (MessageType, &str)> {
+        self.messages
+            .iter()
+            .flat_map(|(typ, message)| message.lines().map(move |line| (*typ, line)))
+    }
+}
+
+fn message_style(typ: MessageType) -> Style {
+    let color = match typ {
+        MessageType::ERROR => Color::Red,
+        MessageType::WARNING => Color::Yellow,
+        MessageType::INFO => Color::Blue,
+        _ => Color::Gray,
+    };
+    Style::default().fg(color)
+}
+
+/// Shows as many of the newest lines as fit below a titled border
+impl Widget for &ServerLog {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let block = Block::default().borders(Borders::TOP).title(" Server log ");
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        let mut lines: Vec<_> = self
+            .lines()
+            .rev()
+            .take(inner.height as usize)
+            .map(|(typ, line)| Spans::from(Span::styled(line, message_style(typ))))
+            .collect();
+        lines.reverse();
+        Paragraph::new(lines).render(inner, buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::{buffer::Buffer, layout::Rect, style::Color, widgets::Widget};
+    use tower_lsp::lsp_types::MessageType;
+
+    use super::{ServerLog, MAX_MESSAGES};
+
+    #[test]
+    fn shows_newest_lines_by_severity() {
+        let mut log = ServerLog::default();
+        for i in 0..MAX_MESSAGES {
+            log.push(MessageType::LOG, format!("message {i}"));
+        }
+        log.push(MessageType::ERROR, "failed\nto start".to_owned());
+        assert_eq!(MAX_MESSAGES, log.messages.len());
+        assert_eq!("message 1", log.messages[0].1);
+
+        let area = Rect::new(0, 0, 12, 4);
+        let mut buf = Buffer::empty(area);
+        log.render(area, &mut buf);
+        let row = |y| {
+            (0..area.width)
+                .map(|x| buf.get(x, y).symbol.as_str())
+                .collect::<String>()
+        };
+        assert_eq!("message 199 ", row(1));
+        assert_eq!("failed      ", row(2));
+        assert_eq!("to start    ", row(3));
+        assert_eq!(Color::Gray, buf.get(0, 1).fg);
+        assert_eq!(Color::Red, buf.get(0, 2).fg);
+    }
+}
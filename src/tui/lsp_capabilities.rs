@@ -1,23 +1,129 @@
 use tower_lsp::lsp_types::*;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Encoding {
     Utf8,
     Utf16,
     Utf32,
 }
 
+impl Encoding {
+    /// The number of code units the character takes up in this encoding
+    pub fn len(self, ch: char) -> usize {
+        match self {
+            Encoding::Utf8 => ch.len_utf8(),
+            Encoding::Utf16 => ch.len_utf16(),
+            Encoding::Utf32 => 1,
+        }
+    }
+}
+
 pub struct LspCapabilities {
+    pub completion: bool,
+    pub hover: bool,
+    pub definition: bool,
+    pub rename: bool,
+    pub formatting: bool,
+    pub code_action: bool,
+    pub document_symbol: bool,
+    pub document_highlight: bool,
+    pub inlay_hint: bool,
     pub trigger_characters: Vec<String>,
+    pub signature_help_trigger_characters: Vec<String>,
+    pub signature_help_retrigger_characters: Vec<String>,
+    pub text_document_sync: TextDocumentSyncKind,
+    /// Whether the server wants `didSave` notifications
+    pub did_save: bool,
+    /// Whether `didSave` should include the saved text
+    pub save_include_text: bool,
+    /// Whether the server can edit the document right before it's saved
+    pub will_save_wait_until: bool,
+    /// What the types and modifiers of semantic tokens stand for, if the server can send the
+    /// tokens for the whole document
+    pub semantic_tokens_legend: Option<SemanticTokensLegend>,
     pub encoding: Encoding,
 }
 
 impl From<ServerCapabilities> for LspCapabilities {
     fn from(capabilities: ServerCapabilities) -> Self {
+        let (save, will_save_wait_until) = match &capabilities.text_document_sync {
+            Some(TextDocumentSyncCapability::Options(options)) => (
+                options.save.clone(),
+                options.will_save_wait_until.unwrap_or(false),
+            ),
+            _ => (None, false),
+        };
+        let semantic_tokens = match &capabilities.semantic_tokens_provider {
+            Some(SemanticTokensServerCapabilities::SemanticTokensOptions(options)) => {
+                Some(options.clone())
+            }
+            Some(SemanticTokensServerCapabilities::SemanticTokensRegistrationOptions(options)) => {
+                Some(options.semantic_tokens_options.clone())
+            }
+            None => None,
+        };
         Self {
+            completion: capabilities.completion_provider.is_some(),
+            hover: match capabilities.hover_provider {
+                Some(HoverProviderCapability::Simple(enabled)) => enabled,
+                Some(HoverProviderCapability::Options(_)) => true,
+                None => false,
+            },
+            definition: is_enabled(&capabilities.definition_provider),
+            rename: is_enabled(&capabilities.rename_provider),
+            formatting: is_enabled(&capabilities.document_formatting_provider),
+            code_action: match capabilities.code_action_provider {
+                Some(CodeActionProviderCapability::Simple(enabled)) => enabled,
+                Some(CodeActionProviderCapability::Options(_)) => true,
+                None => false,
+            },
+            document_symbol: is_enabled(&capabilities.document_symbol_provider),
+            document_highlight: is_enabled(&capabilities.document_highlight_provider),
+            inlay_hint: is_enabled(&capabilities.inlay_hint_provider),
             trigger_characters: capabilities
                 .completion_provider
                 .map(|p| p.trigger_characters.unwrap_or_default())
                 .unwrap_or_default(),
+            signature_help_trigger_characters: capabilities
+                .signature_help_provider
+                .as_ref()
+                .and_then(|p| p.trigger_characters.clone())
+                .unwrap_or_default(),
+            signature_help_retrigger_characters: capabilities
+                .signature_help_provider
+                .as_ref()
+                .and_then(|p| p.retrigger_characters.clone())
+                .unwrap_or_default(),
+            text_document_sync: match capabilities.text_document_sync {
+                Some(TextDocumentSyncCapability::Kind(kind)) => kind,
+                Some(TextDocumentSyncCapability::Options(options)) => {
+                    options.change.unwrap_or(TextDocumentSyncKind::NONE)
+                }
+                None => TextDocumentSyncKind::NONE,
+            },
+            did_save: match save {
+                Some(TextDocumentSyncSaveOptions::Supported(enabled)) => enabled,
+                Some(TextDocumentSyncSaveOptions::SaveOptions(_)) => true,
+                None => false,
+            },
+            save_include_text: matches!(
+                save,
+                Some(TextDocumentSyncSaveOptions::SaveOptions(SaveOptions {
+                    include_text: Some(true)
+                }))
+            ),
+            will_save_wait_until,
+            semantic_tokens_legend: semantic_tokens
+                .filter(|options| {
+                    matches!(
+                        options.full,
+                        Some(
+                            SemanticTokensFullOptions::Bool(true)
+                                | SemanticTokensFullOptions::Delta { .. }
+                        )
+                    )
+                })
+                .map(|options| options.legend),
             encoding: if capabilities.position_encoding == Some(PositionEncodingKind::UTF8) {
                 Encoding::Utf8
             } else if capabilities.position_encoding == Some(PositionEncodingKind::UTF32) {
@@ -28,3 +134,83 @@ impl From<ServerCapabilities> for LspCapabilities {
         }
     }
 }
+
+/// Providers are enabled by either `true` or their options
+fn is_enabled<T>(provider: &Option<OneOf<bool, T>>) -> bool {
+    matches!(provider, Some(OneOf::Left(true) | OneOf::Right(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::*;
+
+    use super::LspCapabilities;
+
+    #[test]
+    fn providers_from_server_capabilities() {
+        let capabilities = LspCapabilities::from(ServerCapabilities {
+            hover_provider: Some(HoverProviderCapability::Simple(true)),
+            rename_provider: Some(OneOf::Right(RenameOptions {
+                prepare_provider: None,
+                work_done_progress_options: Default::default(),
+            })),
+            document_formatting_provider: Some(OneOf::Left(false)),
+            ..Default::default()
+        });
+        assert!(capabilities.hover);
+        assert!(capabilities.rename);
+        assert!(!capabilities.formatting);
+        assert!(!capabilities.completion);
+        assert!(!capabilities.definition);
+        assert!(!capabilities.code_action);
+        assert!(!capabilities.document_symbol);
+        assert!(!capabilities.document_highlight);
+        assert!(!capabilities.inlay_hint);
+        assert!(!capabilities.did_save);
+    }
+
+    #[test]
+    fn save_options() {
+        let capabilities = LspCapabilities::from(ServerCapabilities {
+            text_document_sync: Some(TextDocumentSyncCapability::Options(
+                TextDocumentSyncOptions {
+                    save: Some(TextDocumentSyncSaveOptions::SaveOptions(SaveOptions {
+                        include_text: Some(true),
+                    })),
+                    will_save_wait_until: Some(true),
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        });
+        assert!(capabilities.did_save);
+        assert!(capabilities.save_include_text);
+        assert!(capabilities.will_save_wait_until);
+
+        let capabilities = LspCapabilities::from(crate::server::capabilities());
+        assert!(capabilities.did_save);
+        assert!(!capabilities.save_include_text);
+        assert!(!capabilities.will_save_wait_until);
+    }
+
+    #[test]
+    fn semantic_tokens_need_full_document_support() {
+        let capabilities = LspCapabilities::from(crate::server::capabilities());
+        assert_eq!(
+            Some(crate::core::syntax::semantic_tokens_legend()),
+            capabilities.semantic_tokens_legend
+        );
+
+        let capabilities = LspCapabilities::from(ServerCapabilities {
+            semantic_tokens_provider: Some(
+                SemanticTokensServerCapabilities::SemanticTokensOptions(SemanticTokensOptions {
+                    range: Some(true),
+                    full: None,
+                    ..Default::default()
+                }),
+            ),
+            ..Default::default()
+        });
+        assert_eq!(None, capabilities.semantic_tokens_legend);
+    }
+}
@@ -0,0 +1,156 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::{
+    buffer::Buffer,
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+    widgets::Widget,
+};
+use tower_lsp::lsp_types::{MessageActionItem, MessageType, ShowMessageRequestParams};
+
+use super::popup::{wrap_text, Popup};
+use crate::client::Responder;
+
+const MESSAGE_WIDTH: u16 = 60;
+
+/// A `window/showMessageRequest` from the server, shown as its message with the actions to
+/// choose from listed below it
+pub struct MessageRequest {
+    params: ShowMessageRequestParams,
+    selected: usize,
+    responder: Responder<Option<MessageActionItem>>,
+}
+
+impl MessageRequest {
+    pub fn new(
+        params: ShowMessageRequestParams,
+        responder: Responder<Option<MessageActionItem>>,
+    ) -> Self {
+        Self {
+            params,
+            selected: 0,
+            responder,
+        }
+    }
+
+    fn actions(&self) -> &[MessageActionItem] {
+        self.params.actions.as_deref().unwrap_or_default()
+    }
+
+    /// Returns false once the request has been answered
+    pub fn handle_key_event(&mut self, event: &KeyEvent) -> bool {
+        match event.code {
+            KeyCode::Esc => self.responder.respond(None),
+            KeyCode::Enter => self
+                .responder
+                .respond(self.actions().get(self.selected).cloned()),
+            KeyCode::Up | KeyCode::Left | KeyCode::BackTab => {
+                self.selected = self.selected.saturating_sub(1);
+                return true;
+            }
+            KeyCode::Down | KeyCode::Right | KeyCode::Tab => {
+                self.selected = (self.selected + 1).min(self.actions().len().saturating_sub(1));
+                return true;
+            }
+            _ => return true,
+        }
+        false
+    }
+}
+
+/// Centered in the area it's drawn in
+impl Widget for &MessageRequest {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let width = MESSAGE_WIDTH.min(area.width);
+        let style = match self.params.typ {
+            MessageType::ERROR => Style::default().fg(Color::LightRed),
+            MessageType::WARNING => Style::default().fg(Color::Yellow),
+            _ => Style::default(),
+        };
+        let mut lines: Vec<_> = wrap_text(&self.params.message, width as usize)
+            .into_iter()
+            .map(|line| Spans::from(Span::styled(line, style)))
+            .collect();
+        lines.push(Spans::default());
+        for (i, action) in self.actions().iter().enumerate() {
+            let style = if i == self.selected {
+                Style::default().add_modifier(Modifier::REVERSED)
+            } else {
+                Style::default()
+            };
+            lines.push(Spans::from(Span::styled(
+                format!(" {} ", action.title),
+                style,
+            )));
+        }
+        lines.push(Spans::from(Span::styled(
+            "(enter to choose, esc to dismiss)",
+            Style::default().fg(Color::Gray),
+        )));
+
+        let height = (lines.len() as u16).min(area.height);
+        let popup_area = Rect {
+            x: area.x + (area.width - width) / 2,
+            y: area.y + (area.height - height) / 2,
+            width,
+            height,
+        };
+        Popup::new(lines).render(popup_area, buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+    use tokio::sync::oneshot;
+    use tower_lsp::lsp_types::{MessageActionItem, MessageType, ShowMessageRequestParams};
+
+    use super::MessageRequest;
+    use crate::client::Responder;
+
+    fn message_request(
+        actions: &[&str],
+    ) -> (MessageRequest, oneshot::Receiver<Option<MessageActionItem>>) {
+        let params = ShowMessageRequestParams {
+            typ: MessageType::INFO,
+            message: "Reload the workspace?".to_owned(),
+            actions: Some(
+                actions
+                    .iter()
+                    .map(|title| MessageActionItem {
+                        title: (*title).to_owned(),
+                        properties: Default::default(),
+                    })
+                    .collect(),
+            ),
+        };
+        let (tx, rx) = oneshot::channel();
+        (MessageRequest::new(params, Responder::new(tx)), rx)
+    }
+
+    fn press(request: &mut MessageRequest, code: KeyCode) -> bool {
+        request.handle_key_event(&KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    #[test]
+    fn choose_action() {
+        let (mut request, mut rx) = message_request(&["Yes", "No"]);
+        assert!(press(&mut request, KeyCode::Down));
+        assert!(press(&mut request, KeyCode::Down));
+        assert!(press(&mut request, KeyCode::Char('x')));
+        assert!(!press(&mut request, KeyCode::Enter));
+        assert_eq!("No", rx.try_recv().unwrap().unwrap().title);
+    }
+
+    #[test]
+    fn dismiss_request() {
+        let (mut request, mut rx) = message_request(&["Yes", "No"]);
+        assert!(!press(&mut request, KeyCode::Esc));
+        assert_eq!(None, rx.try_recv().unwrap());
+
+        // Dropping an unanswered request dismisses it too
+        let (request, mut rx) = message_request(&[]);
+        drop(request);
+        assert!(rx.try_recv().is_err());
+    }
+}
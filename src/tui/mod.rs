@@ -1,4 +1,4 @@
-use crossterm::event::DisableMouseCapture;
+use crossterm::event::{DisableMouseCapture, EnableMouseCapture};
 use crossterm::execute;
 use crossterm::terminal::{
     disable_raw_mode, enable_raw_mode, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen,
@@ -9,22 +9,56 @@ use ratatui::Terminal;
 use std::io;
 
 use self::app::App;
+use crate::config::Config;
+use std::path::PathBuf;
+
+pub use self::buffer::Buffer;
+pub use self::completion_menu::CompletionMatch;
+pub use self::lsp_capabilities::{Encoding, LspCapabilities};
+pub use self::theme::Theme;
 
 mod app;
+mod brackets;
+mod buffer;
+mod command_palette;
 mod completion_menu;
+pub mod editor;
 pub mod highlight;
+pub mod keymap;
+mod log_panel;
 mod lsp_capabilities;
+mod message_request;
+mod picker;
+mod popup;
+mod progress;
+mod search;
+mod snippet;
+mod status_bar;
 mod text_area;
+mod theme;
+mod view_state;
 
-pub async fn run() {
+/// Opens each of the files in its own tab, or a new document if none are given
+///
+/// Fails without taking over the terminal if the app can't start, such as when the language
+/// server isn't installed
+pub async fn run(config: Config, files: Vec<PathBuf>) -> anyhow::Result<()> {
+    // Started first so there's nothing to restore if it fails
+    let app = App::initialize(config, files).await?;
     let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, Clear(ClearType::All)).unwrap();
+    execute!(
+        stdout,
+        EnterAlternateScreen,
+        EnableMouseCapture,
+        Clear(ClearType::All)
+    )
+    .unwrap();
     enable_raw_mode().unwrap();
 
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend).unwrap();
 
-    let program = Program::new(App::initialize().await);
+    let program = Program::new(app);
     program.run(&mut terminal).await;
 
     disable_raw_mode().unwrap();
@@ -34,4 +68,5 @@ pub async fn run() {
         DisableMouseCapture,
     )
     .unwrap();
+    Ok(())
 }
@@ -0,0 +1,174 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+use kaolinite::Loc;
+use ratatui::{buffer::Buffer, layout::Rect, widgets::Widget};
+use tower_lsp::lsp_types::CompletionItem;
+
+use super::completion_menu::{CompletionMatch, CompletionMenu, CompletionMenuState};
+use super::popup::Popup;
+
+const PICKER_WIDTH: u16 = 40;
+const PROMPT: &str = "> ";
+
+/// What the host should do after the picker handles a key
+#[derive(Debug, PartialEq, Eq)]
+pub enum PickerEvent<T> {
+    /// Keep the picker open
+    Continue,
+    Close,
+    /// Close the picker and use the chosen value
+    Pick(T),
+}
+
+/// A prompt at the top of the area it's drawn in, with the items that fuzzy match the typed
+/// query listed below it. Items are shown by their label and kind, and picking one hands back the
+/// value it was created with.
+pub struct Picker<T> {
+    query: String,
+    items: Vec<(CompletionItem, T)>,
+    state: CompletionMenuState,
+    /// The index in `items` of each entry in the menu
+    matches: Vec<usize>,
+}
+
+impl<T: Clone> Picker<T> {
+    pub fn new(items: Vec<(CompletionItem, T)>) -> Self {
+        let mut picker = Self {
+            query: String::new(),
+            items,
+            state: CompletionMenuState::default(),
+            matches: vec![],
+        };
+        picker.filter();
+        picker
+    }
+
+    pub fn handle_key_event(&mut self, event: &KeyEvent) -> PickerEvent<T> {
+        match (event.modifiers, event.code) {
+            (_, KeyCode::Esc) => return PickerEvent::Close,
+            (_, KeyCode::Enter) => {
+                return match self.selected() {
+                    Some(value) => PickerEvent::Pick(value),
+                    None => PickerEvent::Close,
+                };
+            }
+            (_, KeyCode::Up) => self.state.previous(),
+            (_, KeyCode::Down) => self.state.next(),
+            (_, KeyCode::Backspace) => {
+                self.query.pop();
+                self.filter();
+            }
+            (KeyModifiers::NONE | KeyModifiers::SHIFT, KeyCode::Char(c)) => {
+                self.query.push(c);
+                self.filter();
+            }
+            _ => {}
+        }
+        PickerEvent::Continue
+    }
+
+    /// Keeps the items matching the query, best matches first. Items that match equally well
+    /// stay in their original order.
+    fn filter(&mut self) {
+        let matcher = SkimMatcherV2::default().smart_case();
+        let mut matches: Vec<_> = self
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(index, (item, _))| {
+                if self.query.is_empty() {
+                    return Some((index, 0, vec![]));
+                }
+                let (score, indices) = matcher.fuzzy_indices(&item.label, &self.query)?;
+                Some((index, score, indices))
+            })
+            .collect();
+        matches.sort_by(|a, b| b.1.cmp(&a.1));
+
+        self.matches = matches.iter().map(|(index, _, _)| *index).collect();
+        self.state.set_completions(
+            matches
+                .into_iter()
+                .map(|(index, score, indices)| CompletionMatch {
+                    item: self.items[index].0.clone(),
+                    score,
+                    indices,
+                })
+                .collect(),
+        );
+    }
+
+    fn selected(&self) -> Option<T> {
+        let index = self.matches.get(self.state.selected_index()?)?;
+        Some(self.items[*index].1.clone())
+    }
+
+    fn prompt_area(&self, area: Rect) -> Rect {
+        let width = PICKER_WIDTH.min(area.width);
+        Rect {
+            x: area.x + (area.width - width) / 2,
+            y: area.y,
+            width,
+            height: 1.min(area.height),
+        }
+    }
+
+    /// Where the terminal cursor goes when the picker is drawn in `area`
+    pub fn cursor_position(&self, area: Rect) -> (u16, u16) {
+        let prompt = self.prompt_area(area);
+        let x = prompt.x + (PROMPT.len() + self.query.chars().count()) as u16;
+        (x.min(prompt.right().saturating_sub(1)), prompt.y)
+    }
+}
+
+impl<T: Clone> Widget for &Picker<T> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let prompt_area = self.prompt_area(area);
+        Popup::new(format!("{PROMPT}{}", self.query)).render(prompt_area, buf);
+
+        if !self.state.is_empty() {
+            let cursor = Loc {
+                x: (prompt_area.x - area.x) as usize,
+                y: 0,
+            };
+            CompletionMenu::new(&self.state, cursor).render(area, buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+    use tower_lsp::lsp_types::CompletionItem;
+
+    use super::{Picker, PickerEvent};
+
+    fn press<T: Clone>(picker: &mut Picker<T>, code: KeyCode) -> PickerEvent<T> {
+        picker.handle_key_event(&KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    #[test]
+    fn items_with_the_same_label_stay_distinct() {
+        let items = [("constructor", 1), ("render", 2), ("constructor", 3)]
+            .into_iter()
+            .map(|(label, value)| {
+                let item = CompletionItem {
+                    label: label.to_owned(),
+                    ..Default::default()
+                };
+                (item, value)
+            })
+            .collect();
+        let mut picker = Picker::new(items);
+        assert_eq!(PickerEvent::Pick(1), press(&mut picker, KeyCode::Enter));
+
+        press(&mut picker, KeyCode::Char('c'));
+        press(&mut picker, KeyCode::Down);
+        assert_eq!(PickerEvent::Pick(3), press(&mut picker, KeyCode::Enter));
+
+        press(&mut picker, KeyCode::Backspace);
+        press(&mut picker, KeyCode::Char('x'));
+        assert_eq!(PickerEvent::Close, press(&mut picker, KeyCode::Enter));
+    }
+}
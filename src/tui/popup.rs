@@ -0,0 +1,152 @@
+use std::mem;
+
+use ratatui::{
+    buffer::Buffer,
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    text::{Span, Spans, Text},
+    widgets::{Clear, Paragraph, Widget},
+};
+
+pub struct Popup<'a> {
+    text: Text<'a>,
+    style: Style,
+}
+
+impl<'a> Popup<'a> {
+    pub fn new(text: impl Into<Text<'a>>) -> Self {
+        Self {
+            text: text.into(),
+            style: Style::default().fg(Color::White).bg(Color::DarkGray),
+        }
+    }
+
+    pub fn style(mut self, style: Style) -> Self {
+        self.style = style;
+        self
+    }
+
+    pub fn width(&self) -> u16 {
+        self.text.width() as u16
+    }
+
+    pub fn height(&self) -> u16 {
+        self.text.height() as u16
+    }
+}
+
+impl<'a> Widget for Popup<'a> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        Clear.render(area, buf);
+        Paragraph::new(self.text)
+            .style(self.style)
+            .render(area, buf);
+    }
+}
+
+/// Positions a popup of the given size on the line below the cursor,
+/// shifting it left if it would run off the right edge of the area.
+/// The cursor position is relative to the area.
+pub fn below_cursor(x: u16, y: u16, width: u16, height: u16, area: Rect) -> Rect {
+    let width = width.min(area.width);
+    let x = (area.x + x).min(area.right().saturating_sub(width));
+    Rect {
+        x,
+        y: area.y + y + 1,
+        width,
+        height,
+    }
+    .intersection(area)
+}
+
+/// Positions a popup of the given size directly above the cursor,
+/// falling back to below the cursor if there isn't enough room.
+/// The cursor position is relative to the area.
+pub fn above_cursor(x: u16, y: u16, width: u16, height: u16, area: Rect) -> Rect {
+    if y < height {
+        return below_cursor(x, y, width, height, area);
+    }
+    let width = width.min(area.width);
+    let x = (area.x + x).min(area.right().saturating_sub(width));
+    Rect {
+        x,
+        y: area.y + y - height,
+        width,
+        height,
+    }
+    .intersection(area)
+}
+
+/// Positions a popup below the cursor if it fits, otherwise above it.
+/// When it fits on neither side, the side with more room is used and the height is clamped to it.
+/// The cursor position is relative to the area.
+pub fn near_cursor(x: u16, y: u16, width: u16, height: u16, area: Rect) -> Rect {
+    let room_below = area.height.saturating_sub(y + 1);
+    let room_above = y;
+    if height <= room_below || room_below >= room_above {
+        below_cursor(x, y, width, height.min(room_below), area)
+    } else {
+        above_cursor(x, y, width, height.min(room_above), area)
+    }
+}
+
+/// Converts markdown to styled text. Only code blocks and headings are styled,
+/// everything else is rendered as wrapped plain text.
+pub fn markdown_to_text(markdown: &str, width: usize) -> Text<'static> {
+    let mut lines = vec![];
+    let mut in_code_block = false;
+    for line in markdown.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code_block = !in_code_block;
+        } else if in_code_block {
+            lines.push(Spans::from(Span::styled(
+                line.to_owned(),
+                Style::default().fg(Color::Cyan),
+            )));
+        } else if line.starts_with('#') {
+            lines.push(Spans::from(Span::styled(
+                line.trim_start_matches('#').trim().to_owned(),
+                Style::default().add_modifier(Modifier::BOLD),
+            )));
+        } else {
+            lines.extend(wrap_text(line, width).into_iter().map(Spans::from));
+        }
+    }
+    // Drop trailing blank lines left behind by fences or separators
+    while lines.last().is_some_and(|l| l.width() == 0) {
+        lines.pop();
+    }
+    lines.into()
+}
+
+/// Word-wraps the text to the given width, preserving existing line breaks.
+/// Words longer than the width are split.
+pub fn wrap_text(text: &str, width: usize) -> Vec<String> {
+    let mut lines = vec![];
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        let mut line_len = 0;
+        for word in paragraph.split_whitespace() {
+            let mut word: Vec<_> = word.chars().collect();
+            if line_len > 0 && line_len + 1 + word.len() > width {
+                lines.push(mem::take(&mut line));
+                line_len = 0;
+            }
+            if line_len > 0 {
+                line.push(' ');
+                line_len += 1;
+            }
+            while line_len + word.len() > width {
+                let rest = word.split_off(width - line_len);
+                line.extend(word);
+                lines.push(mem::take(&mut line));
+                line_len = 0;
+                word = rest;
+            }
+            line_len += word.len();
+            line.extend(word);
+        }
+        lines.push(line);
+    }
+    lines
+}
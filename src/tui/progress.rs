@@ -0,0 +1,130 @@
+use tower_lsp::lsp_types::{NumberOrString, ProgressParams, ProgressParamsValue, WorkDoneProgress};
+
+const SPINNER: &[char] = &['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+#[derive(Debug)]
+struct Task {
+    token: NumberOrString,
+    title: String,
+    message: Option<String>,
+    percentage: Option<u32>,
+}
+
+/// The server's work-done progress tokens that have begun but not ended yet
+#[derive(Debug, Default)]
+pub struct ServerProgress {
+    /// Oldest first
+    tasks: Vec<Task>,
+    /// Advanced on every update so the spinner moves while the server reports progress
+    ticks: usize,
+}
+
+impl ServerProgress {
+    pub fn update(&mut self, params: &ProgressParams) {
+        let ProgressParamsValue::WorkDone(progress) = &params.value;
+        self.ticks = self.ticks.wrapping_add(1);
+        match progress {
+            WorkDoneProgress::Begin(begin) => {
+                self.tasks.retain(|task| task.token != params.token);
+                self.tasks.push(Task {
+                    token: params.token.clone(),
+                    title: begin.title.clone(),
+                    message: begin.message.clone(),
+                    percentage: begin.percentage,
+                });
+            }
+            WorkDoneProgress::Report(report) => {
+                if let Some(task) = self.tasks.iter_mut().find(|t| t.token == params.token) {
+                    // Reports only include what changed
+                    if report.message.is_some() {
+                        task.message = report.message.clone();
+                    }
+                    if report.percentage.is_some() {
+                        task.percentage = report.percentage;
+                    }
+                }
+            }
+            WorkDoneProgress::End(_) => {
+                self.tasks.retain(|task| task.token != params.token);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.tasks.clear();
+    }
+
+    /// Describes the most recently started task, like `⠙ Indexing: src/main.rs (40%)`
+    pub fn status(&self) -> Option<String> {
+        let task = self.tasks.last()?;
+        let mut status = format!("{} {}", SPINNER[self.ticks % SPINNER.len()], task.title);
+        if let Some(message) = &task.message {
+            status += &format!(": {message}");
+        }
+        if let Some(percentage) = task.percentage {
+            status += &format!(" ({percentage}%)");
+        }
+        Some(status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::{
+        NumberOrString, ProgressParams, ProgressParamsValue, WorkDoneProgress,
+        WorkDoneProgressBegin, WorkDoneProgressEnd, WorkDoneProgressReport,
+    };
+
+    use super::ServerProgress;
+
+    fn params(token: &str, progress: WorkDoneProgress) -> ProgressParams {
+        ProgressParams {
+            token: NumberOrString::String(token.to_owned()),
+            value: ProgressParamsValue::WorkDone(progress),
+        }
+    }
+
+    #[test]
+    fn track_tasks_until_they_end() {
+        let mut progress = ServerProgress::default();
+        assert_eq!(None, progress.status());
+
+        progress.update(&params(
+            "index",
+            WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                title: "Indexing".to_owned(),
+                ..Default::default()
+            }),
+        ));
+        progress.update(&params(
+            "index",
+            WorkDoneProgress::Report(WorkDoneProgressReport {
+                message: Some("src/main.rs".to_owned()),
+                percentage: Some(40),
+                ..Default::default()
+            }),
+        ));
+        let status = progress.status().unwrap();
+        assert!(status.ends_with(" Indexing: src/main.rs (40%)"), "{status}");
+
+        progress.update(&params(
+            "load",
+            WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                title: "Loading".to_owned(),
+                ..Default::default()
+            }),
+        ));
+        assert!(progress.status().unwrap().ends_with(" Loading"));
+
+        progress.update(&params(
+            "load",
+            WorkDoneProgress::End(WorkDoneProgressEnd::default()),
+        ));
+        assert!(progress.status().unwrap().ends_with(" (40%)"));
+        progress.update(&params(
+            "index",
+            WorkDoneProgress::End(WorkDoneProgressEnd::default()),
+        ));
+        assert_eq!(None, progress.status());
+    }
+}
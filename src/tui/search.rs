@@ -0,0 +1,354 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use kaolinite::Loc;
+use ratatui::{
+    buffer::Buffer,
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+    widgets::Widget,
+};
+use regex::{Regex, RegexBuilder};
+use ropey::Rope;
+
+use super::popup::Popup;
+
+const SEARCH_WIDTH: u16 = 40;
+const PROMPT: &str = "/";
+const REPLACE_PROMPT: &str = ">";
+/// Shown before the prompt, highlighted while the option is on
+const CASE_SENSITIVE_FLAG: &str = "Aa";
+const REGEX_FLAG: &str = ".*";
+
+/// What the editor should do after the search prompt handles a key
+#[derive(Debug, PartialEq, Eq)]
+pub enum SearchEvent {
+    Continue,
+    /// The query or its options changed, so the matches need to be found again
+    Changed,
+    /// Stop typing and keep the search open to go between its matches
+    Confirm,
+    /// Close the search and put the cursor back where it started
+    Cancel,
+}
+
+/// A query typed into a prompt at the top right of the area it's drawn in, along with what to
+/// replace its matches with when replacing. Matches don't span lines and their columns are char
+/// indices.
+pub struct Search {
+    query: String,
+    /// Inserted as is, even for regex patterns. `None` unless replacing.
+    replacement: Option<String>,
+    /// Keys type into the replacement rather than the query
+    replacing: bool,
+    case_sensitive: bool,
+    regex: bool,
+    /// `None` while the query is empty or isn't a valid pattern
+    pattern: Option<Regex>,
+    invalid: bool,
+    /// Keys type into the prompt until the search is confirmed
+    typing: bool,
+    /// Where the cursor was when the search started
+    origin: Loc,
+}
+
+impl Search {
+    pub fn new(origin: Loc) -> Self {
+        Self {
+            query: String::new(),
+            replacement: None,
+            replacing: false,
+            case_sensitive: false,
+            regex: false,
+            pattern: None,
+            invalid: false,
+            typing: true,
+            origin,
+        }
+    }
+
+    /// Prompts for a replacement once the query is entered
+    pub fn replace(origin: Loc) -> Self {
+        Self {
+            replacement: Some(String::new()),
+            ..Self::new(origin)
+        }
+    }
+
+    pub fn replacement(&self) -> Option<&str> {
+        self.replacement.as_deref()
+    }
+
+    pub fn origin(&self) -> Loc {
+        self.origin
+    }
+
+    pub fn is_typing(&self) -> bool {
+        self.typing
+    }
+
+    /// Alt-c toggles case sensitivity and alt-r toggles regex patterns. When replacing, enter
+    /// moves from the query to the replacement and tab switches between them.
+    pub fn handle_key_event(&mut self, event: &KeyEvent) -> SearchEvent {
+        match (event.modifiers, event.code) {
+            (_, KeyCode::Esc) => SearchEvent::Cancel,
+            (_, KeyCode::Tab) if self.replacement.is_some() => {
+                self.replacing = !self.replacing;
+                SearchEvent::Continue
+            }
+            (_, KeyCode::Enter) if self.replacement.is_some() && !self.replacing => {
+                self.replacing = true;
+                SearchEvent::Continue
+            }
+            (_, KeyCode::Enter) => {
+                self.typing = false;
+                SearchEvent::Confirm
+            }
+            (KeyModifiers::ALT, KeyCode::Char('c')) => {
+                self.case_sensitive = !self.case_sensitive;
+                self.compile()
+            }
+            (KeyModifiers::ALT, KeyCode::Char('r')) => {
+                self.regex = !self.regex;
+                self.compile()
+            }
+            (_, KeyCode::Backspace) => self.edit(|input| {
+                input.pop();
+            }),
+            (KeyModifiers::NONE | KeyModifiers::SHIFT, KeyCode::Char(c)) => {
+                self.edit(|input| input.push(c))
+            }
+            _ => SearchEvent::Continue,
+        }
+    }
+
+    /// Edits whichever of the query and the replacement is being typed
+    fn edit(&mut self, edit: impl FnOnce(&mut String)) -> SearchEvent {
+        if self.replacing {
+            if let Some(replacement) = &mut self.replacement {
+                edit(replacement);
+                return SearchEvent::Continue;
+            }
+        }
+        edit(&mut self.query);
+        self.compile()
+    }
+
+    fn compile(&mut self) -> SearchEvent {
+        self.pattern = None;
+        self.invalid = false;
+        if self.query.is_empty() {
+            return SearchEvent::Changed;
+        }
+        let pattern = if self.regex {
+            self.query.clone()
+        } else {
+            regex::escape(&self.query)
+        };
+        match RegexBuilder::new(&pattern)
+            .case_insensitive(!self.case_sensitive)
+            .build()
+        {
+            Ok(pattern) => self.pattern = Some(pattern),
+            Err(_) => self.invalid = true,
+        }
+        SearchEvent::Changed
+    }
+
+    /// The start and end of every match in the document, in order. Patterns that match an empty
+    /// string, like `a*`, only find their non-empty matches.
+    pub fn matches(&self, rope: &Rope) -> Vec<(Loc, Loc)> {
+        let Some(pattern) = &self.pattern else {
+            return vec![];
+        };
+        let mut matches = vec![];
+        for (y, line) in rope.lines().enumerate() {
+            let line = line.to_string();
+            let line = line.trim_end_matches(['\n', '\r']);
+            for found in pattern.find_iter(line).filter(|found| !found.is_empty()) {
+                let x = line[..found.start()].chars().count();
+                matches.push((
+                    Loc { x, y },
+                    Loc {
+                        x: x + found.as_str().chars().count(),
+                        y,
+                    },
+                ));
+            }
+        }
+        matches
+    }
+
+    fn flags(&self) -> [(&'static str, bool); 2] {
+        [
+            (CASE_SENSITIVE_FLAG, self.case_sensitive),
+            (REGEX_FLAG, self.regex),
+        ]
+    }
+
+    /// The width of the flags before the prompts
+    fn flags_width(&self) -> usize {
+        self.flags().iter().map(|(flag, _)| flag.len() + 1).sum()
+    }
+
+    fn prompt_area(&self, area: Rect) -> Rect {
+        let width = SEARCH_WIDTH.min(area.width);
+        let height = if self.replacement.is_some() { 2 } else { 1 };
+        Rect {
+            x: area.right() - width,
+            y: area.y,
+            width,
+            height: height.min(area.height),
+        }
+    }
+
+    /// Where the terminal cursor goes while typing when the search is drawn in `area`
+    pub fn cursor_position(&self, area: Rect) -> (u16, u16) {
+        let prompt = self.prompt_area(area);
+        let (input, y) = match &self.replacement {
+            Some(replacement) if self.replacing => (replacement, prompt.y + 1),
+            _ => (&self.query, prompt.y),
+        };
+        let x = prompt.x + (self.flags_width() + PROMPT.len() + input.chars().count()) as u16;
+        (
+            x.min(prompt.right().saturating_sub(1)),
+            y.min(prompt.bottom().saturating_sub(1)),
+        )
+    }
+}
+
+impl Widget for &Search {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let mut spans = vec![];
+        for (flag, on) in self.flags() {
+            let style = if on {
+                Style::default().add_modifier(Modifier::REVERSED)
+            } else {
+                Style::default().fg(Color::Gray)
+            };
+            spans.push(Span::styled(flag, style));
+            spans.push(Span::raw(" "));
+        }
+        spans.push(Span::raw(PROMPT));
+        let query_style = if self.invalid {
+            Style::default().fg(Color::LightRed)
+        } else {
+            Style::default()
+        };
+        spans.push(Span::styled(self.query.clone(), query_style));
+        let mut lines = vec![Spans::from(spans)];
+        if let Some(replacement) = &self.replacement {
+            lines.push(Spans::from(format!(
+                "{}{REPLACE_PROMPT}{replacement}",
+                " ".repeat(self.flags_width())
+            )));
+        }
+        Popup::new(lines).render(self.prompt_area(area), buf);
+    }
+}
+
+/// The first match starting after `from`, or at it if `inclusive`, wrapping around to the first
+/// match in the document
+pub fn next_match(matches: &[(Loc, Loc)], from: Loc, inclusive: bool) -> Option<Loc> {
+    let from = (from.y, from.x);
+    matches
+        .iter()
+        .map(|(start, _)| *start)
+        .find(|start| {
+            let start = (start.y, start.x);
+            start > from || (inclusive && start == from)
+        })
+        .or_else(|| matches.first().map(|(start, _)| *start))
+}
+
+/// The last match starting before `from`, wrapping around to the last match in the document
+pub fn previous_match(matches: &[(Loc, Loc)], from: Loc) -> Option<Loc> {
+    let from = (from.y, from.x);
+    matches
+        .iter()
+        .map(|(start, _)| *start)
+        .rfind(|start| (start.y, start.x) < from)
+        .or_else(|| matches.last().map(|(start, _)| *start))
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+    use kaolinite::Loc;
+    use ropey::Rope;
+
+    use super::{next_match, previous_match, Search, SearchEvent};
+
+    fn type_query(search: &mut Search, query: &str) {
+        for c in query.chars() {
+            let event = KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+            assert_eq!(SearchEvent::Changed, search.handle_key_event(&event));
+        }
+    }
+
+    fn starts(search: &Search, rope: &Rope) -> Vec<(usize, usize)> {
+        search
+            .matches(rope)
+            .iter()
+            .map(|(start, _)| (start.y, start.x))
+            .collect()
+    }
+
+    #[test]
+    fn match_case_and_regex_options() {
+        let rope = Rope::from_str("Foo föo\nfoo.bar\n");
+        let mut search = Search::new(Loc { x: 0, y: 0 });
+        type_query(&mut search, "fo");
+        assert_eq!(vec![(0, 0), (1, 0)], starts(&search, &rope));
+
+        search.handle_key_event(&KeyEvent::new(KeyCode::Char('c'), KeyModifiers::ALT));
+        assert_eq!(vec![(1, 0)], starts(&search, &rope));
+
+        search.handle_key_event(&KeyEvent::new(KeyCode::Backspace, KeyModifiers::NONE));
+        type_query(&mut search, ".o");
+        // Without regex the dot only matches itself
+        assert!(starts(&search, &rope).is_empty());
+        search.handle_key_event(&KeyEvent::new(KeyCode::Char('r'), KeyModifiers::ALT));
+        assert_eq!(vec![(0, 4), (1, 0)], starts(&search, &rope));
+        let (start, end) = search.matches(&rope)[0];
+        assert_eq!((4, 7), (start.x, end.x));
+
+        // An unfinished pattern matches nothing until it's valid
+        type_query(&mut search, "(");
+        assert!(search.invalid);
+        assert!(starts(&search, &rope).is_empty());
+    }
+
+    #[test]
+    fn matches_wrap_around() {
+        let loc = |y, x| Loc { x, y };
+        let matches = [(loc(0, 4), loc(0, 7)), (loc(2, 0), loc(2, 3))];
+        assert_eq!(Some(loc(0, 4)), next_match(&matches, loc(0, 4), true));
+        assert_eq!(Some(loc(2, 0)), next_match(&matches, loc(0, 4), false));
+        assert_eq!(Some(loc(0, 4)), next_match(&matches, loc(2, 0), false));
+        assert_eq!(Some(loc(2, 0)), previous_match(&matches, loc(0, 4)));
+        assert_eq!(Some(loc(0, 4)), previous_match(&matches, loc(1, 0)));
+        assert_eq!(None, next_match(&[], loc(0, 0), true));
+    }
+
+    #[test]
+    fn enter_moves_to_the_replacement() {
+        let mut search = Search::replace(Loc { x: 0, y: 0 });
+        type_query(&mut search, "ab");
+        let enter = KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE);
+        assert_eq!(SearchEvent::Continue, search.handle_key_event(&enter));
+        assert!(search.is_typing());
+
+        // Typing the replacement doesn't change the matches
+        let event = KeyEvent::new(KeyCode::Char('x'), KeyModifiers::NONE);
+        assert_eq!(SearchEvent::Continue, search.handle_key_event(&event));
+        let tab = KeyEvent::new(KeyCode::Tab, KeyModifiers::NONE);
+        search.handle_key_event(&tab);
+        type_query(&mut search, "c");
+        assert_eq!("abc", search.query);
+        assert_eq!(Some("x"), search.replacement());
+
+        search.handle_key_event(&tab);
+        assert_eq!(SearchEvent::Confirm, search.handle_key_event(&enter));
+        assert!(!search.is_typing());
+    }
+}
@@ -0,0 +1,204 @@
+use std::ops::Range;
+
+/// A completion snippet with its placeholders expanded
+#[derive(Debug, PartialEq, Eq)]
+pub struct Snippet {
+    pub(crate) text: String,
+    /// Char ranges into `text` in the order they should be visited. The final stop is always `$0`.
+    pub(crate) tabstops: Vec<Range<usize>>,
+}
+
+/// Expands the LSP snippet syntax (`$1`, `${1:placeholder}`, `${1|a,b|}`, `$0`, variables)
+/// into plain text and the positions of its tab stops
+pub fn parse(snippet: &str) -> Snippet {
+    let chars: Vec<_> = snippet.chars().collect();
+    let mut parser = Parser {
+        chars: &chars,
+        pos: 0,
+        text: vec![],
+        tabstops: vec![],
+    };
+    parser.parse_until(None);
+
+    let mut tabstops = parser.tabstops;
+    // Only the first occurrence of a repeated index becomes a stop
+    tabstops.sort_by_key(|(index, _)| *index);
+    tabstops.dedup_by_key(|(index, _)| *index);
+    let final_stop = match tabstops.first() {
+        Some((0, _)) => tabstops.remove(0).1,
+        _ => parser.text.len()..parser.text.len(),
+    };
+    let mut tabstops: Vec<_> = tabstops.into_iter().map(|(_, range)| range).collect();
+    tabstops.push(final_stop);
+
+    Snippet {
+        text: parser.text.into_iter().collect(),
+        tabstops,
+    }
+}
+
+struct Parser<'a> {
+    chars: &'a [char],
+    pos: usize,
+    text: Vec<char>,
+    tabstops: Vec<(u32, Range<usize>)>,
+}
+
+impl<'a> Parser<'a> {
+    fn parse_until(&mut self, end: Option<char>) {
+        while let Some(&ch) = self.chars.get(self.pos) {
+            self.pos += 1;
+            match ch {
+                '\\' => match self.chars.get(self.pos) {
+                    Some(&escaped @ ('$' | '}' | '\\')) => {
+                        self.pos += 1;
+                        self.text.push(escaped);
+                    }
+                    _ => self.text.push(ch),
+                },
+                '$' => self.parse_dollar(),
+                _ if Some(ch) == end => return,
+                _ => self.text.push(ch),
+            }
+        }
+    }
+
+    fn parse_dollar(&mut self) {
+        match self.chars.get(self.pos) {
+            Some(ch) if ch.is_ascii_digit() => {
+                let index = self.parse_index();
+                let start = self.text.len();
+                self.tabstops.push((index, start..start));
+            }
+            Some('{') => {
+                self.pos += 1;
+                self.parse_braced();
+            }
+            Some(ch) if is_variable_char(*ch) => {
+                // Variables like $TM_FILENAME aren't resolved
+                self.parse_name();
+            }
+            _ => self.text.push('$'),
+        }
+    }
+
+    fn parse_braced(&mut self) {
+        let is_tabstop = self
+            .chars
+            .get(self.pos)
+            .is_some_and(|ch| ch.is_ascii_digit());
+        let index = if is_tabstop {
+            Some(self.parse_index())
+        } else {
+            self.parse_name();
+            None
+        };
+        let start = self.text.len();
+        match self.chars.get(self.pos) {
+            Some(':') => {
+                self.pos += 1;
+                self.parse_until(Some('}'));
+            }
+            Some('|') => {
+                self.pos += 1;
+                self.parse_choice();
+            }
+            _ => self.skip_past('}'),
+        }
+        if let Some(index) = index {
+            self.tabstops.push((index, start..self.text.len()));
+        }
+    }
+
+    /// Inserts the first option of a `${1|one,two|}` choice
+    fn parse_choice(&mut self) {
+        let mut first = true;
+        while let Some(&ch) = self.chars.get(self.pos) {
+            self.pos += 1;
+            match ch {
+                '\\' => {
+                    if let Some(&escaped) = self.chars.get(self.pos) {
+                        self.pos += 1;
+                        if first {
+                            self.text.push(escaped);
+                        }
+                    }
+                }
+                ',' => first = false,
+                '|' => {
+                    self.skip_past('}');
+                    return;
+                }
+                _ if first => self.text.push(ch),
+                _ => {}
+            }
+        }
+    }
+
+    fn parse_index(&mut self) -> u32 {
+        let mut index = 0u32;
+        while let Some(digit) = self.chars.get(self.pos).and_then(|ch| ch.to_digit(10)) {
+            index = index.saturating_mul(10).saturating_add(digit);
+            self.pos += 1;
+        }
+        index
+    }
+
+    fn parse_name(&mut self) {
+        while self
+            .chars
+            .get(self.pos)
+            .is_some_and(|ch| is_variable_char(*ch))
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_past(&mut self, end: char) {
+        while let Some(&ch) = self.chars.get(self.pos) {
+            self.pos += 1;
+            if ch == end {
+                return;
+            }
+        }
+    }
+}
+
+fn is_variable_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Snippet};
+
+    #[test]
+    fn placeholders() {
+        let expected = Snippet {
+            text: "forEach(callback)".to_owned(),
+            tabstops: vec![8..16, 17..17],
+        };
+        assert_eq!(expected, parse("forEach(${1:callback})"));
+    }
+
+    #[test]
+    fn stops_are_ordered_by_index() {
+        let expected = Snippet {
+            text: "for (const item of items) {\n\t\n}".to_owned(),
+            tabstops: vec![19..24, 11..15, 29..29],
+        };
+        assert_eq!(
+            expected,
+            parse("for (const ${2:item} of ${1:items}) {\n\t$0\n}")
+        );
+    }
+
+    #[test]
+    fn choices_escapes_and_variables() {
+        let expected = Snippet {
+            text: "log(a, $x) b".to_owned(),
+            tabstops: vec![4..5, 12..12],
+        };
+        assert_eq!(expected, parse("log(${1|a,b|}, \\$x)$TM_FILENAME ${VAR:b}"));
+    }
+}
@@ -0,0 +1,50 @@
+use ratatui::{
+    buffer::Buffer,
+    layout::Rect,
+    style::{Color, Style},
+    text::{Span, Spans},
+    widgets::{Paragraph, Widget},
+};
+
+pub struct StatusBar<'a> {
+    pub(crate) errors: usize,
+    pub(crate) warnings: usize,
+    /// The server's current long-running task
+    pub(crate) progress: Option<&'a str>,
+    pub(crate) message: Option<&'a str>,
+    pub(crate) error: Option<&'a str>,
+}
+
+impl<'a> Widget for StatusBar<'a> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let mut spans = vec![
+            Span::styled(
+                format!(" E:{} ", self.errors),
+                Style::default().fg(Color::Red),
+            ),
+            Span::styled(
+                format!(" W:{} ", self.warnings),
+                Style::default().fg(Color::Yellow),
+            ),
+        ];
+        if let Some(progress) = self.progress {
+            spans.push(Span::styled(
+                format!(" {progress}"),
+                Style::default().fg(Color::Cyan),
+            ));
+        }
+        if let Some(message) = self.message {
+            spans.push(Span::raw(format!(" {message}")));
+        }
+        if let Some(error) = self.error {
+            spans.push(Span::styled(
+                format!(" {error}"),
+                Style::default().fg(Color::Red),
+            ));
+        }
+        let spans = Spans::from(spans);
+        Paragraph::new(spans)
+            .style(Style::default().bg(Color::Black))
+            .render(area, buf);
+    }
+}
@@ -1,15 +1,264 @@
-use kaolinite::Document;
+use std::{iter, mem};
+
+use kaolinite::{Document, Loc};
+use ratatui::style::{Color, Style};
+use ratatui::text::{Span, Spans, Text};
 use ratatui::widgets::{Paragraph, Widget};
+use unicode_width::UnicodeWidthChar;
 
-use super::highlight::highlight;
+use super::highlight::{apply_overlays, highlight, insert_virtual_text, Overlay, VirtualText};
+use super::theme::Theme;
 
 pub struct TextArea<'a> {
     pub(crate) doc: &'a Document,
+    pub(crate) overlays: &'a [Overlay],
+    pub(crate) virtual_text: &'a [VirtualText],
+    pub(crate) language_id: &'a str,
+    pub(crate) theme: &'a Theme,
+    /// The first line and display column in view. The column is ignored when wrapping.
+    pub(crate) scroll: Loc,
+    /// Wrap long lines instead of cutting them off at the right edge
+    pub(crate) wrap: bool,
+    /// Draw tabs and trailing spaces with visible glyphs
+    pub(crate) whitespace: bool,
 }
 
 impl<'a> Widget for TextArea<'a> {
     fn render(self, area: ratatui::layout::Rect, buf: &mut ratatui::buffer::Buffer) {
-        let text = highlight(self.doc.rope(), 0, 0);
-        Paragraph::new(text).render(area, buf);
+        let mut text = highlight(self.doc.rope(), self.language_id, self.theme);
+        apply_overlays(&mut text, self.overlays);
+        insert_virtual_text(&mut text, self.virtual_text);
+        if self.whitespace {
+            show_whitespace(&mut text, self.doc.tab_width, self.theme);
+        }
+        expand_tabs(&mut text, self.doc.tab_width);
+        let lines = text.lines.into_iter().skip(self.scroll.y);
+        let paragraph = if self.wrap {
+            let width = area.width as usize;
+            let lines: Vec<_> = lines
+                .flat_map(|line| wrap_line(line, width))
+                .take(area.height as usize)
+                .collect();
+            Paragraph::new(lines)
+        } else {
+            let lines: Vec<_> = lines.take(area.height as usize).collect();
+            Paragraph::new(lines).scroll((0, self.scroll.x as u16))
+        };
+        paragraph.render(area, buf);
+    }
+}
+
+/// Renders tabs as spaces so the columns line up with the document's cursor
+fn expand_tabs(text: &mut Text, tab_width: usize) {
+    let tab = " ".repeat(tab_width);
+    for span in text.lines.iter_mut().flat_map(|line| line.0.iter_mut()) {
+        if span.content.contains('\t') {
+            span.content = span.content.replace('\t', &tab).into();
+        }
+    }
+}
+
+/// Draws tabs as `→` and trailing spaces as `·`. Each glyph takes up the same columns as the
+/// whitespace it stands for, so the cursor still lines up.
+fn show_whitespace(text: &mut Text, tab_width: usize, theme: &Theme) {
+    let whitespace = theme
+        .style("ui.virtual.whitespace")
+        .unwrap_or_else(|| Style::default().fg(Color::DarkGray));
+    let trailing_whitespace = theme
+        .style("ui.virtual.trailing_whitespace")
+        .unwrap_or_else(|| Style::default().fg(Color::Red));
+    let tab = format!("→{}", " ".repeat(tab_width.saturating_sub(1)));
+    for line in &mut text.lines {
+        let chars = line.0.iter().flat_map(|span| span.content.chars());
+        let trailing = chars
+            .clone()
+            .rev()
+            .take_while(|ch| *ch == ' ' || *ch == '\t')
+            .count();
+        let trailing_start = chars.count() - trailing;
+
+        let mut index = 0;
+        let mut spans = vec![];
+        for span in mem::take(&mut line.0) {
+            let mut content = String::new();
+            for ch in span.content.chars() {
+                let is_trailing = index >= trailing_start;
+                index += 1;
+                let glyph = match ch {
+                    '\t' => tab.as_str(),
+                    ' ' if is_trailing => "·",
+                    ch => {
+                        content.push(ch);
+                        continue;
+                    }
+                };
+                if !content.is_empty() {
+                    spans.push(Span::styled(mem::take(&mut content), span.style));
+                }
+                let style = if is_trailing {
+                    trailing_whitespace
+                } else {
+                    whitespace
+                };
+                spans.push(Span::styled(glyph.to_owned(), span.style.patch(style)));
+            }
+            if !content.is_empty() {
+                spans.push(Span::styled(content, span.style));
+            }
+        }
+        line.0 = spans;
+    }
+}
+
+/// Splits a line into rows of at most `width` columns. A character that doesn't fit at the end
+/// of a row moves to the next one.
+fn wrap_line(line: Spans<'static>, width: usize) -> Vec<Spans<'static>> {
+    if width == 0 {
+        return vec![line];
+    }
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut column = 0;
+    for span in line.0 {
+        let mut content = String::new();
+        for ch in span.content.chars() {
+            let ch_width = ch.width().unwrap_or(0);
+            if column > 0 && column + ch_width > width {
+                row.push(Span::styled(mem::take(&mut content), span.style));
+                rows.push(Spans::from(mem::take(&mut row)));
+                column = 0;
+            }
+            content.push(ch);
+            column += ch_width;
+        }
+        row.push(Span::styled(content, span.style));
+    }
+    rows.push(Spans::from(row));
+    rows
+}
+
+/// The width of each column a character takes up on screen. Tabs are expanded to `tab_width`
+/// spaces, the same as when they're drawn.
+fn cell_widths(ch: char, tab_width: usize) -> impl Iterator<Item = usize> {
+    if ch == '\t' {
+        iter::repeat(1).take(tab_width)
+    } else {
+        iter::repeat(ch.width().unwrap_or(0)).take(1)
+    }
+}
+
+/// How many columns the characters take up on screen
+pub(crate) fn display_width(chars: &[char], tab_width: usize) -> usize {
+    chars
+        .iter()
+        .flat_map(|ch| cell_widths(*ch, tab_width))
+        .sum()
+}
+
+/// Lays out the characters in rows of `width` columns the same way as [`wrap_line`] and returns
+/// the row and column where the last one ends
+fn wrap_end(chars: &[char], tab_width: usize, width: usize) -> (usize, usize) {
+    let (mut row, mut column) = (0, 0);
+    for cell in chars.iter().flat_map(|ch| cell_widths(*ch, tab_width)) {
+        if column > 0 && column + cell > width {
+            row += 1;
+            column = 0;
+        }
+        column += cell;
+    }
+    (row, column)
+}
+
+/// How many rows the line takes up when it's wrapped to `width` columns
+pub(crate) fn wrapped_rows(line: &[char], tab_width: usize, width: usize) -> usize {
+    wrap_end(line, tab_width, width).0 + 1
+}
+
+/// The row and column of the character at index `x` once the line is wrapped to `width` columns.
+/// The end of a full row stays on its last column, since there's no row after it to move to.
+pub(crate) fn wrapped_position(
+    line: &[char],
+    x: usize,
+    tab_width: usize,
+    width: usize,
+) -> (usize, usize) {
+    let (row, column) = wrap_end(&line[..x.min(line.len())], tab_width, width);
+    match line.get(x) {
+        Some(ch) => {
+            let next = cell_widths(*ch, tab_width).next().unwrap_or(0);
+            if column > 0 && column + next > width {
+                (row + 1, 0)
+            } else {
+                (row, column)
+            }
+        }
+        None => (row, column.min(width.saturating_sub(1))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::style::{Color, Style};
+    use ratatui::text::{Spans, Text};
+
+    use super::{display_width, show_whitespace, wrap_line, wrapped_position, wrapped_rows};
+    use crate::tui::Theme;
+
+    #[test]
+    fn wrapped_positions_match_wrapped_rows() {
+        let line: Vec<_> = "ab\tc界d".chars().collect();
+        assert_eq!(display_width(&line, 4), 10);
+
+        // "ab  " / "  c" / "界d"
+        let rows: Vec<_> = wrap_line(Spans::from("ab    c界d"), 4)
+            .into_iter()
+            .map(|row| {
+                row.0
+                    .iter()
+                    .map(|span| span.content.clone())
+                    .collect::<String>()
+            })
+            .collect();
+        assert_eq!(rows, ["ab  ", "  c", "界d"]);
+        assert_eq!(wrapped_rows(&line, 4, 4), 3);
+
+        assert_eq!(wrapped_position(&line, 0, 4, 4), (0, 0));
+        assert_eq!(wrapped_position(&line, 2, 4, 4), (0, 2));
+        assert_eq!(wrapped_position(&line, 3, 4, 4), (1, 2));
+        // The wide character doesn't fit after "  c"
+        assert_eq!(wrapped_position(&line, 4, 4, 4), (2, 0));
+        assert_eq!(wrapped_position(&line, 5, 4, 4), (2, 2));
+        assert_eq!(wrapped_position(&line, 6, 4, 4), (2, 3));
+    }
+
+    #[test]
+    fn empty_line_takes_one_row() {
+        assert_eq!(wrapped_rows(&[], 4, 10), 1);
+        assert_eq!(wrapped_position(&[], 0, 4, 10), (0, 0));
+        assert_eq!(wrap_line(Spans::default(), 10).len(), 1);
+    }
+
+    #[test]
+    fn whitespace_glyphs() {
+        let mut text = Text::raw("\ta b \t");
+        show_whitespace(&mut text, 4, &Theme::dark());
+
+        let spans: Vec<_> = text.lines[0]
+            .0
+            .iter()
+            .map(|span| (span.content.as_ref(), span.style.fg))
+            .collect();
+        assert_eq!(
+            spans,
+            [
+                ("→   ", Some(Color::DarkGray)),
+                ("a b", None),
+                ("·", Some(Color::Red)),
+                ("→   ", Some(Color::Red)),
+            ]
+        );
+        // The glyphs take up the same columns as the whitespace
+        assert_eq!(text.width(), 4 + 3 + 1 + 4);
+        assert_eq!(Style::default(), text.lines[0].0[1].style);
     }
 }
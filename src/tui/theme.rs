@@ -0,0 +1,219 @@
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use anyhow::Context;
+use ratatui::style::{Color, Modifier, Style};
+use toml::{Table, Value};
+
+const THEMES_DIR: &str = "./themes";
+
+/// Maps tree-sitter capture names to the style used to draw them
+#[derive(Clone, Debug)]
+pub struct Theme {
+    styles: HashMap<String, Style>,
+}
+
+impl Theme {
+    /// Loads a built-in theme (`dark` or `light`), a theme from the `themes` directory by name,
+    /// or a theme file from a path
+    pub fn load(name: &str) -> anyhow::Result<Self> {
+        match name {
+            "dark" => Ok(Self::dark()),
+            "light" => Ok(Self::light()),
+            name => {
+                let themes_path = PathBuf::from(THEMES_DIR).join(format!("{name}.toml"));
+                let path = if themes_path.exists() {
+                    themes_path
+                } else {
+                    PathBuf::from(name)
+                };
+                let contents = fs::read_to_string(&path)
+                    .with_context(|| format!("failed to read theme {path:?}"))?;
+                Self::from_toml(&contents)
+            }
+        }
+    }
+
+    /// Parses a theme in the Helix format. Values are either a color or a table with
+    /// `fg`, `bg`, and `modifiers`. Colors can be hex codes or names from the `[palette]` table.
+    pub fn from_toml(contents: &str) -> anyhow::Result<Self> {
+        let mut table = contents.parse::<Table>()?;
+        let palette = match table.remove("palette") {
+            Some(Value::Table(palette)) => palette,
+            _ => Table::new(),
+        };
+        let color = |name: &str| {
+            let value = palette.get(name).and_then(|c| c.as_str()).unwrap_or(name);
+            parse_color(value)
+        };
+
+        let mut styles = HashMap::new();
+        for (scope, value) in table {
+            let style = match value {
+                Value::String(fg) => match color(&fg) {
+                    Some(fg) => Style::default().fg(fg),
+                    None => continue,
+                },
+                Value::Table(attributes) => {
+                    let mut style = Style::default();
+                    let get_color =
+                        |key: &str| attributes.get(key).and_then(|c| c.as_str()).and_then(color);
+                    if let Some(fg) = get_color("fg") {
+                        style = style.fg(fg);
+                    }
+                    if let Some(bg) = get_color("bg") {
+                        style = style.bg(bg);
+                    }
+                    for modifier in attributes
+                        .get("modifiers")
+                        .and_then(|m| m.as_array())
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|m| m.as_str())
+                    {
+                        style = style.add_modifier(parse_modifier(modifier));
+                    }
+                    style
+                }
+                _ => continue,
+            };
+            styles.insert(scope, style);
+        }
+        Ok(Self { styles })
+    }
+
+    pub fn dark() -> Self {
+        Self::from_colors(&[
+            ("attribute", Color::LightBlue),
+            ("constant", Color::LightYellow),
+            ("constant.builtin", Color::Red),
+            ("constructor", Color::Blue),
+            ("function", Color::Blue),
+            ("keyword", Color::Magenta),
+            ("label", Color::LightBlue),
+            ("namespace", Color::Yellow),
+            ("operator", Color::Cyan),
+            ("property", Color::LightCyan),
+            ("punctuation", Color::Gray),
+            ("string", Color::Green),
+            ("tag", Color::LightBlue),
+            ("type", Color::Yellow),
+            ("variable.builtin", Color::Red),
+            ("variable.other.member", Color::LightCyan),
+            ("variable.parameter", Color::LightRed),
+        ])
+        .with_comment(Color::DarkGray)
+    }
+
+    pub fn light() -> Self {
+        Self::from_colors(&[
+            ("attribute", Color::Blue),
+            ("constant", Color::Red),
+            ("constant.builtin", Color::Red),
+            ("constructor", Color::Blue),
+            ("function", Color::Blue),
+            ("keyword", Color::Magenta),
+            ("label", Color::Blue),
+            ("namespace", Color::Rgb(0x98, 0x68, 0x01)),
+            ("operator", Color::Black),
+            ("property", Color::Cyan),
+            ("punctuation", Color::Black),
+            ("string", Color::Rgb(0x0a, 0x7f, 0x0a)),
+            ("tag", Color::Blue),
+            ("type", Color::Rgb(0x98, 0x68, 0x01)),
+            ("variable.builtin", Color::Red),
+            ("variable.other.member", Color::Cyan),
+            ("variable.parameter", Color::Rgb(0x8b, 0x40, 0x00)),
+        ])
+        .with_comment(Color::Gray)
+    }
+
+    fn from_colors(colors: &[(&str, Color)]) -> Self {
+        let styles = colors
+            .iter()
+            .map(|(scope, color)| (scope.to_string(), Style::default().fg(*color)))
+            .collect();
+        Self { styles }
+    }
+
+    fn with_comment(mut self, color: Color) -> Self {
+        self.styles.insert(
+            "comment".to_owned(),
+            Style::default().fg(color).add_modifier(Modifier::ITALIC),
+        );
+        self
+    }
+
+    /// Finds the style for a capture, falling back to its parents (`keyword.control` -> `keyword`)
+    pub fn style(&self, capture: &str) -> Option<Style> {
+        let mut scope = capture;
+        loop {
+            if let Some(style) = self.styles.get(scope) {
+                return Some(*style);
+            }
+            scope = &scope[..scope.rfind('.')?];
+        }
+    }
+}
+
+fn parse_color(color: &str) -> Option<Color> {
+    let hex = color.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let r = u8::from_str_radix(&hex[0..2], 16).ok()?;
+    let g = u8::from_str_radix(&hex[2..4], 16).ok()?;
+    let b = u8::from_str_radix(&hex[4..6], 16).ok()?;
+    Some(Color::Rgb(r, g, b))
+}
+
+fn parse_modifier(modifier: &str) -> Modifier {
+    match modifier {
+        "bold" => Modifier::BOLD,
+        "italic" => Modifier::ITALIC,
+        "underlined" => Modifier::UNDERLINED,
+        "crossed_out" => Modifier::CROSSED_OUT,
+        "dim" => Modifier::DIM,
+        "reversed" => Modifier::REVERSED,
+        _ => Modifier::empty(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+    use ratatui::style::{Color, Modifier, Style};
+
+    use super::Theme;
+
+    #[test]
+    fn theme_from_toml() {
+        let theme = Theme::from_toml(indoc! {r##"
+            "keyword" = "mauve"
+            "string" = "#a6e3a1"
+            "comment" = { fg = "overlay", modifiers = ["italic"] }
+
+            [palette]
+            mauve = "#cba6f7"
+            overlay = "#7f849c"
+        "##})
+        .unwrap();
+
+        assert_eq!(
+            Some(Style::default().fg(Color::Rgb(0xcb, 0xa6, 0xf7))),
+            theme.style("keyword.control.return")
+        );
+        assert_eq!(
+            Some(Style::default().fg(Color::Rgb(0xa6, 0xe3, 0xa1))),
+            theme.style("string")
+        );
+        assert_eq!(
+            Some(
+                Style::default()
+                    .fg(Color::Rgb(0x7f, 0x84, 0x9c))
+                    .add_modifier(Modifier::ITALIC)
+            ),
+            theme.style("comment")
+        );
+        assert_eq!(None, theme.style("function"));
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Where the cursor and the viewport were in a document when it was last closed.
+/// Columns are char indices.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ViewState {
+    pub line: usize,
+    pub column: usize,
+    /// The first line in view
+    pub scroll_line: usize,
+    /// The first display column in view when lines aren't wrapped
+    pub scroll_column: usize,
+}
+
+/// The view state of each file that's been opened before, saved between runs
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ViewStates {
+    files: HashMap<PathBuf, ViewState>,
+}
+
+impl ViewStates {
+    /// Starts over with an empty history if the file is missing or can't be read
+    pub fn load(path: &Path) -> Self {
+        let Ok(contents) = fs::read_to_string(path) else {
+            return Self::default();
+        };
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            warn!("Ignoring invalid view states in {path:?}: {e:?}");
+            Self::default()
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn get(&self, file: &Path) -> Option<ViewState> {
+        self.files.get(file).copied()
+    }
+
+    pub fn insert(&mut self, file: PathBuf, state: ViewState) {
+        self.files.insert(file, state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::{env, fs, process};
+
+    use super::{ViewState, ViewStates};
+
+    #[test]
+    fn save_and_load() {
+        let path = env::temp_dir().join(format!("lsp-tui-view-states-{}.json", process::id()));
+        let state = ViewState {
+            line: 10,
+            column: 4,
+            scroll_line: 2,
+            scroll_column: 0,
+        };
+        let mut states = ViewStates::default();
+        states.insert("/src/main.ts".into(), state);
+        states.save(&path).unwrap();
+
+        let loaded = ViewStates::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(Some(state), loaded.get(Path::new("/src/main.ts")));
+        assert_eq!(None, loaded.get(Path::new("/src/other.ts")));
+
+        // A missing file starts out empty
+        assert_eq!(None, ViewStates::load(&path).get(Path::new("/src/main.ts")));
+    }
+}
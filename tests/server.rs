@@ -0,0 +1,192 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use lsp_tui::client::{serve_client, ClientNotification};
+use lsp_tui::config::ServerConfig;
+use lsp_tui::server::start_local_server;
+use serde_json::Value;
+use tokio::sync::mpsc;
+use tower_lsp::{lsp_types::*, ClientToServer};
+
+/// Connects a client to a freshly started built-in server and completes the handshake.
+/// The receiver needs to be kept alive for the client to accept notifications.
+async fn initialized_client(
+    buffer_size: usize,
+) -> (
+    Arc<tower_lsp::Client<ClientToServer>>,
+    mpsc::UnboundedReceiver<ClientNotification>,
+) {
+    let (in_stream, out_stream, _) = start_local_server(buffer_size);
+    let (notification_tx, notification_rx) = mpsc::unbounded_channel();
+    let lsp_client = serve_client(out_stream, in_stream, notification_tx, Value::Null);
+
+    let InitializeResult { capabilities, .. } = lsp_client
+        .initialize(InitializeParams::default())
+        .await
+        .unwrap();
+    assert_eq!(
+        Some(OneOf::Left(true)),
+        capabilities.document_symbol_provider
+    );
+    // Without any encodings from the client, the server falls back to UTF-16
+    assert_eq!(
+        Some(PositionEncodingKind::UTF16),
+        capabilities.position_encoding
+    );
+    lsp_client.initialized().await;
+    (lsp_client, notification_rx)
+}
+
+/// Requests the symbols for `uri`, retrying while the server is still handling `did_open`
+async fn document_symbols(
+    lsp_client: &tower_lsp::Client<ClientToServer>,
+    uri: &Url,
+) -> Vec<DocumentSymbol> {
+    for _ in 0..1000 {
+        let response = lsp_client
+            .document_symbol(DocumentSymbolParams {
+                text_document: TextDocumentIdentifier::new(uri.clone()),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            })
+            .await;
+        match response {
+            Ok(Some(DocumentSymbolResponse::Nested(symbols))) => return symbols,
+            Ok(response) => panic!("unexpected response {response:?}"),
+            // Notifications are handled asynchronously, so the document may not be open yet
+            Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+        }
+    }
+    panic!("server never returned symbols for {uri}");
+}
+
+#[tokio::test]
+async fn document_symbols_over_duplex() {
+    let buffer_size = ServerConfig::default().local_buffer_size;
+    let (lsp_client, _notification_rx) = initialized_client(buffer_size).await;
+
+    let uri: Url = "file:///test.js".parse().unwrap();
+    let text = indoc::indoc! {"
+        function add(a, b) {
+          return a + b;
+        }
+        class Counter {
+          increment() {}
+        }
+    "};
+    lsp_client
+        .did_open(TextDocumentItem::new(
+            uri.clone(),
+            "javascript".to_owned(),
+            0,
+            text.to_owned(),
+        ))
+        .await;
+
+    let symbols = document_symbols(&lsp_client, &uri).await;
+    let kinds: Vec<_> = symbols.iter().map(|s| (s.name.as_str(), s.kind)).collect();
+    assert_eq!(
+        vec![
+            ("add", SymbolKind::FUNCTION),
+            ("Counter", SymbolKind::CLASS)
+        ],
+        kinds
+    );
+    assert_eq!(
+        Range::new(Position::new(0, 0), Position::new(2, 1)),
+        symbols[0].range
+    );
+    assert_eq!(
+        Range::new(Position::new(0, 9), Position::new(0, 12)),
+        symbols[0].selection_range
+    );
+
+    let children: Vec<_> = symbols[1]
+        .children
+        .iter()
+        .flatten()
+        .map(|s| (s.name.as_str(), s.kind))
+        .collect();
+    assert_eq!(vec![("increment", SymbolKind::METHOD)], children);
+}
+
+#[tokio::test]
+async fn large_document_over_duplex() {
+    const FUNCTIONS: usize = 15_000;
+
+    let text: String = (0..FUNCTIONS)
+        .map(|i| format!("function f{i}() {{}}\n"))
+        .collect();
+    assert!(text.len() > 300 * 1024);
+
+    // Messages many times the size of the buffer have to stream through it
+    for buffer_size in [1024, ServerConfig::default().local_buffer_size] {
+        let (lsp_client, _notification_rx) = initialized_client(buffer_size).await;
+        let uri: Url = "file:///large.js".parse().unwrap();
+        lsp_client
+            .did_open(TextDocumentItem::new(
+                uri.clone(),
+                "javascript".to_owned(),
+                0,
+                text.clone(),
+            ))
+            .await;
+
+        let symbols =
+            tokio::time::timeout(Duration::from_secs(30), document_symbols(&lsp_client, &uri))
+                .await
+                .unwrap_or_else(|_| {
+                    panic!("round trip with a {buffer_size} byte buffer timed out")
+                });
+        assert_eq!(FUNCTIONS, symbols.len());
+        assert_eq!("f14999", symbols[FUNCTIONS - 1].name);
+    }
+}
+
+/// Waits for the next diagnostics published for `uri`, skipping other notifications
+async fn next_diagnostics(
+    notification_rx: &mut mpsc::UnboundedReceiver<ClientNotification>,
+    uri: &Url,
+) -> PublishDiagnosticsParams {
+    let wait = async {
+        loop {
+            match notification_rx.recv().await {
+                Some(ClientNotification::Diagnostics(params)) if &params.uri == uri => {
+                    return params;
+                }
+                Some(_) => {}
+                None => panic!("notification channel closed"),
+            }
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(10), wait)
+        .await
+        .unwrap_or_else(|_| panic!("no diagnostics published for {uri}"))
+}
+
+#[tokio::test]
+async fn saving_publishes_diagnostics() {
+    let buffer_size = ServerConfig::default().local_buffer_size;
+    let (lsp_client, mut notification_rx) = initialized_client(buffer_size).await;
+
+    let uri: Url = "file:///broken.js".parse().unwrap();
+    lsp_client
+        .did_open(TextDocumentItem::new(
+            uri.clone(),
+            "javascript".to_owned(),
+            0,
+            "function (".to_owned(),
+        ))
+        .await;
+    let opened = next_diagnostics(&mut notification_rx, &uri).await;
+    assert!(!opened.diagnostics.is_empty());
+
+    lsp_client
+        .did_save(DidSaveTextDocumentParams {
+            text_document: TextDocumentIdentifier::new(uri.clone()),
+            text: None,
+        })
+        .await;
+    let saved = next_diagnostics(&mut notification_rx, &uri).await;
+    assert_eq!(opened.diagnostics, saved.diagnostics);
+}